@@ -0,0 +1,12 @@
+extern crate prototype_template_language;
+
+use prototype_template_language::lsp;
+
+
+fn main() {
+    let stdin = ::std::io::stdin();
+    let stdout = ::std::io::stdout();
+
+    lsp::run(&mut stdin.lock(), &mut stdout.lock())
+        .expect("Language Server IO Error");
+}
@@ -0,0 +1,471 @@
+//! The `ptl` command line tool, separated from `main` to be testable.
+
+use ::std::io;
+use ::std::collections::HashMap;
+use ::flat::FlatObject;
+use ::scope::Host;
+use ::export::Scalars;
+
+/// external variables by name, see `::scope`
+type Variables = HashMap<String, String>;
+
+
+pub const USAGE: &str = "\
+usage:
+    ptl [options] <file>                  prints the resolved document
+    ptl [options] explain <file> <path>   prints where each part of a path like `comics.spiderman.title` came from
+    ptl [options] uses <file>             prints the names of the prelude objects the document uses
+    ptl [options] html <file> [<path>]    renders the document, or the object at the path, as html
+    ptl [options] xml <file> <path>       writes the object at the path as an xml element, named like the last property,
+                                          where properties starting with `@` are attributes
+    ptl [options] unflatten <file>        prints the document with inferred prototypes, for a resolved document or a json file
+    ptl [options] prototypes <file>       prints the inferred prototypes, which the unflattened document needs as a prelude
+    ptl [options] diff [--source] <old> <new>
+                                          prints the properties that were added, removed, or changed, one per line.
+                                          with `--source`, also prints changed prototypes, and why each property changed
+    ptl [options] impact <file> <path>    prints the resolved properties that change if the declaration at the path changes
+
+options:
+    --var <name>=<value>   makes the value available as `env.name`
+    --env <prefix>         makes every environment variable starting with the prefix available as `env.name`,
+                           where the name is the rest of the environment variable name
+    --prelude <file>       makes the objects of the file available, unless the document declares the same name
+    --format <format>      prints the resolved document as `ptl`, `json`, `yaml`, or `toml`
+    --coerce               writes string literals like `'80'` and `'true'` as numbers and booleans in `json`, `yaml`, and `toml`";
+
+
+/// the options that configure the host, see `::scope::Host`
+#[derive(Default)]
+struct Options<'a> {
+    /// none without any `--var` or `--env`, so that `env` is not reserved
+    variables: Option<Variables>,
+    preludes: Vec<&'a str>,
+    format: Format,
+    scalars: Scalars,
+}
+
+/// how the resolved document is printed, see `::export`
+#[derive(Default, Clone, Copy)]
+enum Format {
+    #[default]
+    Ptl,
+    Json,
+    Yaml,
+    Toml,
+}
+
+
+/// returns the text to print, or an error message.
+/// files are loaded with `read_file`, and `environment` contains the environment variables,
+/// which allows running without a file system or process
+pub fn run<F>(arguments: &[String], environment: &HashMap<String, String>, read_file: F) -> Result<String, String>
+    where F: Fn(&str) -> io::Result<String>
+{
+    let arguments: Vec<&str> = arguments.iter().map(String::as_str).collect();
+    let (arguments, options) = take_options(&arguments, environment)?;
+    let load = |path: &str| read_file(path).map_err(|error| format!("cannot read `{}`: {}", path, error));
+
+    let prelude_sources = options.preludes.iter().map(|path| load(path)).collect::<Result<Vec<String>, String>>()?;
+    let preludes = options.preludes.iter().zip(&prelude_sources)
+        .map(|(path, source)| ::parse::parse(source).map_err(|error| format!("in `{}`: {}", path, parse_error(source, error))))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut host = Host::default();
+    if let Some(variables) = options.variables.as_ref() { host.variables(variables); }
+    for prelude in &preludes { host.prelude(prelude); }
+
+    match arguments.as_slice() {
+        ["explain", file, path] => explain(&load(file)?, path, &host),
+        ["uses", file] => uses(&load(file)?, &host),
+        ["html", file] => html(&load(file)?, None, &host),
+        ["html", file, path] => html(&load(file)?, Some(path), &host),
+        ["xml", file, path] => xml(&load(file)?, path, &host),
+        ["unflatten", file] => unflatten(file, &load(file)?, &host).map(|unflattened| unflattened.document),
+        ["prototypes", file] => unflatten(file, &load(file)?, &host).map(|unflattened| unflattened.prototypes),
+        ["diff", old, new] => diff(&load(old)?, &load(new)?, false, &host),
+        ["diff", "--source", old, new] => diff(&load(old)?, &load(new)?, true, &host),
+        ["impact", file, path] => impact(&load(file)?, path, &host),
+        [file] if !file.starts_with('-') => resolve(&load(file)?, options.format, options.scalars, &host),
+        _ => Err(String::from(USAGE)),
+    }
+}
+
+/// separates the options from the other arguments, where later variables win
+fn take_options<'a>(arguments: &[&'a str], environment: &HashMap<String, String>) -> Result<(Vec<&'a str>, Options<'a>), String> {
+    let mut options = Options::default();
+    let mut remaining = Vec::new();
+    let mut arguments = arguments.iter();
+
+    while let Some(&argument) = arguments.next() {
+        match argument {
+            "--var" => {
+                let assignment = arguments.next().ok_or_else(|| String::from(USAGE))?;
+                let (name, value) = assignment.split_once('=')
+                    .ok_or_else(|| format!("expected `<name>=<value>` after `--var`, found `{}`", assignment))?;
+
+                options.variables.get_or_insert_with(HashMap::new).insert(name.to_string(), value.to_string());
+            },
+
+            "--env" => {
+                let prefix = arguments.next().ok_or_else(|| String::from(USAGE))?;
+                let variables = options.variables.get_or_insert_with(HashMap::new);
+
+                for (name, value) in environment {
+                    if let Some(name) = name.strip_prefix(prefix) {
+                        variables.insert(name.to_string(), value.clone());
+                    }
+                }
+            },
+
+            "--coerce" => options.scalars = Scalars::Coerced,
+
+            "--prelude" => options.preludes.push(arguments.next().ok_or_else(|| String::from(USAGE))?),
+
+            "--format" => options.format = match *arguments.next().ok_or_else(|| String::from(USAGE))? {
+                "ptl" => Format::Ptl,
+                "json" => Format::Json,
+                "yaml" => Format::Yaml,
+                "toml" => Format::Toml,
+                format => return Err(format!("unknown format `{}`, expected `ptl`, `json`, `yaml`, or `toml`", format)),
+            },
+
+            argument => remaining.push(argument),
+        }
+    }
+
+    Ok((remaining, options))
+}
+
+fn resolve(source: &str, format: Format, scalars: Scalars, host: &Host) -> Result<String, String> {
+    let parsed = ::parse::parse(source).map_err(|error| parse_error(source, error))?;
+    let resolved = FlatObject::build_in(&parsed, host).map_err(|error| resolve_error(source, error))?;
+
+    match format {
+        Format::Ptl => Ok(resolved.to_string()),
+        Format::Json => Ok(::export::json(&resolved, scalars).to_string()),
+        Format::Yaml => Ok(::export::yaml::write(&resolved, scalars)),
+        Format::Toml => ::export::toml::write(&resolved, scalars).map_err(|error| format!("export error: {}", error)),
+    }
+}
+
+fn explain(source: &str, path: &str, host: &Host) -> Result<String, String> {
+    let path: Vec<&str> = path.split('.').collect();
+    let parsed = ::parse::parse(source).map_err(|error| parse_error(source, error))?;
+
+    match FlatObject::build_traced_in(&parsed, host) {
+        Ok((_, trace)) => trace.explain(source, &path)
+            .ok_or_else(|| format!("there is no property `{}`", path.join("."))),
+
+        Err(error) => Err(resolve_error(source, error)),
+    }
+}
+
+fn uses(source: &str, host: &Host) -> Result<String, String> {
+    let parsed = ::parse::parse(source).map_err(|error| parse_error(source, error))?;
+
+    match FlatObject::prelude_usage(&parsed, host) {
+        Ok(names) => Ok(names.into_iter().map(|name| name + "\n").collect()),
+        Err(error) => Err(resolve_error(source, error)),
+    }
+}
+
+fn html(source: &str, path: Option<&str>, host: &Host) -> Result<String, String> {
+    let parsed = ::parse::parse(source).map_err(|error| parse_error(source, error))?;
+    let resolved = FlatObject::build_in(&parsed, host).map_err(|error| resolve_error(source, error))?;
+
+    let object = match path {
+        None => &resolved,
+        Some(path) => resolved.get_path(&path.split('.').collect::<Vec<&str>>())
+            .ok_or_else(|| format!("there is no property `{}`", path))?,
+    };
+
+    ::html::render(object).map_err(|error| format!("render error: {}", error))
+}
+
+fn xml(source: &str, path: &str, host: &Host) -> Result<String, String> {
+    let parsed = ::parse::parse(source).map_err(|error| parse_error(source, error))?;
+    let resolved = FlatObject::build_in(&parsed, host).map_err(|error| resolve_error(source, error))?;
+
+    let path: Vec<&str> = path.split('.').collect();
+    let object = resolved.get_path(&path).ok_or_else(|| format!("there is no property `{}`", path.join(".")))?;
+
+    ::export::xml::Xml::default().write(path[path.len() - 1], object)
+        .map_err(|error| format!("export error: {}", error))
+}
+
+fn diff(old: &str, new: &str, source_level: bool, host: &Host) -> Result<String, String> {
+    let old_document = ::parse::parse(old).map_err(|error| format!("in the old document: {}", parse_error(old, error)))?;
+    let new_document = ::parse::parse(new).map_err(|error| format!("in the new document: {}", parse_error(new, error)))?;
+
+    let (old_resolved, old_trace) = FlatObject::build_traced_in(&old_document, host)
+        .map_err(|error| format!("in the old document: {}", resolve_error(old, error)))?;
+
+    let (new_resolved, new_trace) = FlatObject::build_traced_in(&new_document, host)
+        .map_err(|error| format!("in the new document: {}", resolve_error(new, error)))?;
+
+    let lines: Vec<String> = if source_level {
+        let old = ::diff::Version { document: &old_document, resolved: &old_resolved, trace: &old_trace };
+        let new = ::diff::Version { document: &new_document, resolved: &new_resolved, trace: &new_trace };
+        ::diff::diff_versions(&old, &new).iter().map(ToString::to_string).collect()
+    }
+    else {
+        ::diff::diff(&old_resolved, &new_resolved).iter().map(ToString::to_string).collect()
+    };
+
+    Ok(lines.into_iter().map(|line| line + "\n").collect())
+}
+
+fn impact(source: &str, path: &str, host: &Host) -> Result<String, String> {
+    let path: Vec<&str> = path.split('.').collect();
+    let parsed = ::parse::parse(source).map_err(|error| parse_error(source, error))?;
+    let (_, trace) = FlatObject::build_traced_in(&parsed, host).map_err(|error| resolve_error(source, error))?;
+
+    let dependencies = ::impact::Dependencies::new(&parsed, &trace);
+    if !dependencies.is_declared(&path) {
+        return Err(format!("there is no declaration `{}`", path.join(".")));
+    }
+
+    Ok(dependencies.affected(&path).into_iter().map(|path| path.join(".") + "\n").collect())
+}
+
+/// json files are converted, and other files are resolved first
+fn unflatten(file: &str, source: &str, host: &Host) -> Result<::unflatten::Unflattened, String> {
+    let object = if file.ends_with(".json") {
+        let json = ::json::Json::parse(source).map_err(|error| match error {
+            ::json::JsonError::UnexpectedSymbol { expected, index } => {
+                let (line, column) = ::parse::line_and_column(source, index);
+                format!("json error at {}:{}: expected {}", line, column, expected)
+            },
+
+            ::json::JsonError::UnexpectedEndOfInput { expected } => format!("json error: expected {}, found end of input", expected),
+        })?;
+
+        ::unflatten::from_json(&json)
+    }
+    else {
+        let parsed = ::parse::parse(source).map_err(|error| parse_error(source, error))?;
+        Ok(FlatObject::build_in(&parsed, host).map_err(|error| resolve_error(source, error))?)
+    };
+
+    object.and_then(|object| ::unflatten::unflatten(&object))
+        .map_err(|error| format!("unflatten error: {}", error))
+}
+
+fn resolve_error(source: &str, error: ::parse::ResolveError) -> String {
+    match error.span() {
+        Some(span) => {
+            let (line, column) = ::parse::line_and_column(source, span.start);
+            format!("resolve error at {}:{}: {}", line, column, error)
+        },
+
+        None => format!("resolve error: {}", error),
+    }
+}
+
+fn parse_error(source: &str, error: ::parse::ParseError) -> String {
+    let (line, column) = ::parse::line_and_column(source, error.span(source).start);
+    format!("parse error at {}:{}: {}", line, column, error)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const COMICS: &str = "\
+ok_text: 'Ok'
+Comic: {
+    title: 'Untitled'
+    author: { name: 'unknown' }
+}
+Special: Comic { edition: 'special' }
+comics: {
+    spiderman: Special { title: ok_text }
+    draft: Comic { title: !unset }
+}
+";
+
+    fn run_with(arguments: &[&str]) -> Result<String, String> {
+        let arguments: Vec<String> = arguments.iter().map(|argument| argument.to_string()).collect();
+        let environment = vec![ (String::from("PTL_TARGET"), String::from("prod")), (String::from("HOME"), String::from("/")) ];
+        run(&arguments, &environment.into_iter().collect(), |path| {
+            if path == "comics.ptl" { Ok(String::from(COMICS)) }
+            else if path == "html.ptl" { Ok(String::from("div: { html-name: 'div' } p: { html-name: 'p' }")) }
+            else if path == "page.ptl" { Ok(String::from("page: div { inner: 'Hello' }")) }
+            else if path == "script.ptl" { Ok(String::from(": { html-name: '<script>' }")) }
+            else if path == "users.json" { Ok(String::from(r#"[ { "role": "administrator", "team": "platform", "name": "a" }, { "role": "administrator", "team": "platform", "name": "b" }, { "role": "administrator", "team": "platform" } ]"#)) }
+            else if path == "null.json" { Ok(String::from("{ \"a\": null }")) }
+            else if path == "comics-v2.ptl" { Ok(COMICS.replace("edition: 'special'", "edition: 'limited'").replace("spiderman: Special { title: ok_text }", "spiderman: Special")) }
+            else if path == "broken.ptl" { Ok(String::from("a: {")) }
+            else if path == "service.ptl" { Ok(String::from("port: '80' public: 'true'")) }
+            else if path == "comic.ptl" { Ok(String::from("Comic: { title: 'untitled' author: { name: 'unknown' age: '0' } }")) }
+            else if path == "spiderman.ptl" { Ok(String::from("spiderman: Comic { author: { name: 'Stan' } }")) }
+            else if path == "greeting.ptl" { Ok(String::from("greeting: 'Hello ${env.name} in ${env.TARGET}'")) }
+            else { Err(io::Error::new(io::ErrorKind::NotFound, "not found")) }
+        })
+    }
+
+    #[test]
+    fn test_resolve(){
+        assert_eq!(run_with(&["comics.ptl"]).map(|output| output.contains("edition: 'special'")), Ok(true));
+        assert_eq!(
+            run_with(&["comics.ptl"]).map(|output| output.starts_with("{\n    ok_text: 'Ok'\n    Comic: {\n        title: 'Untitled'\n        author: {")),
+            Ok(true)
+        );
+        assert_eq!(run_with(&["missing.ptl"]), Err(String::from("cannot read `missing.ptl`: not found")));
+        assert_eq!(run_with(&[]), Err(String::from(USAGE)));
+
+        assert_eq!(
+            resolve("a: { }\nb: 'x ${a}'", Format::Ptl, Scalars::Strings, &Host::default()),
+            Err(String::from("resolve error at 2:9: `a` is not a string literal and cannot be inserted into one"))
+        );
+    }
+
+    #[test]
+    fn test_variables(){
+        assert_eq!(
+            run_with(&["--var", "name=Peter", "--env", "PTL_", "greeting.ptl"]),
+            Ok(String::from("{\n    greeting: 'Hello Peter in prod'\n}"))
+        );
+
+        // later options win
+        assert_eq!(run_with(&["--env", "PTL_", "--var", "TARGET=dev", "--var", "name=", "greeting.ptl"]).map(|output| output.contains("in dev")), Ok(true));
+
+        assert_eq!(
+            run_with(&["--var", "name=Peter", "greeting.ptl"]),
+            Err(String::from("resolve error: the external variable `TARGET` has not been supplied"))
+        );
+
+        assert_eq!(run_with(&["--var", "name", "greeting.ptl"]), Err(String::from("expected `<name>=<value>` after `--var`, found `name`")));
+        assert_eq!(run_with(&["comics.ptl", "--var"]), Err(String::from(USAGE)));
+        assert_eq!(run_with(&["greeting.ptl"]), Err(String::from("resolve error: no object named `env`")));
+    }
+
+    #[test]
+    fn test_prelude(){
+        assert_eq!(
+            run_with(&["--prelude", "html.ptl", "page.ptl"]),
+            Ok(String::from("{\n    page: {\n        html-name: 'div'\n        inner: 'Hello'\n    }\n}"))
+        );
+
+        assert_eq!(run_with(&["--prelude", "html.ptl", "uses", "page.ptl"]), Ok(String::from("div\n")));
+        assert_eq!(run_with(&["page.ptl"]), Err(String::from("resolve error: no object named `div`")));
+        assert_eq!(run_with(&["--prelude", "page.ptl", "--prelude", "broken.ptl", "page.ptl"]), Err(String::from("in `broken.ptl`: parse error at 1:5: expected `}`, found end of input")));
+    }
+
+    #[test]
+    fn test_html(){
+        assert_eq!(run_with(&["--prelude", "html.ptl", "html", "page.ptl"]), Ok(String::new()));
+        assert_eq!(run_with(&["--prelude", "html.ptl", "html", "page.ptl", "page"]), Ok(String::from("<div>Hello</div>")));
+        assert_eq!(run_with(&["--prelude", "html.ptl", "html", "page.ptl", "missing"]), Err(String::from("there is no property `missing`")));
+        assert_eq!(run_with(&["html", "script.ptl"]), Err(String::from("render error: `<script>` is not a valid html name, at `0.html-name`")));
+    }
+
+    #[test]
+    fn test_format(){
+        assert_eq!(
+            run_with(&["--format", "yaml", "comics.ptl"]).map(|output| output.lines().take(4).collect::<Vec<_>>().join("\n")),
+            Ok(String::from("ok_text: Ok\nComic:\n  title: Untitled\n  author:"))
+        );
+
+        assert_eq!(run_with(&["--var", "name=Peter", "--var", "TARGET=dev", "--format", "toml", "greeting.ptl"]), Ok(String::from("greeting = \"Hello Peter in dev\"\n")));
+        assert_eq!(run_with(&["--format", "json", "page.ptl", "--prelude", "html.ptl"]), Ok(String::from(r#"{"page":{"html-name":"div","inner":"Hello"}}"#)));
+        assert_eq!(run_with(&["--format", "json", "service.ptl"]), Ok(String::from(r#"{"port":"80","public":"true"}"#)));
+        assert_eq!(run_with(&["--coerce", "--format", "json", "service.ptl"]), Ok(String::from(r#"{"port":80,"public":true}"#)));
+        assert_eq!(run_with(&["--format", "toml", "script.ptl"]), Err(String::from("export error: toml documents must be tables, but this is a list")));
+        assert_eq!(run_with(&["--format", "xml", "comics.ptl"]), Err(String::from("unknown format `xml`, expected `ptl`, `json`, `yaml`, or `toml`")));
+    }
+
+    #[test]
+    fn test_xml(){
+        assert_eq!(
+            run_with(&["xml", "comics.ptl", "comics.spiderman"]),
+            Ok(String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                <spiderman><title>Ok</title><author><name>unknown</name></author><edition>special</edition></spiderman>\n"))
+        );
+
+        assert_eq!(run_with(&["xml", "comics.ptl", "comics.batman"]), Err(String::from("there is no property `comics.batman`")));
+        assert_eq!(run_with(&["xml", "script.ptl", "0"]), Err(String::from("export error: `0` is not a valid xml name, at `0`")));
+    }
+
+    #[test]
+    fn test_unflatten(){
+        assert_eq!(run_with(&["prototypes", "users.json"]), Ok(String::from("Prototype0: {\n    role: 'administrator'\n    team: 'platform'\n}\n")));
+        assert_eq!(run_with(&["unflatten", "users.json"]), Ok(String::from(": Prototype0 {\n    name: 'a'\n}\n: Prototype0 {\n    name: 'b'\n}\n: Prototype0\n")));
+        assert_eq!(run_with(&["unflatten", "comics.ptl"]).map(|document| document.contains("spiderman: ")), Ok(true));
+        assert_eq!(run_with(&["unflatten", "null.json"]), Err(String::from("unflatten error: `a` is null, which has no equivalent")));
+    }
+
+    #[test]
+    fn test_diff(){
+        assert_eq!(run_with(&["diff", "comics.ptl", "comics.ptl"]), Ok(String::new()));
+        assert_eq!(
+            run_with(&["diff", "comics.ptl", "comics-v2.ptl"]),
+            Ok(String::from("~ Special.edition: 'special' -> 'limited'\n~ comics.spiderman.title: 'Ok' -> 'Untitled'\n~ comics.spiderman.edition: 'special' -> 'limited'\n"))
+        );
+
+        assert_eq!(
+            run_with(&["diff", "--source", "comics.ptl", "comics-v2.ptl"]),
+            Ok(String::from("\
+~ Special.edition: 'special' -> 'limited'  (override changed)
+~ comics.spiderman.title: 'Ok' -> 'Untitled'  (override removed)
+~ comics.spiderman.edition: 'special' -> 'limited'  (inherited value changed because `Special` changed)
+"))
+        );
+
+        assert_eq!(run_with(&["diff", "comics.ptl", "broken.ptl"]), Err(String::from("in the new document: parse error at 1:5: expected `}`, found end of input")));
+    }
+
+    #[test]
+    fn test_impact(){
+        assert_eq!(
+            run_with(&["impact", "comics.ptl", "Comic.title"]),
+            Ok(String::from("Comic.title\nSpecial.title\n"))
+        );
+
+        assert_eq!(
+            run_with(&["impact", "comics.ptl", "ok_text"]),
+            Ok(String::from("comics.spiderman.title\nok_text\n"))
+        );
+
+        assert_eq!(run_with(&["impact", "comics.ptl", "comics.spiderman.author"]), Err(String::from("there is no declaration `comics.spiderman.author`")));
+    }
+
+    #[test]
+    fn test_explain(){
+        assert_eq!(
+            run_with(&["explain", "comics.ptl", "comics.spiderman.author.name"]),
+            Ok(String::from("\
+`comics` is defined at 7:1
+`spiderman` is defined at 8:5
+`author` is inherited from `Special` (6:1) through `Comic` (2:1), defined at 4:5
+`name` is defined at 4:15
+"))
+        );
+
+        assert_eq!(
+            run_with(&["explain", "comics.ptl", "comics.spiderman.title"]).map(|output| output.lines().last().map(str::to_string)),
+            Ok(Some(String::from("`title` is defined at 8:26, copied from variable `ok_text` (1:1)")))
+        );
+
+        assert_eq!(
+            run_with(&["explain", "comics.ptl", "comics.draft.title"]).map(|output| output.lines().last().map(str::to_string)),
+            Ok(Some(String::from("`title` is unset at 9:20")))
+        );
+
+        assert_eq!(
+            run_with(&["explain", "comics.ptl", "comics.batman"]),
+            Err(String::from("there is no property `comics.batman`"))
+        );
+
+        assert_eq!(
+            run_with(&["--prelude", "comic.ptl", "explain", "spiderman.ptl", "spiderman.title"]),
+            Ok(String::from("`spiderman` is defined at 1:1\n`title` is inherited from `Comic` (prelude), defined in a prelude\n"))
+        );
+
+        assert_eq!(
+            run_with(&["--prelude", "comic.ptl", "explain", "spiderman.ptl", "spiderman.author.age"]),
+            Ok(String::from("`spiderman` is defined at 1:1\n`author` is defined at 1:20\n`age` is inherited from `Comic` (prelude), defined in a prelude\n"))
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Compares two versions of a resolved document, property by property.
+//!
+//! `diff` only compares the resolved objects. `diff_versions` also uses the sources and traces,
+//! to report changed prototypes, and whether a property changed because the document overrides it,
+//! or because the prototype it is inherited from changed.
+
+use ::std::fmt;
+use ::parse::{ NamedObjects, Object, Span };
+use ::flat::FlatObject;
+use ::trace::Trace;
+
+
+/// the paths contain the names from the root of the document to the changed property
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Change {
+    Added { path: Vec<String>, value: FlatObject },
+    Removed { path: Vec<String>, value: FlatObject },
+    ChangedLiteral { path: Vec<String>, old: String, new: String },
+
+    /// a string literal became a compound, or the other way around
+    Replaced { path: Vec<String>, old: FlatObject, new: FlatObject },
+
+    /// the prototypes of a compound, as written in the source, only reported by `diff_versions`
+    ChangedPrototype { path: Vec<String>, old: Vec<String>, new: Vec<String> },
+}
+
+/// why a property changed, see `diff_versions`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Cause {
+    /// the document now overrides the property, which was inherited or did not exist
+    OverrideAdded,
+    OverrideChanged,
+
+    /// the document no longer overrides the property
+    OverrideRemoved,
+
+    /// the document now removes the property with `!unset`
+    Unset,
+
+    /// the property is inherited, and the prototype that declares it changed, or was replaced
+    PrototypeChanged { prototype: String },
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct SourceChange {
+    pub change: Change,
+    pub cause: Cause,
+}
+
+/// one version of a document, resolved with tracing, see `FlatObject::build_traced`
+#[derive(Clone, Copy)]
+pub struct Version<'d> {
+    pub document: &'d NamedObjects<'d>,
+    pub resolved: &'d FlatObject,
+    pub trace: &'d Trace,
+}
+
+/// where a property of a version comes from
+#[derive(Eq, PartialEq, Debug, Clone)]
+enum Origin {
+    Absent,
+    Unset,
+
+    /// the document declares the property, and it is not part of an inherited compound
+    Local,
+
+    /// the name of the prototype that declares the property or a compound containing it,
+    /// or of the object that a containing compound is a copy of
+    Inherited(String),
+
+    /// the document declares the property, whose value is a copy of the named object
+    Copied(String),
+}
+
+
+/// the changes in the order of the properties of the new version, followed by the removed ones
+pub fn diff(old: &FlatObject, new: &FlatObject) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_objects(old, new, &mut Vec::new(), None, &mut changes);
+    changes
+}
+
+/// like `diff`, but also reports changed prototypes, and why each property changed
+pub fn diff_versions(old: &Version, new: &Version) -> Vec<SourceChange> {
+    let mut changes = Vec::new();
+    diff_objects(old.resolved, new.resolved, &mut Vec::new(), Some((old, new)), &mut changes);
+
+    changes.into_iter()
+        .map(|change| {
+            let path = change.path();
+            let cause = cause(old.origin(path), new.origin(path));
+            SourceChange { change, cause }
+        })
+        .collect()
+}
+
+fn diff_objects(old: &FlatObject, new: &FlatObject, path: &mut Vec<String>, versions: Option<(&Version, &Version)>, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (FlatObject::StringLiteral(old), FlatObject::StringLiteral(new)) => if old != new {
+            changes.push(Change::ChangedLiteral { path: path.clone(), old: old.clone(), new: new.clone() });
+        },
+
+        (FlatObject::Compound(old_properties), FlatObject::Compound(new_properties)) => {
+            if let Some((old_version, new_version)) = versions {
+                let (old_prototypes, new_prototypes) = (old_version.prototypes(path), new_version.prototypes(path));
+                if old_prototypes != new_prototypes {
+                    changes.push(Change::ChangedPrototype { path: path.clone(), old: old_prototypes, new: new_prototypes });
+                }
+            }
+
+            for (name, new_value) in new_properties.iter() {
+                path.push(name.clone());
+
+                match old_properties.get(name) {
+                    Some(old_value) => diff_objects(old_value, new_value, path, versions, changes),
+                    None => changes.push(Change::Added { path: path.clone(), value: new_value.clone() }),
+                }
+
+                path.pop();
+            }
+
+            for (name, old_value) in old_properties.iter() {
+                if !new_properties.contains_key(name) {
+                    path.push(name.clone());
+                    changes.push(Change::Removed { path: path.clone(), value: old_value.clone() });
+                    path.pop();
+                }
+            }
+        },
+
+        _ => changes.push(Change::Replaced { path: path.clone(), old: old.clone(), new: new.clone() }),
+    }
+}
+
+fn cause(old: Origin, new: Origin) -> Cause {
+    match (old, new) {
+        (_, Origin::Unset) => Cause::Unset,
+        (Origin::Copied(old), Origin::Copied(ref new)) if old == *new => Cause::PrototypeChanged { prototype: old },
+        (Origin::Local, Origin::Local) | (Origin::Local, Origin::Copied(_))
+            | (Origin::Copied(_), Origin::Local) | (Origin::Copied(_), Origin::Copied(_)) => Cause::OverrideChanged,
+        (_, Origin::Local) | (_, Origin::Copied(_)) => Cause::OverrideAdded,
+        (Origin::Local, _) | (Origin::Copied(_), _) => Cause::OverrideRemoved,
+        (_, Origin::Inherited(prototype)) | (Origin::Inherited(prototype), _) => Cause::PrototypeChanged { prototype },
+        (_, Origin::Absent) => Cause::OverrideRemoved,
+    }
+}
+
+impl Change {
+    pub fn path(&self) -> &[String] {
+        match *self {
+            Change::Added { ref path, .. } | Change::Removed { ref path, .. } | Change::ChangedLiteral { ref path, .. }
+                | Change::Replaced { ref path, .. } | Change::ChangedPrototype { ref path, .. } => path,
+        }
+    }
+}
+
+impl<'d> Version<'d> {
+    /// the nearest inherited or copied compound containing the property decides where it comes from
+    fn origin(&self, path: &[String]) -> Origin {
+        if self.trace.is_unset(path) { return Origin::Unset; }
+
+        let property = match self.trace.get(path) {
+            Some(property) => property,
+            None => return Origin::Absent,
+        };
+
+        if let Some(prototype) = property.inherited_from.last() {
+            return Origin::Inherited(prototype.name.clone());
+        }
+
+        let containing = (1 .. path.len()).rev()
+            .filter_map(|end| self.trace.get(&path[.. end]))
+            .find_map(|provenance| provenance.inherited_from.last().or_else(|| provenance.inlined_from.last()));
+
+        match (containing, property.inlined_from.last()) {
+            (Some(origin), _) => Origin::Inherited(origin.name.clone()),
+            (None, Some(variable)) => Origin::Copied(variable.name.clone()),
+            (None, None) => Origin::Local,
+        }
+    }
+
+    /// the prototypes of the declaration that the property uses, empty if it is not a compound
+    fn prototypes(&self, path: &[String]) -> Vec<String> {
+        let declared = self.trace.get(path)
+            .and_then(|provenance| find_declaration(self.document, provenance.defined_at.span()?));
+
+        match declared {
+            Some(Object::Compound(compound)) => compound.prototypes.iter().map(|prototype| prototype.to_string()).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// the object whose name is at the span, in any compound of the document
+fn find_declaration<'d>(objects: &'d NamedObjects<'d>, span: Span) -> Option<&'d Object<'d>> {
+    let declared = objects.identifiers.iter().map(|(identifier, &index)| (identifier, index))
+        .chain(objects.unnamed.iter().map(|&(ref identifier, index)| (identifier, index)))
+        .find(|&(identifier, _)| identifier.location == Some(span))
+        .map(|(_, index)| &objects.objects[index]);
+
+    declared
+        .or_else(|| objects.objects.iter().find_map(|object| match *object {
+            Object::Compound(ref compound) => find_declaration(&compound.overrides, span),
+            _ => None,
+        }))
+        .or_else(|| objects.conditionals.iter().find_map(|conditional| find_declaration(&conditional.overrides, span)))
+}
+
+/// a compound on a single line, like `{ title: 'x' }`
+fn write_inline(object: &FlatObject, formatter: &mut fmt::Formatter) -> fmt::Result {
+    match *object {
+        FlatObject::StringLiteral(ref text) => write!(formatter, "'{}'", text),
+        FlatObject::Compound(ref properties) if properties.is_empty() => formatter.write_str("{}"),
+        FlatObject::Compound(ref properties) => {
+            formatter.write_str("{")?;
+            for (name, value) in properties.iter() {
+                write!(formatter, " {}: ", name)?;
+                write_inline(value, formatter)?;
+            }
+            formatter.write_str(" }")
+        },
+    }
+}
+
+/// `+` for added, `-` for removed, and `~` for changed properties
+impl fmt::Display for Change {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match *self {
+            Change::Added { .. } => '+',
+            Change::Removed { .. } => '-',
+            _ => '~',
+        };
+
+        write!(formatter, "{} {}: ", symbol, self.path().join("."))?;
+
+        match *self {
+            Change::Added { ref value, .. } | Change::Removed { ref value, .. } => write_inline(value, formatter),
+            Change::ChangedLiteral { ref old, ref new, .. } => write!(formatter, "'{}' -> '{}'", old, new),
+
+            Change::Replaced { ref old, ref new, .. } => {
+                write_inline(old, formatter)?;
+                formatter.write_str(" -> ")?;
+                write_inline(new, formatter)
+            },
+
+            Change::ChangedPrototype { ref old, ref new, .. } => {
+                let describe = |prototypes: &[String]| if prototypes.is_empty() { String::from("no prototype") } else { prototypes.join(" + ") };
+                write!(formatter, "{} -> {}", describe(old), describe(new))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Cause::OverrideAdded => formatter.write_str("override added"),
+            Cause::OverrideChanged => formatter.write_str("override changed"),
+            Cause::OverrideRemoved => formatter.write_str("override removed"),
+            Cause::Unset => formatter.write_str("unset"),
+            Cause::PrototypeChanged { ref prototype } => write!(
+                formatter, "inherited value changed because `{}` changed", prototype
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SourceChange {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}  ({})", self.change, self.cause)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OLD: &str = "
+        Comic: { title: 'Untitled' author: { name: 'unknown' } }
+        Special: Comic { edition: 'special' }
+        comics: {
+            spiderman: Special { title: 'Spiderman' }
+            hulk: Comic
+            draft: Comic { pages: '3' }
+        }
+        ok_text: 'Ok'
+        dialog: { confirm: ok_text }
+    ";
+
+    const NEW: &str = "
+        Comic: { title: 'Untitled' author: { name: 'anonymous' } }
+        Special: Comic { edition: 'special' }
+        comics: {
+            spiderman: Comic { title: 'Spiderman' edition: 'first' }
+            hulk: Comic { title: 'Hulk' }
+            draft: Comic { title: !unset }
+            batman: Special
+        }
+        ok_text: 'OK'
+        dialog: { confirm: ok_text }
+    ";
+
+    #[test]
+    fn test_diff(){
+        let (old, new) = (FlatObject::parse(OLD).unwrap().unwrap(), FlatObject::parse(NEW).unwrap().unwrap());
+        let lines: Vec<String> = diff(&old, &new).iter().map(Change::to_string).collect();
+
+        assert_eq!(lines, vec![
+            "~ Comic.author.name: 'unknown' -> 'anonymous'",
+            "~ Special.author.name: 'unknown' -> 'anonymous'",
+            "~ comics.spiderman.author.name: 'unknown' -> 'anonymous'",
+            "~ comics.spiderman.edition: 'special' -> 'first'",
+            "~ comics.hulk.title: 'Untitled' -> 'Hulk'",
+            "~ comics.hulk.author.name: 'unknown' -> 'anonymous'",
+            "~ comics.draft.author.name: 'unknown' -> 'anonymous'",
+            "- comics.draft.title: 'Untitled'",
+            "- comics.draft.pages: '3'",
+            "+ comics.batman: { title: 'Untitled' author: { name: 'anonymous' } edition: 'special' }",
+            "~ ok_text: 'Ok' -> 'OK'",
+            "~ dialog.confirm: 'Ok' -> 'OK'",
+        ]);
+
+        assert_eq!(diff(&old, &old), Vec::new());
+        assert_eq!(
+            diff(&FlatObject::parse("a: 'x'").unwrap().unwrap(), &FlatObject::parse("a: { }").unwrap().unwrap()),
+            vec![ Change::Replaced { path: vec![ String::from("a") ], old: FlatObject::StringLiteral(String::from("x")), new: FlatObject::Compound(Default::default()) } ]
+        );
+    }
+
+    #[test]
+    fn test_diff_versions(){
+        let (old_document, new_document) = (::parse::parse(OLD).unwrap(), ::parse::parse(NEW).unwrap());
+        let (old_resolved, old_trace) = FlatObject::build_traced(&old_document).unwrap();
+        let (new_resolved, new_trace) = FlatObject::build_traced(&new_document).unwrap();
+
+        let old = Version { document: &old_document, resolved: &old_resolved, trace: &old_trace };
+        let new = Version { document: &new_document, resolved: &new_resolved, trace: &new_trace };
+        let lines: Vec<String> = diff_versions(&old, &new).iter().map(SourceChange::to_string).collect();
+
+        assert_eq!(lines, vec![
+            "~ Comic.author.name: 'unknown' -> 'anonymous'  (override changed)",
+            "~ Special.author.name: 'unknown' -> 'anonymous'  (inherited value changed because `Comic` changed)",
+            "~ comics.spiderman: Special -> Comic  (override changed)",
+            "~ comics.spiderman.author.name: 'unknown' -> 'anonymous'  (inherited value changed because `Comic` changed)",
+            "~ comics.spiderman.edition: 'special' -> 'first'  (override added)",
+            "~ comics.hulk.title: 'Untitled' -> 'Hulk'  (override added)",
+            "~ comics.hulk.author.name: 'unknown' -> 'anonymous'  (inherited value changed because `Comic` changed)",
+            "~ comics.draft.author.name: 'unknown' -> 'anonymous'  (inherited value changed because `Comic` changed)",
+            "- comics.draft.title: 'Untitled'  (unset)",
+            "- comics.draft.pages: '3'  (override removed)",
+            "+ comics.batman: { title: 'Untitled' author: { name: 'anonymous' } edition: 'special' }  (override added)",
+            "~ ok_text: 'Ok' -> 'OK'  (override changed)",
+            "~ dialog.confirm: 'Ok' -> 'OK'  (inherited value changed because `ok_text` changed)",
+        ]);
+    }
+}
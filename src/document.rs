@@ -0,0 +1,852 @@
+//! Edits the source of a document in place, like an editor would, instead of printing a resolved document.
+//! Every operation replaces as little text as possible and keeps the whitespace of the rest of the document,
+//! so that a file which is edited by a program stays formatted the way its author wrote it.
+//!
+//! Paths name declarations, not resolved properties, so `comics.spiderman.title` must be declared in the document,
+//! either directly, or in a conditional block of `comics.spiderman`. Unnamed list entries are found by their index.
+//!
+//! The document keeps its syntax tree, the spans of its parse result, and the top-level objects it resolved
+//! up to date while it is edited, see `Document::apply_edit`,
+//! so that an editor does not parse and resolve the whole document for every keystroke.
+
+use ::std::fmt;
+use ::std::collections::{ HashMap, HashSet };
+use ::parse::{ NamedObjects, Span, ResolveResult, ResolveError, Reference, Identifier };
+use ::syntax::{ SyntaxTree, Node, Kind };
+use ::flat::FlatObject;
+use ::owned;
+
+
+/// a document that owns its source
+#[derive(Debug, Clone)]
+pub struct Document {
+    source: String,
+
+    /// none while the source does not parse
+    syntax: Option<Node>,
+
+    /// the parse result, which is updated together with the syntax tree
+    parsed: owned::ParseResult<owned::NamedObjects>,
+
+    /// the resolved top-level objects, by name
+    resolved: HashMap<String, Resolved>,
+}
+
+#[derive(Debug, Clone)]
+struct Resolved {
+    object: FlatObject,
+
+    /// the top-level objects which the object refers to, also indirectly, and its own name
+    dependencies: HashSet<String>,
+}
+
+/// what an edit parsed again
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Reparsed {
+    /// the declaration or conditional block that was parsed again, in the new source.
+    /// the whole source if the edit was not inside a compound, or if the source does not parse
+    pub span: Span,
+
+    /// the sorted names of the resolved top-level objects which were discarded, because they depend on the edit
+    pub invalidated: Vec<String>,
+}
+
+/// replaces the span of the previous source with the text
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct TextEdit {
+    pub span: Span,
+    pub text: String,
+}
+
+pub type EditResult<T> = ::std::result::Result<T, EditError>;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum EditError {
+    /// the document does not parse, so declarations cannot be found
+    InvalidDocument { message: String },
+
+    /// the text is not exactly one object, or one declaration for `insert_after`
+    InvalidText { text: String },
+
+    /// no declaration has the path
+    NotFound { path: String },
+
+    /// the parent of a new property is not a compound, like `title.text`
+    ExpectedCompound { path: String },
+
+    /// `insert_after` would declare a name twice
+    AlreadyDeclared { path: String },
+
+    /// the span of an edit is not inside the source, or does not start and end at characters
+    InvalidSpan { span: Span },
+}
+
+
+impl Document {
+    pub fn new<S: Into<String>>(source: S) -> Self {
+        let source = source.into();
+        let (syntax, parsed) = parse_trees(&source);
+        Document { source, syntax, parsed, resolved: HashMap::new() }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn into_source(self) -> String {
+        self.source
+    }
+
+    /// the root of the syntax tree, none if the source does not parse, see `::syntax`
+    pub fn syntax(&self) -> Option<&Node> {
+        self.syntax.as_ref()
+    }
+
+    /// the parse result, borrowed from the source, or why the source does not parse
+    pub fn parsed(&self) -> owned::ParseResult<NamedObjects<'_>> {
+        self.parsed.as_ref().map(|parsed| parsed.in_source(&self.source)).map_err(|error| *error)
+    }
+
+    pub fn text(&self, node: &Node) -> &str {
+        &self.source[node.span.start .. node.span.end]
+    }
+
+    /// resolves the top-level object of the path, or reuses it if no edit affected it since.
+    /// none if the source does not parse
+    pub fn resolve(&mut self, path: &str) -> Option<ResolveResult<&FlatObject>> {
+        let names = split(path);
+
+        if !self.resolved.contains_key(names[0]) {
+            let document = self.parsed.as_ref().ok()?.in_source(&self.source);
+            let reference = Reference { identifiers: vec![ Identifier::new(names[0]) ] };
+
+            let object = match FlatObject::resolve(&document, &reference) {
+                Ok(object) => object,
+                Err(error) => return Some(Err(error)),
+            };
+
+            let dependencies = top_level_dependencies(&document, names[0]);
+            self.resolved.insert(names[0].to_string(), Resolved { object, dependencies });
+        }
+
+        let object = &self.resolved[names[0]].object;
+        Some(object.get_path(&names[1 ..]).ok_or_else(|| ResolveError::ReferenceNotFound { identifier: path.to_string() }))
+    }
+
+    /// replaces the span of the source with the text, and parses only the innermost declaration
+    /// or conditional block with braces that contains the edit again, instead of the whole source.
+    /// everything else in the syntax tree and the parse result is kept, and only moves if it comes after the edit
+    pub fn apply_edit(&mut self, span: Span, text: &str) -> EditResult<Reparsed> {
+        let inside = span.start <= span.end && span.end <= self.source.len();
+        if !inside || !self.source.is_char_boundary(span.start) || !self.source.is_char_boundary(span.end) {
+            return Err(EditError::InvalidSpan { span });
+        }
+
+        self.source.replace_range(span.start .. span.end, text);
+        let delta = text.len() as isize - (span.end - span.start) as isize;
+
+        let reparsed = match (self.syntax.as_mut(), self.parsed.as_mut()) {
+            (Some(root), Ok(parsed)) => reparse(&self.source, root, parsed, span, delta),
+            _ => None,
+        };
+
+        match reparsed {
+            Some((span, Some(top_level))) => {
+                let mut invalidated: Vec<String> = self.resolved.iter()
+                    .filter(|(_, resolved)| resolved.dependencies.contains(&top_level))
+                    .map(|(name, _)| name.clone())
+                    .collect();
+
+                for name in &invalidated { self.resolved.remove(name); }
+                invalidated.sort();
+                Ok(Reparsed { span, invalidated })
+            },
+
+            reparsed => {
+                if reparsed.is_none() {
+                    let (syntax, parsed) = parse_trees(&self.source);
+                    self.syntax = syntax;
+                    self.parsed = parsed;
+                }
+
+                let mut invalidated: Vec<String> = self.resolved.drain().map(|(name, _)| name).collect();
+                invalidated.sort();
+
+                let span = reparsed.map_or(Span { start: 0, end: self.source.len() }, |(span, _)| span);
+                Ok(Reparsed { span, invalidated })
+            },
+        }
+    }
+
+    /// replaces the object of the declaration, like `set("comics.superman.title", "'Man of Steel'")`,
+    /// or declares it after the last property of the parent compound
+    pub fn set(&mut self, path: &str, object: &str) -> EditResult<TextEdit> {
+        let names = split(path);
+        let (name, parent_names) = names.split_last().expect("split always returns a name");
+
+        if parse_single(&format!("{}: {}", name, object)).is_none() {
+            return Err(EditError::InvalidText { text: object.to_string() });
+        }
+
+        let edit = {
+            let (source, root) = (&self.source, self.root()?);
+
+            if let Some(found) = find(source, root, &names) {
+                // `+=` only makes sense for compounds, so a new object is declared with a colon
+                let text = if is_extension(found) { format!(": {}", object) } else { object.to_string() };
+                TextEdit { span: object_span(found), text }
+            }
+            else {
+                let declaration = format!("{}: {}", name, object);
+
+                if parent_names.is_empty() {
+                    declare_in(source, root, declaration)
+                }
+                else {
+                    let parent = find(source, root, parent_names)
+                        .ok_or_else(|| EditError::NotFound { path: parent_names.join(".") })?;
+
+                    let compound = compound_of(parent)
+                        .ok_or_else(|| EditError::ExpectedCompound { path: parent_names.join(".") })?;
+
+                    declare_in(source, compound, declaration)
+                }
+            }
+        };
+
+        self.apply(edit)
+    }
+
+    /// removes the declaration, and the line it was on if nothing else is declared there
+    pub fn remove(&mut self, path: &str) -> EditResult<TextEdit> {
+        let source = &self.source;
+        let found = find(source, self.root()?, &split(path)).ok_or_else(|| EditError::NotFound { path: path.to_string() })?;
+        let Span { mut start, mut end } = found.span;
+
+        let before = &source[.. start];
+        let after = &source[end ..];
+        let indentation = before.len() - before.trim_end_matches([' ', '\t']).len();
+        let trailing = after.len() - after.trim_start_matches([' ', '\t']).len();
+
+        let line_start = before[.. before.len() - indentation].is_empty() || before[.. before.len() - indentation].ends_with('\n');
+        let line_end = after[trailing ..].is_empty() || after[trailing ..].starts_with(['\n', '\r']);
+
+        if line_start && line_end {
+            start -= indentation;
+            end += trailing;
+
+            let rest = &after[trailing ..];
+            end += if rest.starts_with("\r\n") { 2 } else if rest.starts_with('\n') { 1 } else { 0 };
+        }
+        else if line_end || after[trailing ..].starts_with('}') {
+            start -= indentation;
+        }
+        else {
+            end += trailing;
+        }
+
+        self.apply(TextEdit { span: Span { start, end }, text: String::new() })
+    }
+
+    /// declares the text directly after the declaration at the path,
+    /// on a new line with the same indentation if the declaration is on its own line
+    pub fn insert_after(&mut self, path: &str, declaration: &str) -> EditResult<TextEdit> {
+        let new_name = parse_single(declaration)
+            .ok_or_else(|| EditError::InvalidText { text: declaration.to_string() })?;
+
+        let (source, root) = (&self.source, self.root()?);
+        let names = split(path);
+        let found = find(source, root, &names).ok_or_else(|| EditError::NotFound { path: path.to_string() })?;
+
+        if let Some(new_name) = new_name {
+            let mut new_path = names[.. names.len() - 1].to_vec();
+            new_path.push(&new_name);
+
+            if find(source, root, &new_path).is_some() {
+                return Err(EditError::AlreadyDeclared { path: new_path.join(".") });
+            }
+        }
+
+        let end = found.span.end;
+        let text = separator(source, found.span.start) + declaration;
+
+        self.apply(TextEdit { span: Span { start: end, end }, text })
+    }
+
+    /// the syntax tree, or an error that describes why the source does not parse
+    fn root(&self) -> EditResult<&Node> {
+        self.syntax.as_ref().ok_or_else(|| invalid_document(&self.source))
+    }
+
+    fn apply(&mut self, edit: TextEdit) -> EditResult<TextEdit> {
+        self.apply_edit(edit.span, &edit.text)?;
+        Ok(edit)
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(&self.source)
+    }
+}
+
+/// parses the innermost declaration or conditional block around the edit again, and puts it into both trees.
+/// returns the span of the new node, and the name of the top-level declaration that contains it,
+/// none if the whole source must be parsed again, in which case the trees may have been changed
+fn reparse(source: &str, root: &mut Node, parsed: &mut owned::NamedObjects, edit: Span, delta: isize) -> Option<(Span, Option<String>)> {
+    // the child indices of the candidates, from the outermost
+    let mut candidates: Vec<Vec<usize>> = Vec::new();
+    let mut indices = Vec::new();
+    let mut node = &*root;
+
+    while let Some(index) = node.children.iter().position(|child| child.span.start < edit.start && edit.end < child.span.end) {
+        node = &node.children[index];
+        indices.push(index);
+
+        let braced = last_token(node).kind == Kind::CloseBrace;
+        if braced && (node.kind == Kind::Declaration || node.kind == Kind::Conditional) {
+            candidates.push(indices.clone());
+        }
+    }
+
+    let name = |node: &Node, source: &str| node.children.iter().find(|child| child.kind == Kind::Name)
+        .map(|name| source[name.span.start .. name.span.end].to_string());
+
+    // names before the edit are still in the new source
+    let edited = |node: &Node| node.children.iter().any(|child| child.kind == Kind::Name && child.span.end > edit.start);
+
+    let top_level_name = candidates.first()
+        .map(|indices| &root.children[indices[0]])
+        .filter(|&declaration| !edited(declaration))
+        .and_then(|declaration| name(declaration, source));
+
+    parsed.shift(edit.end, delta);
+
+    for indices in candidates.iter().rev() {
+        let old = node_at(root, indices);
+        let span = Span { start: old.span.start, end: moved(old.span.end, delta) };
+        let snippet = &source[span.start .. span.end];
+
+        let objects = match ::parse::parse(snippet) {
+            Ok(objects) => objects,
+            Err(_) => continue,
+        };
+
+        let mut members = SyntaxTree::parse(snippet).into_root().children;
+        if members.len() != 1 || members[0].kind != old.kind || !snippet.ends_with('}') { continue; }
+
+        // a renamed declaration could collide with another one, which the parent must decide
+        if edited(old) || name(old, source) != name(&members[0], snippet) { continue; }
+
+        let mut new = members.remove(0);
+        shift(&mut new, 0, span.start as isize);
+
+        let mut member = owned::NamedObjects::of(snippet, &objects);
+        member.shift(0, span.start as isize);
+        if !replace_member(parsed, span, member) { return None; }
+
+        shift(root, edit.end, delta);
+        *node_at_mut(root, indices) = new;
+
+        return Some((span, top_level_name));
+    }
+
+    None
+}
+
+/// replaces the declaration or conditional block whose name or condition is inside the span,
+/// if the member is a single declaration or conditional block of the same shape. false if there is none
+fn replace_member(objects: &mut owned::NamedObjects, span: Span, mut member: owned::NamedObjects) -> bool {
+    let inside = |key: Span| span.start <= key.start && key.end <= span.end;
+
+    let named = objects.identifiers.keys().find(|identifier| inside(identifier.name)).cloned();
+    let unnamed = objects.unnamed.iter().position(|(identifier, _)| inside(identifier.name));
+    let conditional = objects.conditionals.iter().position(|conditional| inside(conditional.condition.text));
+    let single = member.objects.len() == 1 && member.conditionals.is_empty();
+
+    if let Some(identifier) = named {
+        if !single || member.identifiers.len() != 1 { return false; }
+        let index = objects.identifiers.remove(&identifier).expect("the identifier was found");
+
+        objects.objects[index] = member.objects.remove(0);
+        objects.identifiers.extend(member.identifiers.into_keys().map(|identifier| (identifier, index)));
+        return true;
+    }
+
+    if let Some(position) = unnamed {
+        if !single || member.unnamed.len() != 1 { return false; }
+        let index = objects.unnamed[position].1;
+
+        objects.objects[index] = member.objects.remove(0);
+        objects.unnamed[position].0 = member.unnamed[0].0;
+        return true;
+    }
+
+    if let Some(position) = conditional {
+        if !member.objects.is_empty() || member.conditionals.len() != 1 { return false; }
+        objects.conditionals[position] = member.conditionals.remove(0);
+        return true;
+    }
+
+    let compounds = objects.objects.iter_mut().filter_map(|object| match *object {
+        owned::Object::Compound(ref mut compound) | owned::Object::Replacement(ref mut compound)
+            | owned::Object::Extension(_, ref mut compound) => Some(&mut compound.overrides),
+        _ => None,
+    });
+
+    let conditionals = objects.conditionals.iter_mut().map(|conditional| &mut conditional.overrides);
+
+    // only the compound that contains the span can contain the member
+    compounds.chain(conditionals)
+        .find(|overrides| contains_key(overrides, &inside))
+        .is_some_and(|overrides| replace_member(overrides, span, member))
+}
+
+/// whether a name or condition of the objects, or of their descendants, is inside the span
+fn contains_key<F: Fn(Span) -> bool>(objects: &owned::NamedObjects, inside: &F) -> bool {
+    objects.identifiers.keys().any(|identifier| inside(identifier.name))
+        || objects.unnamed.iter().any(|(identifier, _)| inside(identifier.name))
+        || objects.conditionals.iter().any(|conditional| inside(conditional.condition.text) || contains_key(&conditional.overrides, inside))
+        || objects.objects.iter().any(|object| match *object {
+            owned::Object::Compound(ref compound) | owned::Object::Replacement(ref compound)
+                | owned::Object::Extension(_, ref compound) => contains_key(&compound.overrides, inside),
+            _ => false,
+        })
+}
+
+fn last_token(node: &Node) -> &Node {
+    node.children.last().map_or(node, last_token)
+}
+
+fn node_at<'n>(root: &'n Node, indices: &[usize]) -> &'n Node {
+    indices.iter().fold(root, |node, &index| &node.children[index])
+}
+
+fn node_at_mut<'n>(root: &'n mut Node, indices: &[usize]) -> &'n mut Node {
+    indices.iter().fold(root, |node, &index| &mut node.children[index])
+}
+
+/// moves every offset at or behind the offset
+fn shift(node: &mut Node, offset: usize, delta: isize) {
+    if node.span.start >= offset { node.span.start = moved(node.span.start, delta); }
+    if node.span.end >= offset { node.span.end = moved(node.span.end, delta); }
+
+    for child in &mut node.children {
+        shift(child, offset, delta);
+    }
+}
+
+fn moved(offset: usize, delta: isize) -> usize {
+    (offset as isize + delta) as usize
+}
+
+/// the top-level names the object refers to, also through the objects it refers to
+fn top_level_dependencies(document: &NamedObjects, name: &str) -> HashSet<String> {
+    let mut dependencies = HashSet::new();
+    let mut pending = vec![ name.to_string() ];
+
+    while let Some(name) = pending.pop() {
+        if dependencies.contains(&name) { continue; }
+
+        if let Some(object) = document.get(&name) {
+            pending.extend(object.references().into_iter()
+                .filter_map(|reference| reference.identifiers.first())
+                .map(|identifier| identifier.name.to_string()));
+        }
+
+        dependencies.insert(name);
+    }
+
+    dependencies
+}
+
+fn split(path: &str) -> Vec<&str> {
+    path.split('.').collect()
+}
+
+/// the syntax tree and the parse result, none if the source does not parse
+fn parse_trees(source: &str) -> (Option<Node>, owned::ParseResult<owned::NamedObjects>) {
+    match ::parse::parse(source) {
+        Ok(objects) => (Some(SyntaxTree::parse(source).into_root()), Ok(owned::NamedObjects::of(source, &objects))),
+        Err(error) => (None, Err(owned::ParseError::of(source, &error))),
+    }
+}
+
+/// the name of the only declaration of the text, none for a conditional block.
+/// the outer none means the text is not a single declaration
+fn parse_single(text: &str) -> Option<Option<String>> {
+    let parsed = ::parse::parse(text).ok()?;
+
+    match (parsed.objects.len(), parsed.conditionals.as_slice()) {
+        (1, []) => Some(parsed.in_declaration_order().first().map(|(name, _, _)| name.to_string())),
+        (0, [conditional]) if conditional.overrides.objects.len() == 1 && conditional.overrides.identifiers.len() == 1 => {
+            // `name if condition: object` is parsed as a conditional with one named object
+            Some(conditional.overrides.identifiers.keys().next().map(|identifier| identifier.name.to_string()))
+        },
+        (0, [_]) => Some(None),
+        _ => None,
+    }
+}
+
+/// searches the declarations of the document, a compound, or a conditional block, and then its conditional blocks
+fn find<'n>(source: &str, members: &'n Node, path: &[&str]) -> Option<&'n Node> {
+    let (name, children) = path.split_first()?;
+
+    let declarations: Vec<&Node> = members.children.iter()
+        .filter(|child| child.kind == Kind::Declaration && !is_conditional(child))
+        .collect();
+
+    // unnamed list entries are named by their position, unless that name is declared explicitly
+    let declared = declarations.iter().find(|declaration| name_of(source, declaration) == Some(name))
+        .or_else(|| declarations.iter().filter(|declaration| name_of(source, declaration).is_none()).nth(::list::index(name)?));
+
+    let found = match declared {
+        Some(found) => found,
+
+        None => return members.children.iter().find_map(|child| match child.kind {
+            Kind::Conditional => find(source, child, path),
+
+            // `name if condition: object` is a conditional block with a single declaration
+            Kind::Declaration if is_conditional(child) && name_of(source, child) == Some(name) => {
+                if children.is_empty() { Some(child) } else { find(source, compound_of(child)?, children) }
+            },
+
+            _ => None,
+        }),
+    };
+
+    if children.is_empty() { Some(found) }
+    else { find(source, compound_of(found)?, children) }
+}
+
+fn name_of<'s>(source: &'s str, declaration: &Node) -> Option<&'s str> {
+    let name = declaration.children.iter().find(|child| child.kind == Kind::Name)?;
+    Some(&source[name.span.start .. name.span.end])
+}
+
+fn is_conditional(declaration: &Node) -> bool {
+    declaration.children.iter().any(|child| child.kind == Kind::Keyword)
+}
+
+/// the object is the last child of a declaration
+fn compound_of(declaration: &Node) -> Option<&Node> {
+    declaration.children.last().filter(|object| object.kind == Kind::Compound)
+}
+
+/// `name += compound`, where the compound starts with `+=`
+fn is_extension(declaration: &Node) -> bool {
+    compound_of(declaration).and_then(|compound| compound.children.first()).is_some_and(|first| first.kind == Kind::Append)
+}
+
+/// the object after the colon, or everything after the name if it is appended with `+=`
+fn object_span(declaration: &Node) -> Span {
+    let end = declaration.span.end;
+    let name = declaration.children.iter().find(|child| child.kind == Kind::Name);
+
+    match (is_extension(declaration), name) {
+        (true, Some(name)) => Span { start: name.span.end, end },
+        _ => declaration.children.last().map_or(Span { start: end, end }, |object| object.span),
+    }
+}
+
+/// declares after the last declaration of the document or compound, or inside its braces if it has none
+fn declare_in(source: &str, members: &Node, declaration: String) -> TextEdit {
+    let last = members.children.iter().rev()
+        .find(|child| child.kind == Kind::Declaration && !is_conditional(child));
+
+    if let Some(last) = last {
+        let end = last.span.end;
+        return TextEdit { span: Span { start: end, end }, text: separator(source, last.span.start) + &declaration };
+    }
+
+    if members.kind == Kind::Document {
+        let end = source.len();
+        let text = if source.trim().is_empty() || source.ends_with('\n') { declaration + "\n" } else { format!("\n{}", declaration) };
+        return TextEdit { span: Span { start: end, end }, text };
+    }
+
+    // a compound without braces, like a variable, gets overrides
+    let brace = match members.children.iter().find(|child| child.kind == Kind::OpenBrace) {
+        Some(brace) => brace.span.end,
+
+        None => {
+            let end = members.span.end;
+            return TextEdit { span: Span { start: end, end }, text: format!(" {{ {} }}", declaration) };
+        },
+    };
+
+    // the braces of an empty compound only contain whitespace, or conditionals after the new declaration
+    let after_brace = &source[brace ..];
+    let text = if after_brace.starts_with(char::is_whitespace) { format!(" {}", declaration) } else { format!(" {} ", declaration) };
+
+    TextEdit { span: Span { start: brace, end: brace }, text }
+}
+
+/// a line break with the indentation of the declaration if it starts its line, otherwise a space
+fn separator(source: &str, declaration_start: usize) -> String {
+    let line_start = source[.. declaration_start].rfind('\n').map_or(0, |index| index + 1);
+    let indentation = &source[line_start .. declaration_start];
+
+    if indentation.trim().is_empty() { format!("\n{}", indentation) }
+    else { String::from(" ") }
+}
+
+/// parses the source again, which is only needed to describe the error
+fn invalid_document(source: &str) -> EditError {
+    let message = match ::parse::parse(source) {
+        Err(error) => {
+            let (line, column) = ::parse::line_and_column(source, error.span(source).start);
+            format!("{} at {}:{}", error, line, column)
+        },
+
+        Ok(_) => String::from("the syntax tree is missing"),
+    };
+
+    EditError::InvalidDocument { message }
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EditError::InvalidDocument { ref message } => write!(
+                formatter, "the document cannot be edited, because it does not parse: {}", message
+            ),
+
+            EditError::InvalidText { ref text } => write!(
+                formatter, "`{}` is not a single declaration or object", text
+            ),
+
+            EditError::NotFound { ref path } => write!(
+                formatter, "there is no declaration `{}`", path
+            ),
+
+            EditError::ExpectedCompound { ref path } => write!(
+                formatter, "`{}` is not a compound and cannot have properties", path
+            ),
+
+            EditError::AlreadyDeclared { ref path } => write!(
+                formatter, "`{}` is already declared", path
+            ),
+
+            EditError::InvalidSpan { span } => write!(
+                formatter, "the edit {}..{} is not inside the document", span.start, span.end
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const COMICS: &str = "\
+Comic: {
+    title: 'Untitled'
+    author: { name: 'unknown' }
+}
+
+comics: {
+    superman: Comic {
+        title: 'Superman'
+    }
+
+    hulk: Comic
+    tags: { : 'a' : 'b' }
+}
+";
+
+    #[test]
+    fn test_set(){
+        let mut document = Document::new(COMICS);
+
+        assert_eq!(
+            document.set("comics.superman.title", "'Man of Steel'"),
+            Ok(TextEdit { span: Span { start: COMICS.find("'Superman'").unwrap(), end: COMICS.find("'Superman'").unwrap() + 10 }, text: String::from("'Man of Steel'") })
+        );
+
+        assert_eq!(document.source(), COMICS.replace("'Superman'", "'Man of Steel'"));
+
+        document.set("Comic.author.age", "'unknown'").unwrap();
+        document.set("comics.hulk.title", "'Hulk'").unwrap();
+        document.set("comics.superman.year", "'2013'").unwrap();
+        document.set("editor", "{ }").unwrap();
+        document.set("editor.name", "'Jo'").unwrap();
+        document.set("comics.tags.1", "'c'").unwrap();
+
+        assert_eq!(document.source(), "\
+Comic: {
+    title: 'Untitled'
+    author: { name: 'unknown' age: 'unknown' }
+}
+
+comics: {
+    superman: Comic {
+        title: 'Man of Steel'
+        year: '2013'
+    }
+
+    hulk: Comic { title: 'Hulk' }
+    tags: { : 'a' : 'c' }
+}
+editor: { name: 'Jo' }
+");
+
+        assert_eq!(document.syntax(), Some(&SyntaxTree::parse(document.source()).into_root()));
+    }
+
+    #[test]
+    fn test_remove(){
+        let mut document = Document::new(COMICS);
+
+        document.remove("comics.superman.title").unwrap();
+        document.remove("Comic.author.name").unwrap();
+        document.remove("comics.tags.0").unwrap();
+        document.remove("comics.hulk").unwrap();
+
+        assert_eq!(document.source(), "\
+Comic: {
+    title: 'Untitled'
+    author: { }
+}
+
+comics: {
+    superman: Comic {
+    }
+
+    tags: { : 'b' }
+}
+");
+
+        assert_eq!(document.remove("comics.hulk"), Err(EditError::NotFound { path: String::from("comics.hulk") }));
+    }
+
+    #[test]
+    fn test_insert_after(){
+        let mut document = Document::new("a: {\n    b: 'x'\n}\nc: { d: 'y' }\n");
+
+        document.insert_after("a.b", "e: 'z'").unwrap();
+        document.insert_after("c.d", "if $d == 'y' { f: 'w' }").unwrap();
+        document.insert_after("c", "g if 1 < 2: 'v'").unwrap();
+
+        assert_eq!(document.source(), "a: {\n    b: 'x'\n    e: 'z'\n}\nc: { d: 'y' if $d == 'y' { f: 'w' } }\ng if 1 < 2: 'v'\n");
+
+        assert_eq!(document.insert_after("a.b", "e: 'again'"), Err(EditError::AlreadyDeclared { path: String::from("a.e") }));
+        assert_eq!(document.insert_after("a.b", "e: 'x' f: 'y'"), Err(EditError::InvalidText { text: String::from("e: 'x' f: 'y'") }));
+    }
+
+    #[test]
+    fn test_conditionals_and_extensions(){
+        let mut document = Document::new("a: { if $x == 'y' { b: 'c' } d if 1 < 2: { e: 'f' } }\nlist += { : 'x' }\n");
+
+        document.set("a.b", "'z'").unwrap();
+        document.set("a.d.e", "'g'").unwrap();
+        document.set("a.h", "'i'").unwrap();
+        document.set("list", "'v'").unwrap();
+
+        assert_eq!(document.source(), "a: { h: 'i' if $x == 'y' { b: 'z' } d if 1 < 2: { e: 'g' } }\nlist: 'v'\n");
+        assert_eq!(document.remove("a.d"), Ok(TextEdit { span: Span { start: 35, end: 58 }, text: String::new() }));
+        assert_eq!(document.source(), "a: { h: 'i' if $x == 'y' { b: 'z' } }\nlist: 'v'\n");
+    }
+
+    #[test]
+    fn test_errors(){
+        let mut document = Document::new("a: 'x' b: { }");
+
+        assert_eq!(document.set("a.b", "'y'").map_err(|error| error.to_string()), Err(String::from("`a` is not a compound and cannot have properties")));
+        assert_eq!(document.set("b", "'y' 'z'").map_err(|error| error.to_string()), Err(String::from("`'y' 'z'` is not a single declaration or object")));
+        assert_eq!(document.set("c.d", "'y'").map_err(|error| error.to_string()), Err(String::from("there is no declaration `c`")));
+        assert_eq!(document.source(), "a: 'x' b: { }");
+
+        let mut broken = Document::new("a: {");
+        assert_eq!(broken.remove("a").map_err(|error| error.to_string()), Err(String::from("the document cannot be edited, because it does not parse: expected `}`, found end of input at 1:5")));
+    }
+
+    #[test]
+    fn test_apply_edit(){
+        let source = "\
+Comic: { title: 'Untitled' }
+comics: {
+    spiderman: Comic { title: 'Spiderman' }
+    hulk: Comic
+}
+other: { a: 'b' }
+";
+
+        let mut document = Document::new(source);
+        assert_eq!(document.resolve("comics.spiderman.title").map(|title| title.cloned()), Some(Ok(FlatObject::StringLiteral(String::from("Spiderman")))));
+        assert_eq!(document.resolve("other.a").map(|a| a.is_ok()), Some(true));
+        assert_eq!(document.resolve("comics.batman").map(|batman| batman.map(|_| ())), Some(Err(ResolveError::ReferenceNotFound { identifier: String::from("comics.batman") })));
+
+        let reparse_is_exact = |document: &Document| (document.syntax.clone(), document.parsed.clone()) == parse_trees(document.source());
+
+        // only the declaration of spiderman is parsed again, and only `comics` is resolved again
+        let title = source.find("'Spiderman'").unwrap();
+        let reparsed = document.apply_edit(Span { start: title + 1, end: title + 7 }, "Bat").unwrap();
+        assert_eq!(&document.source()[reparsed.span.start .. reparsed.span.end], "spiderman: Comic { title: 'Batman' }");
+        assert_eq!(reparsed.invalidated, vec![ "comics" ]);
+        assert!(reparse_is_exact(&document));
+        assert_eq!(document.resolve("comics.spiderman.title").map(|title| title.cloned()), Some(Ok(FlatObject::StringLiteral(String::from("Batman")))));
+
+        // the prototype is used by `comics`
+        let untitled = document.source().find("'Untitled'").unwrap();
+        let reparsed = document.apply_edit(Span { start: untitled + 1, end: untitled + 9 }, "Unknown").unwrap();
+        assert_eq!(reparsed.invalidated, vec![ "comics" ]);
+        assert!(reparse_is_exact(&document));
+
+        // an edit that breaks the document discards everything, until the next edit fixes it
+        document.resolve("other").unwrap().unwrap();
+        let reparsed = document.apply_edit(Span { start: untitled, end: untitled }, "'New' ").unwrap();
+        assert_eq!((document.syntax(), reparsed.invalidated), (None, vec![ String::from("other") ]));
+
+        let reparsed = document.apply_edit(Span { start: untitled, end: untitled + 6 }, "").unwrap();
+        assert_eq!(reparsed.span, Span { start: 0, end: document.source().len() });
+        assert!(reparse_is_exact(&document) && document.syntax().is_some());
+
+        document.resolve("comics").unwrap().unwrap();
+        let braces = document.source().find("{ a").unwrap();
+        let reparsed = document.apply_edit(Span { start: braces + 1, end: braces + 1 }, " c: { d: 'e' }").unwrap();
+        assert_eq!(reparsed.invalidated, Vec::<String>::new());
+        assert!(reparse_is_exact(&document));
+
+        // a new top-level declaration is not inside a compound
+        let reparsed = document.apply_edit(Span { start: 0, end: 0 }, "x: 'y' ").unwrap();
+        assert_eq!(reparsed.span, Span { start: 0, end: document.source().len() });
+        assert_eq!(reparsed.invalidated, vec![ "comics" ]);
+        assert!(reparse_is_exact(&document));
+
+        // a conditional declaration that loses its condition is a different kind of member
+        document.apply_edit(Span { start: 0, end: 0 }, "c if 1 < 2: { d: 'e' } ").unwrap();
+        document.apply_edit(Span { start: 1, end: 10 }, "").unwrap();
+        assert!(document.source().starts_with("c: { d: 'e' } x: 'y'"));
+        assert!(reparse_is_exact(&document));
+        assert_eq!(document.resolve("c.d").map(|d| d.cloned()), Some(Ok(FlatObject::StringLiteral(String::from("e")))));
+    }
+
+    #[test]
+    fn test_invalid_span(){
+        let mut document = Document::new("é: 'x'");
+
+        for &span in &[ Span { start: 1, end: 1 }, Span { start: 3, end: 2 }, Span { start: 0, end: 10 } ] {
+            assert_eq!(document.apply_edit(span, "y"), Err(EditError::InvalidSpan { span }));
+        }
+
+        assert_eq!(document.source(), "é: 'x'");
+        assert_eq!(document.apply_edit(Span { start: 0, end: 2 }, "e").map(|_| document.source().to_string()), Ok(String::from("e: 'x'")));
+    }
+
+    #[test]
+    fn test_apply_edit_matches_parse(){
+        let source = include_str!("../assets/example.ptl");
+
+        for offset in (0 .. source.len()).filter(|&offset| source.is_char_boundary(offset)) {
+            let end = (offset + 1 .. source.len() + 1).find(|&end| source.is_char_boundary(end)).unwrap();
+
+            for &(span, text) in &[ (Span { start: offset, end: offset }, " "), (Span { start: offset, end }, ""), (Span { start: offset, end: offset }, "}") ] {
+                let mut document = Document::new(source);
+                document.apply_edit(span, text).unwrap();
+                assert_eq!((document.syntax.clone(), document.parsed.clone()), parse_trees(document.source()), "{:?} {:?}", span, text);
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! The operators and builtin functions of expressions.
+//!
+//! All values are string literals or compounds. Arithmetic and comparisons treat
+//! string literals like `'12'` or `'-0.5'` as numbers, and results are formatted back into strings,
+//! without a fraction if the number is whole. Logical operators expect `'true'` or `'false'`.
+//! Operators which evaluate their operands lazily, like `&&` and `? :`, are handled by the resolver.
+//!
+//! Errors are plain messages, which the resolver locates at the failing expression.
+
+use ::parse::{ UnaryOperator, BinaryOperator };
+use ::flat::FlatObject;
+
+
+pub type EvaluationResult = ::std::result::Result<FlatObject, String>;
+
+
+/// the string literal as a number, none for compounds and non-numeric text
+pub fn number(value: &FlatObject) -> Option<f64> {
+    let text = match *value {
+        FlatObject::StringLiteral(ref text) => text.trim(),
+        FlatObject::Compound(_) => return None,
+    };
+
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let mut parts = digits.splitn(2, '.');
+    let is_numeric = parts.all(|part| !part.is_empty() && part.chars().all(|symbol| symbol.is_ascii_digit()));
+
+    if is_numeric { text.parse().ok() } else { None }
+}
+
+pub fn format_number(number: f64) -> String {
+    if number.fract() == 0.0 && number.abs() < 1e15 {
+        format!("{}", number as i64)
+    } else {
+        format!("{}", number)
+    }
+}
+
+pub fn boolean(value: bool) -> FlatObject {
+    FlatObject::StringLiteral(String::from(if value { "true" } else { "false" }))
+}
+
+/// `'true'` or `'false'`
+pub fn truth(value: &FlatObject) -> Result<bool, String> {
+    match *value {
+        FlatObject::StringLiteral(ref text) if text == "true" => Ok(true),
+        FlatObject::StringLiteral(ref text) if text == "false" => Ok(false),
+        _ => Err(format!("expected `'true'` or `'false'`, found {}", describe(value))),
+    }
+}
+
+pub fn unary(operator: UnaryOperator, operand: &FlatObject) -> EvaluationResult {
+    match operator {
+        UnaryOperator::Not => Ok(boolean(!truth(operand)?)),
+        UnaryOperator::Negate => Ok(FlatObject::StringLiteral(format_number(-expect_number(operand)?))),
+    }
+}
+
+/// all operators except `&&` and `||`
+pub fn binary(operator: BinaryOperator, left: &FlatObject, right: &FlatObject) -> EvaluationResult {
+    let arithmetic = |operation: fn(f64, f64) -> f64| -> EvaluationResult {
+        let result = operation(expect_number(left)?, expect_number(right)?);
+
+        if result.is_finite() {
+            Ok(FlatObject::StringLiteral(format_number(result)))
+        } else {
+            Err(String::from("the result is not a finite number, probably due to a division by zero"))
+        }
+    };
+
+    match operator {
+        BinaryOperator::Add => arithmetic(|left, right| left + right),
+        BinaryOperator::Subtract => arithmetic(|left, right| left - right),
+        BinaryOperator::Multiply => arithmetic(|left, right| left * right),
+        BinaryOperator::Divide => arithmetic(|left, right| left / right),
+        BinaryOperator::Remainder => arithmetic(|left, right| left % right),
+
+        BinaryOperator::Concatenate => Ok(FlatObject::StringLiteral(expect_text(left)?.to_string() + expect_text(right)?)),
+
+        BinaryOperator::Equal => Ok(boolean(equal(left, right))),
+        BinaryOperator::NotEqual => Ok(boolean(!equal(left, right))),
+
+        BinaryOperator::Less => compare(left, right).map(|ordering| boolean(ordering.is_lt())),
+        BinaryOperator::LessOrEqual => compare(left, right).map(|ordering| boolean(ordering.is_le())),
+        BinaryOperator::Greater => compare(left, right).map(|ordering| boolean(ordering.is_gt())),
+        BinaryOperator::GreaterOrEqual => compare(left, right).map(|ordering| boolean(ordering.is_ge())),
+
+        BinaryOperator::And | BinaryOperator::Or => unreachable!("logical operators are evaluated lazily by the resolver"),
+    }
+}
+
+/// whether `call` implements the function, instead of a native function of the host
+pub fn is_builtin(function: &str) -> bool {
+    matches!(function, "len" | "upper" | "join")
+}
+
+pub fn call(function: &str, arguments: &[FlatObject]) -> EvaluationResult {
+    match (function, arguments) {
+        ("len", [ FlatObject::StringLiteral(text) ]) => Ok(FlatObject::StringLiteral(text.chars().count().to_string())),
+        ("len", [ FlatObject::Compound(properties) ]) => Ok(FlatObject::StringLiteral(properties.len().to_string())),
+        ("upper", [ value ]) => Ok(FlatObject::StringLiteral(expect_text(value)?.to_uppercase())),
+
+        ("join", [ list ]) => join(list, ""),
+        ("join", [ list, separator ]) => join(list, expect_text(separator)?),
+
+        ("len", _) | ("upper", _) => Err(format!("`{}` expects 1 argument, found {}", function, arguments.len())),
+        ("join", _) => Err(format!("`join` expects 1 or 2 arguments, found {}", arguments.len())),
+        _ => Err(format!("there is no function named `{}`", function)),
+    }
+}
+
+/// the string literals of a compound, sorted by their names, where numeric names are sorted by value
+fn join(list: &FlatObject, separator: &str) -> EvaluationResult {
+    let properties = match *list {
+        FlatObject::Compound(ref properties) => properties,
+        FlatObject::StringLiteral(_) => return Err(format!("expected a compound, found {}", describe(list))),
+    };
+
+    let mut entries: Vec<(&String, &FlatObject)> = properties.iter().collect();
+    entries.sort_by(|&(a, _), &(b, _)| match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    });
+
+    let texts = entries.into_iter()
+        .map(|(_, value)| expect_text(value))
+        .collect::<Result<Vec<&str>, String>>()?;
+
+    Ok(FlatObject::StringLiteral(texts.join(separator)))
+}
+
+/// numbers are equal if their values are, like `'1.0'` and `'1'`
+fn equal(left: &FlatObject, right: &FlatObject) -> bool {
+    match (number(left), number(right)) {
+        (Some(left), Some(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+/// numbers by value, other string literals alphabetically
+fn compare(left: &FlatObject, right: &FlatObject) -> Result<::std::cmp::Ordering, String> {
+    if let (Some(left_number), Some(right_number)) = (number(left), number(right)) {
+        return left_number.partial_cmp(&right_number).ok_or_else(|| String::from("numbers cannot be compared"));
+    }
+
+    Ok(expect_text(left)?.cmp(expect_text(right)?))
+}
+
+fn expect_number(value: &FlatObject) -> Result<f64, String> {
+    number(value).ok_or_else(|| format!("expected a number, found {}", describe(value)))
+}
+
+fn expect_text(value: &FlatObject) -> Result<&str, String> {
+    match *value {
+        FlatObject::StringLiteral(ref text) => Ok(text),
+        FlatObject::Compound(_) => Err(String::from("expected a string literal, found a compound")),
+    }
+}
+
+fn describe(value: &FlatObject) -> String {
+    match *value {
+        FlatObject::StringLiteral(ref text) => format!("`'{}'`", text),
+        FlatObject::Compound(_) => String::from("a compound"),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn literal(text: &str) -> FlatObject {
+        FlatObject::StringLiteral(String::from(text))
+    }
+
+    #[test]
+    fn test_operators(){
+        assert_eq!(number(&literal(" -12.5 ")), Some(-12.5));
+        assert_eq!(number(&literal("1e5")), None);
+        assert_eq!(number(&literal("12.")), None);
+
+        assert_eq!(binary(BinaryOperator::Multiply, &literal("3"), &literal("0.5")), Ok(literal("1.5")));
+        assert_eq!(binary(BinaryOperator::Divide, &literal("6"), &literal("3")), Ok(literal("2")));
+        assert!(binary(BinaryOperator::Divide, &literal("1"), &literal("0")).is_err());
+        assert_eq!(binary(BinaryOperator::Concatenate, &literal("1"), &literal("2")), Ok(literal("12")));
+        assert_eq!(binary(BinaryOperator::Equal, &literal("1.0"), &literal("1")), Ok(literal("true")));
+        assert_eq!(binary(BinaryOperator::Less, &literal("9"), &literal("10")), Ok(literal("true")));
+        assert_eq!(binary(BinaryOperator::Less, &literal("b"), &literal("a")), Ok(literal("false")));
+        assert_eq!(unary(UnaryOperator::Not, &literal("false")), Ok(literal("true")));
+
+        assert_eq!(
+            binary(BinaryOperator::Add, &literal("1"), &literal("px")),
+            Err(String::from("expected a number, found `'px'`"))
+        );
+    }
+
+    #[test]
+    fn test_functions(){
+        let list = FlatObject::compound(vec![
+            (String::from("10"), literal("c")), (String::from("2"), literal("b")), (String::from("1"), literal("a")),
+        ].into_iter().collect());
+
+        assert_eq!(call("len", &[ literal("äbc") ]), Ok(literal("3")));
+        assert_eq!(call("len", ::std::slice::from_ref(&list)), Ok(literal("3")));
+        assert_eq!(call("upper", &[ literal("abc") ]), Ok(literal("ABC")));
+        assert_eq!(call("join", &[ list, literal(", ") ]), Ok(literal("a, b, c")));
+        assert_eq!(call("upper", &[]), Err(String::from("`upper` expects 1 argument, found 0")));
+        assert_eq!(call("lower", &[]), Err(String::from("there is no function named `lower`")));
+    }
+}
@@ -0,0 +1,115 @@
+//! Writes resolved documents in formats which other programs consume, see `json`, `yaml::write`, `toml::write`, and `xml::Xml`.
+//! Properties are written in declaration order.
+//!
+//! A compound whose properties are exactly the list entries `0` to `n - 1` is written as a list.
+//! String literals are written as strings, unless `Scalars::Coerced` is chosen:
+//! then canonical numbers, like `'80'` or `'-0.5'`, are written as numbers,
+//! and `'true'` and `'false'` as booleans, because expressions treat them like that.
+//! All other string literals stay strings, so `'007'` and `'1.0'` keep their text.
+
+pub mod yaml;
+pub mod toml;
+pub mod xml;
+
+use ::flat::{ FlatObject, FlatCompound };
+use ::json::Json;
+
+
+/// whether string literals which look like numbers and booleans are written as such
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum Scalars {
+    /// a zip code like `'01234'` and a version like `'2'` are both strings
+    #[default]
+    Strings,
+
+    /// `'80'` is a number and `'true'` a boolean
+    Coerced,
+}
+
+/// what a string literal is written as
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Scalar<'t> {
+    Text(&'t str),
+    Number(&'t str),
+    Boolean(bool),
+}
+
+
+pub fn scalar(text: &str, scalars: Scalars) -> Scalar<'_> {
+    match text {
+        _ if scalars == Scalars::Strings => Scalar::Text(text),
+        "true" => Scalar::Boolean(true),
+        "false" => Scalar::Boolean(false),
+        _ => {
+            let number = ::evaluate::number(&FlatObject::StringLiteral(text.to_string()));
+            if number.map(::evaluate::format_number).as_deref() == Some(text) { Scalar::Number(text) }
+            else { Scalar::Text(text) }
+        },
+    }
+}
+
+/// the entries in the order of their indices, none if the compound is empty or has other properties
+pub fn list(properties: &FlatCompound) -> Option<Vec<&FlatObject>> {
+    if properties.is_empty() { return None; }
+
+    (0 .. properties.len())
+        .map(|index| properties.get(index.to_string().as_str()))
+        .collect()
+}
+
+pub fn json(object: &FlatObject, scalars: Scalars) -> Json {
+    match *object {
+        FlatObject::StringLiteral(ref text) => match scalar(text, scalars) {
+            Scalar::Text(text) => Json::string(text),
+            Scalar::Number(number) => Json::Number(number.parse().expect("canonical numbers can be parsed")),
+            Scalar::Boolean(boolean) => Json::Bool(boolean),
+        },
+
+        FlatObject::Compound(ref properties) => match list(properties) {
+            Some(entries) => Json::Array(entries.into_iter().map(|entry| json(entry, scalars)).collect()),
+            None => Json::Object(properties.iter().map(|(name, value)| (name.clone(), json(value, scalars))).collect()),
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scalar(){
+        let coerced = |text| scalar(text, Scalars::Coerced);
+        assert_eq!(coerced("80"), Scalar::Number("80"));
+        assert_eq!(coerced("-0.5"), Scalar::Number("-0.5"));
+        assert_eq!(coerced("007"), Scalar::Text("007"));
+        assert_eq!(coerced("1.0"), Scalar::Text("1.0"));
+        assert_eq!(coerced(" 1"), Scalar::Text(" 1"));
+        assert_eq!(coerced("true"), Scalar::Boolean(true));
+        assert_eq!(coerced("True"), Scalar::Text("True"));
+
+        assert_eq!(scalar("80", Scalars::Strings), Scalar::Text("80"));
+        assert_eq!(scalar("true", Scalars::default()), Scalar::Text("true"));
+    }
+
+    #[test]
+    fn test_json(){
+        let source = "
+            Service: { name: 'unnamed' port: '80' }
+            api: Service { name: 'api' public: 'true' hosts: { : 'a' : 'b' } sparse: { 1: 'x' } }
+        ";
+
+        let resolved = FlatObject::parse(source).unwrap().unwrap();
+        let api = resolved.get_path(&["api"]).unwrap();
+
+        assert_eq!(
+            json(api, Scalars::Coerced).to_string(),
+            r#"{"name":"api","port":80,"public":true,"hosts":["a","b"],"sparse":{"1":"x"}}"#
+        );
+
+        assert_eq!(
+            json(api, Scalars::Strings).to_string(),
+            r#"{"name":"api","port":"80","public":"true","hosts":["a","b"],"sparse":{"1":"x"}}"#
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Writes resolved documents as TOML. Compounds become tables, and lists of non-empty compounds
+//! become arrays of tables, like `[[servers]]`. Other compounds inside lists are written as inline tables.
+
+use ::std::fmt;
+use ::flat::{ FlatObject, FlatCompound };
+use super::{ Scalar, Scalars };
+
+
+pub type TomlResult<T> = ::std::result::Result<T, TomlError>;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum TomlError {
+    /// toml documents are tables, so a string literal or a list cannot be the whole document
+    ExpectedTable { found: &'static str },
+}
+
+
+pub fn write(object: &FlatObject, scalars: Scalars) -> TomlResult<String> {
+    let properties = match *object {
+        FlatObject::StringLiteral(_) => return Err(TomlError::ExpectedTable { found: "a string literal" }),
+        FlatObject::Compound(ref properties) if super::list(properties).is_some() => return Err(TomlError::ExpectedTable { found: "a list" }),
+        FlatObject::Compound(ref properties) => properties,
+    };
+
+    let mut toml = String::new();
+    write_table(properties, &mut Vec::new(), scalars, &mut toml);
+    Ok(toml)
+}
+
+/// the key value pairs come first, because every table header ends the previous table
+fn write_table(properties: &FlatCompound, path: &mut Vec<String>, scalars: Scalars, toml: &mut String) {
+    for (name, value) in properties.iter() {
+        if section(value).is_none() {
+            write_key(name, toml);
+            toml.push_str(" = ");
+            write_inline(value, scalars, toml);
+            toml.push('\n');
+        }
+    }
+
+    for (name, value) in properties.iter() {
+        path.push(name.clone());
+
+        match section(value) {
+            Some(Section::Table(table)) => {
+                // a table that only contains tables does not need its own header
+                if table.values().any(|value| section(value).is_none()) {
+                    write_header(path, "[", "]", toml);
+                }
+
+                write_table(table, path, scalars, toml);
+            },
+
+            Some(Section::Array(tables)) => for table in tables {
+                write_header(path, "[[", "]]", toml);
+                write_table(table, path, scalars, toml);
+            },
+
+            None => {},
+        }
+
+        path.pop();
+    }
+}
+
+/// how a value is written if it is not inline
+enum Section<'f> {
+    Table(&'f FlatCompound),
+    Array(Vec<&'f FlatCompound>),
+}
+
+fn section(object: &FlatObject) -> Option<Section<'_>> {
+    let properties = match *object {
+        FlatObject::Compound(ref properties) if !properties.is_empty() => properties,
+        _ => return None,
+    };
+
+    match super::list(properties) {
+        None => Some(Section::Table(properties)),
+        Some(entries) => entries.into_iter()
+            .map(|entry| match *entry {
+                FlatObject::Compound(ref table) if !table.is_empty() && super::list(table).is_none() => Some(&**table),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(Section::Array),
+    }
+}
+
+fn write_header(path: &[String], open: &str, close: &str, toml: &mut String) {
+    if !toml.is_empty() { toml.push('\n'); }
+    toml.push_str(open);
+
+    for (index, name) in path.iter().enumerate() {
+        if index != 0 { toml.push('.'); }
+        write_key(name, toml);
+    }
+
+    toml.push_str(close);
+    toml.push('\n');
+}
+
+fn write_inline(object: &FlatObject, scalars: Scalars, toml: &mut String) {
+    let properties = match *object {
+        FlatObject::StringLiteral(ref text) => return write_scalar(text, scalars, toml),
+        FlatObject::Compound(ref properties) => properties,
+    };
+
+    match super::list(properties) {
+        Some(entries) => {
+            toml.push('[');
+            for (index, entry) in entries.into_iter().enumerate() {
+                if index != 0 { toml.push_str(", "); }
+                write_inline(entry, scalars, toml);
+            }
+            toml.push(']');
+        },
+
+        None if properties.is_empty() => toml.push_str("{}"),
+
+        None => {
+            toml.push_str("{ ");
+            for (index, (name, value)) in properties.iter().enumerate() {
+                if index != 0 { toml.push_str(", "); }
+                write_key(name, toml);
+                toml.push_str(" = ");
+                write_inline(value, scalars, toml);
+            }
+            toml.push_str(" }");
+        },
+    }
+}
+
+/// integers which do not fit into 64 bits stay strings
+fn write_scalar(text: &str, scalars: Scalars, toml: &mut String) {
+    match super::scalar(text, scalars) {
+        Scalar::Boolean(boolean) => toml.push_str(if boolean { "true" } else { "false" }),
+        Scalar::Number(number) if number.contains('.') || number.parse::<i64>().is_ok() => toml.push_str(number),
+        _ => write_string(text, toml),
+    }
+}
+
+fn write_key(name: &str, toml: &mut String) {
+    let is_bare = !name.is_empty() && name.chars()
+        .all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '-');
+
+    if is_bare { toml.push_str(name) }
+    else { write_string(name, toml) }
+}
+
+fn write_string(text: &str, toml: &mut String) {
+    toml.push('"');
+
+    for character in text.chars() {
+        match character {
+            '"' => toml.push_str("\\\""),
+            '\\' => toml.push_str("\\\\"),
+            '\n' => toml.push_str("\\n"),
+            '\t' => toml.push_str("\\t"),
+            '\r' => toml.push_str("\\r"),
+            character if character.is_control() => toml.push_str(&format!("\\u{:04X}", character as u32)),
+            character => toml.push(character),
+        }
+    }
+
+    toml.push('"');
+}
+
+impl fmt::Display for TomlError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TomlError::ExpectedTable { found } => write!(
+                formatter, "toml documents must be tables, but this is {}", found
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write(){
+        let source = r#"
+            Server: { host: 'localhost' port: '8080' }
+            title: 'Service "api"'
+            database: {
+                url: 'postgres://db'
+                pool: { size: '10' timeout: '2.5' }
+            }
+            servers: { : Server { port: '80' } : Server { tls: { enabled: 'true' } } }
+            tags: { : 'a' : { name: 'b' } : { } }
+            limits: { outer: { inner: { max: '99999999999999999999' } } }
+            empty: { }
+            a<b: '007'
+        "#;
+
+        let resolved = FlatObject::parse(source).unwrap().unwrap();
+        assert_eq!(write(&resolved, Scalars::Coerced), Ok(String::from(r#"title = "Service \"api\""
+tags = ["a", { name = "b" }, {}]
+empty = {}
+"a<b" = "007"
+
+[Server]
+host = "localhost"
+port = 8080
+
+[database]
+url = "postgres://db"
+
+[database.pool]
+size = 10
+timeout = 2.5
+
+[[servers]]
+host = "localhost"
+port = 80
+
+[[servers]]
+host = "localhost"
+port = 8080
+
+[servers.tls]
+enabled = true
+
+[limits.outer.inner]
+max = "99999999999999999999"
+"#)));
+
+        let pool = resolved.get_path(&["database"]).unwrap();
+        assert_eq!(write(pool, Scalars::Strings), Ok(String::from("url = \"postgres://db\"\n\n[pool]\nsize = \"10\"\ntimeout = \"2.5\"\n")));
+    }
+
+    #[test]
+    fn test_unrepresentable(){
+        let write_source = |source: &str| write(&FlatObject::parse(source).unwrap().unwrap(), Scalars::Strings).map_err(|error| error.to_string());
+        assert_eq!(write_source(": 'a' : 'b'"), Err(String::from("toml documents must be tables, but this is a list")));
+        assert_eq!(write(&FlatObject::StringLiteral(String::from("a")), Scalars::Strings), Err(TomlError::ExpectedTable { found: "a string literal" }));
+    }
+}
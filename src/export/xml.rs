@@ -0,0 +1,311 @@
+//! Writes resolved documents as XML. A compound is an element whose properties are its child elements,
+//! named like the property, and a string literal is an element that contains only text.
+//! A list is written as one element per entry, each named like the property of the list.
+//!
+//! A property is an attribute if its name starts with the attribute prefix, `@` by default,
+//! which is not part of the attribute name, or if the name was declared an attribute, see `Xml::attribute`.
+//! The string literal `#text` is the text of its element, which comes before the child elements.
+//!
+//! Text and attribute values are escaped. Names which are not valid XML names are errors,
+//! because the parser accepts almost any character in an identifier.
+//! Two properties that are the same attribute, like `@id` and a declared attribute `id`, are errors too.
+
+use ::std::fmt;
+use ::std::collections::HashSet;
+use ::flat::{ FlatObject, FlatCompound };
+
+
+/// the property that contains the text of an element with attributes or children
+pub const TEXT: &str = "#text";
+
+pub type XmlResult<T> = ::std::result::Result<T, XmlError>;
+
+/// the path points to the property that cannot be written, like `order.@id`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum XmlError {
+    /// an attribute or `#text` is a compound
+    ExpectedStringLiteral { path: String },
+
+    /// an element or attribute name which is not a valid xml name, like `0` or `a<b`
+    InvalidName { path: String, name: String },
+
+    /// a control character which xml 1.0 documents cannot contain, even escaped
+    InvalidCharacter { path: String, character: char },
+
+    /// two properties are the same attribute, like `@id` and `id` when `id` is declared an attribute
+    DuplicateAttribute { path: String, name: String },
+}
+
+/// how properties are mapped to attributes
+#[derive(Clone, Debug)]
+pub struct Xml<'x> {
+    prefix: Option<&'x str>,
+    attributes: Vec<&'x str>,
+}
+
+
+impl<'x> Default for Xml<'x> {
+    fn default() -> Self {
+        Xml { prefix: Some("@"), attributes: Vec::new() }
+    }
+}
+
+impl<'x> Xml<'x> {
+    /// none if no prefix marks attributes
+    pub fn prefix(&mut self, prefix: Option<&'x str>) -> &mut Self {
+        self.prefix = prefix;
+        self
+    }
+
+    /// properties with this name are always attributes, like a schema would declare
+    pub fn attribute(&mut self, name: &'x str) -> &mut Self {
+        self.attributes.push(name);
+        self
+    }
+
+    /// the object as an element with the name, which is the root of the document
+    pub fn write(&self, name: &str, object: &FlatObject) -> XmlResult<String> {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        self.write_element(name, object, &mut vec![ name.to_string() ], &mut xml)?;
+        xml.push('\n');
+        Ok(xml)
+    }
+
+    fn write_element(&self, name: &str, object: &FlatObject, path: &mut Vec<String>, xml: &mut String) -> XmlResult<()> {
+        if !is_name(name) {
+            return Err(XmlError::InvalidName { path: path.join("."), name: name.to_string() });
+        }
+
+        let properties = match *object {
+            FlatObject::StringLiteral(ref text) => {
+                xml.push('<');
+                xml.push_str(name);
+                xml.push('>');
+                escape(text, false, path, xml)?;
+                return write_end(name, xml);
+            },
+
+            FlatObject::Compound(ref properties) => properties,
+        };
+
+        xml.push('<');
+        xml.push_str(name);
+        self.write_attributes(properties, path, xml)?;
+
+        let text = match properties.get(TEXT) {
+            None => None,
+            Some(FlatObject::StringLiteral(text)) => Some(text),
+            Some(FlatObject::Compound(_)) => return Err(XmlError::ExpectedStringLiteral { path: join(path, TEXT) }),
+        };
+
+        let children: Vec<(&String, &FlatObject)> = properties.iter()
+            .filter(|&(name, _)| name != TEXT && self.attribute_name(name).is_none())
+            .collect();
+
+        if text.is_none() && children.is_empty() {
+            xml.push_str("/>");
+            return Ok(());
+        }
+
+        xml.push('>');
+
+        if let Some(text) = text {
+            path.push(String::from(TEXT));
+            escape(text, false, path, xml)?;
+            path.pop();
+        }
+
+        for (child, value) in children {
+            path.push(child.clone());
+
+            match list(value) {
+                Some(entries) => for (index, entry) in entries.into_iter().enumerate() {
+                    path.push(index.to_string());
+                    self.write_element(child, entry, path, xml)?;
+                    path.pop();
+                },
+
+                None => self.write_element(child, value, path, xml)?,
+            }
+
+            path.pop();
+        }
+
+        write_end(name, xml)
+    }
+
+    fn write_attributes(&self, properties: &FlatCompound, path: &[String], xml: &mut String) -> XmlResult<()> {
+        let mut written = HashSet::new();
+
+        for (property, value) in properties.iter() {
+            let name = match self.attribute_name(property) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            let mut attribute_path = path.to_vec();
+            attribute_path.push(property.clone());
+
+            if !is_name(name) {
+                return Err(XmlError::InvalidName { path: attribute_path.join("."), name: name.to_string() });
+            }
+
+            if !written.insert(name) {
+                return Err(XmlError::DuplicateAttribute { path: attribute_path.join("."), name: name.to_string() });
+            }
+
+            let value = match *value {
+                FlatObject::StringLiteral(ref value) => value,
+                FlatObject::Compound(_) => return Err(XmlError::ExpectedStringLiteral { path: attribute_path.join(".") }),
+            };
+
+            xml.push(' ');
+            xml.push_str(name);
+            xml.push_str("=\"");
+            escape(value, true, &attribute_path, xml)?;
+            xml.push('"');
+        }
+
+        Ok(())
+    }
+
+    /// the name of the attribute, none if the property is not an attribute
+    fn attribute_name<'p>(&self, property: &'p str) -> Option<&'p str> {
+        if self.attributes.contains(&property) { return Some(property); }
+        self.prefix.and_then(|prefix| property.strip_prefix(prefix))
+    }
+}
+
+fn list(object: &FlatObject) -> Option<Vec<&FlatObject>> {
+    match *object {
+        FlatObject::Compound(ref properties) => super::list(properties),
+        FlatObject::StringLiteral(_) => None,
+    }
+}
+
+fn write_end(name: &str, xml: &mut String) -> XmlResult<()> {
+    xml.push_str("</");
+    xml.push_str(name);
+    xml.push('>');
+    Ok(())
+}
+
+fn join(path: &[String], name: &str) -> String {
+    path.iter().map(String::as_str).chain(::std::iter::once(name)).collect::<Vec<&str>>().join(".")
+}
+
+/// quotes only need to be escaped inside attribute values, where line breaks and tabs are escaped too,
+/// because they would be normalized to spaces
+fn escape(text: &str, attribute: bool, path: &[String], xml: &mut String) -> XmlResult<()> {
+    for character in text.chars() {
+        match character {
+            '&' => xml.push_str("&amp;"),
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '"' if attribute => xml.push_str("&quot;"),
+            '\n' if attribute => xml.push_str("&#10;"),
+            '\t' if attribute => xml.push_str("&#9;"),
+            '\r' => xml.push_str("&#13;"),
+            '\n' | '\t' => xml.push(character),
+            character if character.is_control() && (character as u32) < 0x20 => {
+                return Err(XmlError::InvalidCharacter { path: path.join("."), character });
+            },
+            character => xml.push(character),
+        }
+    }
+
+    Ok(())
+}
+
+/// letters, digits, `_`, `-`, and `.`, not starting with a digit, `-`, or `.`.
+/// colons are not allowed, because they would declare namespaces
+fn is_name(name: &str) -> bool {
+    name.starts_with(|character: char| character.is_alphabetic() || character == '_')
+        && name.chars().all(|character| character.is_alphanumeric() || "_-.".contains(character))
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            XmlError::ExpectedStringLiteral { ref path } => write!(
+                formatter, "`{}` must be a string literal to be written as xml", path
+            ),
+
+            XmlError::InvalidName { ref path, ref name } => write!(
+                formatter, "`{}` is not a valid xml name, at `{}`", name, path
+            ),
+
+            XmlError::InvalidCharacter { ref path, character } => write!(
+                formatter, "xml cannot contain the character {:?}, at `{}`", character, path
+            ),
+
+            XmlError::DuplicateAttribute { ref path, ref name } => write!(
+                formatter, "the attribute `{}` is written twice, at `{}`", name, path
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write(){
+        let source = r#"
+            Item: { @currency: 'EUR' }
+            order: {
+                @id: '42'
+                status: 'paid & "shipped"'
+                items: {
+                    : Item { name: 'Pen' price: { @currency: 'USD' #text: '2' } }
+                    : Item { name: '<Ink>' }
+                }
+                note: { }
+                kind: 'gift'
+            }
+        "#;
+
+        let resolved = FlatObject::parse(source).unwrap().unwrap();
+        let order = resolved.get_path(&["order"]).unwrap();
+
+        assert_eq!(Xml::default().write("order", order), Ok(String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <order id=\"42\"><status>paid &amp; \"shipped\"</status>\
+            <items currency=\"EUR\"><name>Pen</name><price currency=\"USD\">2</price></items>\
+            <items currency=\"EUR\"><name>&lt;Ink&gt;</name></items>\
+            <note/><kind>gift</kind></order>\n"
+        )));
+
+        let mut schema = Xml::default();
+        schema.attribute("kind");
+        assert_eq!(schema.write("order", order).map(|xml| xml.contains("<order id=\"42\" kind=\"gift\"><status>")), Ok(true));
+
+        assert_eq!(
+            Xml::default().prefix(None).write("order", order),
+            Err(XmlError::InvalidName { path: String::from("order.@id"), name: String::from("@id") })
+        );
+    }
+
+    #[test]
+    fn test_invalid(){
+        let write_source = |source: &str| {
+            let resolved = FlatObject::parse(source).unwrap().unwrap();
+            Xml::default().write("root", &resolved).map_err(|error| error.to_string())
+        };
+
+        assert_eq!(write_source("a<b: 'x'"), Err(String::from("`a<b` is not a valid xml name, at `root.a<b`")));
+        assert_eq!(write_source(": 'x'"), Err(String::from("`0` is not a valid xml name, at `root.0`")));
+        assert_eq!(write_source("a: { @b: { } }"), Err(String::from("`root.a.@b` must be a string literal to be written as xml")));
+        assert_eq!(write_source("a: { #text: { } }"), Err(String::from("`root.a.#text` must be a string literal to be written as xml")));
+        assert_eq!(write_source("a: 'bell \u{7}'"), Err(String::from("xml cannot contain the character '\\u{7}', at `root.a`")));
+        assert_eq!(write_source("a: { @b: 'x\ny' }"), Ok(String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<root><a b=\"x&#10;y\"/></root>\n")));
+
+        let resolved = FlatObject::parse("order: { @id: '1' id: '2' }").unwrap().unwrap();
+        let order = resolved.get_path(&["order"]).unwrap();
+        assert_eq!(
+            Xml::default().attribute("id").write("order", order),
+            Err(XmlError::DuplicateAttribute { path: String::from("order.id"), name: String::from("id") })
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Writes resolved documents as YAML, in block style with an indentation of two spaces.
+//! Multi-line string literals are written as block strings, like `|`,
+//! and other strings are only quoted if they would be read as something else.
+
+use ::flat::FlatObject;
+use super::{ Scalar, Scalars };
+
+
+const INDENTATION: usize = 2;
+
+/// words that yaml reads as null or booleans
+const RESERVED_WORDS: &[&str] = &[ "null", "true", "false", "yes", "no", "on", "off", "y", "n" ];
+
+
+pub fn write(object: &FlatObject, scalars: Scalars) -> String {
+    let mut yaml = String::new();
+
+    match *object {
+        FlatObject::Compound(ref properties) if !properties.is_empty() => write_block(object, 0, scalars, &mut yaml),
+        _ => {
+            write_inline(object, INDENTATION, scalars, &mut yaml);
+            yaml.remove(0);
+            yaml.push('\n');
+        },
+    }
+
+    yaml
+}
+
+/// the lines of a non-empty compound, each starting with the indentation
+fn write_block(object: &FlatObject, indentation: usize, scalars: Scalars, yaml: &mut String) {
+    let properties = match *object {
+        FlatObject::Compound(ref properties) => properties,
+        FlatObject::StringLiteral(_) => unreachable!("only compounds are written as blocks"),
+    };
+
+    match super::list(properties) {
+        Some(entries) => for entry in entries {
+            if is_block(entry) {
+                // the first line of the nested block follows the dash
+                let mut nested = String::new();
+                write_block(entry, indentation + INDENTATION, scalars, &mut nested);
+
+                indent(indentation, yaml);
+                yaml.push_str("- ");
+                yaml.push_str(&nested[indentation + INDENTATION ..]);
+            }
+            else {
+                indent(indentation, yaml);
+                yaml.push('-');
+                write_inline(entry, indentation + INDENTATION, scalars, yaml);
+                yaml.push('\n');
+            }
+        },
+
+        None => for (name, value) in properties.iter() {
+            indent(indentation, yaml);
+            write_key(name, yaml);
+            yaml.push(':');
+
+            if is_block(value) {
+                yaml.push('\n');
+                write_block(value, indentation + INDENTATION, scalars, yaml);
+            }
+            else {
+                write_inline(value, indentation + INDENTATION, scalars, yaml);
+                yaml.push('\n');
+            }
+        },
+    }
+}
+
+/// a value after a key or dash, starting with a space. block strings continue on the following lines
+fn write_inline(object: &FlatObject, indentation: usize, scalars: Scalars, yaml: &mut String) {
+    let text = match *object {
+        FlatObject::Compound(_) => return yaml.push_str(" {}"),
+        FlatObject::StringLiteral(ref text) => text,
+    };
+
+    yaml.push(' ');
+
+    match super::scalar(text, scalars) {
+        Scalar::Number(number) => yaml.push_str(number),
+        Scalar::Boolean(boolean) => yaml.push_str(if boolean { "true" } else { "false" }),
+        Scalar::Text(text) if is_plain(text) => yaml.push_str(text),
+        Scalar::Text(text) if is_block_string(text) => write_block_string(text, indentation, yaml),
+        Scalar::Text(text) => write_quoted(text, yaml),
+    }
+}
+
+fn is_block(object: &FlatObject) -> bool {
+    match *object {
+        FlatObject::Compound(ref properties) => !properties.is_empty(),
+        FlatObject::StringLiteral(_) => false,
+    }
+}
+
+/// keys that look like numbers are quoted, because plain ones start with a letter
+fn write_key(name: &str, yaml: &mut String) {
+    if is_plain(name) { yaml.push_str(name) }
+    else { write_quoted(name, yaml) }
+}
+
+/// whether the text can be written without quotes and is still read as the same string
+fn is_plain(text: &str) -> bool {
+    let mut characters = text.chars();
+
+    let starts_with_letter = characters.next()
+        .is_some_and(|first| first.is_alphabetic() || first == '_' || first == '/');
+
+    starts_with_letter && !text.ends_with(' ') && !text.ends_with(':') && !text.contains(": ")
+        && characters.all(|character| character.is_alphanumeric() || " _-./:@+".contains(character))
+        && !RESERVED_WORDS.contains(&text.to_lowercase().as_str())
+}
+
+/// multi-line text, which would not need escapes inside a block string
+fn is_block_string(text: &str) -> bool {
+    let content = text.trim_end_matches('\n');
+
+    (content.contains('\n') || content.len() < text.len()) && !content.is_empty()
+        && !content.starts_with(' ')
+        && !text.chars().any(|character| character.is_control() && character != '\n' && character != '\t')
+}
+
+/// `|-` strips the final line break, `|` keeps it, and `|+` keeps all trailing line breaks
+fn write_block_string(text: &str, indentation: usize, yaml: &mut String) {
+    let content = text.trim_end_matches('\n');
+    let trailing_breaks = text.len() - content.len();
+
+    yaml.push_str(match trailing_breaks { 0 => "|-", 1 => "|", _ => "|+" });
+
+    for line in content.split('\n') {
+        yaml.push('\n');
+        if !line.is_empty() {
+            indent(indentation, yaml);
+            yaml.push_str(line);
+        }
+    }
+
+    for _ in 1 .. trailing_breaks {
+        yaml.push('\n');
+    }
+}
+
+fn write_quoted(text: &str, yaml: &mut String) {
+    yaml.push('"');
+
+    for character in text.chars() {
+        match character {
+            '"' => yaml.push_str("\\\""),
+            '\\' => yaml.push_str("\\\\"),
+            '\n' => yaml.push_str("\\n"),
+            '\t' => yaml.push_str("\\t"),
+            '\r' => yaml.push_str("\\r"),
+            character if character.is_control() => yaml.push_str(&format!("\\u{:04x}", character as u32)),
+            character => yaml.push(character),
+        }
+    }
+
+    yaml.push('"');
+}
+
+fn indent(indentation: usize, yaml: &mut String) {
+    yaml.push_str(&" ".repeat(indentation));
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write(){
+        let source = r#"
+            Service: { image: 'nginx:1.25' replicas: '1' }
+            web: Service {
+                replicas: '3'
+                name: 'web'
+                ports: { : { port: '80' public: 'true' } : { port: '443' } }
+                hosts: { : 'example.com' : 'yes' }
+                script: 'set -e
+run --verbose
+'
+                note: '  indented: "quoted"'
+                labels: { }
+                10: '007'
+            }
+        "#;
+
+        let resolved = FlatObject::parse(source).unwrap().unwrap();
+        let web = resolved.get_path(&["web"]).unwrap();
+        assert_eq!(write(web, Scalars::Coerced), "\
+image: nginx:1.25
+replicas: 3
+name: web
+ports:
+  - port: 80
+    public: true
+  - port: 443
+hosts:
+  - example.com
+  - \"yes\"
+script: |
+  set -e
+  run --verbose
+note: \"  indented: \\\"quoted\\\"\"
+labels: {}
+\"10\": \"007\"
+");
+
+        let ports = web.get_path(&["ports", "0"]).unwrap();
+        assert_eq!(write(ports, Scalars::Strings), "port: \"80\"\npublic: \"true\"\n");
+    }
+
+    #[test]
+    fn test_block_strings(){
+        let write_text = |text: &str| write(&FlatObject::StringLiteral(text.to_string()), Scalars::Strings);
+        assert_eq!(write_text("a\n\nb"), "|-\n  a\n\n  b\n");
+        assert_eq!(write_text("a\n\n"), "|+\n  a\n\n");
+        assert_eq!(write_text(" a\nb"), "\" a\\nb\"\n");
+        assert_eq!(write_text("\n"), "\"\\n\"\n");
+        assert_eq!(write(&FlatObject::Compound(Default::default()), Scalars::Strings), "{}\n");
+    }
+}
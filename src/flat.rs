@@ -1,103 +1,1289 @@
-use ::std::collections::HashMap;
+use ::std::collections::{ HashMap, HashSet, BTreeSet };
+use ::std::borrow::Cow;
+use ::std::sync::Arc;
+use ::std::sync::atomic::{ AtomicUsize, Ordering };
 use ::parse::*;
+use ::list::Placement;
+use ::ordered::OrderedMap;
+use ::scope::{ Scope, Host };
+use ::trace::{ Trace, Provenance, Origin, Chain, Conflict, Location };
+use ::shared::Interner;
 
-pub type FlatCompound = HashMap<String, FlatObject>;
+/// the properties are in declaration order, see `Resolver::build_unconditional`
+pub type FlatCompound = OrderedMap<String, FlatObject>;
 
-#[derive(Eq, PartialEq, Debug, Clone)]
+/// compounds are shared, so cloning is cheap, and the resolver shares equal compounds, see `::shared`.
+/// shared compounds are equal without comparing their properties
+#[derive(Eq, Debug, Clone)]
 pub enum FlatObject {
     StringLiteral(String),
-    Compound(FlatCompound),
+    Compound(Arc<FlatCompound>),
+}
+
+
+/// resolves the parse result into flat objects
+struct Resolver<'o> {
+    scope: Scope<'o>,
+
+    /// objects which are currently being resolved, to detect cycles
+    resolving: Vec<&'o Object<'o>>,
+
+    /// only present when provenance is requested
+    tracing: Option<Tracing>,
+
+    /// the layers of the compound whose condition is being evaluated, where `$name` is looked up
+    parameters: Option<Vec<Layer<'o>>>,
+
+    /// the names of the prelude objects that were referenced, only present when requested
+    prelude_usage: Option<BTreeSet<String>>,
+
+    /// every resolved compound, to share equal ones
+    interner: Interner,
+
+    /// resolved compounds by the address of their declaration, only present for `Lazy`.
+    /// a compound that is resolved on its own does not depend on where it is used
+    memo: Option<HashMap<*const Object<'o>, FlatObject>>,
+
+    /// the linearization of every compound by the address of its declaration, see `Resolver::linearize`.
+    /// the first ancestor has no object, and the chains start where the compound is
+    linearizations: HashMap<*const Compound<'o>, Vec<Ancestor<'o>>>,
+
+    /// the string literals that references to properties resolved to outside of conditions, by the address of the reference,
+    /// because every compound that inherits the reference would otherwise look up its parents again
+    referenced: HashMap<*const Reference<'o>, FlatObject>,
+}
+
+/// a document which is only resolved where it is accessed, see `Lazy::get`.
+/// compounds are resolved at most once, so prototypes which many objects use are not resolved again for each of them.
+/// only the objects of the document can be accessed, not those of a prelude
+pub struct Lazy<'o> {
+    document: &'o NamedObjects<'o>,
+    resolver: Resolver<'o>,
+
+    /// the results of `get`, by path
+    values: HashMap<String, FlatObject>,
+}
+
+struct Tracing {
+    trace: Trace,
+
+    /// the property which is currently being resolved
+    path: Vec<String>,
+
+    /// the variables that the current property value has been copied from
+    inlined: Vec<Origin>,
+}
+
+/// a property of a compound, see `Resolver::declared_properties`
+struct Declared<'o> {
+    name: Cow<'o, str>,
+    identifier: &'o Identifier<'o>,
+    object: &'o Object<'o>,
+
+    /// the index of the layer that declares it
+    layer: usize,
+}
+
+/// the overrides of a compound, or of one of the prototypes in its chain.
+/// properties of earlier layers take precedence over later layers
+#[derive(Clone)]
+struct Layer<'o> {
+    overrides: &'o NamedObjects<'o>,
+
+    /// the prototypes this layer was reached through, only recorded when tracing
+    inherited_from: Chain,
+}
+
+/// a compound in the linearization of another compound
+#[derive(Clone)]
+struct Ancestor<'o> {
+    compound: &'o Compound<'o>,
+
+    /// the object the compound was referenced as, none for the compound that is linearized
+    object: Option<&'o Object<'o>>,
+
+    inherited_from: Chain,
+
+    /// the linearization of this compound itself
+    linearization: Vec<&'o Compound<'o>>,
 }
 
 
 impl FlatObject {
+    pub fn compound(properties: FlatCompound) -> Self {
+        FlatObject::Compound(Arc::new(properties))
+    }
+
     pub fn parse(source: ::parse::Source) -> ::parse::ParseResult<ResolveResult<FlatObject>> {
         ::parse::parse(source).map(|parsed| {
             Self::build_from_parsed(&parsed)
         })
     }
 
-    pub fn build_from_parsed(parsed: &NamedObjects) -> ResolveResult<FlatObject> {
-        Self::build_from_parsed_named_objects(parsed, parsed)
-            .map(|objects| FlatObject::Compound(objects))
+    pub fn build_from_parsed(parsed: &NamedObjects) -> ResolveResult<FlatObject> {
+        Resolver::new(Scope::document(parsed)).build_from_layers(&[ Layer { overrides: parsed, inherited_from: Chain::default() } ])
+            .map(FlatObject::compound)
+    }
+
+    /// like `parse`, but `env.name` refers to the supplied external variables
+    pub fn parse_with<'s>(source: Source<'s>, variables: &HashMap<String, String>) -> ::parse::ParseResult<'s, ResolveResult<FlatObject>> {
+        ::parse::parse(source).map(|parsed| {
+            Self::build_with(&parsed, variables)
+        })
+    }
+
+    /// fails if the document declares `env` itself
+    pub fn build_with(parsed: &NamedObjects, variables: &HashMap<String, String>) -> ResolveResult<FlatObject> {
+        Self::build_in(parsed, Host::default().variables(variables))
+    }
+
+    /// resolves references that the document does not declare with the objects that the host supplies
+    pub fn build_in<'o>(parsed: &'o NamedObjects<'o>, host: &'o Host<'o>) -> ResolveResult<FlatObject> {
+        Resolver::new(host.scope(parsed)?).build_from_layers(&[ Layer { overrides: parsed, inherited_from: Chain::default() } ])
+            .map(FlatObject::compound)
+    }
+
+    /// like `build_from_parsed`, but resolves the top-level objects on up to `threads` threads
+    pub fn build_parallel(parsed: &NamedObjects, threads: usize) -> ResolveResult<FlatObject> {
+        Resolver::build_parallel(Scope::document(parsed), threads)
+    }
+
+    /// like `build_in`, but resolves the top-level objects on up to `threads` threads
+    pub fn build_parallel_in<'o>(parsed: &'o NamedObjects<'o>, host: &'o Host<'o>, threads: usize) -> ResolveResult<FlatObject> {
+        Resolver::build_parallel(host.scope(parsed)?, threads)
+    }
+
+    /// the names of the prelude objects that the document uses while resolving, sorted.
+    /// also contains the ones used by other prelude objects
+    pub fn prelude_usage<'o>(parsed: &'o NamedObjects<'o>, host: &'o Host<'o>) -> ResolveResult<Vec<String>> {
+        let mut resolver = Resolver::new(host.scope(parsed)?);
+        resolver.prelude_usage = Some(BTreeSet::new());
+        resolver.build_from_layers(&[ Layer { overrides: parsed, inherited_from: Chain::default() } ])?;
+        Ok(resolver.prelude_usage.expect("usage was recorded").into_iter().collect())
+    }
+
+    /// like `parse`, but also records where every property came from
+    pub fn parse_traced(source: ::parse::Source) -> ::parse::ParseResult<ResolveResult<(FlatObject, Trace)>> {
+        ::parse::parse(source).map(|parsed| {
+            Self::build_traced(&parsed)
+        })
+    }
+
+    /// the spans of the trace are located in the source of the document
+    pub fn build_traced<'o>(parsed: &'o NamedObjects<'o>) -> ResolveResult<(FlatObject, Trace)> {
+        Self::build_traced_in(parsed, &Host::default())
+    }
+
+    /// like `build_traced`, with the objects that the host supplies.
+    /// properties declared outside of the document have no provenance
+    pub fn build_traced_in<'o>(parsed: &'o NamedObjects<'o>, host: &'o Host<'o>) -> ResolveResult<(FlatObject, Trace)> {
+        let mut resolver = Resolver::new(host.scope(parsed)?);
+        resolver.tracing = Some(Tracing {
+            trace: Trace::default(),
+            path: Vec::new(), inlined: Vec::new(),
+        });
+
+        let properties = resolver.build_from_layers(&[ Layer { overrides: parsed, inherited_from: Chain::default() } ])?;
+        let trace = resolver.tracing.expect("tracing was enabled").trace;
+        Ok((FlatObject::compound(properties), trace))
+    }
+
+    /// flattens only the object the reference points to, instead of the whole document
+    pub fn resolve<'o>(world: &'o NamedObjects<'o>, reference: &'o Reference<'o>) -> ResolveResult<FlatObject> {
+        Resolver::new(Scope::document(world)).build_referenced(reference)
+    }
+
+    /// flattens a compound of the document which is not necessarily named, e.g. a property value
+    pub fn resolve_compound<'o>(world: &'o NamedObjects<'o>, compound: &'o Compound<'o>) -> ResolveResult<FlatObject> {
+        let mut resolver = Resolver::new(Scope::document(world));
+        let mut layers = Vec::new();
+        resolver.push_compound_layers(compound, Chain::default(), &mut layers)?;
+        resolver.build_object_from_layers(&layers)
+    }
+
+    /// flattens a single object of the document, e.g. a string literal or an expression
+    pub fn resolve_object<'o>(world: &'o NamedObjects<'o>, object: &'o Object<'o>) -> ResolveResult<FlatObject> {
+        let mut resolver = Resolver::new(Scope::document(world));
+        resolver.resolving.push(object);
+        resolver.build_from_parsed_unnamed_object(object)
+    }
+
+    /// the object as if it had been parsed, where compounds have no prototypes
+    pub fn to_object(&self) -> Object<'_> {
+        match *self {
+            FlatObject::StringLiteral(ref literal) => Object::StringLiteral(literal),
+            FlatObject::Compound(ref properties) => {
+                let mut overrides = NamedObjects::default();
+
+                for (name, property) in properties.iter() {
+                    overrides.declare(Identifier::new(name), property.to_object());
+                }
+
+                Object::Compound(Compound { prototypes: Vec::new(), overrides })
+            },
+        }
+    }
+
+    /// looks up a nested property, e.g. `["text", "cancel"]`
+    pub fn get_path<S: AsRef<str>>(&self, path: &[S]) -> Option<&FlatObject> {
+        match path.split_first() {
+            None => Some(self),
+            Some((first, rest)) => match *self {
+                FlatObject::Compound(ref properties) => properties.get(first.as_ref())?.get_path(rest),
+                FlatObject::StringLiteral(_) => None,
+            }
+        }
+    }
+
+}
+
+
+impl Tracing {
+    /// starts resolving the value of a property, returns the state of the enclosing property
+    fn enter_property(&mut self, name: &str) -> Vec<Origin> {
+        self.path.push(name.to_string());
+        ::std::mem::take(&mut self.inlined)
+    }
+
+    /// properties declared outside of the document and the preludes, like external variables, have no provenance
+    fn exit_property(&mut self, defined_at: Option<Location>, inherited_from: &Chain, inlined: Vec<Origin>) {
+        let inlined_from = ::std::mem::replace(&mut self.inlined, inlined);
+
+        if let Some(defined_at) = defined_at {
+            let provenance = Provenance { defined_at, inherited_from: inherited_from.clone(), inlined_from };
+            self.trace.provenances.insert(self.path.clone(), provenance);
+        }
+
+        self.path.pop();
+    }
+
+    /// like properties, unset properties declared outside of the document and the preludes are not recorded
+    fn record_unset(&mut self, name: &str, defined_at: Option<Location>, inherited_from: &Chain) {
+        let defined_at = match defined_at {
+            Some(defined_at) => defined_at,
+            None => return,
+        };
+
+        let mut path = self.path.clone();
+        path.push(name.to_string());
+
+        self.trace.unset.insert(path, Provenance { defined_at, inherited_from: inherited_from.clone(), inlined_from: Vec::new() });
+    }
+
+    /// removes everything that has been recorded for the properties of the current property
+    fn take_nested(&mut self) -> Trace {
+        let current = &self.path;
+        let is_nested = |path: &Vec<String>| path.len() > current.len() && path[.. current.len()] == current[..];
+
+        let (provenances, remaining) = ::std::mem::take(&mut self.trace.provenances).into_iter()
+            .partition(|(path, _)| is_nested(path));
+        self.trace.provenances = remaining;
+
+        let (unset, remaining) = ::std::mem::take(&mut self.trace.unset).into_iter()
+            .partition(|(path, _)| is_nested(path));
+        self.trace.unset = remaining;
+
+        let (conflicts, remaining) = ::std::mem::take(&mut self.trace.conflicts).into_iter()
+            .partition(|conflict| is_nested(&conflict.path));
+        self.trace.conflicts = remaining;
+
+        Trace { provenances, unset, conflicts }
+    }
+
+    /// records the properties of the current property again, after some of them have been renamed.
+    /// `inherited_from` is prepended to the chain of the direct properties.
+    /// list entries which have not been renamed are no longer part of the list, and are dropped
+    fn restore_nested(&mut self, nested: Trace, renamed: &Renamed, inherited_from: &Chain) {
+        let depth = self.path.len();
+
+        let rename = |mut path: Vec<String>| {
+            match renamed.get(&path[depth]) {
+                Some(name) => path[depth] = name.clone(),
+                None if ::list::index(&path[depth]).is_some() => return None,
+                None => {},
+            }
+
+            Some(path)
+        };
+
+        let inherit = |path: &Vec<String>, mut provenance: Provenance| {
+            if path.len() == depth + 1 {
+                provenance.inherited_from = provenance.inherited_from.after(inherited_from);
+            }
+
+            provenance
+        };
+
+        for (path, provenance) in nested.provenances {
+            if let Some(path) = rename(path) {
+                let provenance = inherit(&path, provenance);
+                self.trace.provenances.insert(path, provenance);
+            }
+        }
+
+        for (path, provenance) in nested.unset {
+            if let Some(path) = rename(path) {
+                let provenance = inherit(&path, provenance);
+                self.trace.unset.insert(path, provenance);
+            }
+        }
+
+        for mut conflict in nested.conflicts {
+            if let Some(path) = rename(conflict.path) {
+                conflict.path = path;
+                self.trace.conflicts.push(conflict);
+            }
+        }
+    }
+}
+
+/// ancestors are identified by their declaration
+impl<'o> PartialEq for Ancestor<'o> {
+    fn eq(&self, other: &Self) -> bool {
+        ::std::ptr::eq(self.compound, other.compound)
+    }
+}
+
+impl<'o> Resolver<'o> {
+    fn new(scope: Scope<'o>) -> Self {
+        Resolver { scope, resolving: Vec::new(), tracing: None, parameters: None, prelude_usage: None, interner: Interner::default(), memo: None, linearizations: HashMap::new(), referenced: HashMap::new() }
+    }
+
+    /// resolves the reference, failing if the target is currently being resolved,
+    /// which would otherwise recurse infinitely
+    fn enter_reference(&mut self, reference: &'o Reference<'o>) -> ResolveResult<&'o Object<'o>> {
+        let target = self.scope.resolve_reference(reference)?;
+        self.enter(reference, target)?;
+        Ok(target)
+    }
+
+    /// see `enter_reference`, for a target that was already looked up
+    fn enter(&mut self, reference: &'o Reference<'o>, target: &'o Object<'o>) -> ResolveResult<()> {
+        if let Some(usage) = self.prelude_usage.as_mut() {
+            if self.scope.is_prelude(reference) {
+                usage.insert(reference.identifiers[0].name.to_string());
+            }
+        }
+
+        if self.resolving.iter().any(|object| ::std::ptr::eq(*object, target)) {
+            return Err(ResolveError::CyclicReference { identifier: reference.to_string() });
+        }
+
+        self.resolving.push(target);
+        Ok(())
+    }
+
+    fn exit_references(&mut self, count: usize) {
+        let remaining = self.resolving.len() - count;
+        self.resolving.truncate(remaining);
+    }
+
+    fn origin(&self, reference: &'o Reference<'o>) -> Option<Origin> {
+        self.tracing.as_ref()?;
+        let (identifier, _) = self.scope.resolve_definition(reference).ok()?;
+        let location = self.declared_at(identifier)?;
+        Some(Origin { name: reference.to_string(), location })
+    }
+
+    /// where a declaration is written, none if it is supplied by the host, like external variables
+    fn declared_at(&self, identifier: &'o Identifier<'o>) -> Option<Location> {
+        if self.scope.is_prelude_part(identifier) {
+            return Some(Location::Prelude);
+        }
+
+        identifier.location.map(Location::Document)
+    }
+
+
+    /// adds the compound and all of its prototypes as layers, in the order of their linearization,
+    /// returns how many references were entered
+    fn push_compound_layers(
+        &mut self,
+        compound: &'o Compound<'o>,
+        inherited_from: Chain,
+        layers: &mut Vec<Layer<'o>>
+    ) -> ResolveResult<usize> {
+        // conflicts are reported where the compound is declared, not wherever it is used
+        let report_conflicts = inherited_from.is_empty() && self.tracing.as_ref()
+            .is_some_and(|tracing| tracing.inlined.is_empty());
+
+        let ancestors = self.linearize(compound, None, inherited_from, report_conflicts)?;
+        let mut entered = 0;
+
+        for ancestor in ancestors {
+            layers.push(Layer { overrides: &ancestor.compound.overrides, inherited_from: ancestor.inherited_from });
+
+            // keep the prototypes on the stack while their properties are resolved
+            if let Some(object) = ancestor.object {
+                self.resolving.push(object);
+                entered += 1;
+            }
+        }
+
+        Ok(entered)
+    }
+
+    /// the compound followed by all of its prototypes, see `::linearize`.
+    /// each compound is only linearized once, because prototypes that share a prototype would otherwise
+    /// linearize it again for each path to it, which grows exponentially with the number of diamonds
+    fn linearize(
+        &mut self,
+        compound: &'o Compound<'o>,
+        object: Option<&'o Object<'o>>,
+        inherited_from: Chain,
+        report_conflicts: bool
+    ) -> ResolveResult<Vec<Ancestor<'o>>> {
+        let key = compound as *const Compound;
+
+        // a prototype that is currently being resolved is a cycle, which only the uncached linearization reports
+        let cached = self.linearizations.get(&key)
+            .filter(|ancestors| !report_conflicts && !self.is_entered(ancestors))
+            .cloned();
+
+        let mut ancestors = match cached {
+            Some(ancestors) => ancestors,
+            None => {
+                let ancestors = self.linearize_uncached(compound, report_conflicts)?;
+                self.linearizations.insert(key, ancestors.clone());
+                ancestors
+            },
+        };
+
+        ancestors[0].object = object;
+
+        if !inherited_from.is_empty() {
+            for ancestor in &mut ancestors {
+                ancestor.inherited_from = ancestor.inherited_from.after(&inherited_from);
+            }
+        }
+
+        Ok(ancestors)
+    }
+
+    fn is_entered(&self, ancestors: &[Ancestor<'o>]) -> bool {
+        ancestors.iter().filter_map(|ancestor| ancestor.object)
+            .any(|object| self.resolving.iter().any(|resolving| ::std::ptr::eq(*resolving, object)))
+    }
+
+    /// the linearization of the compound as if it was declared without prototypes above it
+    fn linearize_uncached(&mut self, compound: &'o Compound<'o>, report_conflicts: bool) -> ResolveResult<Vec<Ancestor<'o>>> {
+        let mut linearizations = Vec::with_capacity(compound.prototypes.len() + 1);
+        let mut direct_prototypes = Vec::with_capacity(compound.prototypes.len());
+
+        for reference in &compound.prototypes {
+            let prototype = self.enter_reference(reference)?;
+
+            let chain = self.origin(reference).into_iter().collect();
+
+            let linearization = match prototype.as_compound() {
+                Some(prototype_compound) => self.linearize(prototype_compound, Some(prototype), chain, false)?,
+                None => Vec::new(), // string literals have no properties to inherit
+            };
+
+            self.exit_references(1);
+
+            direct_prototypes.extend(linearization.first().cloned());
+            linearizations.push(linearization);
+        }
+
+        let merged = ::linearize::c3_merge(
+            linearizations.iter().cloned()
+                .chain(::std::iter::once(direct_prototypes))
+                .collect()
+        );
+
+        let merged = merged.ok_or_else(|| ResolveError::InconsistentPrototypeOrder {
+            prototypes: compound.prototypes.iter()
+                .map(Reference::to_string)
+                .collect::<Vec<_>>().join(" + ")
+        })?;
+
+        if report_conflicts && linearizations.len() > 1 {
+            self.record_conflicts(compound, &linearizations, &merged);
+        }
+
+        let linearization = ::std::iter::once(compound)
+            .chain(merged.iter().map(|ancestor| ancestor.compound))
+            .collect();
+
+        Ok(
+            ::std::iter::once(Ancestor { compound, object: None, inherited_from: Chain::default(), linearization })
+                .chain(merged)
+                .collect()
+        )
+    }
+
+    /// a property is ambiguous if the prototypes inherit it from different compounds,
+    /// and the one that takes precedence does not itself inherit from the other
+    fn record_conflicts(&mut self, compound: &'o Compound<'o>, linearizations: &[Vec<Ancestor<'o>>], merged: &[Ancestor<'o>]) {
+        let tracing = match self.tracing.as_mut() {
+            Some(tracing) => tracing,
+            None => return,
+        };
+
+        let mut names: Vec<&str> = merged.iter()
+            .flat_map(|ancestor| ancestor.compound.overrides.identifiers.keys())
+            .map(|identifier| identifier.name)
+            .filter(|name| compound.overrides.get(name).is_none())
+            .collect();
+
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            let declares = |ancestor: &&Ancestor<'o>| ancestor.compound.overrides.get(name).is_some();
+            let chosen = merged.iter().find(declares).expect("name was collected from the ancestors");
+
+            let ignored = linearizations.iter()
+                .filter_map(|linearization| linearization.iter().find(declares))
+                .find(|winner| !chosen.linearization.iter().any(|ancestor| ::std::ptr::eq(*ancestor, winner.compound)));
+
+            if let (Some(chosen), Some(ignored)) = (chosen.inherited_from.last(), ignored.and_then(|ignored| ignored.inherited_from.last())) {
+                let mut path = tracing.path.clone();
+                path.push(name.to_string());
+                tracing.trace.conflicts.push(Conflict { path, chosen: chosen.clone(), ignored: ignored.clone() });
+            }
+        }
+    }
+
+    /// like `build_from_layers`, but the first native prototype of the layers replaces the result, see `::scope`
+    fn build_object_from_layers(&mut self, layers: &[Layer<'o>]) -> ResolveResult<FlatObject> {
+        let properties = self.build_from_layers(layers)?;
+
+        match layers.iter().find_map(|layer| self.scope.native_prototype(layer.overrides)) {
+            None => Ok(self.interner.compound(properties)),
+            Some((name, prototype)) => {
+                // the value is not declared anywhere in the source
+                if let Some(tracing) = self.tracing.as_mut() {
+                    tracing.take_nested();
+                }
+
+                prototype(&properties).map_err(|message| ResolveError::NativeError {
+                    name: name.to_string(), message, span: None,
+                })
+            },
+        }
+    }
+
+    /// conditional objects are added as layers, if their conditions hold
+    fn build_from_layers(&mut self, layers: &[Layer<'o>]) -> ResolveResult<FlatCompound> {
+        if layers.iter().all(|layer| layer.overrides.conditionals.is_empty()) {
+            layers.iter().try_for_each(|layer| self.check_indices(layer.overrides))?;
+            return self.build_unconditional(layers);
+        }
+
+        let expanded = self.expand_layers(layers)?;
+        self.build_unconditional(&expanded)
+    }
+
+    /// the layers with the conditionals whose conditions hold, see `expand`
+    fn expand_layers(&mut self, layers: &[Layer<'o>]) -> ResolveResult<Vec<Layer<'o>>> {
+        let outer = self.parameters.replace(layers.to_vec());
+        let mut expanded = Vec::with_capacity(layers.len());
+        let result = layers.iter().try_for_each(|layer| self.expand(layer.clone(), &mut expanded));
+        self.parameters = outer;
+
+        result?;
+        Ok(expanded)
+    }
+
+    /// fails if an unnamed entry would be ignored, because its index is also declared by name
+    fn check_indices(&self, overrides: &'o NamedObjects<'o>) -> ResolveResult<()> {
+        match overrides.duplicate_index() {
+            None => Ok(()),
+            Some((index, identifier)) => Err(ResolveError::DuplicateIndex {
+                index, span: self.scope.locate(identifier, identifier.location),
+            }),
+        }
+    }
+
+    /// adds the objects of the conditionals whose conditions hold as layers before the layer that declares them,
+    /// where later conditionals take precedence over earlier ones
+    fn expand(&mut self, layer: Layer<'o>, expanded: &mut Vec<Layer<'o>>) -> ResolveResult<()> {
+        self.check_indices(layer.overrides)?;
+
+        for conditional in layer.overrides.conditionals.iter().rev() {
+            if self.evaluate_truth(&conditional.condition)? {
+                let inherited_from = layer.inherited_from.clone();
+                self.expand(Layer { overrides: &conditional.overrides, inherited_from }, expanded)?;
+            }
+        }
+
+        expanded.push(layer);
+        Ok(())
+    }
+
+    /// the property as declared in the layers, ignoring their conditionals, none if it does not exist
+    fn build_parameter(&mut self, name: &str, layers: &[Layer<'o>]) -> ResolveResult<Option<FlatObject>> {
+        for (index, layer) in layers.iter().enumerate() {
+            match layer.overrides.get(name) {
+                None => continue,
+                Some(&Object::Unset) => return Ok(None),
+                Some(object) => return self.build_property(name, object, layer, &layers[index + 1 ..]).map(Some),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// top-level objects only depend on the parsed document, not on each other's resolution,
+    /// so every thread takes the next unresolved one, with a resolver of its own.
+    /// equal compounds are only shared if the same thread resolved them.
+    /// fails with the error of the first object in declaration order, like when resolving on one thread
+    fn build_parallel(scope: Scope<'o>, threads: usize) -> ResolveResult<FlatObject> {
+        let mut resolver = Resolver::new(scope);
+        let layers = resolver.expand_layers(&[ Layer { overrides: scope.document, inherited_from: Chain::default() } ])?;
+        let declared = resolver.declared_properties(&layers);
+        let next = AtomicUsize::new(0);
+
+        let mut resolved: Vec<(usize, ResolveResult<FlatObject>)> = ::std::thread::scope(|spawner| {
+            let workers: Vec<_> = (0 .. threads.clamp(1, declared.len().max(1)))
+                .map(|_| spawner.spawn(|| {
+                    let mut resolver = Resolver::new(scope);
+                    let mut resolved = Vec::new();
+
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let property = match declared.get(index) { Some(property) => property, None => break };
+
+                        let value = resolver.build_property(&property.name, property.object, &layers[property.layer], &layers[property.layer + 1 ..]);
+                        resolved.push((index, value));
+
+                        // an error may have left references on the stack
+                        resolver.resolving.clear();
+                    }
+
+                    resolved
+                }))
+                .collect();
+
+            workers.into_iter()
+                .flat_map(|worker| worker.join().unwrap_or_else(|panic| ::std::panic::resume_unwind(panic)))
+                .collect()
+        });
+
+        resolved.sort_by_key(|&(index, _)| index);
+
+        let properties = declared.into_iter().zip(resolved)
+            .map(|(property, (_, value))| value.map(|value| (property.name.into_owned(), value)))
+            .collect::<ResolveResult<HashMap<String, FlatObject>>>()?;
+
+        Ok(FlatObject::compound(in_declaration_order(&layers, properties)))
+    }
+
+    /// ignores the conditionals of the layers. the properties are ordered like they are declared,
+    /// where properties declared by prototypes come first
+    fn build_unconditional(&mut self, layers: &[Layer<'o>]) -> ResolveResult<FlatCompound> {
+        let mut properties = HashMap::new();
+
+        for declared in self.declared_properties(layers) {
+            let layer = &layers[declared.layer];
+            let outer = self.tracing.as_mut()
+                .map(|tracing| tracing.enter_property(&declared.name));
+
+            let value = self.build_property(&declared.name, declared.object, layer, &layers[declared.layer + 1 ..])?;
+
+            let defined_at = self.tracing.as_ref().and_then(|_| self.declared_at(declared.identifier));
+            if let (Some(tracing), Some(outer)) = (self.tracing.as_mut(), outer) {
+                tracing.exit_property(defined_at, &layer.inherited_from, outer);
+            }
+
+            properties.insert(declared.name.into_owned(), value);
+        }
+
+        Ok(in_declaration_order(layers, properties))
+    }
+
+    /// the properties which are not unset, each from the first layer that declares it.
+    /// unset properties are recorded when tracing
+    fn declared_properties(&mut self, layers: &[Layer<'o>]) -> Vec<Declared<'o>> {
+        let mut declared = Vec::new();
+        let mut names = HashSet::new();
+        let mut unset = HashSet::new();
+
+        for (index, layer) in layers.iter().enumerate() {
+            for (name, identifier, object_index) in layer.overrides.in_declaration_order() {
+                if unset.contains(&name) || names.contains(&name) {
+                    continue;
+                }
+
+                let object = &layer.overrides.objects[object_index];
+
+                if let Object::Unset = *object {
+                    let defined_at = self.tracing.as_ref().and_then(|_| self.declared_at(identifier));
+                    if let Some(tracing) = self.tracing.as_mut() {
+                        tracing.record_unset(&name, defined_at, &layer.inherited_from);
+                    }
+
+                    unset.insert(name);
+                    continue;
+                }
+
+                names.insert(name.clone());
+                declared.push(Declared { name, identifier, object, layer: index });
+            }
+        }
+
+        declared
+    }
+
+    /// a compound without a prototype is merged with the compounds of the same name
+    /// in the later layers, as long as those are also compounds without a prototype.
+    /// `layer` is the layer the property was found in
+    fn build_property(
+        &mut self,
+        name: &str,
+        parsed: &'o Object<'o>,
+        layer: &Layer<'o>,
+        later_layers: &[Layer<'o>]
+    ) -> ResolveResult<FlatObject> {
+        let compound = match *parsed {
+            Object::Compound(ref compound) if !compound.has_prototype() => compound,
+            Object::Extension(ref placement, ref compound) => return self.build_extension(
+                name, placement, compound, &layer.inherited_from, later_layers
+            ),
+
+            _ => return self.build_from_parsed_unnamed_object(parsed),
+        };
+
+        let (layers, entered) = self.merged_layers(name, compound, layer, later_layers)?;
+
+        // without inherited compounds to merge with, the value does not depend on the layers
+        if self.memo.is_some() && layers.len() == 1 {
+            return self.build_from_parsed_unnamed_object(parsed);
+        }
+
+        let object = self.build_object_from_layers(&layers)?;
+        self.exit_references(entered);
+        Ok(object)
+    }
+
+    /// the layers of a compound without a prototype, which merges with the compounds of the same name in the later layers.
+    /// returns how many references were entered
+    fn merged_layers(
+        &mut self,
+        name: &str,
+        compound: &'o Compound<'o>,
+        layer: &Layer<'o>,
+        later_layers: &[Layer<'o>]
+    ) -> ResolveResult<(Vec<Layer<'o>>, usize)> {
+        // the properties of the value are local to the value
+        let mut layers = vec![ Layer { overrides: &compound.overrides, inherited_from: Chain::default() } ];
+        let mut entered = 0;
+
+        for later_layer in later_layers {
+            let (inherited, replaces) = match later_layer.overrides.get(name) {
+                None => continue,
+                Some(Object::Compound(inherited)) => (inherited, false),
+                Some(Object::Replacement(inherited)) => (inherited, true),
+                Some(_) => break, // literals and unset properties are not merged
+            };
+
+            // only the part of the chain which leads away from the layer of the property
+            let shared = layer.inherited_from.iter().zip(&later_layer.inherited_from)
+                .take_while(|&(own, later)| own == later)
+                .count();
+
+            let inherited_from = later_layer.inherited_from.skip(shared);
+            entered += self.push_compound_layers(inherited, inherited_from, &mut layers)?;
+
+            // a compound with a prototype, or a replacement, replaces everything it inherits
+            if inherited.has_prototype() || replaces {
+                break;
+            }
+        }
+
+        Ok((layers, entered))
+    }
+
+    /// adds the list entries of the compound to the inherited list, and renumbers all entries.
+    /// the other properties of the compound override the inherited ones.
+    /// `inherited_from` is the chain of the layer that declares the extension
+    fn build_extension(
+        &mut self,
+        name: &str,
+        placement: &'o Placement<Identifier<'o>>,
+        compound: &'o Compound<'o>,
+        inherited_from: &Chain,
+        later_layers: &[Layer<'o>]
+    ) -> ResolveResult<FlatObject> {
+        let declaration = later_layers.iter().enumerate()
+            .find_map(|(index, later_layer)| later_layer.overrides.get(name).map(|object| (index, object)));
+
+        // the value the property would have without the extension
+        let (inherited, chain) = match declaration {
+            None | Some((_, &Object::Unset)) => (FlatCompound::new(), Chain::default()),
+            Some((index, object)) => {
+                let later_layer = &later_layers[index];
+                let value = self.build_property(name, object, later_layer, &later_layers[index + 1 ..])?;
+
+                let shared = inherited_from.iter().zip(&later_layer.inherited_from)
+                    .take_while(|&(own, later)| own == later)
+                    .count();
+
+                match value {
+                    FlatObject::Compound(properties) => (Arc::unwrap_or_clone(properties), later_layer.inherited_from.skip(shared)),
+                    FlatObject::StringLiteral(_) => (FlatCompound::new(), Chain::default()), // replaced, like by any other compound
+                }
+            },
+        };
+
+        let inherited_trace = self.tracing.as_mut().map(Tracing::take_nested);
+        let added = match self.build_compound(compound)? {
+            FlatObject::Compound(added) => Arc::unwrap_or_clone(added),
+
+            // a native prototype returned a string literal, which has no entries
+            FlatObject::StringLiteral(_) => {
+                let span = compound.prototypes.first().and_then(|prototype| self.scope.locate(prototype, prototype.location()));
+                let message = format!("`{}` can only be extended with a list, not with a string literal", name);
+                return Err(ResolveError::TypeError { message, span });
+            },
+        };
+        let added_trace = self.tracing.as_mut().map(Tracing::take_nested);
+
+        let anchor = placement.anchor().and_then(|anchor| self.scope.locate(anchor, anchor.location));
+        let (properties, inherited_names, added_names) = extend_list(inherited, added, placement, anchor)?;
+
+        if let (Some(tracing), Some(inherited_trace), Some(added_trace)) = (self.tracing.as_mut(), inherited_trace, added_trace) {
+            tracing.restore_nested(inherited_trace, &inherited_names, &chain);
+            tracing.restore_nested(added_trace, &added_names, &Chain::default());
+        }
+
+        Ok(self.interner.compound(properties))
+    }
+
+    fn build_compound(&mut self, compound: &'o Compound<'o>) -> ResolveResult<FlatObject> {
+        let mut layers = Vec::new();
+        let entered = self.push_compound_layers(compound, Chain::default(), &mut layers)?;
+        let object = self.build_object_from_layers(&layers)?;
+        self.exit_references(entered);
+        Ok(object)
+    }
+
+    /// compounds are memoized if requested, unless their provenance is traced
+    fn build_from_parsed_unnamed_object(
+        &mut self,
+        parsed: &'o Object<'o>
+    ) -> ResolveResult<FlatObject> {
+        let memoize = self.tracing.is_none() && parsed.as_compound().is_some();
+
+        if let (true, Some(memo)) = (memoize, self.memo.as_ref()) {
+            if let Some(object) = memo.get(&(parsed as *const Object)) {
+                return Ok(object.clone());
+            }
+        }
+
+        let object = self.build_unmemoized(parsed)?;
+
+        if let (true, Some(memo)) = (memoize, self.memo.as_mut()) {
+            memo.insert(parsed, object.clone());
+        }
+
+        Ok(object)
+    }
+
+    fn build_unmemoized(&mut self, parsed: &'o Object<'o>) -> ResolveResult<FlatObject> {
+        Ok(match *parsed {
+            Object::StringLiteral(literal) => {
+                FlatObject::StringLiteral(literal.to_string())
+            },
+
+            Object::Template(ref parts) => FlatObject::StringLiteral(self.interpolate_parts(parts)?),
+            Object::Expression(ref expression) => self.evaluate(expression)?,
+
+            // inlining of variables,
+            // needed for the special case where the prototype is a string literal
+            Object::Compound(ref compound) | Object::Replacement(ref compound) if compound.is_variable() => {
+                let origin = self.origin(&compound.prototypes[0]);
+
+                if let (Some(tracing), Some(origin)) = (self.tracing.as_mut(), origin) {
+                    tracing.inlined.push(origin);
+                }
+
+                self.build_referenced(&compound.prototypes[0])?
+            },
+
+            Object::Unset => unreachable!("unset objects are never resolved"),
+
+            // without a property to inherit from, the list only contains the added entries
+            Object::Extension(ref placement, ref compound) => self.build_extension("", placement, compound, &Chain::default(), &[])?,
+
+            // plain object with some overrides, or empty
+            Object::Compound(ref compound) | Object::Replacement(ref compound) => self.build_compound(compound)?,
+        })
+    }
+}
+
+impl<'o> Lazy<'o> {
+    pub fn new(document: &'o NamedObjects<'o>) -> Self {
+        let mut resolver = Resolver::new(Scope::document(document));
+        resolver.memo = Some(HashMap::new());
+        Lazy { document, resolver, values: HashMap::new() }
+    }
+
+    /// resolves references that the document does not declare with the objects that the host supplies
+    pub fn in_host(document: &'o NamedObjects<'o>, host: &'o Host<'o>) -> ResolveResult<Self> {
+        let mut resolver = Resolver::new(host.scope(document)?);
+        resolver.memo = Some(HashMap::new());
+        Ok(Lazy { document, resolver, values: HashMap::new() })
+    }
+
+    /// resolves only the property at the path, like `comics.spiderman.title`, and the objects it depends on.
+    /// the enclosing compounds are not resolved, only their layers are collected
+    pub fn get(&mut self, path: &str) -> ResolveResult<&FlatObject> {
+        if !self.values.contains_key(path) {
+            let names: Vec<&str> = path.split('.').collect();
+
+            // an earlier error may have left references on the stack
+            self.resolver.resolving.clear();
+
+            let mut entered = 0;
+            let value = self.resolve_path(&names, &mut entered);
+            self.resolver.exit_references(entered);
+
+            let value = value?.ok_or_else(|| ResolveError::ReferenceNotFound { identifier: path.to_string() })?;
+            self.values.insert(path.to_string(), value);
+        }
+
+        Ok(&self.values[path])
+    }
+
+    /// none if the path does not exist
+    fn resolve_path(&mut self, names: &[&str], entered: &mut usize) -> ResolveResult<Option<FlatObject>> {
+        let mut layers = self.resolver.expand_layers(&[ Layer { overrides: self.document, inherited_from: Chain::default() } ])?;
+
+        for (depth, name) in names.iter().enumerate() {
+            let declared = layers.iter().enumerate()
+                .find_map(|(index, layer)| layer.overrides.get(name).map(|object| (index, object)));
+
+            let (index, object) = match declared {
+                None | Some((_, &Object::Unset)) => return Ok(None),
+                Some(declared) => declared,
+            };
+
+            let (layer, later_layers) = (&layers[index], &layers[index + 1 ..]);
+            let rest = &names[depth + 1 ..];
+
+            let property_layers = match *object {
+                _ if rest.is_empty() => None,
+                Object::Compound(ref compound) if !compound.has_prototype() => Some(self.resolver.merged_layers(name, compound, layer, later_layers)?),
+                Object::Compound(ref compound) | Object::Replacement(ref compound) if !compound.is_variable() => {
+                    let mut property_layers = Vec::new();
+                    let count = self.resolver.push_compound_layers(compound, Chain::default(), &mut property_layers)?;
+                    Some((property_layers, count))
+                },
+
+                // extensions renumber the whole list, and variables are memoized anyway
+                _ => None,
+            };
+
+            if let Some((ref property_layers, count)) = property_layers {
+                *entered += count;
+
+                // native prototypes replace the whole object
+                let is_native = property_layers.iter().any(|layer| self.resolver.scope.native_prototype(layer.overrides).is_some());
+                if !is_native {
+                    layers = self.resolver.expand_layers(property_layers)?;
+                    continue;
+                }
+            }
+
+            let value = self.resolver.build_property(name, object, layer, later_layers)?;
+            return Ok(value.get_path(rest).cloned());
+        }
+
+        unreachable!("paths have at least one name, and the last one is always resolved")
+    }
+}
+
+impl<'o> Resolver<'o> {
+    /// the resolved object a reference inside a string literal or an expression refers to
+    fn resolve_referenced(&mut self, reference: &'o Reference<'o>) -> ResolveResult<FlatObject> {
+        // variables followed here are not where the enclosing property came from
+        let inlined = self.tracing.as_mut().map(|tracing| ::std::mem::take(&mut tracing.inlined));
+
+        // parameters are only visible in the condition itself, not in the objects it refers to
+        let parameters = self.parameters.take();
+
+        let value = self.build_referenced(reference)?;
+
+        self.parameters = parameters;
+        if let (Some(tracing), Some(inlined)) = (self.tracing.as_mut(), inlined) {
+            tracing.inlined = inlined;
+        }
+
+        Ok(value)
+    }
+
+    /// the object the reference refers to, merged with the compounds its parents inherit,
+    /// as if it was resolved as part of its parents
+    fn build_referenced(&mut self, reference: &'o Reference<'o>) -> ResolveResult<FlatObject> {
+        let key = reference as *const Reference;
+        if let (None, Some(literal)) = (self.parameters.as_ref(), self.referenced.get(&key)) {
+            return Ok(literal.clone());
+        }
+
+        let (last, parents) = reference.identifiers.split_last().expect("references are never empty");
+
+        // the parents are only looked up, not resolved, so they are not cycles, and have no provenance
+        let resolving = ::std::mem::take(&mut self.resolving);
+        let tracing = self.tracing.take();
+        let layers = if parents.is_empty() { Ok(None) } else { self.parent_layers(self.scope.root(reference), parents) };
+        self.tracing = tracing;
+        let entered = ::std::mem::replace(&mut self.resolving, resolving);
+        let layers = layers?.unwrap_or_default();
+
+        // the parents may only inherit the property, instead of declaring it
+        let target = match layers.iter().find_map(|layer| layer.overrides.get(last.name)) {
+            Some(&Object::Unset) => return Err(ResolveError::ReferenceToUnset { identifier: last.name.to_string() }),
+            Some(target) => target,
+            None => self.scope.resolve_reference(reference)?,
+        };
+
+        self.enter(reference, target)?;
+        let count = entered.len() + 1;
+        self.resolving.extend(entered);
+
+        let value = if layers.is_empty() {
+            self.build_from_parsed_unnamed_object(target)
+        } else {
+            self.build_parameter(last.name, &layers).map(|value| value.expect("the target is declared in the layers"))
+        };
+
+        self.exit_references(count);
+
+        if let (None, false, Ok(literal @ FlatObject::StringLiteral(_))) = (self.parameters.as_ref(), layers.is_empty(), value.as_ref()) {
+            self.referenced.insert(key, literal.clone());
+        }
+
+        value
+    }
+
+    /// the layers of the compound at the end of the names, none if it is not a compound that can be merged,
+    /// like an extension
+    fn parent_layers(&mut self, root: &'o NamedObjects<'o>, names: &'o [Identifier<'o>]) -> ResolveResult<Option<Vec<Layer<'o>>>> {
+        let mut layers = vec![ Layer { overrides: root, inherited_from: Chain::default() } ];
+
+        for identifier in names {
+            let declared = layers.iter().enumerate()
+                .find_map(|(index, layer)| layer.overrides.get(identifier.name).map(|object| (index, object)));
+
+            let (index, object) = match declared {
+                Some(declared) => declared,
+                None => return Ok(None),
+            };
+
+            let (layer, later_layers) = (&layers[index], &layers[index + 1 ..]);
+
+            layers = match *object {
+                Object::Compound(ref compound) if !compound.has_prototype() => self.merged_layers(identifier.name, compound, layer, later_layers)?.0,
+                Object::Compound(ref compound) | Object::Replacement(ref compound) => {
+                    let mut property_layers = Vec::new();
+                    self.push_compound_layers(compound, Chain::default(), &mut property_layers)?;
+                    property_layers
+                },
+
+                _ => return Ok(None),
+            };
+        }
+
+        Ok(Some(layers))
+    }
+
+    /// `$name`, looked up in the compound whose condition is being evaluated
+    fn resolve_parameter(&mut self, reference: &'o Reference<'o>, span: Option<Span>) -> ResolveResult<FlatObject> {
+        let layers = self.parameters.take().ok_or_else(|| ResolveError::ParameterOutsideCondition {
+            identifier: reference.to_string(), span,
+        })?;
+
+        // only the value is used, not where it came from
+        let tracing = self.tracing.take();
+        let (first, rest) = reference.identifiers.split_first().expect("parameters are never empty");
+        let value = self.build_parameter(first.name, &layers);
+
+        self.tracing = tracing;
+        self.parameters = Some(layers);
+
+        let rest: Vec<&str> = rest.iter().map(|identifier| identifier.name).collect();
+        value?.and_then(|value| value.get_path(&rest).cloned())
+            .ok_or_else(|| ResolveError::ReferenceNotFound { identifier: format!("${}", reference) })
     }
 
+    fn interpolate_parts(&mut self, parts: &'o [TemplatePart<'o>]) -> ResolveResult<String> {
+        let mut text = String::new();
 
-    /// not recursive, will not add children
-    fn fill_named_objects(
-        objects: &NamedObjects,
-        world: &NamedObjects,
-        properties: &mut FlatCompound
-    ) -> ResolveResult<()> {
-        for (override_identifier, override_index) in &objects.identifiers {
-            let name_string = override_identifier.name.to_owned();
-            if !properties.contains_key(&name_string) {
-                properties.insert(
-                    name_string,
-                    FlatObject::build_from_parsed_unnamed_object(
-                        &objects.objects[*override_index],
-                        world
-                    )?
-                );
+        for part in parts {
+            match *part {
+                TemplatePart::Text(part) => text += part,
+                TemplatePart::Reference(ref reference) => text += &self.interpolate(reference)?,
             }
         }
 
-        Ok(())
+        Ok(text)
+    }
+
+    /// the string literal that `${reference}` refers to
+    fn interpolate(&mut self, reference: &'o Reference<'o>) -> ResolveResult<String> {
+        match self.resolve_referenced(reference)? {
+            FlatObject::StringLiteral(text) => Ok(text),
+            FlatObject::Compound(_) => Err(ResolveError::InterpolatedCompound {
+                identifier: reference.to_string(),
+                span: self.scope.locate(reference, reference.location()),
+            }),
+        }
     }
 
+    /// only evaluates the operands which are needed, so that the other ones may contain errors
+    fn evaluate(&mut self, expression: &'o Expression<'o>) -> ResolveResult<FlatObject> {
+        let span = self.scope.locate(expression, Some(expression.span));
+        let located = |message| ResolveError::TypeError { message, span };
 
-    fn build_from_parsed_named_objects(
-        objects: &NamedObjects,
-        world: &NamedObjects
-    ) -> ResolveResult<FlatCompound> {
-        let mut properties = HashMap::new();
-        Self::fill_named_objects(objects, world, &mut properties)?;
-        Ok(properties)
+        match expression.operation {
+            Operation::Literal(literal) => Ok(FlatObject::StringLiteral(literal.to_string())),
+            Operation::Template(ref parts) => Ok(FlatObject::StringLiteral(self.interpolate_parts(parts)?)),
+            Operation::Reference(ref reference) => self.resolve_referenced(reference),
+            Operation::Parameter(ref reference) => self.resolve_parameter(reference, span),
+
+            Operation::Unary(operator, ref operand) => {
+                let operand = self.evaluate(operand)?;
+                ::evaluate::unary(operator, &operand).map_err(located)
+            },
+
+            Operation::Binary(operator @ BinaryOperator::And, ref left, ref right) |
+            Operation::Binary(operator @ BinaryOperator::Or, ref left, ref right) => {
+                let left_value = self.evaluate_truth(left)?;
+
+                // the right operand decides, unless the left one already did
+                if left_value == (operator == BinaryOperator::Or) {
+                    Ok(::evaluate::boolean(left_value))
+                } else {
+                    Ok(::evaluate::boolean(self.evaluate_truth(right)?))
+                }
+            },
+
+            Operation::Binary(operator, ref left, ref right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                ::evaluate::binary(operator, &left, &right).map_err(located)
+            },
+
+            Operation::Conditional(ref condition, ref then, ref otherwise) => {
+                if self.evaluate_truth(condition)? { self.evaluate(then) } else { self.evaluate(otherwise) }
+            },
+
+            Operation::Call(function, ref arguments) => {
+                let arguments = arguments.iter()
+                    .map(|argument| self.evaluate(argument))
+                    .collect::<ResolveResult<Vec<FlatObject>>>()?;
+
+                match self.scope.native_function(function).filter(|_| !::evaluate::is_builtin(function)) {
+                    None => ::evaluate::call(function, &arguments).map_err(located),
+                    Some(native) => native(&arguments).map_err(|message| ResolveError::NativeError {
+                        name: function.to_string(), message, span,
+                    }),
+                }
+            },
+        }
+    }
+
+    fn evaluate_truth(&mut self, expression: &'o Expression<'o>) -> ResolveResult<bool> {
+        let value = self.evaluate(expression)?;
+        let span = self.scope.locate(expression, Some(expression.span));
+        ::evaluate::truth(&value).map_err(|message| ResolveError::TypeError { message, span })
     }
+}
 
+/// the new names of list entries, by their old names
+type Renamed = HashMap<String, String>;
 
-    fn deep_fill_parsed_compound(
-        compound: &Compound,
-        world: &NamedObjects,
-        properties: &mut FlatCompound
-    ) -> ResolveResult<()> {
-        Self::fill_named_objects(&compound.overrides, world, properties)?;
+/// combines the properties, see `::list`.
+/// also returns the new names of the inherited and of the added list entries, by their old names
+/// orders the resolved properties of the layers like they are declared, where properties declared by prototypes come first
+fn in_declaration_order(layers: &[Layer], mut properties: HashMap<String, FlatObject>) -> FlatCompound {
+    let mut ordered = FlatCompound::with_capacity(properties.len());
 
-        if compound.prototype.has_target() {
-            // insert all inherited properties, if not already overridden
-            if let Object::Compound(ref compound) = *world.resolve_reference(&compound.prototype)? {
-                Self::deep_fill_parsed_compound(compound, world, properties)?;
+    for layer in layers.iter().rev() {
+        for (name, _, _) in layer.overrides.in_declaration_order() {
+            if let Some(value) = properties.remove(name.as_ref()) {
+                ordered.insert(name.into_owned(), value);
             }
         }
+    }
 
-        Ok(())
+    ordered
+}
+
+fn extend_list(
+    mut inherited: FlatCompound,
+    mut added: FlatCompound,
+    placement: &Placement<Identifier>,
+    anchor_span: Option<Span>
+) -> ResolveResult<(FlatCompound, Renamed, Renamed)> {
+    let order: Vec<(String, bool)> = {
+        let inherited_entries = ::list::entries(inherited.keys().map(String::as_str));
+        let added_entries = ::list::entries(added.keys().map(String::as_str));
+
+        let order = ::list::extend(&inherited_entries, &added_entries, &placement.map(|anchor| anchor.name))
+            .ok_or_else(|| {
+                let anchor = placement.anchor().expect("only anchors can be missing").name;
+                ResolveError::ListEntryNotFound { identifier: anchor.to_string(), span: anchor_span }
+            })?;
+
+        order.into_iter().map(|(name, is_added)| (name.to_string(), is_added)).collect()
+    };
+
+    let mut entries = Vec::with_capacity(order.len());
+    let mut inherited_names = HashMap::new();
+    let mut added_names = HashMap::new();
+
+    for (position, (name, is_added)) in order.into_iter().enumerate() {
+        let (list, names) = if is_added { (&mut added, &mut added_names) } else { (&mut inherited, &mut inherited_names) };
+        entries.push((position.to_string(), list.remove(&name).expect("entries are properties")));
+        names.insert(name, position.to_string());
     }
 
-    fn build_from_parsed_unnamed_object(
-        parsed: &Object,
-        world: &NamedObjects
-    ) -> ResolveResult<FlatObject> {
-        Ok(match *parsed {
-            Object::StringLiteral(ref literal) => {
-                FlatObject::StringLiteral(literal.to_string())
-            },
+    // only properties which are not list entries are left
+    inherited.extend(added);
+    inherited.extend(entries);
+    Ok((inherited, inherited_names, added_names))
+}
+
+impl FlatObject {
+    fn write_indented(&self, formatter: &mut ::std::fmt::Formatter, indentation: usize) -> ::std::fmt::Result {
+        match *self {
+            FlatObject::StringLiteral(ref literal) => write!(formatter, "'{}'", literal),
+            FlatObject::Compound(ref properties) => {
+                if properties.is_empty() {
+                    return formatter.write_str("{}");
+                }
 
-            Object::Compound(ref compound) => {
-                // inlining of variables,
-                // needed for the special case where the prototype is a string literal
-                if compound.overrides.objects.is_empty() && compound.prototype.has_target() {
-                    let prototype = world.resolve_reference(&compound.prototype)?;
-                    Self::build_from_parsed_unnamed_object(prototype, world)?
-
-                } else { // plain object with some overrides, or empty
-                    FlatObject::Compound({
-                        let mut properties = HashMap::new();
-                        Self::deep_fill_parsed_compound(compound, world, &mut properties)?;
-                        properties
-                    })
+                formatter.write_str("{\n")?;
+                for (name, property) in properties.iter() {
+                    write!(formatter, "{:indent$}{}: ", "", name, indent = (indentation + 1) * 4)?;
+                    property.write_indented(formatter, indentation + 1)?;
+                    formatter.write_str("\n")?;
                 }
+
+                write!(formatter, "{:indent$}}}", "", indent = indentation * 4)
             }
-        })
+        }
+    }
+}
+
+impl PartialEq for FlatObject {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (FlatObject::StringLiteral(text), FlatObject::StringLiteral(other)) => text == other,
+            (FlatObject::Compound(properties), FlatObject::Compound(other)) => Arc::ptr_eq(properties, other) || properties == other,
+            _ => false,
+        }
+    }
+}
+
+/// prints the object in proto-template syntax
+impl ::std::fmt::Display for FlatObject {
+    fn fmt(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        self.write_indented(formatter, 0)
     }
 }
 
@@ -110,7 +1296,7 @@ mod test {
     }
 
     fn compound(properties: Vec<(&str, FlatObject)>) -> FlatObject {
-        FlatObject::Compound({
+        FlatObject::compound({
             properties.into_iter()
                 .map(|(name, obj)| (String::from(name), obj))
                 .collect()
@@ -221,4 +1407,691 @@ mod test {
 
         // TODO test resolve-errors and parse-errors
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_cyclic_reference(){
+        assert_eq!(
+            FlatObject::parse(r#"
+                a: b
+                b: a
+            "#).expect("Parsing Error"),
+
+            Err(ResolveError::CyclicReference {
+                identifier: String::from("b"),
+            })
+        );
+
+        assert_eq!(
+            FlatObject::parse(r#"
+                Node: { child: Node }
+            "#).expect("Parsing Error"),
+
+            Err(ResolveError::CyclicReference {
+                identifier: String::from("Node"),
+            })
+        );
+
+        // the same prototype may be used several times, as long as it does not contain itself
+        assert!(FlatObject::parse(r#"
+            Color: { r: '0' }
+            theme: { primary: Color { r: '1' } secondary: Color }
+        "#).expect("Parsing Error").is_ok());
+    }
+
+    #[test]
+    fn test_deep_merge(){
+        let comic = r#"
+            Person: { name: 'unknown' age: 'unknown' }
+
+            Comic: {
+                title: 'Untitled'
+                author: { name: 'unknown' age: 'unknown' address: { city: 'unknown' } }
+            }
+        "#;
+
+        let resolved = FlatObject::parse(&format!("{} {}", comic, r#"
+            stan: Comic { author: { name: 'Stan' address: { street: 'Main' } } }
+            kirby: Comic { author: !replace { name: 'Jack' } }
+            ditko: Comic { author: Person { name: 'Steve' } }
+            anonymous: Comic { author: 'anonymous' }
+        "#)).expect("Parsing Error").expect("Resolve Error");
+
+        assert_eq!(
+            resolved.get_path(&["stan", "author"]),
+            Some(&compound(vec![
+                ("name", literal("Stan")),
+                ("age", literal("unknown")),
+                ("address", compound(vec![
+                    ("city", literal("unknown")),
+                    ("street", literal("Main")),
+                ])),
+            ]))
+        );
+
+        assert_eq!(resolved.get_path(&["kirby", "author"]), Some(&compound(vec![ ("name", literal("Jack")) ])));
+
+        // a compound with its own prototype does not merge with the inherited compound
+        assert_eq!(
+            resolved.get_path(&["ditko", "author"]),
+            Some(&compound(vec![ ("name", literal("Steve")), ("age", literal("unknown")) ]))
+        );
+
+        assert_eq!(resolved.get_path(&["anonymous", "author"]), Some(&literal("anonymous")));
+
+        // merging continues through the whole prototype chain
+        let resolved = FlatObject::parse(&format!("{} {}", comic, r#"
+            Special: Comic { author: { age: '42' } }
+            special: Special { author: { name: 'Stan' } }
+        "#)).expect("Parsing Error").expect("Resolve Error");
+
+        assert_eq!(
+            resolved.get_path(&["special", "author"]),
+            Some(&compound(vec![
+                ("name", literal("Stan")),
+                ("age", literal("42")),
+                ("address", compound(vec![ ("city", literal("unknown")) ])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_reference_to_merged_property(){
+        let parsed = ::parse::parse(r#"
+            Comic: { author: { name: 'x' age: '1' } }
+            spiderman: Comic { author: { name: 'Stan' } }
+            v: spiderman.author
+            age: '${spiderman.author.age}'
+            copy: Comic { author: { name: 'Steve' } again: copy.author }
+            variable: Comic
+            name: variable.author.name
+        "#).expect("Parsing Error");
+
+        let resolved = FlatObject::build_from_parsed(&parsed).expect("Resolve Error");
+        let author = compound(vec![ ("name", literal("Stan")), ("age", literal("1")) ]);
+
+        assert_eq!(resolved.get_path(&["spiderman", "author"]), Some(&author));
+        assert_eq!(resolved.get_path(&["v"]), Some(&author));
+        assert_eq!(resolved.get_path(&["age"]), Some(&literal("1")));
+        assert_eq!(resolved.get_path(&["copy", "again"]), resolved.get_path(&["copy", "author"]));
+        assert_eq!(resolved.get_path(&["name"]), Some(&literal("x")));
+
+        let reference = Reference { identifiers: vec![ Identifier::new("spiderman"), Identifier::new("author") ] };
+        assert_eq!(FlatObject::resolve(&parsed, &reference), Ok(author));
+    }
+
+    #[test]
+    fn test_inherited_replacement(){
+        let resolved = FlatObject::parse(r#"
+            Comic: { author: { name: 'unknown' age: 'unknown' } }
+            Special: Comic { author: !replace { name: 'Stan' } }
+            s: Special { author: { age: '2' } }
+        "#).expect("Parsing Error").expect("Resolve Error");
+
+        assert_eq!(
+            resolved.get_path(&["s", "author"]),
+            Some(&compound(vec![ ("name", literal("Stan")), ("age", literal("2")) ]))
+        );
+
+        let resolved = FlatObject::parse("ok: 'fine' Comic: { x: { a: 'b' } } c: Comic { x: !replace ok }")
+            .expect("Parsing Error").expect("Resolve Error");
+
+        assert_eq!(resolved.get_path(&["c", "x"]), Some(&literal("fine")));
+    }
+
+    #[test]
+    fn test_deep_merge_provenance(){
+        let source = r#"
+            Comic: { author: { name: 'unknown' age: 'unknown' } }
+            stan: Comic { author: { name: 'Stan' } }
+        "#;
+
+        let (_, trace) = FlatObject::parse_traced(source)
+            .expect("Parsing Error").expect("Resolve Error");
+
+        assert!(trace.get(&["stan", "author", "name"]).expect("no provenance").is_local());
+
+        let age = trace.get(&["stan", "author", "age"]).expect("no provenance");
+        assert_eq!(age.inherited_from.len(), 1);
+        assert_eq!(age.inherited_from.first().unwrap().name, "Comic");
+        assert_eq!(age.defined_at.span().map(|span| span.start), source.find("age"));
+    }
+
+    #[test]
+    fn test_unset(){
+        let source = r#"
+            Comic: { title: 'Untitled' published: 'true' author: { name: 'unknown' age: 'unknown' } }
+            Draft: Comic { published: !unset author: { age: !unset } }
+            draft: Draft { title: 'Draft' }
+            republished: Draft { published: 'again' }
+            unset_variable: !unset
+        "#;
+
+        let (resolved, trace) = FlatObject::parse_traced(source)
+            .expect("Parsing Error").expect("Resolve Error");
+
+        assert_eq!(
+            resolved.get_path(&["draft"]),
+            Some(&compound(vec![
+                ("title", literal("Draft")),
+                ("author", compound(vec![ ("name", literal("unknown")) ])),
+            ]))
+        );
+
+        assert_eq!(resolved.get_path(&["republished", "published"]), Some(&literal("again")));
+        assert_eq!(resolved.get_path(&["unset_variable"]), None);
+
+        assert!(trace.is_unset(&["draft", "published"]));
+        assert!(trace.is_unset(&["draft", "author", "age"]));
+        assert!(!trace.is_unset(&["draft", "missing"]));
+        assert!(!trace.is_unset(&["republished", "published"]));
+        assert_eq!(trace.unset[&vec![String::from("draft"), String::from("published")]].inherited_from.first().unwrap().name, "Draft");
+
+        assert_eq!(
+            FlatObject::parse("a: !unset b: a").expect("Parsing Error"),
+            Err(ResolveError::ReferenceToUnset { identifier: String::from("a") })
+        );
+    }
+
+    #[test]
+    fn test_multiple_prototypes(){
+        let source = r#"
+            Element: { id: 'element' tag: 'div' style: { color: 'black' } }
+            Clickable: Element { on_click: 'click' tag: 'button' }
+            Styled: Element { style: { font: 'serif' } class: 'styled' tag: 'span' }
+            Button: Clickable + Styled { id: 'button' }
+        "#;
+
+        let (resolved, trace) = FlatObject::parse_traced(source).expect("Parsing Error").expect("Resolve Error");
+
+        // Button, Clickable, Styled, Element
+        assert_eq!(
+            resolved.get_path(&["Button"]),
+            Some(&compound(vec![
+                ("id", literal("button")),
+                ("tag", literal("button")),
+                ("on_click", literal("click")),
+                ("class", literal("styled")),
+                ("style", compound(vec![ ("font", literal("serif")), ("color", literal("black")) ])),
+            ]))
+        );
+
+        // `Clickable` overrides `Element`, so only `tag` is ambiguous
+        let conflicts: Vec<(Vec<String>, &str, &str)> = trace.conflicts.iter()
+            .map(|conflict| (conflict.path.clone(), conflict.chosen.name.as_str(), conflict.ignored.name.as_str()))
+            .collect();
+
+        assert_eq!(conflicts, vec![ (vec![ String::from("Button"), String::from("tag") ], "Clickable", "Styled") ]);
+
+        assert_eq!(
+            FlatObject::parse("X: {} Y: {} A: X + Y {} B: Y + X {} C: A + B {}").expect("Parsing Error"),
+            Err(ResolveError::InconsistentPrototypeOrder { prototypes: String::from("A + B") })
+        );
+
+        assert_eq!(
+            FlatObject::parse("A: B + A {} B: {}").expect("Parsing Error"),
+            Err(ResolveError::CyclicReference { identifier: String::from("A") })
+        );
+    }
+
+    #[test]
+    fn test_diamonds(){
+        // every level inherits the previous one twice, which takes exponential time without caching linearizations
+        let mut source = String::from("L0: { v: '0' }\n");
+        for level in 1 ..= 40 {
+            source += &format!("A{1}: L{0} {{ a: '{1}' }} B{1}: L{0} {{ b: '{1}' }} L{1}: A{1} + B{1}\n", level - 1, level);
+        }
+
+        let expected = compound(vec![ ("a", literal("40")), ("b", literal("40")), ("v", literal("0")) ]);
+        let resolved = FlatObject::parse(&source).unwrap().unwrap();
+        assert_eq!(resolved.get_path(&["L40"]), Some(&expected));
+
+        let (traced, _) = FlatObject::parse_traced(&source).unwrap().unwrap();
+        assert_eq!(traced.get_path(&["L40"]), Some(&expected));
+    }
+
+    #[test]
+    fn test_interpolation(){
+        let source = r#"
+            persons: { peter: { real_name: 'Peter Parker' } }
+            name: persons.peter.real_name
+            greeting: 'Hello ${persons.peter.real_name}!'
+            nested: { text: '${greeting} (${ name }) costs \${price}' }
+        "#;
+
+        let resolved = FlatObject::parse(source).expect("Parsing Error").expect("Resolve Error");
+        assert_eq!(resolved.get_path(&["greeting"]), Some(&literal("Hello Peter Parker!")));
+        assert_eq!(resolved.get_path(&["nested", "text"]), Some(&literal("Hello Peter Parker! (Peter Parker) costs ${price}")));
+
+        let compound_source = "persons: { peter: { } }\ngreeting: 'Hello ${persons.peter}!'";
+        let error = FlatObject::parse(compound_source).expect("Parsing Error").unwrap_err();
+        assert_eq!(error.to_string(), "`persons.peter` is not a string literal and cannot be inserted into one");
+        let reference_start = compound_source.find("persons.peter}").unwrap();
+        assert_eq!(error.span(), Some(Span { start: reference_start, end: reference_start + 13 }));
+
+        assert_eq!(
+            FlatObject::parse("a: '${a}'").expect("Parsing Error"),
+            Err(ResolveError::CyclicReference { identifier: String::from("a") })
+        );
+    }
+
+    #[test]
+    fn test_expressions(){
+        let source = r#"
+            size: { width: '40' height: '30' }
+            name: 'peter'
+            theme: 'dark'
+            tags: { 0: 'a' 1: 'b' }
+
+            area: (size.width * size.height)
+            half: (size.width / 2 - 0.5)
+            title: (upper(name) ~ ' (' ~ len(name) ~ ')')
+            wide: (size.width > size.height && !(theme == 'light'))
+            background: (theme == 'dark' ? 'black' : missing.reference)
+            tag_list: (join(tags, ', '))
+            greeting: ('Hi ${name}' ~ '!')
+        "#;
+
+        let resolved = FlatObject::parse(source).expect("Parsing Error").expect("Resolve Error");
+        assert_eq!(resolved.get_path(&["area"]), Some(&literal("1200")));
+        assert_eq!(resolved.get_path(&["half"]), Some(&literal("19.5")));
+        assert_eq!(resolved.get_path(&["title"]), Some(&literal("PETER (5)")));
+        assert_eq!(resolved.get_path(&["wide"]), Some(&literal("true")));
+        assert_eq!(resolved.get_path(&["background"]), Some(&literal("black")));
+        assert_eq!(resolved.get_path(&["tag_list"]), Some(&literal("a, b")));
+        assert_eq!(resolved.get_path(&["greeting"]), Some(&literal("Hi peter!")));
+
+        let invalid = "name: 'peter'\nsize: ('2' * name)";
+        let error = FlatObject::parse(invalid).expect("Parsing Error").unwrap_err();
+        assert_eq!(error.to_string(), "expected a number, found `'peter'`");
+        assert_eq!(error.span().map(|span| &invalid[span.start .. span.end]), Some("'2' * name"));
+
+        assert_eq!(
+            FlatObject::parse("a: (a ~ 'x')").expect("Parsing Error"),
+            Err(ResolveError::CyclicReference { identifier: String::from("a") })
+        );
+    }
+
+    #[test]
+    fn test_list_extension(){
+        let source = r#"
+            Page: { nav: { : 'home' : 'blog' title: 'Menu' } }
+            About: Page { nav += { : 'about' } }
+            Contact: About { nav += { : 'contact' title: 'Contact' } }
+            Landing: Page { nav: !prepend { : 'welcome' } }
+            Archive: Page { nav: !insert_after 0 { : 'archive' : 'tags' } }
+            Plain: Page { nav: { : 'start' } }
+            Sparse: { nav: { 3: 'c' 10: 'd' } }
+            Inserted: Sparse { nav: !insert_before 10 { : 'x' } }
+            links: { : 'a' }
+            Linked: Page { nav += links }
+            Fresh: { nav += { : 'only' } }
+        "#;
+
+        let (resolved, trace) = FlatObject::parse_traced(source).expect("Parsing Error").expect("Resolve Error");
+        let list = |entries: Vec<&str>| entries.into_iter().enumerate()
+            .map(|(index, entry)| (index.to_string(), literal(entry)))
+            .collect::<Vec<_>>();
+
+        let nav = |path: &str, entries: Vec<&str>, title: Option<&str>| {
+            let mut properties = list(entries);
+            properties.extend(title.map(|title| (String::from("title"), literal(title))));
+            assert_eq!(resolved.get_path(&[path, "nav"]), Some(&FlatObject::compound(properties.into_iter().collect())), "{}", path);
+        };
+
+        nav("About", vec![ "home", "blog", "about" ], Some("Menu"));
+        nav("Contact", vec![ "home", "blog", "about", "contact" ], Some("Contact"));
+        nav("Landing", vec![ "welcome", "home", "blog" ], Some("Menu"));
+        nav("Archive", vec![ "home", "archive", "tags", "blog" ], Some("Menu"));
+        nav("Plain", vec![ "start", "blog" ], Some("Menu"));
+        nav("Inserted", vec![ "c", "x", "d" ], None);
+        nav("Linked", vec![ "home", "blog", "a" ], Some("Menu"));
+        nav("Fresh", vec![ "only" ], None);
+
+        // provenance follows the renumbered entries
+        assert_eq!(trace.get(&["Landing", "nav", "1"]).unwrap().inherited_from.first().unwrap().name, "Page");
+        assert!(trace.get(&["Landing", "nav", "0"]).unwrap().is_local());
+        assert_eq!(trace.get(&["Contact", "nav", "2"]).unwrap().inherited_from.first().unwrap().name, "About");
+
+        let missing = "Page: { nav: { : 'home' } }\nAbout: Page { nav: !insert_after 4 { : 'about' } }";
+        let error = FlatObject::parse(missing).expect("Parsing Error").unwrap_err();
+        assert_eq!(error.to_string(), "the inherited list has no entry `4`");
+        assert_eq!(error.span().map(|span| &missing[span.start .. span.end]), Some("4"));
+
+        // the second unnamed entry would be `1`, which is also declared explicitly
+        let duplicate = "tags: { : 'a' 1: 'b' : 'c' }";
+        let error = FlatObject::parse(duplicate).expect("Parsing Error").unwrap_err();
+        assert_eq!(error, ResolveError::DuplicateIndex { index: 1, span: Some(Span { start: 21, end: 21 }) });
+        assert_eq!(error.to_string(), "the unnamed entry has the index 1, which is already declared explicitly");
+    }
+
+    #[test]
+    fn test_conditional(){
+        let source = r#"
+            dark_theme: 'dark'
+            Button: {
+                theme: 'light'
+                background: 'white'
+                if $theme == dark_theme { background: 'black' border: 'none' }
+                if $theme == 'dark' && $size.large { background: 'gray' }
+                size: { large: 'false' }
+                label if $size.large: 'Big'
+            }
+
+            dark: Button { theme: 'dark' }
+            large: dark { size: { large: 'true' } }
+            fixed: dark { background: 'red' }
+        "#;
+
+        let resolved = FlatObject::parse(source).expect("Parsing Error").expect("Resolve Error");
+        let get = |path: &[&str]| resolved.get_path(path).cloned();
+
+        assert_eq!(get(&["Button", "background"]), Some(literal("white")));
+        assert_eq!(get(&["Button", "border"]), None);
+        assert_eq!(get(&["dark", "background"]), Some(literal("black")));
+        assert_eq!(get(&["dark", "border"]), Some(literal("none")));
+        assert_eq!(get(&["dark", "label"]), None);
+
+        // the later conditional wins, and an override wins over the inherited conditionals
+        assert_eq!(get(&["large", "background"]), Some(literal("gray")));
+        assert_eq!(get(&["large", "label"]), Some(literal("Big")));
+        assert_eq!(get(&["fixed", "background"]), Some(literal("red")));
+        assert_eq!(get(&["fixed", "border"]), Some(literal("none")));
+
+        let outside = "Button: { theme: 'dark' color: ($theme) }";
+        let error = FlatObject::parse(outside).expect("Parsing Error").unwrap_err();
+        assert_eq!(error.to_string(), "`$theme` can only be used in conditions");
+
+        let missing = FlatObject::parse("Button: { if $theme { } }").expect("Parsing Error");
+        assert_eq!(missing, Err(ResolveError::ReferenceNotFound { identifier: String::from("$theme") }));
+    }
+
+    #[test]
+    fn test_variables(){
+        let variables: HashMap<String, String> = vec![
+            (String::from("TARGET"), String::from("prod")),
+            (String::from("PORT"), String::from("80")),
+        ].into_iter().collect();
+
+        let source = r#"
+            Server: { target: env.TARGET if $target == 'prod' { replicas: '3' } }
+            server: Server { port: (env.PORT + 1) }
+            all: env
+        "#;
+
+        let resolved = FlatObject::parse_with(source, &variables).expect("Parsing Error").expect("Resolve Error");
+        assert_eq!(resolved.get_path(&["server", "target"]), Some(&literal("prod")));
+        assert_eq!(resolved.get_path(&["server", "replicas"]), Some(&literal("3")));
+        assert_eq!(resolved.get_path(&["server", "port"]), Some(&literal("81")));
+        assert_eq!(resolved.get_path(&["all", "PORT"]), Some(&literal("80")));
+        assert_eq!(resolved.get_path(&["env"]), None);
+
+        let parsed = ::parse::parse(source).unwrap();
+        let (_, trace) = FlatObject::build_traced_in(&parsed, Host::default().variables(&variables)).expect("Resolve Error");
+        assert!(trace.get(&["server", "target"]).unwrap().inlined_from.is_empty());
+
+        let missing = FlatObject::parse_with("a: env.USER", &variables).expect("Parsing Error");
+        assert_eq!(missing, Err(ResolveError::MissingVariable { name: String::from("USER") }));
+
+        let reserved = "a: 'x'\nenv: { }";
+        let error = FlatObject::parse_with(reserved, &variables).expect("Parsing Error").unwrap_err();
+        assert_eq!(error.span().map(|span| span.start), Some(7));
+        assert!(FlatObject::parse(reserved).expect("Parsing Error").is_ok());
+    }
+
+    #[test]
+    fn test_prelude(){
+        let prelude = ::parse::parse("Element: { tag: 'div' } Body: Element { tag: 'body' title: ('PTL ' ~ Heading.tag) }").unwrap();
+        let heading = FlatObject::compound(vec![ (String::from("tag"), literal("h1")) ].into_iter().collect());
+
+        let mut host = Host::default();
+        host.prelude(&prelude).object("Heading", &heading);
+
+        let source = "Element: { tag: 'section' }\nbody: Body { header: Heading { } }";
+        let parsed = ::parse::parse(source).unwrap();
+        let resolved = FlatObject::build_in(&parsed, &host).expect("Resolve Error");
+
+        // the document shadows prelude objects, but only declared objects are part of the result
+        assert_eq!(resolved.get_path(&["body", "tag"]), Some(&literal("body")));
+        assert_eq!(resolved.get_path(&["Element", "tag"]), Some(&literal("section")));
+        assert_eq!(resolved.get_path(&["body", "title"]), Some(&literal("PTL h1")));
+        assert_eq!(resolved.get_path(&["body", "header", "tag"]), Some(&literal("h1")));
+        assert_eq!(resolved.get_path(&["Body"]), None);
+
+        assert_eq!(FlatObject::prelude_usage(&parsed, &host), Ok(vec![ String::from("Body"), String::from("Element"), String::from("Heading") ]));
+        assert_eq!(FlatObject::build_from_parsed(&parsed), Err(ResolveError::ReferenceNotFound { identifier: String::from("Body") }));
+
+        // properties declared in the prelude are recorded without their span, those of native objects not at all
+        let (_, trace) = FlatObject::build_traced_in(&parsed, &host).expect("Resolve Error");
+        assert!(trace.get(&["body", "header"]).is_some());
+        assert!(trace.get(&["body", "header", "tag"]).is_none());
+
+        let tag = trace.get(&["body", "tag"]).expect("the tag is declared in the prelude");
+        assert_eq!(tag.defined_at, Location::Prelude);
+        assert_eq!(tag.inherited_from.iter().collect::<Vec<_>>(), vec![ &Origin { name: String::from("Body"), location: Location::Prelude } ]);
+
+        // prelude objects only see the prelude, even if the document declares the same name
+        let prelude = ::parse::parse("Element: { tag: 'div' } Body: Element { title: 'x' }").unwrap();
+        let mut host = Host::default();
+        host.prelude(&prelude);
+
+        let parsed = ::parse::parse("Element: { color: 'red' } body: Body { }").unwrap();
+        let resolved = FlatObject::build_in(&parsed, &host).expect("Resolve Error");
+        assert_eq!(resolved.get_path(&["body"]), Some(&compound(vec![ ("tag", literal("div")), ("title", literal("x")) ])));
+
+        let prelude = ::parse::parse("Base: { a: 'x' b: 'y' } Derived: Base { a: !unset }").unwrap();
+        let mut host = Host::default();
+        host.prelude(&prelude);
+
+        let source = "d: Derived { }";
+        let parsed = ::parse::parse(source).unwrap();
+        let (resolved, trace) = FlatObject::build_traced_in(&parsed, &host).expect("Resolve Error");
+        assert_eq!(resolved.get_path(&["d", "a"]), None);
+        assert_eq!(trace.unset.get(&vec![ String::from("d"), String::from("a") ]).map(|unset| unset.defined_at), Some(Location::Prelude));
+
+        // errors inside the prelude cannot be located in the document
+        let prelude = ::parse::parse("Bad: { x: ('a' * 2) y: 'in ${Base}' } Base: { }").unwrap();
+        let mut host = Host::default();
+        host.prelude(&prelude);
+
+        for &(source, span) in &[ ("b: Bad { y: 'z' }", None), ("c: Bad { x: 'z' }", None), ("d: Bad { x: 'z' y: 'z' w: (1 * 'v') }", Some(27)) ] {
+            let parsed = ::parse::parse(source).unwrap();
+            let error = FlatObject::build_in(&parsed, &host).unwrap_err();
+            assert_eq!(error.span().map(|span| span.start), span, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_natives(){
+        let files: HashMap<&str, &str> = vec![ ("greeting.txt", "Hello") ].into_iter().collect();
+        let counter = AtomicUsize::new(0);
+
+        let mut host = Host::default();
+        host.function("uuid", |_| {
+            let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(literal(&format!("id-{}", count)))
+        });
+
+        host.function("read_file", |arguments| match arguments {
+            [ FlatObject::StringLiteral(path) ] => files.get(path.as_str()).map(|text| literal(text))
+                .ok_or_else(|| format!("cannot read `{}`", path)),
+
+            _ => Err(String::from("expected a path")),
+        });
+
+        host.function("len", |_| Ok(literal("native")));
+
+        host.prototype("Rgb", |properties| {
+            let channel = |name: &str| match properties.get(name) {
+                Some(FlatObject::StringLiteral(value)) => value.parse::<u8>().map_err(|_| format!("`{}` is not a channel", value)),
+                _ => Err(format!("missing `{}`", name)),
+            };
+
+            Ok(literal(&format!("#{:02x}{:02x}{:02x}", channel("r")?, channel("g")?, channel("b")?)))
+        });
+
+        let source = r#"
+            Red: Rgb { r: '255' g: '0' b: '0' }
+            colors: { red: Red { } dark_red: Red { r: ('100' + 28) } }
+            ids: { a: (uuid()) b: (uuid()) }
+            text: (read_file('greeting.txt') ~ ' ' ~ len('ab'))
+        "#;
+
+        let parsed = ::parse::parse(source).unwrap();
+        let resolved = FlatObject::build_in(&parsed, &host).expect("Resolve Error");
+
+        assert_eq!(resolved.get_path(&["Red"]), Some(&literal("#ff0000")));
+        assert_eq!(resolved.get_path(&["colors", "dark_red"]), Some(&literal("#800000")));
+        assert_ne!(resolved.get_path(&["ids", "a"]), resolved.get_path(&["ids", "b"]));
+        assert_eq!(resolved.get_path(&["text"]), Some(&literal("Hello 2")));
+
+        let invalid = "color: Rgb { r: '300' g: '0' b: '0' }";
+        let parsed = ::parse::parse(invalid).unwrap();
+        assert_eq!(FlatObject::build_in(&parsed, &host).unwrap_err().to_string(), "`Rgb` failed: `300` is not a channel");
+
+        let missing = "text: (read_file('missing.txt'))";
+        let parsed = ::parse::parse(missing).unwrap();
+        let error = FlatObject::build_in(&parsed, &host).unwrap_err();
+        assert_eq!(error.to_string(), "`read_file` failed: cannot read `missing.txt`");
+        assert_eq!(error.span().map(|span| &missing[span.start .. span.end]), Some("read_file('missing.txt')"));
+
+        let extended = "Palette: { colors: { : 'red' } } mine: Palette { colors += Rgb { r: '1' g: '2' b: '3' } }";
+        let parsed = ::parse::parse(extended).unwrap();
+        let error = FlatObject::build_in(&parsed, &host).unwrap_err();
+        assert_eq!(error.to_string(), "`colors` can only be extended with a list, not with a string literal");
+        assert_eq!(error.span().map(|span| &extended[span.start .. span.end]), Some("Rgb"));
+
+        // the document can shadow native prototypes
+        let shadowed = ::parse::parse("Rgb: { } color: Rgb { r: '1' }").unwrap();
+        let resolved = FlatObject::build_in(&shadowed, &host).expect("Resolve Error");
+        assert_eq!(resolved.get_path(&["color", "r"]), Some(&literal("1")));
+    }
+
+    #[test]
+    fn test_resolve_and_display(){
+        let parsed = ::parse::parse(r#"
+            Button: { visible: 'true' text: 'Click Here' }
+            ok_button: Button { text: 'Ok' }
+        "#).expect("Parsing Error");
+
+        let reference = Reference { identifiers: vec![ Identifier::new("ok_button") ] };
+        let resolved = FlatObject::resolve(&parsed, &reference).expect("Resolve Error");
+
+        assert_eq!(resolved.get_path(&["text"]), Some(&literal("Ok")));
+        assert_eq!(resolved.get_path(&["text", "x"]), None);
+        assert_eq!(resolved.to_string(), "{\n    visible: 'true'\n    text: 'Ok'\n}");
+    }
+
+    #[test]
+    fn test_lazy(){
+        let sources = [
+            include_str!("../assets/example.ptl"),
+            "
+                dark_theme: 'dark'
+                Button: { theme: 'light' if $theme == dark_theme { background: 'black' } size: { large: 'false' } }
+                dark: Button { theme: 'dark' size: { large: 'true' } }
+                list: { : 'a' } more: list { += { : 'b' } } copy: dark.size
+                label: 'size ${dark.size.large}' Replaced: dark { size: !replace { small: 'true' } }
+            ",
+        ];
+
+        for source in &sources {
+            let parsed = parse(source).unwrap();
+            let resolved = FlatObject::build_from_parsed(&parsed).unwrap();
+            let mut lazy = Lazy::new(&parsed);
+
+            let mut paths = Vec::new();
+            collect_paths(&resolved, &mut Vec::new(), &mut paths);
+
+            for path in paths {
+                let names: Vec<&str> = path.split('.').collect();
+                assert_eq!(lazy.get(&path).ok(), resolved.get_path(&names), "{}", path);
+            }
+        }
+
+        let parsed = parse("a: { b: 'c' } d: a { e: (f) }").unwrap();
+        let mut lazy = Lazy::new(&parsed);
+        assert_eq!(lazy.get("a.x"), Err(ResolveError::ReferenceNotFound { identifier: String::from("a.x") }));
+        assert_eq!(lazy.get("d.e"), Err(ResolveError::ReferenceNotFound { identifier: String::from("f") }));
+        assert_eq!(lazy.get("d.b"), Ok(&literal("c")));
+    }
+
+    #[test]
+    fn test_lazy_memoizes(){
+        let calls = AtomicUsize::new(0);
+        let mut host = Host::default();
+        host.prototype("Counted", |_: &FlatCompound| { calls.fetch_add(1, Ordering::SeqCst); Ok(literal("counted")) });
+
+        let parsed = parse("shared: Counted { } x: shared y: shared z: { value: shared }").unwrap();
+        let mut lazy = Lazy::in_host(&parsed, &host).unwrap();
+
+        assert_eq!(lazy.get("x"), Ok(&literal("counted")));
+        assert_eq!(lazy.get("y"), Ok(&literal("counted")));
+        assert_eq!(lazy.get("z.value"), Ok(&literal("counted")));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        FlatObject::build_in(&parsed, &host).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_parallel(){
+        let sources = [
+            include_str!("../assets/example.ptl"), include_str!("../assets/html.ptl"),
+            "
+                dark_theme: 'dark' if dark_theme == 'dark' { background: 'black' list += { : 'c' } }
+                Button: { theme: 'light' size: { large: 'false' } }
+                dark: Button { theme: 'dark' size: { large: 'true' } }
+                list: { : 'a' } : 'unnamed' more: list { += { : 'b' } } copy: dark.size
+                label: 'size ${dark.size.large}' Replaced: dark { size: !replace { small: 'true' } }
+            ",
+        ];
+
+        for source in &sources {
+            let parsed = parse(source).unwrap();
+            let resolved = FlatObject::build_from_parsed(&parsed).unwrap();
+
+            for threads in 1 .. 5 {
+                let parallel = FlatObject::build_parallel(&parsed, threads).unwrap();
+                assert_eq!(parallel.to_string(), resolved.to_string());
+            }
+        }
+
+        let parsed = parse("a: (b) c: { d: c } e: (f)").unwrap();
+        assert_eq!(FlatObject::build_parallel(&parsed, 3), FlatObject::build_from_parsed(&parsed));
+        assert_eq!(FlatObject::build_parallel(&parse("").unwrap(), 2), Ok(FlatObject::compound(FlatCompound::new())));
+
+        let calls = AtomicUsize::new(0);
+        let mut host = Host::default();
+        host.prototype("Counted", |_: &FlatCompound| { calls.fetch_add(1, Ordering::SeqCst); Ok(literal("counted")) });
+
+        let parsed = parse("a: Counted { } b: Counted { } c: { d: Counted { } }").unwrap();
+        let parallel = ::std::thread::scope(|threads| {
+            threads.spawn(|| FlatObject::build_parallel_in(&parsed, &host, 2)).join().unwrap()
+        });
+
+        assert_eq!(parallel, FlatObject::build_in(&parsed, &host));
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn test_thread_safety(){
+        fn shareable<T: Send + Sync>() {}
+
+        shareable::<NamedObjects>();
+        shareable::<Host>();
+        shareable::<FlatObject>();
+        shareable::<ResolveError>();
+        shareable::<Trace>();
+    }
+
+    fn collect_paths(object: &FlatObject, path: &mut Vec<String>, paths: &mut Vec<String>) {
+        if let FlatObject::Compound(ref properties) = *object {
+            for (name, value) in properties.iter() {
+                path.push(name.clone());
+                paths.push(path.join("."));
+                collect_paths(value, path, paths);
+                path.pop();
+            }
+        }
+    }
+}
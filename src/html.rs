@@ -0,0 +1,275 @@
+//! Renders resolved documents as HTML.
+//!
+//! A compound with a `html-name` is an element with that tag. The string literal `inner` is its text,
+//! and the string literals inside `attr` are its attributes. Its list entries, like `0` and `1`,
+//! are its children, in the order of their indices, where string literals are text.
+//! All other properties are ignored, so that elements can carry data which is only used for interpolation.
+//! A compound without a `html-name` only renders its children, like a fragment.
+//!
+//! Text and attribute values are escaped, except for the text of `script` and `style` elements,
+//! which is written as is and must not close its element. Tag and attribute names which cannot be written are errors.
+
+use ::std::fmt;
+use ::flat::{ FlatObject, FlatCompound };
+
+
+/// the property that contains the tag of an element
+pub const NAME: &str = "html-name";
+
+/// the property that contains the text of an element, which comes before its children
+pub const INNER: &str = "inner";
+
+/// the property that contains the attributes of an element
+pub const ATTRIBUTES: &str = "attr";
+
+/// elements which have no closing tag, and therefore no content
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr",
+];
+
+/// elements whose text is not parsed as html, and therefore cannot be escaped
+const RAW_TEXT_ELEMENTS: &[&str] = &[ "script", "style" ];
+
+pub type HtmlResult<T> = ::std::result::Result<T, HtmlError>;
+
+/// the path points to the property that cannot be rendered, like `body.0.attr`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum HtmlError {
+    /// `html-name`, `inner`, or an attribute is a compound
+    ExpectedStringLiteral { path: String },
+
+    /// a tag or attribute name which would change the meaning of the surrounding html
+    InvalidName { path: String, name: String },
+
+    /// an element like `br` has text or children
+    VoidElementContent { path: String, name: String },
+
+    /// the text of an element like `script` contains its closing tag, like `</script`
+    RawTextClosingTag { path: String, name: String },
+}
+
+
+/// the html of an element, a text, or a fragment
+pub fn render(object: &FlatObject) -> HtmlResult<String> {
+    let mut html = String::new();
+    render_into(object, &mut Vec::new(), &mut html)?;
+    Ok(html)
+}
+
+fn render_into(object: &FlatObject, path: &mut Vec<String>, html: &mut String) -> HtmlResult<()> {
+    let properties = match *object {
+        FlatObject::StringLiteral(ref text) => {
+            escape(text, false, html);
+            return Ok(());
+        },
+        FlatObject::Compound(ref properties) => properties,
+    };
+
+    let name = match properties.get(NAME) {
+        None => None,
+        Some(name) => Some(text(name, path, NAME)?),
+    };
+
+    let inner = match properties.get(INNER) {
+        None => None,
+        Some(inner) => Some(text(inner, path, INNER)?),
+    };
+
+    let children = ::list::entries(properties.keys().map(String::as_str));
+
+    if let Some(name) = name {
+        if !is_tag_name(name) {
+            return Err(HtmlError::InvalidName { path: join(path, NAME), name: name.to_string() });
+        }
+
+        html.push('<');
+        html.push_str(name);
+        render_attributes(properties, path, html)?;
+        html.push('>');
+
+        if VOID_ELEMENTS.contains(&name.to_ascii_lowercase().as_str()) {
+            if inner.is_some() || !children.is_empty() {
+                return Err(HtmlError::VoidElementContent { path: path.join("."), name: name.to_string() });
+            }
+
+            return Ok(());
+        }
+    }
+
+    let raw_text = name.filter(|name| RAW_TEXT_ELEMENTS.contains(&name.to_ascii_lowercase().as_str()));
+
+    if let Some(name) = raw_text {
+        if let Some(inner) = inner {
+            push_raw_text(inner, name, &join(path, INNER), html)?;
+        }
+
+        for child in children {
+            push_raw_text(text(&properties[child], path, child)?, name, &join(path, child), html)?;
+        }
+    }
+    else {
+        if let Some(inner) = inner {
+            escape(inner, false, html);
+        }
+
+        for child in children {
+            path.push(child.to_string());
+            render_into(&properties[child], path, html)?;
+            path.pop();
+        }
+    }
+
+    if let Some(name) = name {
+        html.push_str("</");
+        html.push_str(name);
+        html.push('>');
+    }
+
+    Ok(())
+}
+
+/// sorted by name, so that the output does not depend on the order of the hash map
+fn render_attributes(properties: &FlatCompound, path: &[String], html: &mut String) -> HtmlResult<()> {
+    let attributes = match properties.get(ATTRIBUTES) {
+        None => return Ok(()),
+        Some(FlatObject::Compound(attributes)) => attributes,
+        Some(FlatObject::StringLiteral(_)) => return Err(HtmlError::ExpectedStringLiteral { path: join(path, ATTRIBUTES) }),
+    };
+
+    for (name, attribute) in attributes.iter() {
+        let mut attribute_path = path.to_vec();
+        attribute_path.push(String::from(ATTRIBUTES));
+
+        let value = text(attribute, &attribute_path, name)?;
+
+        if !is_attribute_name(name) {
+            return Err(HtmlError::InvalidName { path: join(&attribute_path, name), name: name.clone() });
+        }
+
+        html.push(' ');
+        html.push_str(name);
+        html.push_str("=\"");
+        escape(value, true, html);
+        html.push('"');
+    }
+
+    Ok(())
+}
+
+fn text<'f>(object: &'f FlatObject, path: &[String], name: &str) -> HtmlResult<&'f str> {
+    match *object {
+        FlatObject::StringLiteral(ref text) => Ok(text),
+        FlatObject::Compound(_) => Err(HtmlError::ExpectedStringLiteral { path: join(path, name) }),
+    }
+}
+
+fn join(path: &[String], name: &str) -> String {
+    path.iter().map(String::as_str).chain(::std::iter::once(name)).collect::<Vec<&str>>().join(".")
+}
+
+/// the text of a `script` or `style` element ends at the first closing tag, regardless of its case
+fn push_raw_text(text: &str, name: &str, path: &str, html: &mut String) -> HtmlResult<()> {
+    let closing_tag = format!("</{}", name.to_ascii_lowercase());
+
+    if text.to_ascii_lowercase().contains(&closing_tag) {
+        return Err(HtmlError::RawTextClosingTag { path: path.to_string(), name: name.to_string() });
+    }
+
+    html.push_str(text);
+    Ok(())
+}
+
+/// quotes only need to be escaped inside attribute values
+fn escape(text: &str, quotes: bool, html: &mut String) {
+    for character in text.chars() {
+        match character {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' if quotes => html.push_str("&quot;"),
+            character => html.push(character),
+        }
+    }
+}
+
+fn is_tag_name(name: &str) -> bool {
+    name.starts_with(|character: char| character.is_ascii_alphabetic())
+        && name.chars().all(|character| character.is_ascii_alphanumeric() || character == '-')
+}
+
+fn is_attribute_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(|character| {
+        character.is_whitespace() || character.is_control() || "\"'<>/=".contains(character)
+    })
+}
+
+impl fmt::Display for HtmlError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HtmlError::ExpectedStringLiteral { ref path } => write!(
+                formatter, "`{}` must be a string literal to be rendered", path
+            ),
+
+            HtmlError::InvalidName { ref path, ref name } => write!(
+                formatter, "`{}` is not a valid html name, at `{}`", name, path
+            ),
+
+            HtmlError::VoidElementContent { ref path, ref name } => write!(
+                formatter, "`{}` elements cannot have content, at `{}`", name, path
+            ),
+
+            HtmlError::RawTextClosingTag { ref path, ref name } => write!(
+                formatter, "the text of `{}` elements cannot contain `</{}`, at `{}`", name, name, path
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render(){
+        let source = r#"
+            p: { html-name: 'p' }
+            br: { html-name: 'br' }
+            div: { html-name: 'div' }
+
+            page: {
+                html-name: 'body'
+                title: 'not rendered'
+                attr: { class: 'home "main"' data-index: '1' }
+                : p { inner: 'Tom & <Jerry>' }
+                : br
+                3: div { : p { inner: 'nested' } }
+                10: 'last'
+                2: { : 'fragment' }
+            }
+        "#;
+
+        let resolved = FlatObject::parse(source).expect("Parsing Error").expect("Resolve Error");
+        assert_eq!(
+            resolved.get_path(&["page"]).map(render),
+            Some(Ok(String::from(
+                "<body class=\"home &quot;main&quot;\" data-index=\"1\">\
+                <p>Tom &amp; &lt;Jerry&gt;</p><br>fragment<div><p>nested</p></div>last</body>"
+            )))
+        );
+
+        let render_source = |source: &str| render(&FlatObject::parse(source).unwrap().unwrap()).map_err(|error| error.to_string());
+        assert_eq!(render_source(": { html-name: 'p' inner: { } }"), Err(String::from("`0.inner` must be a string literal to be rendered")));
+        assert_eq!(render_source(": { html-name: 'p x' }"), Err(String::from("`p x` is not a valid html name, at `0.html-name`")));
+        assert_eq!(render_source(": { html-name: 'p' attr: { a<b: 'c' } }"), Err(String::from("`a<b` is not a valid html name, at `0.attr.a<b`")));
+        assert_eq!(render_source(": { html-name: 'p' attr: { x: { } } }"), Err(String::from("`0.attr.x` must be a string literal to be rendered")));
+        assert_eq!(render_source(": { html-name: 'p' attr: { z: '1' a: '2' } }"), Ok(String::from("<p z=\"1\" a=\"2\"></p>")));
+        assert_eq!(render_source(": { html-name: 'br' : 'x' }"), Err(String::from("`br` elements cannot have content, at `0`")));
+
+        assert_eq!(render_source(": { html-name: 'script' inner: 'if (a < b && c) { }' : ' f(\"<p>\")' }"), Ok(String::from("<script>if (a < b && c) { } f(\"<p>\")</script>")));
+        assert_eq!(render_source(": { html-name: 'STYLE' inner: 'p > a { }' }"), Ok(String::from("<STYLE>p > a { }</STYLE>")));
+        assert_eq!(render_source(": { html-name: 'script' inner: 'x = \"</SCRIPT>\"' }"), Err(String::from("the text of `script` elements cannot contain `</script`, at `0.inner`")));
+        assert_eq!(render_source(": { html-name: 'style' : '</style><p>' }"), Err(String::from("the text of `style` elements cannot contain `</style`, at `0.0`")));
+        assert_eq!(render_source(": { html-name: 'script' : { } }"), Err(String::from("`0.0` must be a string literal to be rendered")));
+    }
+}
@@ -0,0 +1,265 @@
+//! Finds the resolved properties that depend on a declaration, to know what changing it affects.
+//!
+//! A resolved property depends on the declaration it was resolved from, the prototypes it was inherited through,
+//! and the variables it was copied from, see `::trace::Provenance`. String literals computed from other objects,
+//! with templates or expressions, also depend on the objects they refer to, and conditional properties
+//! on the objects their condition refers to. Properties also depend on everything the compounds containing them
+//! depend on, because the trace only records where an inherited or copied compound came from, not its contents.
+//! A compound itself also depends on its prototypes, and on the inherited compounds it overrides and is merged with.
+
+use ::std::collections::{ HashMap, HashSet };
+use ::parse::{ NamedObjects, Object, Reference, TemplatePart, Expression, Operation, Span };
+use ::trace::Trace;
+
+
+/// which declarations each resolved property depends on, by path
+pub struct Dependencies {
+    dependencies: HashMap<Vec<String>, Sources>,
+    declarations: HashSet<Vec<String>>,
+}
+
+/// the declaration paths a resolved property depends on
+#[derive(Default, Clone)]
+struct Sources {
+    /// where the property and the compounds containing it were declared, which only matter if they are edited
+    declared: Vec<Vec<String>>,
+
+    /// the objects it was inherited, copied, merged, or computed from, which also matter if their resolved value changes
+    referenced: Vec<Vec<String>>,
+}
+
+/// the declarations of a document, found by walking it
+struct Declarations {
+    /// by the span of the identifier
+    paths: HashMap<Span, Vec<String>>,
+
+    /// the objects that computed string literals and conditional objects refer to, by declaration path
+    references: HashMap<Vec<String>, Vec<Vec<String>>>,
+
+    /// the prototypes of compounds, by declaration path
+    prototypes: HashMap<Vec<String>, Vec<Vec<String>>>,
+}
+
+
+impl Dependencies {
+    /// the trace must belong to the document, see `::flat::FlatObject::build_traced`
+    pub fn new(document: &NamedObjects, trace: &Trace) -> Self {
+        let mut declarations = Declarations { paths: HashMap::new(), references: HashMap::new(), prototypes: HashMap::new() };
+        declarations.collect(document, &mut Vec::new(), &[]);
+
+        let direct: HashMap<&Vec<String>, Sources> = trace.provenances.iter()
+            .map(|(path, provenance)| {
+                let declared = provenance.defined_at.span().and_then(|span| declarations.paths.get(&span));
+
+                let mut referenced: Vec<Vec<String>> = provenance.inherited_from.iter().chain(&provenance.inlined_from)
+                    .filter_map(|origin| declarations.paths.get(&origin.location.span()?).cloned())
+                    .collect();
+
+                if let Some(references) = declared.and_then(|declared| declarations.references.get(declared)) {
+                    referenced.extend(references.iter().cloned());
+                }
+
+                (path, Sources { declared: declared.into_iter().cloned().collect(), referenced })
+            })
+            .collect();
+
+        let mut dependencies: HashMap<Vec<String>, Sources> = direct.iter()
+            .map(|(&path, sources)| {
+                let mut sources = sources.clone();
+
+                for length in 1 .. path.len() {
+                    if let Some(containing) = direct.get(&path[.. length].to_vec()) {
+                        sources.declared.extend(containing.declared.iter().cloned());
+                        sources.referenced.extend(containing.referenced.iter().cloned());
+                    }
+                }
+
+                (path.clone(), sources)
+            })
+            .collect();
+
+        // a compound changes with its prototypes, like `spiderman: Special`, and with an inherited compound
+        // that it overrides and is merged with, like `hulk: Comic { author: { age: '3' } }`.
+        // the properties it declares itself do not depend on them, and the inherited ones record their own provenance
+        for (path, provenance) in &trace.provenances {
+            let declared = match provenance.defined_at.span().and_then(|span| declarations.paths.get(&span)) {
+                Some(declared) => declared,
+                None => continue,
+            };
+
+            if let (Some(prototypes), Some(sources)) = (declarations.prototypes.get(declared), dependencies.get_mut(path)) {
+                sources.referenced.extend(prototypes.iter().cloned());
+            }
+
+            if declared.len() < 2 || path.len() < 2 { continue; }
+
+            let merged = declared[.. declared.len() - 1].to_vec();
+            if let Some(sources) = dependencies.get_mut(&path[.. path.len() - 1]) {
+                if !sources.declared.contains(&merged) && !sources.referenced.contains(&merged) {
+                    sources.referenced.push(merged);
+                }
+            }
+        }
+
+        Dependencies { dependencies, declarations: declarations.paths.into_values().collect() }
+    }
+
+    pub fn is_declared<S: AsRef<str>>(&self, path: &[S]) -> bool {
+        self.declarations.contains(&owned_path(path))
+    }
+
+    /// the sorted paths of the resolved properties which change if the declaration at the path changes,
+    /// including the properties inside it
+    pub fn affected<S: AsRef<str>>(&self, edited: &[S]) -> Vec<Vec<String>> {
+        let edited = owned_path(edited);
+        let mut affected: HashSet<&Vec<String>> = HashSet::new();
+
+        // until no property depends on a newly affected one
+        loop {
+            let newly_affected: Vec<&Vec<String>> = self.dependencies.iter()
+                .filter(|&(path, _)| !affected.contains(path))
+                .filter(|&(_, sources)| {
+                    sources.declared.iter().chain(&sources.referenced).any(|dependency| dependency.starts_with(&edited))
+                        || sources.referenced.iter().any(|dependency| affected.contains(dependency))
+                })
+                .map(|(path, _)| path)
+                .collect();
+
+            if newly_affected.is_empty() { break; }
+            affected.extend(newly_affected);
+        }
+
+        let mut affected: Vec<Vec<String>> = affected.into_iter().cloned().collect();
+        affected.sort();
+        affected
+    }
+}
+
+impl Declarations {
+    fn collect(&mut self, objects: &NamedObjects, path: &mut Vec<String>, conditions: &[Vec<String>]) {
+        for (name, identifier, index) in objects.in_declaration_order() {
+            path.push(name.into_owned());
+
+            // unnamed list entries have no name to point at
+            if let (false, Some(span)) = (identifier.name.is_empty(), identifier.location) {
+                self.paths.insert(span, path.clone());
+            }
+
+            let mut references = conditions.to_vec();
+
+            match objects.objects[index] {
+                Object::Template(ref parts) => collect_template(parts, &mut references),
+                Object::Expression(ref expression) => collect_expression(expression, &mut references),
+                ref object => if let Some(compound) = object.as_compound() {
+                    if !compound.prototypes.is_empty() {
+                        self.prototypes.insert(path.clone(), compound.prototypes.iter().map(reference_path).collect());
+                    }
+
+                    self.collect(&compound.overrides, path, &[]);
+                },
+            }
+
+            if !references.is_empty() {
+                self.references.insert(path.clone(), references);
+            }
+
+            path.pop();
+        }
+
+        for conditional in &objects.conditionals {
+            let mut references = conditions.to_vec();
+            collect_expression(&conditional.condition, &mut references);
+            self.collect(&conditional.overrides, path, &references);
+        }
+    }
+}
+
+fn collect_template(parts: &[TemplatePart], references: &mut Vec<Vec<String>>) {
+    for part in parts {
+        if let TemplatePart::Reference(ref reference) = *part {
+            references.push(reference_path(reference));
+        }
+    }
+}
+
+/// parameters refer to the compound itself, which already is a dependency
+fn collect_expression(expression: &Expression, references: &mut Vec<Vec<String>>) {
+    match expression.operation {
+        Operation::Literal(_) | Operation::Parameter(_) => {},
+        Operation::Template(ref parts) => collect_template(parts, references),
+        Operation::Reference(ref reference) => references.push(reference_path(reference)),
+        Operation::Unary(_, ref operand) => collect_expression(operand, references),
+
+        Operation::Binary(_, ref left, ref right) => {
+            collect_expression(left, references);
+            collect_expression(right, references);
+        },
+
+        Operation::Conditional(ref condition, ref then, ref otherwise) => {
+            collect_expression(condition, references);
+            collect_expression(then, references);
+            collect_expression(otherwise, references);
+        },
+
+        Operation::Call(_, ref arguments) => for argument in arguments {
+            collect_expression(argument, references);
+        },
+    }
+}
+
+fn reference_path(reference: &Reference) -> Vec<String> {
+    reference.identifiers.iter().map(|identifier| identifier.name.to_string()).collect()
+}
+
+fn owned_path<S: AsRef<str>>(path: &[S]) -> Vec<String> {
+    path.iter().map(|name| name.as_ref().to_string()).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::flat::FlatObject;
+
+    #[test]
+    fn test_affected(){
+        let source = "
+            Comic: { title: 'Untitled' author: { name: 'unknown' } }
+            Special: Comic { edition: 'special' }
+            comics: {
+                spiderman: Special { title: 'Spiderman' }
+                hulk: Comic { author: { age: '3' } }
+                copy: comics.hulk
+            }
+            credits: 'by ${Comic.author.name}'
+            pages: (len(credits))
+            unrelated: Comic { author: !replace { name: 'Stan' } }
+        ";
+
+        let document = ::parse::parse(source).unwrap();
+        let (_, trace) = FlatObject::build_traced(&document).unwrap();
+        let dependencies = Dependencies::new(&document, &trace);
+
+        let affected = |path: &[&str]| dependencies.affected(path).into_iter().map(|path| path.join(".")).collect::<Vec<String>>();
+
+        // `comics.hulk.author` merges its own `age` with the inherited author, which does not change
+        assert_eq!(affected(&[ "Comic", "author" ]), vec![
+            "Comic.author", "Comic.author.name", "Special.author", "Special.author.name",
+            "comics.copy.author", "comics.copy.author.name", "comics.hulk.author", "comics.hulk.author.name",
+            "comics.spiderman.author", "comics.spiderman.author.name", "credits", "pages",
+        ]);
+
+        // the instance changes with its prototype, but its own `title` does not
+        assert_eq!(affected(&[ "Special" ]), vec![
+            "Special", "Special.author", "Special.author.name", "Special.edition", "Special.title",
+            "comics.spiderman", "comics.spiderman.author", "comics.spiderman.author.name", "comics.spiderman.edition",
+        ]);
+
+        assert!(affected(&[ "Comic" ]).contains(&String::from("comics.hulk")));
+        assert!(!affected(&[ "Comic", "author" ]).contains(&String::from("comics.hulk.author.age")));
+
+        assert_eq!(affected(&[ "comics", "spiderman", "title" ]), vec![ "comics.spiderman.title" ]);
+        assert!(dependencies.is_declared(&[ "Comic", "author", "name" ]));
+        assert!(!dependencies.is_declared(&[ "comics", "hulk", "title" ]));
+    }
+}
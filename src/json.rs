@@ -0,0 +1,322 @@
+use ::std::fmt;
+
+/// a minimal json value, used for exchanging data with other programs.
+/// objects remember the order of their members.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+pub type JsonResult<T> = ::std::result::Result<T, JsonError>;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum JsonError {
+    UnexpectedSymbol { expected: &'static str, index: usize },
+    UnexpectedEndOfInput { expected: &'static str },
+}
+
+
+impl Json {
+    pub fn parse(source: &str) -> JsonResult<Json> {
+        let mut parser = Parser { source, index: 0 };
+        let value = parser.parse_value()?;
+
+        parser.skip_whitespace();
+        if parser.index < source.len() {
+            return Err(JsonError::UnexpectedSymbol { expected: "end of input", index: parser.index });
+        }
+
+        Ok(value)
+    }
+
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+    }
+
+    pub fn string<S: Into<String>>(string: S) -> Json {
+        Json::String(string.into())
+    }
+
+    /// the member of an object, none if this is not an object
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter()
+                .find(|&(member, _)| member == name)
+                .map(|(_, value)| value),
+
+            _ => None,
+        }
+    }
+
+    /// follows a series of object members
+    pub fn get_path(&self, path: &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |json, name| json.get(name))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self { Json::String(ref string) => Some(string), _ => None }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Json::Number(number) if number >= 0.0 && number.fract() == 0.0 => Some(number as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self { Json::Bool(boolean) => Some(boolean), _ => None }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self { Json::Array(ref array) => Some(array), _ => None }
+    }
+}
+
+
+struct Parser<'s> {
+    source: &'s str,
+    index: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn remaining(&self) -> &'s str {
+        &self.source[self.index ..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let remaining = self.remaining();
+        self.index += remaining.len() - remaining.trim_start().len();
+    }
+
+    fn unexpected(&self, expected: &'static str) -> JsonError {
+        if self.index < self.source.len() {
+            JsonError::UnexpectedSymbol { expected, index: self.index }
+        } else {
+            JsonError::UnexpectedEndOfInput { expected }
+        }
+    }
+
+    /// skips whitespace, and the symbol if it is the next character
+    fn skip(&mut self, symbol: char) -> bool {
+        self.skip_whitespace();
+        if self.remaining().starts_with(symbol) {
+            self.index += symbol.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char, expected: &'static str) -> JsonResult<()> {
+        if self.skip(symbol) { Ok(()) } else { Err(self.unexpected(expected)) }
+    }
+
+    fn parse_value(&mut self) -> JsonResult<Json> {
+        self.skip_whitespace();
+        let remaining = self.remaining();
+
+        for &(keyword, ref value) in &[("null", Json::Null), ("true", Json::Bool(true)), ("false", Json::Bool(false))] {
+            if remaining.starts_with(keyword) {
+                self.index += keyword.len();
+                return Ok(value.clone());
+            }
+        }
+
+        match remaining.chars().next() {
+            Some('"') => self.parse_string().map(Json::String),
+            Some('[') => self.parse_array(),
+            Some('{') => self.parse_object(),
+            Some(character) if character == '-' || character.is_ascii_digit() => self.parse_number(),
+            _ => Err(self.unexpected("value")),
+        }
+    }
+
+    fn parse_number(&mut self) -> JsonResult<Json> {
+        let remaining = self.remaining();
+        let length = remaining.find(|character: char| !(character.is_ascii_digit() || "+-.eE".contains(character)))
+            .unwrap_or(remaining.len());
+
+        let number = remaining[.. length].parse().map_err(|_| self.unexpected("number"))?;
+        self.index += length;
+        Ok(Json::Number(number))
+    }
+
+    fn parse_hex_escape(&mut self) -> JsonResult<u32> {
+        let digits = self.remaining().get(.. 4).ok_or_else(|| self.unexpected("four hex digits"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.unexpected("four hex digits"))?;
+        self.index += 4;
+        Ok(code)
+    }
+
+    fn parse_string(&mut self) -> JsonResult<String> {
+        self.expect('"', "`\"`")?;
+        let mut string = String::new();
+
+        loop {
+            let character = self.remaining().chars().next().ok_or_else(|| self.unexpected("`\"`"))?;
+            self.index += character.len_utf8();
+
+            match character {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escaped = self.remaining().chars().next().ok_or_else(|| self.unexpected("escape sequence"))?;
+                    self.index += escaped.len_utf8();
+
+                    string.push(match escaped {
+                        '"' => '"', '\\' => '\\', '/' => '/',
+                        'b' => '\u{8}', 'f' => '\u{c}', 'n' => '\n', 'r' => '\r', 't' => '\t',
+                        'u' => {
+                            let mut code = self.parse_hex_escape()?;
+
+                            // utf-16 surrogate pair
+                            if (0xd800 .. 0xdc00).contains(&code) && self.remaining().starts_with("\\u") {
+                                self.index += 2;
+                                let low = self.parse_hex_escape()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+
+                            ::std::char::from_u32(code).unwrap_or('\u{fffd}')
+                        },
+
+                        _ => return Err(self.unexpected("escape sequence")),
+                    });
+                },
+
+                _ => string.push(character),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> JsonResult<Json> {
+        self.expect('[', "`[`")?;
+        let mut elements = Vec::new();
+
+        if !self.skip(']') {
+            loop {
+                elements.push(self.parse_value()?);
+                if self.skip(']') { break; }
+                self.expect(',', "`,` or `]`")?;
+            }
+        }
+
+        Ok(Json::Array(elements))
+    }
+
+    fn parse_object(&mut self) -> JsonResult<Json> {
+        self.expect('{', "`{`")?;
+        let mut members = Vec::new();
+
+        if !self.skip('}') {
+            loop {
+                self.skip_whitespace();
+                let name = self.parse_string()?;
+                self.expect(':', "`:`")?;
+                members.push((name, self.parse_value()?));
+
+                if self.skip('}') { break; }
+                self.expect(',', "`,` or `}`")?;
+            }
+        }
+
+        Ok(Json::Object(members))
+    }
+}
+
+
+fn write_string(formatter: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    formatter.write_str("\"")?;
+
+    for character in string.chars() {
+        match character {
+            '"' => formatter.write_str("\\\"")?,
+            '\\' => formatter.write_str("\\\\")?,
+            '\n' => formatter.write_str("\\n")?,
+            '\r' => formatter.write_str("\\r")?,
+            '\t' => formatter.write_str("\\t")?,
+            control if (control as u32) < 0x20 => write!(formatter, "\\u{:04x}", control as u32)?,
+            _ => write!(formatter, "{}", character)?,
+        }
+    }
+
+    formatter.write_str("\"")
+}
+
+/// compact json, without any whitespace
+impl fmt::Display for Json {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => formatter.write_str("null"),
+            Json::Bool(boolean) => write!(formatter, "{}", boolean),
+            Json::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => write!(formatter, "{}", number as i64),
+            Json::Number(number) if number.is_finite() => write!(formatter, "{}", number),
+            Json::Number(_) => formatter.write_str("null"),
+            Json::String(ref string) => write_string(formatter, string),
+
+            Json::Array(ref elements) => {
+                formatter.write_str("[")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index != 0 { formatter.write_str(",")?; }
+                    write!(formatter, "{}", element)?;
+                }
+                formatter.write_str("]")
+            },
+
+            Json::Object(ref members) => {
+                formatter.write_str("{")?;
+                for (index, (name, value)) in members.iter().enumerate() {
+                    if index != 0 { formatter.write_str(",")?; }
+                    write_string(formatter, name)?;
+                    write!(formatter, ":{}", value)?;
+                }
+                formatter.write_str("}")
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_json(){
+        assert_eq!(Json::parse(" null "), Ok(Json::Null));
+        assert_eq!(Json::parse("[true, false]"), Ok(Json::Array(vec![Json::Bool(true), Json::Bool(false)])));
+        assert_eq!(Json::parse("-1.5e2"), Ok(Json::Number(-150.0)));
+        assert_eq!(Json::parse(r#" "a\"b\nä😀" "#), Ok(Json::string("a\"b\nä😀")));
+
+        assert_eq!(
+            Json::parse(r#"{ "id": 1, "params": { "uri": "file:///x" }, "list": [] }"#),
+            Ok(Json::object(vec![
+                ("id", Json::Number(1.0)),
+                ("params", Json::object(vec![ ("uri", Json::string("file:///x")) ])),
+                ("list", Json::Array(vec![])),
+            ]))
+        );
+
+        assert_eq!(Json::parse("[1,"), Err(JsonError::UnexpectedEndOfInput { expected: "value" }));
+        assert_eq!(Json::parse("[1 2]"), Err(JsonError::UnexpectedSymbol { expected: "`,` or `]`", index: 3 }));
+        assert_eq!(Json::parse("{} x"), Err(JsonError::UnexpectedSymbol { expected: "end of input", index: 3 }));
+    }
+
+    #[test]
+    fn test_write_json(){
+        let json = Json::object(vec![
+            ("id", Json::Number(7.0)),
+            ("half", Json::Number(0.5)),
+            ("text", Json::string("say \"hi\"\n")),
+            ("list", Json::Array(vec![Json::Null, Json::Bool(true)])),
+        ]);
+
+        assert_eq!(json.to_string(), r#"{"id":7,"half":0.5,"text":"say \"hi\"\n","list":[null,true]}"#);
+        assert_eq!(Json::parse(&json.to_string()), Ok(json.clone()));
+        assert_eq!(json.get_path(&["list"]).and_then(Json::as_array).map(|list| list.len()), Some(2));
+    }
+}
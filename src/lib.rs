@@ -0,0 +1,21 @@
+pub mod parse;
+pub mod flat;
+pub mod scope;
+pub mod evaluate;
+pub mod linearize;
+pub mod list;
+pub mod ordered;
+pub mod shared;
+pub mod trace;
+pub mod referenced;
+pub mod json;
+pub mod html;
+pub mod export;
+pub mod unflatten;
+pub mod diff;
+pub mod impact;
+pub mod document;
+pub mod syntax;
+pub mod owned;
+pub mod cli;
+pub mod lsp;
@@ -0,0 +1,63 @@
+//! The order in which the prototypes of a compound are searched for inherited properties.
+//!
+//! A compound with several prototypes, like `Button: Clickable + Styled { }`,
+//! is linearized using C3, the same algorithm that Python uses for its classes:
+//! the compound itself comes first, followed by its prototypes from left to right,
+//! where every prototype comes before its own prototypes,
+//! and a prototype which is shared by several others only comes after all of them.
+//! A property is inherited from the first compound in this order that declares it.
+//!
+//! With a single prototype, this is simply the prototype chain.
+
+
+/// merges the linearizations of all prototypes, followed by the list of the prototypes themselves,
+/// which does not include the compound that is being linearized.
+/// returns none if the prototypes are declared in contradicting orders,
+/// like `A: X + Y {}` and `B: Y + X {}` in `C: A + B {}`
+pub fn c3_merge<T: PartialEq + Clone>(mut sequences: Vec<Vec<T>>) -> Option<Vec<T>> {
+    let mut merged = Vec::new();
+
+    loop {
+        sequences.retain(|sequence| !sequence.is_empty());
+        if sequences.is_empty() {
+            return Some(merged);
+        }
+
+        // the first head which does not appear in the tail of any sequence
+        let next = sequences.iter()
+            .map(|sequence| &sequence[0])
+            .find(|candidate| sequences.iter().all(|sequence| !sequence[1 ..].contains(candidate)))?
+            .clone();
+
+        for sequence in &mut sequences {
+            if sequence[0] == next {
+                sequence.remove(0);
+            }
+        }
+
+        merged.push(next);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_c3_merge(){
+        assert_eq!(c3_merge::<char>(vec![]), Some(vec![]));
+
+        // a single chain stays as it is
+        assert_eq!(c3_merge(vec![ vec!['a', 'b'], vec!['a'] ]), Some(vec!['a', 'b']));
+
+        // diamond: Button: Clickable + Styled, both inheriting from Element
+        assert_eq!(
+            c3_merge(vec![ vec!['c', 'e'], vec!['s', 'e'], vec!['c', 's'] ]),
+            Some(vec!['c', 's', 'e'])
+        );
+
+        // contradicting orders
+        assert_eq!(c3_merge(vec![ vec!['a', 'x', 'y'], vec!['b', 'y', 'x'], vec!['a', 'b'] ]), None);
+    }
+}
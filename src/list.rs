@@ -0,0 +1,101 @@
+//! Lists are compounds whose properties are named by indices, like `0` and `1`,
+//! either explicitly or implicitly by leaving the name empty.
+//!
+//! An override can add entries to an inherited list instead of replacing it.
+//! The entries of the resulting list are renumbered, starting at zero,
+//! in the order of their indices, so the result does not depend on how the indices were spaced.
+
+/// where the entries of an extension are inserted into the inherited list.
+/// the anchor is the name of an inherited list entry
+#[derive(Eq, PartialEq, Debug, Hash, Clone)]
+pub enum Placement<A> {
+    /// `+=`, or `!append`
+    Append,
+
+    /// `!prepend`
+    Prepend,
+
+    /// `!insert_before index`
+    Before(A),
+
+    /// `!insert_after index`
+    After(A),
+}
+
+
+impl<A> Placement<A> {
+    pub fn anchor(&self) -> Option<&A> {
+        match *self {
+            Placement::Before(ref anchor) | Placement::After(ref anchor) => Some(anchor),
+            Placement::Append | Placement::Prepend => None,
+        }
+    }
+
+    pub fn map<'a, B, F: FnOnce(&'a A) -> B>(&'a self, convert: F) -> Placement<B> {
+        match *self {
+            Placement::Append => Placement::Append,
+            Placement::Prepend => Placement::Prepend,
+            Placement::Before(ref anchor) => Placement::Before(convert(anchor)),
+            Placement::After(ref anchor) => Placement::After(convert(anchor)),
+        }
+    }
+}
+
+/// the index a property name stands for, none if the property is not a list entry
+pub fn index(name: &str) -> Option<usize> {
+    if !name.is_empty() && name.bytes().all(|byte| byte.is_ascii_digit()) { name.parse().ok() } else { None }
+}
+
+/// the names of the list entries, sorted by their index, ignoring all other names
+pub fn entries<'n, I: IntoIterator<Item = &'n str>>(names: I) -> Vec<&'n str> {
+    let mut entries: Vec<(usize, &str)> = names.into_iter()
+        .filter_map(|name| index(name).map(|index| (index, name)))
+        .collect();
+
+    entries.sort();
+    entries.into_iter().map(|(_, name)| name).collect()
+}
+
+/// the names of the combined list entries, in their new order,
+/// each with whether it was added or inherited. the new name of an entry is its position.
+/// both lists must be sorted, see `entries`. none if the anchor is not an inherited entry
+pub fn extend<'n>(inherited: &[&'n str], added: &[&'n str], placement: &Placement<&str>) -> Option<Vec<(&'n str, bool)>> {
+    let position = |anchor: &str| inherited.iter().position(|&name| index(name) == index(anchor));
+
+    let split = match *placement {
+        Placement::Append => inherited.len(),
+        Placement::Prepend => 0,
+        Placement::Before(anchor) => position(anchor)?,
+        Placement::After(anchor) => position(anchor)? + 1,
+    };
+
+    Some(
+        inherited[.. split].iter().map(|&name| (name, false))
+            .chain(added.iter().map(|&name| (name, true)))
+            .chain(inherited[split ..].iter().map(|&name| (name, false)))
+            .collect()
+    )
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extend(){
+        assert_eq!(index("12"), Some(12));
+        assert_eq!(index("-1"), None);
+        assert_eq!(index(""), None);
+        assert_eq!(entries(vec![ "10", "title", "2", "0" ]), vec![ "0", "2", "10" ]);
+
+        let inherited = [ "0", "5" ];
+        let added = [ "0", "1" ];
+
+        assert_eq!(extend(&inherited, &added, &Placement::Append), Some(vec![ ("0", false), ("5", false), ("0", true), ("1", true) ]));
+        assert_eq!(extend(&inherited, &added, &Placement::Prepend), Some(vec![ ("0", true), ("1", true), ("0", false), ("5", false) ]));
+        assert_eq!(extend(&inherited, &added, &Placement::Before("5")), Some(vec![ ("0", false), ("0", true), ("1", true), ("5", false) ]));
+        assert_eq!(extend(&inherited, &added, &Placement::After("05")), Some(vec![ ("0", false), ("5", false), ("0", true), ("1", true) ]));
+        assert_eq!(extend(&inherited, &added, &Placement::After("1")), None);
+    }
+}
@@ -0,0 +1,478 @@
+use ::parse::*;
+use ::flat::{ FlatObject, Lazy };
+use ::document::Document;
+
+
+/// converts between byte offsets and lsp positions,
+/// which count lines and utf-16 code units inside the line
+pub struct LineIndex<'s> {
+    source: Source<'s>,
+    line_starts: Vec<usize>,
+}
+
+/// where an object is declared, e.g. `name` in `name: 'Peter'`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Definition {
+    pub path: Vec<String>,
+    pub span: Span,
+}
+
+/// one identifier of a prototype reference, e.g. `b` in `a.b.c`, which refers to the path `a.b`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Usage {
+    pub path: Vec<String>,
+    pub span: Span,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub severity: Severity,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    /// the document cannot be resolved
+    Error,
+
+    /// the document can be resolved, but probably not as intended
+    Warning,
+}
+
+/// all symbols of a document, and the problems found in it
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Analysis {
+    pub definitions: Vec<Definition>,
+    pub usages: Vec<Usage>,
+    pub diagnostics: Vec<Diagnostic>,
+
+    /// every reference, like `a.b`, and where it is written, to locate errors that only name the reference
+    references: Vec<(String, Span)>,
+
+    /// the prototypes of compounds with several prototypes, like `A + B`, and where they are listed
+    prototype_lists: Vec<(String, Span)>,
+}
+
+
+impl<'s> LineIndex<'s> {
+    pub fn new(source: Source<'s>) -> Self {
+        let line_starts = ::std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+
+        LineIndex { source, line_starts }
+    }
+
+    /// returns (line, character)
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.source.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+
+        let line_start = self.line_starts[line];
+        let character = self.source[line_start .. offset].encode_utf16().count();
+        (line, character)
+    }
+
+    /// clamps positions outside of the source to the nearest valid offset
+    pub fn offset(&self, line: usize, character: usize) -> usize {
+        let line_start = match self.line_starts.get(line) {
+            Some(&start) => start,
+            None => return self.source.len(),
+        };
+
+        let line_end = self.line_starts.get(line + 1).map_or(self.source.len(), |next| next - 1);
+        let mut units = 0;
+
+        for (index, symbol) in self.source[line_start .. line_end].char_indices() {
+            if units >= character {
+                return line_start + index;
+            }
+
+            units += symbol.len_utf16();
+        }
+
+        line_end
+    }
+}
+
+
+impl Analysis {
+    pub fn of(source: Source) -> Analysis {
+        match ::parse::parse(source) {
+            Ok(parsed) => Self::of_parsed(&parsed),
+            Err(error) => Analysis {
+                diagnostics: vec![ Diagnostic::error(error.span(source), error.to_string()) ],
+                .. Analysis::default()
+            }
+        }
+    }
+
+    /// uses the parse result the document keeps up to date while it is edited, instead of parsing it again
+    pub fn of_document(document: &Document) -> Analysis {
+        match document.parsed() {
+            Ok(parsed) => Self::of_parsed(&parsed),
+            Err(error) => Analysis {
+                diagnostics: vec![ Diagnostic::error(error.span(document.source()), error.to_string()) ],
+                .. Analysis::default()
+            }
+        }
+    }
+
+    pub fn of_parsed(parsed: &NamedObjects) -> Analysis {
+        let mut analysis = Analysis::default();
+        analysis.collect_named_objects(parsed, parsed, &mut Vec::new());
+        analysis.collect_resolved(parsed);
+        analysis.definitions.sort_by_key(|definition| definition.span.start);
+        analysis.usages.sort_by_key(|usage| usage.span.start);
+        analysis.diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        analysis
+    }
+
+    fn collect_named_objects<'o>(
+        &mut self,
+        objects: &'o NamedObjects<'o>,
+        world: &'o NamedObjects<'o>,
+        path: &mut Vec<String>
+    ) {
+        for (name, identifier, index) in objects.in_declaration_order() {
+            path.push(name.into_owned());
+
+            // unnamed list entries have no name to point at
+            if !identifier.name.is_empty() {
+                self.definitions.push(Definition { path: path.clone(), span: identifier.location.unwrap_or_default() });
+            }
+
+            match objects.objects[index] {
+                Object::Template(ref parts) => self.collect_template(parts, world),
+                Object::Expression(ref expression) => self.collect_expression(expression, world),
+
+                ref object => if let Some(compound) = object.as_compound() {
+                    self.collect_compound(compound, world, path);
+                },
+            }
+
+            path.pop();
+        }
+
+        // the overrides of a conditional belong to the same compound
+        for conditional in &objects.conditionals {
+            self.collect_expression(&conditional.condition, world);
+            self.collect_named_objects(&conditional.overrides, world, path);
+        }
+    }
+
+    fn collect_compound<'o>(
+        &mut self,
+        compound: &'o Compound<'o>,
+        world: &'o NamedObjects<'o>,
+        path: &mut Vec<String>
+    ) {
+        for prototype in &compound.prototypes {
+            self.collect_reference(prototype, world);
+        }
+
+        if compound.prototypes.len() > 1 {
+            let prototypes: Vec<String> = compound.prototypes.iter().map(Reference::to_string).collect();
+            let first = compound.prototypes[0].location().unwrap_or_default();
+            let last = compound.prototypes[compound.prototypes.len() - 1].location().unwrap_or_default();
+            self.prototype_lists.push((prototypes.join(" + "), Span { start: first.start, end: last.end }));
+        }
+
+        self.collect_named_objects(&compound.overrides, world, path);
+    }
+
+    fn collect_template<'o>(&mut self, parts: &'o [TemplatePart<'o>], world: &'o NamedObjects<'o>) {
+        for part in parts {
+            if let TemplatePart::Reference(ref reference) = *part {
+                self.collect_reference(reference, world);
+            }
+        }
+    }
+
+    fn collect_expression<'o>(&mut self, expression: &'o Expression<'o>, world: &'o NamedObjects<'o>) {
+        match expression.operation {
+            Operation::Literal(_) | Operation::Parameter(_) => {},
+            Operation::Template(ref parts) => self.collect_template(parts, world),
+            Operation::Reference(ref reference) => self.collect_reference(reference, world),
+            Operation::Unary(_, ref operand) => self.collect_expression(operand, world),
+
+            Operation::Binary(_, ref left, ref right) => {
+                self.collect_expression(left, world);
+                self.collect_expression(right, world);
+            },
+
+            Operation::Conditional(ref condition, ref then, ref otherwise) => {
+                for operand in &[ condition, then, otherwise ] {
+                    self.collect_expression(operand, world);
+                }
+            },
+
+            Operation::Call(_, ref arguments) => for argument in arguments {
+                self.collect_expression(argument, world);
+            },
+        }
+    }
+
+    /// every identifier of the reference uses the path up to that identifier.
+    /// only the declarations are looked up, the document is resolved once, see `collect_resolved`
+    fn collect_reference<'o>(&mut self, reference: &'o Reference<'o>, world: &'o NamedObjects<'o>) {
+        let mut referenced_path = Vec::new();
+        for identifier in &reference.identifiers {
+            referenced_path.push(identifier.name.to_string());
+            self.usages.push(Usage { path: referenced_path.clone(), span: identifier.location.unwrap_or_default() });
+        }
+
+        let span = reference.location().unwrap_or_default();
+        self.references.push((reference.to_string(), span));
+
+        match world.resolve_reference(reference) {
+            // the compound may inherit the property instead of declaring it
+            Err(ResolveError::ReferenceNotFound { .. }) if world.get(reference.identifiers[0].name).is_some() => {},
+            Err(error) => self.diagnostics.push(Diagnostic::error(span, error.to_string())),
+            Ok(_) => {},
+        }
+    }
+
+    /// resolves the whole document once, for the first error that was not found by looking up the declarations,
+    /// and for the properties which are inherited from several prototypes, reported at the inheriting compound
+    fn collect_resolved(&mut self, parsed: &NamedObjects) {
+        let trace = match FlatObject::build_traced(parsed) {
+            Ok((_, trace)) => trace,
+            Err(error) => return self.collect_error(parsed, error),
+        };
+
+        for conflict in trace.conflicts {
+            let (name, compound) = conflict.path.split_last().expect("conflicts are always properties");
+            let span = match self.definition(compound) {
+                Some(definition) => definition.span,
+                None => continue, // inherited compounds are reported at their declaration
+            };
+
+            let message = format!(
+                "`{}` is inherited from both `{}` and `{}`, `{}` takes precedence",
+                name, conflict.chosen.name, conflict.ignored.name, conflict.chosen.name
+            );
+
+            self.diagnostics.push(Diagnostic { span, message, severity: Severity::Warning });
+        }
+    }
+
+    /// cycles are reported at the references that lead into them, and contradicting prototypes where they are listed.
+    /// other errors without a span are reported at the top-level declaration that cannot be resolved
+    fn collect_error(&mut self, parsed: &NamedObjects, error: ResolveError) {
+        let message = error.to_string();
+        if self.diagnostics.iter().any(|diagnostic| diagnostic.message == message) {
+            return;
+        }
+
+        let named = |lists: &[(String, Span)], name: &str| -> Vec<Span> {
+            lists.iter().filter(|&(listed, _)| listed == name).map(|&(_, span)| span).collect()
+        };
+
+        let mut spans = match error {
+            ResolveError::CyclicReference { ref identifier } => named(&self.references, identifier),
+            ResolveError::InconsistentPrototypeOrder { ref prototypes } => named(&self.prototype_lists, prototypes),
+            _ => error.span().into_iter().collect(),
+        };
+
+        if spans.is_empty() {
+            let mut lazy = Lazy::new(parsed);
+            let declaration = parsed.in_declaration_order().into_iter()
+                .find(|(name, _, _)| lazy.get(name).err().as_ref() == Some(&error))
+                .or_else(|| parsed.in_declaration_order().into_iter().next());
+
+            spans.extend(declaration.map(|(_, identifier, _)| identifier.location.unwrap_or_default()));
+        }
+
+        for span in spans {
+            self.diagnostics.push(Diagnostic::error(span, message.clone()));
+        }
+    }
+
+    /// the path of the definition or usage at the cursor
+    pub fn symbol_at(&self, offset: usize) -> Option<&[String]> {
+        self.definitions.iter().map(|definition| (&definition.path, definition.span))
+            .chain(self.usages.iter().map(|usage| (&usage.path, usage.span)))
+            .find(|&(_, span)| span.touches(offset))
+            .map(|(path, _)| path.as_slice())
+    }
+
+    pub fn definition<S: AsRef<str>>(&self, path: &[S]) -> Option<&Definition> {
+        self.definitions.iter().find(|definition| paths_equal(&definition.path, path))
+    }
+
+    pub fn usages_of<'a, S: AsRef<str>>(&'a self, path: &'a [S]) -> impl Iterator<Item = &'a Usage> + 'a {
+        self.usages.iter().filter(move |usage| paths_equal(&usage.path, path))
+    }
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: String) -> Self {
+        Diagnostic { span, message, severity: Severity::Error }
+    }
+}
+
+fn paths_equal<S: AsRef<str>>(path: &[String], other: &[S]) -> bool {
+    path.len() == other.len() && path.iter().zip(other).all(|(a, b)| a == b.as_ref())
+}
+
+
+/// the prototype references of the innermost `{` that is still open at the cursor,
+/// found without parsing, because the document is probably incomplete while typing
+pub fn enclosing_prototypes<'s>(source: Source<'s>, offset: usize) -> Option<Vec<Vec<&'s str>>> {
+    let mut open_braces = Vec::new();
+    let mut in_literal = false;
+
+    for (index, symbol) in source[.. offset.min(source.len())].char_indices() {
+        match symbol {
+            '\'' => in_literal = !in_literal,
+            '{' if !in_literal => open_braces.push(index),
+            '}' if !in_literal => { open_braces.pop(); },
+            _ => {},
+        }
+    }
+
+    // the prototypes are listed after the colon, separated by `+`
+    let before_brace = source[.. *open_braces.last()?].trim_end();
+    let prototypes_start = before_brace.rfind(|symbol: char| ":{}".contains(symbol)).map_or(0, |index| index + 1);
+
+    Some(
+        before_brace[prototypes_start ..].split('+')
+            .filter_map(|prototype| prototype.split_whitespace().last())
+            .map(|prototype| prototype.split('.').filter(|name| !name.is_empty()).collect())
+            .collect()
+    )
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_index(){
+        let source = "a: 'ä'\nb: '😀x'\n";
+        let lines = LineIndex::new(source);
+
+        assert_eq!(lines.position(0), (0, 0));
+        assert_eq!(lines.position(source.find('b').unwrap()), (1, 0));
+        assert_eq!(lines.position(source.find('x').unwrap()), (1, 6));
+        assert_eq!(lines.offset(1, 6), source.find('x').unwrap());
+        assert_eq!(lines.offset(0, 100), source.find('\n').unwrap());
+        assert_eq!(lines.offset(9, 0), source.len());
+    }
+
+    #[test]
+    fn test_symbols(){
+        let source = "text: { cancel: 'Abbrechen' }\nbutton: { label: text.cancel }";
+        let analysis = Analysis::of(source);
+
+        let usage = source.rfind("cancel").unwrap();
+        assert_eq!(analysis.symbol_at(usage + 2), Some(&["text".to_string(), "cancel".to_string()][..]));
+
+        let definition = analysis.definition(&["text", "cancel"]).unwrap();
+        assert_eq!(definition.span.start, source.find("cancel").unwrap());
+        assert_eq!(analysis.usages_of(&["text"]).count(), 1);
+        assert!(analysis.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_conditional_symbols(){
+        let source = "dark: 'dark'\nButton: { theme: 'light' if $theme == dark { background: 'black' } }";
+        let analysis = Analysis::of(source);
+
+        let usage = source.rfind("dark {").unwrap();
+        assert_eq!(analysis.symbol_at(usage + 1), Some(&["dark".to_string()][..]));
+
+        let definition = analysis.definition(&["Button", "background"]).unwrap();
+        assert_eq!(definition.span.start, source.find("background").unwrap());
+        assert!(analysis.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_diagnostics(){
+        let source = "a: 'x'\nb: missing\nc: a.b";
+        let messages: Vec<(usize, String)> = Analysis::of(source).diagnostics.into_iter()
+            .map(|diagnostic| (diagnostic.span.start, diagnostic.message))
+            .collect();
+
+        assert_eq!(messages, vec![
+            (source.find("missing").unwrap(), String::from("no object named `missing`")),
+            (source.find("a.b").unwrap(), String::from("a string literal has no properties")),
+        ]);
+
+        // reported at the references to where the cycle was found, `c` is not part of the cycle, but its target is
+        let cycle = "a: b\nb: a\nc: b";
+        let spans: Vec<usize> = Analysis::of(cycle).diagnostics.iter()
+            .map(|diagnostic| diagnostic.span.start).collect();
+
+        assert_eq!(spans, vec![ 3, 13 ]);
+
+        let inconsistent = "X: {} Y: {} A: X + Y {} B: Y + X {} C: A + B {}";
+        assert_eq!(Analysis::of(inconsistent).diagnostics, vec![ Diagnostic::error(
+            Span { start: inconsistent.find("A + B").unwrap(), end: inconsistent.len() - 3 },
+            String::from("the prototypes of `A + B` inherit in contradicting orders")
+        ) ]);
+
+        let conflict = "Clickable: { label: 'click' }\nStyled: { label: 'styled' }\nButton: Clickable + Styled { }";
+        let warning = &Analysis::of(conflict).diagnostics[0];
+        assert_eq!(warning.severity, Severity::Warning);
+        assert_eq!(warning.span.start, conflict.find("Button").unwrap());
+        assert_eq!(warning.message, "`label` is inherited from both `Clickable` and `Styled`, `Clickable` takes precedence");
+
+        let template = "person: { name: 'Peter' }\ngreeting: 'Hi ${person.name}, ${person}'";
+        let analysis = Analysis::of(template);
+        assert_eq!(analysis.usages_of(&["person", "name"]).count(), 1);
+        assert_eq!(analysis.diagnostics, vec![ Diagnostic::error(
+            Span { start: template.rfind("person").unwrap(), end: template.len() - 2 },
+            String::from("`person` is not a string literal and cannot be inserted into one")
+        ) ]);
+
+        let expression = "size: '4'\nname: 'x'\narea: (size * name ~ missing)";
+        let messages: Vec<(&str, String)> = Analysis::of(expression).diagnostics.into_iter()
+            .map(|diagnostic| (&expression[diagnostic.span.start .. diagnostic.span.end], diagnostic.message))
+            .collect();
+
+        assert_eq!(messages, vec![
+            ("size * name", String::from("expected a number, found `'x'`")),
+            ("missing", String::from("no object named `missing`")),
+        ]);
+
+        let list = "Page: { nav: { : 'home' } }\nAbout: Page { nav: !insert_before 3 { : 'about' } }";
+        assert_eq!(Analysis::of(list).diagnostics, vec![ Diagnostic::error(
+            Span { start: list.find('3').unwrap(), end: list.find('3').unwrap() + 1 },
+            String::from("the inherited list has no entry `3`")
+        ) ]);
+
+        let parameter = "a: ($x)";
+        assert_eq!(Analysis::of(parameter).diagnostics, vec![ Diagnostic::error(
+            Span { start: 4, end: 6 }, String::from("`$x` can only be used in conditions")
+        ) ]);
+
+        let index = "a: { : 'x' 0: 'y' }";
+        assert_eq!(Analysis::of(index).diagnostics.iter().map(|diagnostic| diagnostic.span.start).collect::<Vec<_>>(), vec![ 5 ]);
+
+        // an error without a span is reported at the top-level declaration that cannot be resolved
+        let inherited = "A: { b: 'x' }\na: A\nc: a.d";
+        assert_eq!(Analysis::of(inherited).diagnostics, vec![ Diagnostic::error(
+            Span { start: 19, end: 20 }, String::from("no object named `d`")
+        ) ]);
+        assert!(Analysis::of("A: { b: 'x' }\na: A\nc: a.b").diagnostics.is_empty());
+
+        let unclosed = Analysis::of("a: { b: 'x' ");
+        assert_eq!(unclosed.diagnostics[0].span, Span { start: 12, end: 12 });
+    }
+
+    #[test]
+    fn test_enclosing_prototypes(){
+        assert_eq!(enclosing_prototypes("a: Button { t", 13), Some(vec![vec!["Button"]]));
+        assert_eq!(enclosing_prototypes("a: x.Button{ b: '{' ", 19), Some(vec![vec!["x", "Button"]]));
+        assert_eq!(enclosing_prototypes("a: Button { } b: ", 17), None);
+        assert_eq!(enclosing_prototypes("a: { b: Label { } ", 18), Some(vec![]));
+        assert_eq!(enclosing_prototypes("a: Clickable +\n x.Styled { ", 27), Some(vec![vec!["Clickable"], vec!["x", "Styled"]]));
+    }
+}
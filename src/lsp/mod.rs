@@ -0,0 +1,672 @@
+//! A language server for proto-templates, speaking the language server protocol.
+//! Documents are synchronized incrementally, and only the declarations that contain a change are parsed again,
+//! see `Document::apply_edit`.
+
+pub mod analysis;
+pub mod transport;
+
+use ::std::collections::HashMap;
+use ::std::io::{ self, BufRead, Write };
+use ::json::Json;
+use ::parse::{ Reference, Identifier, Span };
+use ::flat::FlatObject;
+use ::document::Document;
+use ::owned;
+use self::analysis::{ Analysis, LineIndex, Severity };
+
+
+/// json-rpc error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+
+type RequestResult = ::std::result::Result<Json, (i64, String)>;
+
+pub struct Server {
+    documents: HashMap<String, OpenDocument>,
+    shutdown_requested: bool,
+    exited: bool,
+}
+
+struct OpenDocument {
+    document: Document,
+
+    /// the analysis of the current version, which every request uses
+    analysis: Analysis,
+
+    /// the most recent version which could be parsed, used for completions while typing
+    last_parsed: Option<owned::ParsedDocument>,
+}
+
+
+/// serves a single client until it exits or the input ends
+pub fn run<R: BufRead, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    Server::new().serve(reader, writer)
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server { documents: HashMap::new(), shutdown_requested: false, exited: false }
+    }
+
+    /// true after the client sent `exit`
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// handles framed messages until the input ends or the client exits
+    pub fn serve<R: BufRead, W: Write>(&mut self, reader: &mut R, writer: &mut W) -> io::Result<()> {
+        while !self.exited {
+            let content = match transport::read_message(reader)? {
+                Some(content) => content,
+                None => break,
+            };
+
+            let outgoing = match Json::parse(&content) {
+                Ok(message) => self.handle(&message),
+                Err(error) => vec![ error_response(Json::Null, PARSE_ERROR, format!("{:?}", error)) ],
+            };
+
+            for message in outgoing {
+                transport::write_message(writer, &message.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// returns the responses and notifications that should be sent to the client
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = match message.get("method").and_then(Json::as_str) {
+            Some(method) => method,
+            None => return Vec::new(), // a response to a request we never send
+        };
+
+        let empty = Json::Object(Vec::new());
+        let params = message.get("params").unwrap_or(&empty);
+
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.handle_notification(method, params),
+        };
+
+        let result = if self.shutdown_requested && method != "shutdown" {
+            Err((INVALID_REQUEST, String::from("the server is shutting down")))
+        } else {
+            self.handle_request(method, params)
+        };
+
+        vec![ match result {
+            Ok(result) => Json::object(vec![
+                ("jsonrpc", Json::string("2.0")), ("id", id), ("result", result)
+            ]),
+
+            Err((code, message)) => error_response(id, code, message),
+        } ]
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params.get_path(&["textDocument", "uri"]).and_then(Json::as_str)
+            .map(str::to_string);
+
+        match (method, uri) {
+            ("exit", _) => {
+                self.exited = true;
+                Vec::new()
+            },
+
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params.get_path(&["textDocument", "text"]).and_then(Json::as_str).unwrap_or("");
+                self.documents.insert(uri.clone(), OpenDocument { document: Document::new(text), analysis: Analysis::default(), last_parsed: None });
+                self.update_document(uri)
+            },
+
+            ("textDocument/didChange", Some(uri)) => {
+                let (document, changes) = match (self.documents.get_mut(&uri), params.get("contentChanges").and_then(Json::as_array)) {
+                    (Some(open), Some(changes)) => (&mut open.document, changes),
+                    _ => return Vec::new(),
+                };
+
+                // each change applies to the text after the previous ones
+                for change in changes {
+                    let text = change.get("text").and_then(Json::as_str).unwrap_or("");
+                    let span = match change.get("range") {
+                        Some(range) => span(&LineIndex::new(document.source()), range),
+                        None => Span { start: 0, end: document.source().len() },
+                    };
+
+                    // a span outside of the text leaves the document as it is
+                    let _ = document.apply_edit(span, text);
+                }
+
+                self.update_document(uri)
+            },
+
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+                vec![ publish_diagnostics(&uri, Vec::new()) ]
+            },
+
+            _ => Vec::new(),
+        }
+    }
+
+    fn update_document(&mut self, uri: String) -> Vec<Json> {
+        let document = self.documents.get_mut(&uri).expect("update_document: document must be open");
+        document.analysis = Analysis::of_document(&document.document);
+
+        if let Ok(parsed) = document.document.parsed() {
+            document.last_parsed = Some(owned::ParsedDocument::new(document.document.source(), &parsed));
+        }
+
+        let lines = LineIndex::new(document.document.source());
+        let diagnostics = document.analysis.diagnostics.iter().map(|diagnostic| Json::object(vec![
+            ("range", range(&lines, diagnostic.span)),
+            ("severity", Json::Number(match diagnostic.severity {
+                Severity::Error => 1.0,
+                Severity::Warning => 2.0,
+            })),
+            ("source", Json::string("ptl")),
+            ("message", Json::string(diagnostic.message.as_str())),
+        ])).collect();
+
+        vec![ publish_diagnostics(&uri, diagnostics) ]
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Json) -> RequestResult {
+        match method {
+            "initialize" => Ok(Json::object(vec![
+                ("capabilities", Json::object(vec![
+                    ("textDocumentSync", Json::Number(2.0)), // incremental
+                    ("definitionProvider", Json::Bool(true)),
+                    ("referencesProvider", Json::Bool(true)),
+                    ("hoverProvider", Json::Bool(true)),
+                    ("renameProvider", Json::Bool(true)),
+                    ("completionProvider", Json::object(vec![
+                        ("triggerCharacters", Json::Array(vec![ Json::string("{") ])),
+                    ])),
+                ])),
+
+                ("serverInfo", Json::object(vec![ ("name", Json::string("ptl-lsp")) ])),
+            ])),
+
+            "shutdown" => {
+                self.shutdown_requested = true;
+                Ok(Json::Null)
+            },
+
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/rename" => self.rename(params),
+
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method `{}`", method))),
+        }
+    }
+
+    /// the uri, the document, and the byte offset of the position in the params
+    fn document_position<'p>(&self, params: &'p Json) -> ::std::result::Result<(&'p str, &OpenDocument, usize), (i64, String)> {
+        let uri = params.get_path(&["textDocument", "uri"]).and_then(Json::as_str)
+            .ok_or_else(|| (INVALID_PARAMS, String::from("missing text document")))?;
+
+        let document = self.documents.get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("document `{}` is not open", uri)))?;
+
+        let line = params.get_path(&["position", "line"]).and_then(Json::as_u64);
+        let character = params.get_path(&["position", "character"]).and_then(Json::as_u64);
+
+        match (line, character) {
+            (Some(line), Some(character)) => {
+                let offset = LineIndex::new(document.document.source()).offset(line as usize, character as usize);
+                Ok((uri, document, offset))
+            },
+
+            _ => Err((INVALID_PARAMS, String::from("missing position"))),
+        }
+    }
+
+    fn definition(&self, params: &Json) -> RequestResult {
+        let (uri, document, offset) = self.document_position(params)?;
+        let analysis = &document.analysis;
+
+        Ok(
+            analysis.symbol_at(offset)
+                .and_then(|path| analysis.definition(path))
+                .map_or(Json::Null, |definition| location(uri, document.document.source(), definition.span))
+        )
+    }
+
+    fn references(&self, params: &Json) -> RequestResult {
+        let (uri, document, offset) = self.document_position(params)?;
+        let analysis = &document.analysis;
+        let include_declaration = params.get_path(&["context", "includeDeclaration"])
+            .and_then(Json::as_bool).unwrap_or(false);
+
+        let path = match analysis.symbol_at(offset) {
+            Some(path) => path,
+            None => return Ok(Json::Null),
+        };
+
+        let declaration = analysis.definition(path).filter(|_| include_declaration)
+            .map(|definition| definition.span);
+
+        Ok(Json::Array(
+            declaration.into_iter()
+                .chain(analysis.usages_of(path).map(|usage| usage.span))
+                .map(|span| location(uri, document.document.source(), span))
+                .collect()
+        ))
+    }
+
+    fn hover(&self, params: &Json) -> RequestResult {
+        let (_, document, offset) = self.document_position(params)?;
+        let analysis = &document.analysis;
+        let parsed = document.document.parsed();
+
+        let (path, parsed) = match (analysis.symbol_at(offset), parsed) {
+            (Some(path), Ok(parsed)) => (path, parsed),
+            _ => return Ok(Json::Null),
+        };
+
+        let reference = Reference {
+            identifiers: path.iter().map(|name| Identifier::new(name)).collect()
+        };
+
+        let contents = match FlatObject::resolve(&parsed, &reference) {
+            Ok(resolved) => format!("```\n{}: {}\n```", reference, resolved),
+            Err(error) => format!("`{}` cannot be resolved: {}", reference, error),
+        };
+
+        Ok(Json::object(vec![
+            ("contents", Json::object(vec![
+                ("kind", Json::string("markdown")),
+                ("value", Json::string(contents)),
+            ])),
+        ]))
+    }
+
+    /// suggests the properties of the prototypes of the compound around the cursor
+    fn completion(&self, params: &Json) -> RequestResult {
+        let (_, document, offset) = self.document_position(params)?;
+
+        let prototypes = match analysis::enclosing_prototypes(document.document.source(), offset) {
+            Some(prototypes) => prototypes,
+            None => return Ok(Json::Array(Vec::new())),
+        };
+
+        let parsed = match document.last_parsed {
+            Some(ref parsed) => parsed.to_objects(),
+            None => return Ok(Json::Array(Vec::new())),
+        };
+
+        let references: Vec<Reference> = prototypes.iter()
+            .map(|prototype| Reference { identifiers: prototype.iter().map(|&name| Identifier::new(name)).collect() })
+            .collect();
+
+        // earlier prototypes take precedence, like when resolving
+        let mut properties = HashMap::new();
+        for reference in &references {
+            if let Ok(FlatObject::Compound(inherited)) = FlatObject::resolve(&parsed, reference) {
+                for (name, value) in inherited.iter() {
+                    properties.entry(name.clone()).or_insert_with(|| value.clone());
+                }
+            }
+        }
+
+        let mut names: Vec<&String> = properties.keys().collect();
+        names.sort();
+
+        Ok(Json::Array(names.into_iter().map(|name| Json::object(vec![
+            ("label", Json::string(name.as_str())),
+            ("kind", Json::Number(10.0)), // property
+            ("detail", Json::string(match properties[name] {
+                FlatObject::StringLiteral(_) => properties[name].to_string(),
+                FlatObject::Compound(_) => String::from("{ … }"),
+            })),
+        ])).collect()))
+    }
+
+    fn rename(&self, params: &Json) -> RequestResult {
+        let (uri, document, offset) = self.document_position(params)?;
+        let new_name = params.get("newName").and_then(Json::as_str)
+            .ok_or_else(|| (INVALID_PARAMS, String::from("missing new name")))?;
+
+        if !::parse::is_valid_name(new_name) {
+            return Err((INVALID_PARAMS, format!("`{}` is not a valid name", new_name)));
+        }
+
+        let analysis = &document.analysis;
+        let path = analysis.symbol_at(offset)
+            .ok_or_else(|| (INVALID_PARAMS, String::from("there is no symbol at this position")))?;
+
+        // every usage of the path renames its last identifier
+        let lines = LineIndex::new(document.document.source());
+        let edits = analysis.definition(path).map(|definition| definition.span).into_iter()
+            .chain(analysis.usages_of(path).map(|usage| usage.span))
+            .map(|span| Json::object(vec![
+                ("range", range(&lines, span)),
+                ("newText", Json::string(new_name)),
+            ]))
+            .collect();
+
+        Ok(Json::object(vec![
+            ("changes", Json::Object(vec![ (uri.to_string(), Json::Array(edits)) ])),
+        ]))
+    }
+}
+
+
+fn error_response(id: Json, code: i64, message: String) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::string("2.0")),
+        ("id", id),
+        ("error", Json::object(vec![
+            ("code", Json::Number(code as f64)),
+            ("message", Json::String(message)),
+        ])),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::string("2.0")),
+        ("method", Json::string("textDocument/publishDiagnostics")),
+        ("params", Json::object(vec![
+            ("uri", Json::string(uri)),
+            ("diagnostics", Json::Array(diagnostics)),
+        ])),
+    ])
+}
+
+fn position(lines: &LineIndex, offset: usize) -> Json {
+    let (line, character) = lines.position(offset);
+    Json::object(vec![
+        ("line", Json::Number(line as f64)),
+        ("character", Json::Number(character as f64)),
+    ])
+}
+
+fn range(lines: &LineIndex, span: Span) -> Json {
+    Json::object(vec![
+        ("start", position(lines, span.start)),
+        ("end", position(lines, span.end)),
+    ])
+}
+
+/// the span of an lsp range in the text
+fn span(lines: &LineIndex, range: &Json) -> Span {
+    let offset = |position: &str| {
+        let line = range.get_path(&[position, "line"]).and_then(Json::as_u64).unwrap_or(0);
+        let character = range.get_path(&[position, "character"]).and_then(Json::as_u64).unwrap_or(0);
+        lines.offset(line as usize, character as usize)
+    };
+
+    Span { start: offset("start"), end: offset("end") }
+}
+
+fn location(uri: &str, text: &str, span: Span) -> Json {
+    Json::object(vec![
+        ("uri", Json::string(uri)),
+        ("range", range(&LineIndex::new(text), span)),
+    ])
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const URI: &str = "file:///comics.ptl";
+
+    /// line and character
+    type Position = (u64, u64);
+
+    /// talks to a server in the same process, through the same framing as stdio
+    struct Client {
+        server: Server,
+        next_id: u64,
+        notifications: Vec<Json>,
+    }
+
+    impl Client {
+        fn new() -> Self {
+            let mut client = Client { server: Server::new(), next_id: 0, notifications: Vec::new() };
+            let initialized = client.request("initialize", Json::object(vec![]));
+            assert!(initialized.get_path(&["capabilities", "hoverProvider"]).is_some());
+            client.notify("initialized", Json::object(vec![]));
+            client
+        }
+
+        fn exchange(&mut self, message: Json) -> Vec<Json> {
+            let mut input = Vec::new();
+            transport::write_message(&mut input, &message.to_string()).unwrap();
+
+            let mut output = Vec::new();
+            self.server.serve(&mut io::Cursor::new(input), &mut output).unwrap();
+
+            let mut output = io::Cursor::new(output);
+            let mut messages = Vec::new();
+            while let Some(content) = transport::read_message(&mut output).unwrap() {
+                messages.push(Json::parse(&content).unwrap());
+            }
+
+            messages
+        }
+
+        fn notify(&mut self, method: &str, params: Json) {
+            let received = self.exchange(Json::object(vec![
+                ("jsonrpc", Json::string("2.0")), ("method", Json::string(method)), ("params", params)
+            ]));
+
+            self.notifications.extend(received);
+        }
+
+        /// returns the result, panics on error responses
+        fn request(&mut self, method: &str, params: Json) -> Json {
+            self.next_id += 1;
+            let id = Json::Number(self.next_id as f64);
+
+            let mut received = self.exchange(Json::object(vec![
+                ("jsonrpc", Json::string("2.0")), ("id", id.clone()),
+                ("method", Json::string(method)), ("params", params)
+            ]));
+
+            assert_eq!(received.len(), 1);
+            let response = received.remove(0);
+            assert_eq!(response.get("id"), Some(&id));
+            response.get("result").cloned().unwrap_or_else(|| panic!("error response: {}", response))
+        }
+
+        fn open(&mut self, text: &str) {
+            self.notify("textDocument/didOpen", Json::object(vec![
+                ("textDocument", Json::object(vec![
+                    ("uri", Json::string(URI)), ("languageId", Json::string("ptl")),
+                    ("version", Json::Number(1.0)), ("text", Json::string(text)),
+                ])),
+            ]));
+        }
+
+        fn change(&mut self, text: &str) {
+            self.notify("textDocument/didChange", Json::object(vec![
+                ("textDocument", Json::object(vec![ ("uri", Json::string(URI)), ("version", Json::Number(2.0)) ])),
+                ("contentChanges", Json::Array(vec![ Json::object(vec![ ("text", Json::string(text)) ]) ])),
+            ]));
+        }
+
+        /// replaces the text between the positions
+        fn edit(&mut self, edits: &[(Position, Position, &str)]) {
+            let position = |(line, character): Position| Json::object(vec![
+                ("line", Json::Number(line as f64)), ("character", Json::Number(character as f64)),
+            ]);
+
+            let changes = edits.iter().map(|&(start, end, text)| Json::object(vec![
+                ("range", Json::object(vec![ ("start", position(start)), ("end", position(end)) ])),
+                ("text", Json::string(text)),
+            ])).collect();
+
+            self.notify("textDocument/didChange", Json::object(vec![
+                ("textDocument", Json::object(vec![ ("uri", Json::string(URI)), ("version", Json::Number(2.0)) ])),
+                ("contentChanges", Json::Array(changes)),
+            ]));
+        }
+
+        fn at(&mut self, method: &str, line: usize, character: usize, mut extra: Vec<(&str, Json)>) -> Json {
+            extra.push(("textDocument", Json::object(vec![ ("uri", Json::string(URI)) ])));
+            extra.push(("position", Json::object(vec![
+                ("line", Json::Number(line as f64)), ("character", Json::Number(character as f64)),
+            ])));
+
+            self.request(method, Json::object(extra))
+        }
+
+        fn last_diagnostics(&self) -> Vec<String> {
+            self.notifications.iter().rev()
+                .find(|notification| notification.get("method").and_then(Json::as_str) == Some("textDocument/publishDiagnostics"))
+                .and_then(|notification| notification.get_path(&["params", "diagnostics"]))
+                .and_then(Json::as_array).unwrap_or(&[])
+                .iter().map(|diagnostic| diagnostic.get("message").and_then(Json::as_str).unwrap().to_string())
+                .collect()
+        }
+    }
+
+    fn start(range: &Json) -> (u64, u64) {
+        (
+            range.get_path(&["start", "line"]).and_then(Json::as_u64).unwrap(),
+            range.get_path(&["start", "character"]).and_then(Json::as_u64).unwrap(),
+        )
+    }
+
+    const COMICS: &str = "Comic: {\n    title: 'Untitled'\n    published: 'true'\n}\nspiderman: Comic { title: 'Spiderman' }\nsuperman: Comic\n";
+
+    #[test]
+    fn test_diagnostics(){
+        let mut client = Client::new();
+        client.open(COMICS);
+        assert!(client.last_diagnostics().is_empty());
+
+        client.change("a: missing\nb: { c: 'x'");
+        assert_eq!(client.last_diagnostics(), vec![ String::from("expected `}`, found end of input") ]);
+
+        client.change("a: missing");
+        assert_eq!(client.last_diagnostics(), vec![ String::from("no object named `missing`") ]);
+
+        let diagnostic = &client.notifications.last().unwrap().get_path(&["params", "diagnostics"]).unwrap().as_array().unwrap()[0];
+        assert_eq!(start(diagnostic.get("range").unwrap()), (0, 3));
+    }
+
+    #[test]
+    fn test_incremental_sync(){
+        let mut client = Client::new();
+        client.open(COMICS);
+
+        // the second change applies to the text after the first one
+        client.edit(&[ ((4, 27), (4, 36), "Batman"), ((5, 10), (5, 15), "Missing") ]);
+        assert_eq!(client.last_diagnostics(), vec![ String::from("no object named `Missing`") ]);
+
+        let hover = client.at("textDocument/hover", 4, 2, vec![]);
+        assert_eq!(
+            hover.get_path(&["contents", "value"]).and_then(Json::as_str),
+            Some("```\nspiderman: {\n    title: 'Batman'\n    published: 'true'\n}\n```")
+        );
+
+        client.edit(&[ ((5, 10), (5, 17), "Comic") ]);
+        assert!(client.last_diagnostics().is_empty());
+
+        client.edit(&[ ((3, 0), (3, 1), "") ]);
+        assert_eq!(client.last_diagnostics(), vec![ String::from("expected `}`, found end of input") ]);
+    }
+
+    #[test]
+    fn test_definition_and_references(){
+        let mut client = Client::new();
+        client.open(COMICS);
+
+        let definition = client.at("textDocument/definition", 4, 12, vec![]);
+        assert_eq!(definition.get("uri").and_then(Json::as_str), Some(URI));
+        assert_eq!(start(definition.get("range").unwrap()), (0, 0));
+
+        let references = client.at("textDocument/references", 0, 2, vec![
+            ("context", Json::object(vec![ ("includeDeclaration", Json::Bool(true)) ])),
+        ]);
+
+        let starts: Vec<(u64, u64)> = references.as_array().unwrap().iter()
+            .map(|location| start(location.get("range").unwrap())).collect();
+
+        assert_eq!(starts, vec![ (0, 0), (4, 11), (5, 10) ]);
+        assert_eq!(client.at("textDocument/definition", 1, 14, vec![]), Json::Null);
+    }
+
+    #[test]
+    fn test_hover(){
+        let mut client = Client::new();
+        client.open(COMICS);
+
+        let hover = client.at("textDocument/hover", 4, 2, vec![]);
+        assert_eq!(
+            hover.get_path(&["contents", "value"]).and_then(Json::as_str),
+            Some("```\nspiderman: {\n    title: 'Spiderman'\n    published: 'true'\n}\n```")
+        );
+    }
+
+    #[test]
+    fn test_completion(){
+        let mut client = Client::new();
+        client.open(COMICS);
+
+        // the document does not parse while typing, so the last valid version is used
+        client.change(&COMICS.replace("superman: Comic", "superman: Comic { ti"));
+        let completions = client.at("textDocument/completion", 5, 20, vec![]);
+
+        let labels: Vec<&str> = completions.as_array().unwrap().iter()
+            .map(|item| item.get("label").and_then(Json::as_str).unwrap()).collect();
+
+        assert_eq!(labels, vec![ "published", "title" ]);
+        assert_eq!(client.at("textDocument/completion", 5, 0, vec![]), Json::Array(vec![]));
+    }
+
+    #[test]
+    fn test_rename(){
+        let mut client = Client::new();
+        client.open(COMICS);
+
+        let edit = client.at("textDocument/rename", 5, 12, vec![ ("newName", Json::string("Magazine")) ]);
+        let edits = edit.get_path(&["changes", URI]).and_then(Json::as_array).unwrap();
+
+        let starts: Vec<(u64, u64)> = edits.iter().map(|edit| start(edit.get("range").unwrap())).collect();
+        assert_eq!(starts, vec![ (0, 0), (4, 11), (5, 10) ]);
+        assert!(edits.iter().all(|edit| edit.get("newText").and_then(Json::as_str) == Some("Magazine")));
+
+        for &name in &[ "a.b", "a b", "!Magazine", "a: 'x' b", "" ] {
+            let mut invalid = Vec::new();
+            transport::write_message(&mut invalid, &Json::object(vec![
+                ("jsonrpc", Json::string("2.0")), ("id", Json::Number(99.0)), ("method", Json::string("textDocument/rename")),
+                ("params", Json::object(vec![
+                    ("textDocument", Json::object(vec![ ("uri", Json::string(URI)) ])),
+                    ("position", Json::object(vec![ ("line", Json::Number(0.0)), ("character", Json::Number(1.0)) ])),
+                    ("newName", Json::string(name)),
+                ])),
+            ]).to_string()).unwrap();
+
+            let mut output = Vec::new();
+            client.server.serve(&mut io::Cursor::new(invalid), &mut output).unwrap();
+            let response = Json::parse(&transport::read_message(&mut io::Cursor::new(output)).unwrap().unwrap()).unwrap();
+            assert_eq!(response.get_path(&["error", "code"]), Some(&Json::Number(INVALID_PARAMS as f64)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_shutdown(){
+        let mut client = Client::new();
+        assert_eq!(client.request("shutdown", Json::Null), Json::Null);
+        client.notify("exit", Json::Null);
+        assert!(client.server.has_exited());
+    }
+}
@@ -0,0 +1,74 @@
+use ::std::io::{ self, BufRead, Write };
+
+
+/// reads the content of the next `Content-Length` framed message,
+/// returns none if the input ended before a message started
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut content_length = None;
+    let mut header_started = false;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return if header_started {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "message header ended unexpectedly"))
+            } else {
+                Ok(None)
+            };
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            if header_started { break; } else { continue; }
+        }
+
+        header_started = true;
+        let mut header = line.splitn(2, ':');
+        let name = header.next().unwrap_or("").trim();
+        let value = header.next().unwrap_or("").trim();
+
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.parse::<usize>().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length header")
+            })?);
+        }
+    }
+
+    let length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+
+    let mut content = vec![ 0; length ];
+    reader.read_exact(&mut content)?;
+
+    String::from_utf8(content)
+        .map(Some)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "message is not valid utf-8"))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, content: &str) -> io::Result<()> {
+    write!(writer, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+    writer.flush()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_framing(){
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, "{\"a\":\"ä\"}").unwrap();
+        write_message(&mut buffer, "[]").unwrap();
+        assert!(buffer.starts_with(b"Content-Length: 10\r\n\r\n"));
+
+        let mut reader = io::Cursor::new(buffer);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(String::from("{\"a\":\"ä\"}")));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(String::from("[]")));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        let mut truncated = io::Cursor::new(b"Content-Length: 5\r\n\r\n[]".to_vec());
+        assert!(read_message(&mut truncated).is_err());
+    }
+}
@@ -1,21 +1,17 @@
-pub mod parse;
-pub mod flat;
-pub mod referenced;
+extern crate prototype_template_language;
 
+use prototype_template_language::cli;
 
-fn main() {
-    let parsed = ::flat::FlatObject::parse(r#"
 
-        ok_text: 'Ok'
+fn main() {
+    let arguments: Vec<String> = ::std::env::args().skip(1).collect();
+    let environment = ::std::env::vars().collect();
 
-        Button: {
-            visible: 'true'
-            text: 'Click Here'
+    match cli::run(&arguments, &environment, |path| ::std::fs::read_to_string(path)) {
+        Ok(output) => println!("{}", output.trim_end_matches('\n')),
+        Err(message) => {
+            eprintln!("{}", message);
+            ::std::process::exit(1);
         }
-
-        ok_button: Button { text: ok_text }
-
-    "#);
-
-    println!("parsed: \n{:#?}", parsed.expect("Parsing Error"));
+    }
 }
@@ -0,0 +1,164 @@
+//! A map which remembers the order in which its keys were inserted,
+//! so that resolved documents can be written in declaration order.
+//! Two maps are equal if they contain the same entries, in any order.
+
+use ::std::borrow::Borrow;
+use ::std::collections::HashMap;
+use ::std::hash::Hash;
+use ::std::fmt;
+
+
+#[derive(Clone)]
+pub struct OrderedMap<K, V> {
+    entries: Vec<(K, V)>,
+
+    /// indices into `entries`
+    indices: HashMap<K, usize>,
+}
+
+
+impl<K: Hash + Eq + Clone, V> OrderedMap<K, V> {
+    pub fn new() -> Self {
+        OrderedMap { entries: Vec::new(), indices: HashMap::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        OrderedMap { entries: Vec::with_capacity(capacity), indices: HashMap::with_capacity(capacity) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<&V> where K: Borrow<Q> {
+        self.indices.get(key).map(|&index| &self.entries[index].1)
+    }
+
+    pub fn get_mut<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<&mut V> where K: Borrow<Q> {
+        let index = *self.indices.get(key)?;
+        Some(&mut self.entries[index].1)
+    }
+
+    pub fn contains_key<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> bool where K: Borrow<Q> {
+        self.indices.contains_key(key)
+    }
+
+    /// an existing key keeps its position, but its value is replaced and returned
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.indices.get(&key) {
+            Some(&index) => Some(::std::mem::replace(&mut self.entries[index].1, value)),
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            },
+        }
+    }
+
+    /// the following entries keep their order
+    pub fn remove<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<V> where K: Borrow<Q> {
+        let index = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(index);
+
+        for (following, _) in &self.entries[index ..] {
+            *self.indices.get_mut::<K>(following).expect("all keys are indexed") -= 1;
+        }
+
+        Some(value)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Default for OrderedMap<K, V> {
+    fn default() -> Self {
+        OrderedMap::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: PartialEq> PartialEq for OrderedMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Eq> Eq for OrderedMap<K, V> {}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for OrderedMap<K, V> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_map().entries(self.entries.iter().map(|(key, value)| (key, value))).finish()
+    }
+}
+
+impl<'q, K: Hash + Eq + Clone + Borrow<Q>, Q: ?Sized + Hash + Eq, V> ::std::ops::Index<&'q Q> for OrderedMap<K, V> {
+    type Output = V;
+
+    fn index(&self, key: &'q Q) -> &V {
+        self.get(key).expect("OrderedMap: key not found")
+    }
+}
+
+/// later entries replace the values of earlier ones with the same key
+impl<K: Hash + Eq + Clone, V> Extend<(K, V)> for OrderedMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) {
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> ::std::iter::FromIterator<(K, V)> for OrderedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> Self {
+        let mut map = OrderedMap::new();
+        map.extend(entries);
+        map
+    }
+}
+
+impl<K, V> IntoIterator for OrderedMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = ::std::vec::IntoIter<(K, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_order(){
+        let mut map: OrderedMap<String, usize> = vec![ ("b", 1), ("a", 2), ("c", 3) ].into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+
+        assert_eq!(map.insert(String::from("a"), 4), Some(2));
+        assert_eq!(map.remove("b"), Some(1));
+        map.insert(String::from("b"), 5);
+
+        assert_eq!(map.keys().map(String::as_str).collect::<Vec<_>>(), vec![ "a", "c", "b" ]);
+        assert_eq!(map["c"], 3);
+        assert_eq!(map.get("x"), None);
+
+        let reversed: OrderedMap<String, usize> = map.clone().into_iter().rev().collect();
+        assert_eq!(reversed, map);
+        assert_ne!(reversed, OrderedMap::new());
+    }
+}
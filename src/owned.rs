@@ -0,0 +1,514 @@
+//! The parse result without the lifetime of the source, to cache a parsed document or send it to another thread.
+//!
+//! Every type mirrors the one of the same name in `::parse`, where each slice of the source is replaced by its span.
+//! A `ParsedDocument` owns the source together with the spans, and builds the usual parse result, borrowed from itself, on demand,
+//! so that identifiers and errors are still located in its source.
+
+use ::std::collections::HashMap;
+use ::std::fmt;
+use ::list::Placement;
+use ::parse::{ self, Source, Span, UnaryOperator, BinaryOperator };
+
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Object {
+    StringLiteral(Span),
+    Template(Vec<TemplatePart>),
+    Expression(Expression),
+    Compound(Compound),
+    Replacement(Compound),
+    Unset,
+    Extension(Placement<Identifier>, Compound),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Compound {
+    pub prototypes: Vec<Reference>,
+    pub overrides: NamedObjects,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct NamedObjects {
+    pub objects: Vec<Object>,
+    pub identifiers: HashMap<Identifier, usize>,
+    pub unnamed: Vec<(Identifier, usize)>,
+    pub conditionals: Vec<Conditional>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Conditional {
+    pub condition: Expression,
+    pub overrides: NamedObjects,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum TemplatePart {
+    Text(Span),
+    Reference(Reference),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Expression {
+    pub operation: Operation,
+    pub text: Span,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Operation {
+    Literal(Span),
+    Template(Vec<TemplatePart>),
+    Reference(Reference),
+    Parameter(Reference),
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+    Call(Span, Vec<Expression>),
+}
+
+/// identifiers with the same name have different spans, so they are only equal if they are the same declaration
+#[derive(Eq, PartialEq, Debug, Hash, Clone, Copy)]
+pub struct Identifier {
+    pub name: Span,
+}
+
+#[derive(Eq, PartialEq, Debug, Hash, Clone)]
+pub struct Reference {
+    pub identifiers: Vec<Identifier>,
+}
+
+pub type ParseResult<T> = ::std::result::Result<T, ParseError>;
+
+/// like `parse::ParseError`, but also remembers the unexpected symbol, to describe the error without the source
+#[derive(Eq, PartialEq, Debug, Hash, Clone, Copy)]
+pub enum ParseError {
+    /// `found` starts with the symbol, which is none if it is empty
+    UnexpectedSymbol { expected: Option<char>, found: Span, symbol: Option<char> },
+    UnexpectedEndOfInput { expected: Option<char> },
+}
+
+/// a source which owns its parse result
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ParsedDocument {
+    source: String,
+    objects: NamedObjects,
+}
+
+
+impl ParsedDocument {
+    pub fn parse<S: Into<String>>(source: S) -> ParseResult<Self> {
+        let source = source.into();
+
+        let objects = match parse::parse(&source) {
+            Ok(parsed) => NamedObjects::of(&source, &parsed),
+            Err(error) => return Err(ParseError::of(&source, &error)),
+        };
+
+        Ok(ParsedDocument { source, objects })
+    }
+
+    /// the objects must have been parsed from the source
+    pub fn new(source: Source, parsed: &parse::NamedObjects) -> Self {
+        ParsedDocument { source: source.to_string(), objects: NamedObjects::of(source, parsed) }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn into_source(self) -> String {
+        self.source
+    }
+
+    /// the spans of the parse result
+    pub fn spans(&self) -> &NamedObjects {
+        &self.objects
+    }
+
+    /// the parse result, borrowed from the source of this document, without parsing it again.
+    /// builds the whole tree on every call, which takes time proportional to the size of the document,
+    /// so keep the result instead of calling it for each access
+    pub fn to_objects(&self) -> parse::NamedObjects<'_> {
+        self.objects.in_source(&self.source)
+    }
+}
+
+impl Object {
+    pub fn of(source: Source, object: &parse::Object) -> Self {
+        match *object {
+            parse::Object::StringLiteral(literal) => Object::StringLiteral(literal_span(source, literal)),
+            parse::Object::Template(ref parts) => Object::Template(TemplatePart::all_of(source, parts)),
+            parse::Object::Expression(ref expression) => Object::Expression(Expression::of(source, expression)),
+            parse::Object::Compound(ref compound) => Object::Compound(Compound::of(source, compound)),
+            parse::Object::Replacement(ref compound) => Object::Replacement(Compound::of(source, compound)),
+            parse::Object::Unset => Object::Unset,
+            parse::Object::Extension(ref placement, ref compound) => Object::Extension(
+                placement.map(Identifier::of), Compound::of(source, compound)
+            ),
+        }
+    }
+
+    /// the spans must be part of the source
+    pub fn in_source<'s>(&self, source: Source<'s>) -> parse::Object<'s> {
+        match *self {
+            Object::StringLiteral(literal) => parse::Object::StringLiteral(slice(source, literal)),
+            Object::Template(ref parts) => parse::Object::Template(TemplatePart::all_in_source(source, parts)),
+            Object::Expression(ref expression) => parse::Object::Expression(expression.in_source(source)),
+            Object::Compound(ref compound) => parse::Object::Compound(compound.in_source(source)),
+            Object::Replacement(ref compound) => parse::Object::Replacement(compound.in_source(source)),
+            Object::Unset => parse::Object::Unset,
+            Object::Extension(ref placement, ref compound) => parse::Object::Extension(
+                placement.map(|anchor| anchor.in_source(source)), compound.in_source(source)
+            ),
+        }
+    }
+
+    fn shift(&mut self, offset: usize, delta: isize) {
+        match *self {
+            Object::StringLiteral(ref mut literal) => shift(literal, offset, delta),
+            Object::Template(ref mut parts) => TemplatePart::shift_all(parts, offset, delta),
+            Object::Expression(ref mut expression) => expression.shift(offset, delta),
+            Object::Compound(ref mut compound) | Object::Replacement(ref mut compound) => compound.shift(offset, delta),
+            Object::Unset => {},
+            Object::Extension(ref mut placement, ref mut compound) => {
+                *placement = placement.map(|&anchor| Identifier { name: moved(anchor.name, offset, delta) });
+                compound.shift(offset, delta);
+            },
+        }
+    }
+}
+
+impl Compound {
+    pub fn of(source: Source, compound: &parse::Compound) -> Self {
+        Compound {
+            prototypes: compound.prototypes.iter().map(|prototype| Reference::of(prototype)).collect(),
+            overrides: NamedObjects::of(source, &compound.overrides),
+        }
+    }
+
+    pub fn in_source<'s>(&self, source: Source<'s>) -> parse::Compound<'s> {
+        parse::Compound {
+            prototypes: self.prototypes.iter().map(|prototype| prototype.in_source(source)).collect(),
+            overrides: self.overrides.in_source(source),
+        }
+    }
+
+    fn shift(&mut self, offset: usize, delta: isize) {
+        for prototype in &mut self.prototypes { prototype.shift(offset, delta); }
+        self.overrides.shift(offset, delta);
+    }
+}
+
+impl NamedObjects {
+    pub fn of(source: Source, objects: &parse::NamedObjects) -> Self {
+        NamedObjects {
+            objects: objects.objects.iter().map(|object| Object::of(source, object)).collect(),
+
+            identifiers: objects.identifiers.iter()
+                .map(|(identifier, &index)| (Identifier::of(identifier), index))
+                .collect(),
+
+            unnamed: objects.unnamed.iter()
+                .map(|(identifier, index)| (Identifier::of(identifier), *index))
+                .collect(),
+
+            conditionals: objects.conditionals.iter()
+                .map(|conditional| Conditional {
+                    condition: Expression::of(source, &conditional.condition),
+                    overrides: NamedObjects::of(source, &conditional.overrides),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn in_source<'s>(&self, source: Source<'s>) -> parse::NamedObjects<'s> {
+        parse::NamedObjects {
+            objects: self.objects.iter().map(|object| object.in_source(source)).collect(),
+
+            identifiers: self.identifiers.iter()
+                .map(|(identifier, &index)| (identifier.in_source(source), index))
+                .collect(),
+
+            unnamed: self.unnamed.iter()
+                .map(|(identifier, index)| (identifier.in_source(source), *index))
+                .collect(),
+
+            conditionals: self.conditionals.iter()
+                .map(|conditional| parse::Conditional {
+                    condition: conditional.condition.in_source(source),
+                    overrides: conditional.overrides.in_source(source),
+                })
+                .collect(),
+        }
+    }
+
+    /// moves every span that starts or ends at or behind the offset, after text was inserted or removed there
+    pub fn shift(&mut self, offset: usize, delta: isize) {
+        for object in &mut self.objects { object.shift(offset, delta); }
+
+        self.identifiers = self.identifiers.drain()
+            .map(|(mut identifier, index)| { shift(&mut identifier.name, offset, delta); (identifier, index) })
+            .collect();
+
+        for (identifier, _) in &mut self.unnamed { shift(&mut identifier.name, offset, delta); }
+
+        for conditional in &mut self.conditionals {
+            conditional.condition.shift(offset, delta);
+            conditional.overrides.shift(offset, delta);
+        }
+    }
+}
+
+impl TemplatePart {
+    fn all_of(source: Source, parts: &[parse::TemplatePart]) -> Vec<Self> {
+        parts.iter()
+            .map(|part| match *part {
+                parse::TemplatePart::Text(text) => TemplatePart::Text(literal_span(source, text)),
+                parse::TemplatePart::Reference(ref reference) => TemplatePart::Reference(Reference::of(reference)),
+            })
+            .collect()
+    }
+
+    fn all_in_source<'s>(source: Source<'s>, parts: &[TemplatePart]) -> Vec<parse::TemplatePart<'s>> {
+        parts.iter()
+            .map(|part| match *part {
+                TemplatePart::Text(text) => parse::TemplatePart::Text(slice(source, text)),
+                TemplatePart::Reference(ref reference) => parse::TemplatePart::Reference(reference.in_source(source)),
+            })
+            .collect()
+    }
+
+    fn shift_all(parts: &mut [TemplatePart], offset: usize, delta: isize) {
+        for part in parts {
+            match *part {
+                TemplatePart::Text(ref mut text) => shift(text, offset, delta),
+                TemplatePart::Reference(ref mut reference) => reference.shift(offset, delta),
+            }
+        }
+    }
+}
+
+impl Expression {
+    pub fn of(source: Source, expression: &parse::Expression) -> Self {
+        let of = |operand: &parse::Expression| Box::new(Expression::of(source, operand));
+
+        let operation = match expression.operation {
+            // a quoted literal is written without its quotes, unlike a number
+            parse::Operation::Literal(_) if expression.text.starts_with('\'') => Operation::Literal(
+                Span { start: expression.span.start + 1, end: expression.span.end - 1 }
+            ),
+
+            parse::Operation::Literal(_) => Operation::Literal(expression.span),
+            parse::Operation::Template(ref parts) => Operation::Template(TemplatePart::all_of(source, parts)),
+            parse::Operation::Reference(ref reference) => Operation::Reference(Reference::of(reference)),
+            parse::Operation::Parameter(ref reference) => Operation::Parameter(Reference::of(reference)),
+            parse::Operation::Unary(operator, ref operand) => Operation::Unary(operator, of(operand)),
+            parse::Operation::Binary(operator, ref left, ref right) => Operation::Binary(operator, of(left), of(right)),
+            parse::Operation::Conditional(ref condition, ref then, ref otherwise) => Operation::Conditional(
+                of(condition), of(then), of(otherwise)
+            ),
+
+            parse::Operation::Call(name, ref arguments) => Operation::Call(
+                Span { start: expression.span.start, end: expression.span.start + name.len() }, arguments.iter().map(|argument| Expression::of(source, argument)).collect()
+            ),
+        };
+
+        Expression { operation, text: expression.span }
+    }
+
+    pub fn in_source<'s>(&self, source: Source<'s>) -> parse::Expression<'s> {
+        let in_source = |operand: &Expression| Box::new(operand.in_source(source));
+
+        let operation = match self.operation {
+            Operation::Literal(literal) => parse::Operation::Literal(slice(source, literal)),
+            Operation::Template(ref parts) => parse::Operation::Template(TemplatePart::all_in_source(source, parts)),
+            Operation::Reference(ref reference) => parse::Operation::Reference(reference.in_source(source)),
+            Operation::Parameter(ref reference) => parse::Operation::Parameter(reference.in_source(source)),
+            Operation::Unary(operator, ref operand) => parse::Operation::Unary(operator, in_source(operand)),
+            Operation::Binary(operator, ref left, ref right) => parse::Operation::Binary(operator, in_source(left), in_source(right)),
+            Operation::Conditional(ref condition, ref then, ref otherwise) => parse::Operation::Conditional(
+                in_source(condition), in_source(then), in_source(otherwise)
+            ),
+
+            Operation::Call(name, ref arguments) => parse::Operation::Call(
+                slice(source, name), arguments.iter().map(|argument| argument.in_source(source)).collect()
+            ),
+        };
+
+        parse::Expression { operation, text: slice(source, self.text), span: self.text }
+    }
+
+    fn shift(&mut self, offset: usize, delta: isize) {
+        match self.operation {
+            Operation::Literal(ref mut literal) => shift(literal, offset, delta),
+            Operation::Template(ref mut parts) => TemplatePart::shift_all(parts, offset, delta),
+            Operation::Reference(ref mut reference) | Operation::Parameter(ref mut reference) => reference.shift(offset, delta),
+            Operation::Unary(_, ref mut operand) => operand.shift(offset, delta),
+            Operation::Binary(_, ref mut left, ref mut right) => { left.shift(offset, delta); right.shift(offset, delta); },
+            Operation::Conditional(ref mut condition, ref mut then, ref mut otherwise) => {
+                condition.shift(offset, delta);
+                then.shift(offset, delta);
+                otherwise.shift(offset, delta);
+            },
+
+            Operation::Call(ref mut name, ref mut arguments) => {
+                shift(name, offset, delta);
+                for argument in arguments { argument.shift(offset, delta); }
+            },
+        }
+
+        shift(&mut self.text, offset, delta);
+    }
+}
+
+impl Identifier {
+    /// the identifier must have been parsed
+    pub fn of(identifier: &parse::Identifier) -> Self {
+        Identifier { name: identifier.location.expect("parsed identifiers are located") }
+    }
+
+    pub fn in_source<'s>(&self, source: Source<'s>) -> parse::Identifier<'s> {
+        parse::Identifier { name: slice(source, self.name), location: Some(self.name) }
+    }
+}
+
+impl Reference {
+    pub fn of(reference: &parse::Reference) -> Self {
+        Reference { identifiers: reference.identifiers.iter().map(Identifier::of).collect() }
+    }
+
+    pub fn in_source<'s>(&self, source: Source<'s>) -> parse::Reference<'s> {
+        parse::Reference { identifiers: self.identifiers.iter().map(|identifier| identifier.in_source(source)).collect() }
+    }
+
+    fn shift(&mut self, offset: usize, delta: isize) {
+        for identifier in &mut self.identifiers { shift(&mut identifier.name, offset, delta); }
+    }
+}
+
+impl ParseError {
+    /// the error must have occurred while parsing the source
+    pub fn of(source: Source, error: &parse::ParseError) -> Self {
+        match *error {
+            // the parser only ever fails at the remaining source
+            parse::ParseError::UnexpectedSymbol { expected, found } => ParseError::UnexpectedSymbol {
+                expected, found: Span { start: source.len() - found.len(), end: source.len() }, symbol: found.chars().next(),
+            },
+
+            parse::ParseError::UnexpectedEndOfInput { expected } => ParseError::UnexpectedEndOfInput { expected },
+        }
+    }
+
+    pub fn in_source<'s>(&self, source: Source<'s>) -> parse::ParseError<'s> {
+        match *self {
+            ParseError::UnexpectedSymbol { expected, found, .. } => parse::ParseError::UnexpectedSymbol { expected, found: slice(source, found) },
+            ParseError::UnexpectedEndOfInput { expected } => parse::ParseError::UnexpectedEndOfInput { expected },
+        }
+    }
+
+    /// the first unexpected character, or the end of the source
+    pub fn span(&self, source: Source) -> Span {
+        match *self {
+            ParseError::UnexpectedSymbol { found, symbol, .. } => Span { start: found.start, end: found.start + symbol.map_or(0, char::len_utf8) },
+            ParseError::UnexpectedEndOfInput { .. } => Span { start: source.len(), end: source.len() },
+        }
+    }
+}
+
+/// the same message as the borrowed error
+impl fmt::Display for ParseError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UnexpectedSymbol { expected, symbol, .. } => {
+                let found = symbol.map(String::from).unwrap_or_default();
+                parse::ParseError::UnexpectedSymbol { expected, found: &found }.fmt(formatter)
+            },
+
+            ParseError::UnexpectedEndOfInput { expected } => parse::ParseError::UnexpectedEndOfInput { expected }.fmt(formatter),
+        }
+    }
+}
+
+
+/// the parse result does not locate string literals and the text of templates, unlike identifiers and expressions,
+/// so they are located by their address, which must be inside the source
+fn literal_span(source: Source, literal: &str) -> Span {
+    let start = (literal.as_ptr() as usize).wrapping_sub(source.as_ptr() as usize);
+    assert!(start <= source.len() && start + literal.len() <= source.len(), "the literal is not part of the source");
+    Span { start, end: start + literal.len() }
+}
+
+fn slice<'s>(source: Source<'s>, span: Span) -> &'s str {
+    &source[span.start .. span.end]
+}
+
+fn shift(span: &mut Span, offset: usize, delta: isize) {
+    *span = moved(*span, offset, delta);
+}
+
+fn moved(span: Span, offset: usize, delta: isize) -> Span {
+    let move_offset = |position: usize| if position >= offset { (position as isize + delta) as usize } else { position };
+    Span { start: move_offset(span.start), end: move_offset(span.end) }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip(){
+        let sources = [
+            include_str!("../assets/example.ptl"), include_str!("../assets/html.ptl"), "",
+            "b: !replace A + B.c {d:!unset}  e: !insert_after 0 { : 'x' } list += { : 'a' }",
+            "if $x == 'y' { a if (1 < 2) : ('z') } g: 'in ${a} \\${}' h: (len(g) > 2 ? -a : 'x' ~ 'y')",
+        ];
+
+        for source in &sources {
+            let parsed = parse::parse(source).unwrap();
+            let document = ParsedDocument::parse(source.to_string()).unwrap();
+
+            assert_eq!(document.to_objects(), parsed);
+            assert_eq!(ParsedDocument::new(source, &parsed), document);
+            assert_eq!(NamedObjects::of(source, &parsed).in_source(source), parsed);
+        }
+    }
+
+    #[test]
+    fn test_shift(){
+        let source = "a: { b: 'x' c: A.b { : (1 + d) } } if $e { f: 'y ${g}' }";
+        let moved = format!("    {}", source);
+
+        let mut shifted = NamedObjects::of(source, &parse::parse(source).unwrap());
+        shifted.shift(0, 4);
+        assert_eq!(shifted, NamedObjects::of(&moved, &parse::parse(&moved).unwrap()));
+
+        shifted.shift(2, -4);
+        assert_eq!(shifted.in_source(source), parse::parse(source).unwrap());
+    }
+
+    #[test]
+    fn test_threads(){
+        let document = ParsedDocument::parse("persons: { peter: { name: 'Peter' } } comic: { author: 'by ${persons.peter}' }").unwrap();
+        let location = ::std::thread::spawn(move || {
+            let error = ::flat::FlatObject::build_from_parsed(&document.to_objects()).unwrap_err();
+            (document.source()[error.span().unwrap().start ..].to_string(), error)
+        });
+
+        let (located, error) = location.join().unwrap();
+        assert!(located.starts_with("persons.peter}'"));
+        assert_eq!(error.to_string(), "`persons.peter` is not a string literal and cannot be inserted into one");
+    }
+
+    #[test]
+    fn test_parse_error(){
+        for source in &[ "a: { b: 'x' ", "a: 'x' }", "a: (1 +", "a: 'é${x'", "nav + { }" ] {
+            let error = parse::parse(source).unwrap_err();
+            let owned = ParsedDocument::parse(source.to_string()).unwrap_err();
+
+            assert_eq!(owned, ParseError::of(source, &error));
+            assert_eq!(owned.in_source(source), error);
+            assert_eq!(owned.span(source), error.span(source));
+            assert_eq!(owned.to_string(), error.to_string());
+        }
+    }
+}
@@ -1,33 +1,121 @@
+use ::std::borrow::Cow;
 use ::std::collections::HashMap;
+use ::std::hash::{ Hash, Hasher };
+use ::list::Placement;
 
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum Object<'s> {
     /// literal may be empty
     StringLiteral(&'s str),
-    Compound(Compound<'s>)
+
+    /// a string literal containing `${reference}`, which is replaced by the referenced string literal
+    Template(Vec<TemplatePart<'s>>),
+
+    /// a value computed from other objects, written in parentheses
+    Expression(Expression<'s>),
+    Compound(Compound<'s>),
+
+    /// `!replace`, a compound which does not merge with the inherited compound of the same name
+    Replacement(Compound<'s>),
+
+    /// `!unset`, removes the inherited property of the same name
+    Unset,
+
+    /// `+=`, `!prepend`, `!insert_before` or `!insert_after`,
+    /// adds the list entries of the compound to the inherited list of the same name
+    Extension(Placement<Identifier<'s>>, Compound<'s>),
 }
 
 /// only the result of parsing. does not do any smart stuff. only holds string results.
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub struct Compound<'s> {
-    pub prototype: Reference<'s>, /// may be an empty string
+    /// may be empty. several prototypes are separated by `+`
+    pub prototypes: Vec<Reference<'s>>,
     pub overrides: NamedObjects<'s>,
 }
 
 /// parse result. supports looking up variables, e.g. prototypes by name
-#[derive(Eq, PartialEq, Debug, Clone)]
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
 pub struct NamedObjects<'s> {
     pub objects: Vec<Object<'s>>, // separated from hashmap, to perserve declaration order
 
     /// indices into self.objects
     pub identifiers: HashMap<Identifier<'s>, usize>,
+
+    /// indices into self.objects, of the objects declared with an empty name, like `: 'x'`.
+    /// these are named by their position in this list, unless that name is declared explicitly
+    pub unnamed: Vec<(Identifier<'s>, usize)>,
+
+    /// objects which are only declared if a condition holds, in declaration order
+    pub conditionals: Vec<Conditional<'s>>,
 }
 
-/// the local, simple name of an object
-#[derive(Eq, PartialEq, Debug, Hash, Clone)]
+/// `if condition { overrides }`, or `name if condition: object` for a single object
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Conditional<'s> {
+    pub condition: Expression<'s>,
+    pub overrides: NamedObjects<'s>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum TemplatePart<'s> {
+    /// does not contain the backslash of an escaped `\${`
+    Text(&'s str),
+    Reference(Reference<'s>),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Expression<'s> {
+    pub operation: Operation<'s>,
+
+    /// the source of this part of the expression
+    pub text: &'s str,
+
+    /// where the text is in the source, to locate errors
+    pub span: Span,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Operation<'s> {
+    /// a quoted string literal, or a number
+    Literal(&'s str),
+    Template(Vec<TemplatePart<'s>>),
+    Reference(Reference<'s>),
+
+    /// `$name`, a property of the compound whose condition is evaluated
+    Parameter(Reference<'s>),
+
+    Unary(UnaryOperator, Box<Expression<'s>>),
+    Binary(BinaryOperator, Box<Expression<'s>>, Box<Expression<'s>>),
+
+    /// `condition ? then : otherwise`
+    Conditional(Box<Expression<'s>>, Box<Expression<'s>>, Box<Expression<'s>>),
+
+    /// a builtin function, e.g. `len(title)`
+    Call(&'s str, Vec<Expression<'s>>),
+}
+
+#[derive(Eq, PartialEq, Debug, Hash, Clone, Copy)]
+pub enum UnaryOperator {
+    Not, Negate,
+}
+
+#[derive(Eq, PartialEq, Debug, Hash, Clone, Copy)]
+pub enum BinaryOperator {
+    Or, And,
+    Equal, NotEqual, Less, LessOrEqual, Greater, GreaterOrEqual,
+    Concatenate,
+    Add, Subtract, Multiply, Divide, Remainder,
+}
+
+/// the local, simple name of an object. identifiers with the same name are equal, wherever they are
+#[derive(Debug, Clone)]
 pub struct Identifier<'s> {
     pub name: &'s str,
+
+    /// where the name is in the source, none if it was not parsed, like the names of natives
+    pub location: Option<Span>,
 }
 
 /// the absolute, qualified name for a prototype
@@ -54,8 +142,342 @@ pub type ResolveResult<T> = ::std::result::Result<T, ResolveError>;
 pub enum ResolveError {
     ReferenceNotFound { identifier: String }, // resolve-error cannot borrow because it should be able to outlive the source code
     StringLiteralHasNoProperties,
+    CyclicReference { identifier: String },
+    ReferenceToUnset { identifier: String },
+
+    /// the prototypes, for example `A + B`, inherit from the same prototypes in contradicting orders
+    InconsistentPrototypeOrder { prototypes: String },
+
+    /// `${reference}` inside a string literal refers to a compound
+    InterpolatedCompound { identifier: String, span: Option<Span> },
+
+    /// an operator or function of an expression cannot handle its operands
+    TypeError { message: String, span: Option<Span> },
+
+    /// `!insert_before` or `!insert_after` refers to an entry the inherited list does not have
+    ListEntryNotFound { identifier: String, span: Option<Span> },
+
+    /// an unnamed list entry has the implicit index of an entry declared with that index, like `{ : 'a' 1: 'b' : 'c' }`.
+    /// located at the unnamed entry
+    DuplicateIndex { index: usize, span: Option<Span> },
+
+    /// `$name` is used in an expression which is not a condition
+    ParameterOutsideCondition { identifier: String, span: Option<Span> },
+
+    /// the document declares a name that is reserved for objects supplied by the caller, see `::scope`
+    ReservedName { identifier: String, span: Option<Span> },
+
+    /// `env.name` refers to an external variable that the caller did not supply
+    MissingVariable { name: String },
+
+    /// a native prototype or function of the host failed, see `::scope`.
+    /// functions are located at their call
+    NativeError { name: String, message: String, span: Option<Span> },
+}
+
+
+/// byte offsets of a section of the source, `start` inclusive and `end` exclusive
+#[derive(Eq, PartialEq, Debug, Hash, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+
+impl Span {
+    /// also true if the offset is directly behind the last character, like a text cursor
+    pub fn touches(&self, offset: usize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+}
+
+impl<'s> Identifier<'s> {
+    /// an identifier that was not parsed
+    pub fn new(name: &'s str) -> Self {
+        Identifier { name, location: None }
+    }
+
+    /// a name that is directly followed by the remaining source, located like every part while parsing, see `locate_named`
+    fn parsed(name: &'s str, remaining: Source<'s>) -> Self {
+        Identifier { name, location: Some(Span { start: name.len() + remaining.len(), end: remaining.len() }) }
+    }
+}
+
+impl<'s> PartialEq for Identifier<'s> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl<'s> Eq for Identifier<'s> {}
+
+impl<'s> Hash for Identifier<'s> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.name.hash(hasher)
+    }
+}
+
+impl<'s> ::std::fmt::Display for Reference<'s> {
+    fn fmt(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        for (index, identifier) in self.identifiers.iter().enumerate() {
+            if index != 0 { formatter.write_str(".")?; }
+            formatter.write_str(identifier.name)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'s> ParseError<'s> {
+    /// the first unexpected character, or the end of the source
+    pub fn span(&self, source: Source) -> Span {
+        match *self {
+            // the parser only ever fails at the remaining source
+            ParseError::UnexpectedSymbol { found, .. } => {
+                let start = source.len() - found.len();
+                Span { start, end: start + found.chars().next().map_or(0, char::len_utf8) }
+            },
+
+            ParseError::UnexpectedEndOfInput { .. } => Span { start: source.len(), end: source.len() },
+        }
+    }
+}
+
+impl<'s> ::std::fmt::Display for ParseError<'s> {
+    fn fmt(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            ParseError::UnexpectedSymbol { expected: Some(expected), found } => write!(
+                formatter, "expected `{}`, found `{}`",
+                expected, found.chars().next().unwrap_or(' ')
+            ),
+
+            ParseError::UnexpectedSymbol { expected: None, found } => write!(
+                formatter, "unexpected `{}`", found.chars().next().unwrap_or(' ')
+            ),
+
+            ParseError::UnexpectedEndOfInput { expected: Some(expected) } => write!(
+                formatter, "expected `{}`, found end of input", expected
+            ),
+
+            ParseError::UnexpectedEndOfInput { expected: None } => write!(
+                formatter, "unexpected end of input"
+            ),
+        }
+    }
 }
 
+impl ResolveError {
+    /// where the error occurred, if it can be attributed to a single place in the source of the document
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            ResolveError::InterpolatedCompound { span, .. } | ResolveError::TypeError { span, .. }
+                | ResolveError::ListEntryNotFound { span, .. } | ResolveError::DuplicateIndex { span, .. }
+                | ResolveError::ParameterOutsideCondition { span, .. }
+                | ResolveError::ReservedName { span, .. } | ResolveError::NativeError { span, .. } => span,
+
+            _ => None,
+        }
+    }
+}
+
+impl ::std::fmt::Display for ResolveError {
+    fn fmt(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            ResolveError::ReferenceNotFound { ref identifier } => write!(
+                formatter, "no object named `{}`", identifier
+            ),
+
+            ResolveError::StringLiteralHasNoProperties => write!(
+                formatter, "a string literal has no properties"
+            ),
+
+            ResolveError::CyclicReference { ref identifier } => write!(
+                formatter, "`{}` refers to itself", identifier
+            ),
+
+            ResolveError::ReferenceToUnset { ref identifier } => write!(
+                formatter, "`{}` has been unset", identifier
+            ),
+
+            ResolveError::InconsistentPrototypeOrder { ref prototypes } => write!(
+                formatter, "the prototypes of `{}` inherit in contradicting orders", prototypes
+            ),
+
+            ResolveError::InterpolatedCompound { ref identifier, .. } => write!(
+                formatter, "`{}` is not a string literal and cannot be inserted into one", identifier
+            ),
+
+            ResolveError::TypeError { ref message, .. } => formatter.write_str(message),
+
+            ResolveError::ListEntryNotFound { ref identifier, .. } => write!(
+                formatter, "the inherited list has no entry `{}`", identifier
+            ),
+
+            ResolveError::DuplicateIndex { index, .. } => write!(
+                formatter, "the unnamed entry has the index {}, which is already declared explicitly", index
+            ),
+
+            ResolveError::ParameterOutsideCondition { ref identifier, .. } => write!(
+                formatter, "`${}` can only be used in conditions", identifier
+            ),
+
+            ResolveError::ReservedName { ref identifier, .. } => write!(
+                formatter, "`{}` is reserved for external variables", identifier
+            ),
+
+            ResolveError::MissingVariable { ref name } => write!(
+                formatter, "the external variable `{}` has not been supplied", name
+            ),
+
+            ResolveError::NativeError { ref name, ref message, .. } => write!(
+                formatter, "`{}` failed: {}", name, message
+            ),
+        }
+    }
+}
+
+
+impl<'s> Object<'s> {
+    /// the compound of compounds, replacements, and extensions
+    pub fn as_compound(&self) -> Option<&Compound<'s>> {
+        match *self {
+            Object::Compound(ref compound) | Object::Replacement(ref compound) | Object::Extension(_, ref compound) => Some(compound),
+            Object::StringLiteral(_) | Object::Template(_) | Object::Expression(_) | Object::Unset => None,
+        }
+    }
+
+    /// the references of prototypes, templates, expressions, and conditions inside the object,
+    /// including nested objects, but not `$parameters`, which are not absolute
+    pub fn references(&self) -> Vec<&Reference<'s>> {
+        let mut references = Vec::new();
+        collect_object_references(self, &mut references);
+        references
+    }
+}
+
+impl<'s> NamedObjects<'s> {
+    /// the references inside all objects and conditions, see `Object::references`
+    pub fn references(&self) -> Vec<&Reference<'s>> {
+        let mut references = Vec::new();
+        collect_named_references(self, &mut references);
+        references
+    }
+}
+
+fn collect_object_references<'o, 's>(object: &'o Object<'s>, references: &mut Vec<&'o Reference<'s>>) {
+    match *object {
+        Object::StringLiteral(_) | Object::Unset => {},
+        Object::Template(ref parts) => collect_template_references(parts, references),
+        Object::Expression(ref expression) => collect_expression_references(expression, references),
+
+        Object::Compound(ref compound) | Object::Replacement(ref compound) | Object::Extension(_, ref compound) => {
+            references.extend(&compound.prototypes);
+            collect_named_references(&compound.overrides, references);
+        },
+    }
+}
+
+fn collect_named_references<'o, 's>(objects: &'o NamedObjects<'s>, references: &mut Vec<&'o Reference<'s>>) {
+    for object in &objects.objects {
+        collect_object_references(object, references);
+    }
+
+    for conditional in &objects.conditionals {
+        collect_expression_references(&conditional.condition, references);
+        collect_named_references(&conditional.overrides, references);
+    }
+}
+
+fn collect_template_references<'o, 's>(parts: &'o [TemplatePart<'s>], references: &mut Vec<&'o Reference<'s>>) {
+    for part in parts {
+        if let TemplatePart::Reference(ref reference) = *part {
+            references.push(reference);
+        }
+    }
+}
+
+fn collect_expression_references<'o, 's>(expression: &'o Expression<'s>, references: &mut Vec<&'o Reference<'s>>) {
+    match expression.operation {
+        Operation::Literal(_) | Operation::Parameter(_) => {},
+        Operation::Template(ref parts) => collect_template_references(parts, references),
+        Operation::Reference(ref reference) => references.push(reference),
+        Operation::Unary(_, ref operand) => collect_expression_references(operand, references),
+
+        Operation::Binary(_, ref left, ref right) => {
+            collect_expression_references(left, references);
+            collect_expression_references(right, references);
+        },
+
+        Operation::Conditional(ref condition, ref then, ref otherwise) => {
+            collect_expression_references(condition, references);
+            collect_expression_references(then, references);
+            collect_expression_references(otherwise, references);
+        },
+
+        Operation::Call(_, ref arguments) => for argument in arguments {
+            collect_expression_references(argument, references);
+        },
+    }
+}
+
+impl<'s> Compound<'s> {
+    /// a compound without overrides is a copy of its prototype, like a variable
+    pub fn is_variable(&self) -> bool {
+        self.overrides.objects.is_empty() && self.overrides.conditionals.is_empty() && self.prototypes.len() == 1
+    }
+
+    pub fn has_prototype(&self) -> bool {
+        !self.prototypes.is_empty()
+    }
+}
+
+impl<'s> NamedObjects<'s> {
+    pub fn get(&self, name: &str) -> Option<&Object<'s>> {
+        self.get_declaration(name).map(|(_, index)| &self.objects[index])
+    }
+
+    /// the identifier and the index of an object, where unnamed objects are found by their implicit index
+    fn get_declaration<'n>(&'n self, name: &'n str) -> Option<(&'n Identifier<'n>, usize)> {
+        match self.identifiers.get_key_value(&Identifier::new(name)) {
+            Some((identifier, &index)) => Some((identifier, index)),
+            None => self.unnamed.get(::list::index(name)?).map(|(identifier, index)| (identifier, *index)),
+        }
+    }
+
+    /// the names of the objects in the order they were declared,
+    /// where unnamed objects are named by their implicit index
+    pub fn in_declaration_order(&self) -> Vec<(Cow<'s, str>, &Identifier<'s>, usize)> {
+        let named = self.identifiers.iter()
+            .map(|(identifier, &index)| (Cow::Borrowed(identifier.name), identifier, index));
+
+        let unnamed = self.unnamed.iter().enumerate()
+            .map(|(position, (identifier, index))| (Cow::Owned(position.to_string()), identifier, *index))
+            .filter(|(name, _, _)| !self.identifiers.contains_key(&Identifier::new(name)));
+
+        let mut declarations: Vec<(Cow<'s, str>, &Identifier<'s>, usize)> = named.chain(unnamed).collect();
+        declarations.sort_by_key(|&(_, _, index)| index);
+        declarations
+    }
+
+    /// the implicit index and the identifier of the first unnamed object whose index is also declared by name,
+    /// which `in_declaration_order` and `get` ignore
+    pub fn duplicate_index(&self) -> Option<(usize, &Identifier<'s>)> {
+        self.unnamed.iter().enumerate()
+            .find(|&(position, _)| self.identifiers.contains_key(&Identifier::new(&position.to_string())))
+            .map(|(position, (identifier, _))| (position, identifier))
+    }
+
+    /// adds an object after all other objects, where an empty name is an implicit index
+    pub fn declare(&mut self, identifier: Identifier<'s>, object: Object<'s>) {
+        if identifier.name.is_empty() {
+            self.unnamed.push((identifier, self.objects.len()));
+        } else {
+            self.identifiers.insert(identifier, self.objects.len());
+        }
+
+        self.objects.push(object);
+    }
+}
 
 impl<'s> Reference<'s> {
     /// false if this reference is an empty string
@@ -63,45 +485,64 @@ impl<'s> Reference<'s> {
         !self.identifiers.is_empty()
             && self.identifiers.iter().any(|id| !id.name.is_empty())
     }
+
+    /// from the first to the last identifier, none if there are no identifiers or they were not parsed
+    pub fn location(&self) -> Option<Span> {
+        let first = self.identifiers.first()?.location?;
+        let last = self.identifiers.last()?.location?;
+        Some(Span { start: first.start, end: last.end })
+    }
 }
 
 impl<'s> NamedObjects<'s> {
     pub fn resolve_reference<'o>(&'o self, reference: &'o Reference<'o>) -> ResolveResult<&'o Object<'o>> {
+        self.resolve_definition(reference).map(|(_, object)| object)
+    }
+
+    /// also returns the identifier of the declaration, which points into the source
+    pub fn resolve_definition<'o>(&'o self, reference: &'o Reference<'o>) -> ResolveResult<(&'o Identifier<'o>, &'o Object<'o>)> {
         self.resolve_reference_names(&reference.identifiers)
     }
 
-    fn resolve_reference_names<'o>(&'o self, identifiers: &'o [Identifier<'o>]) -> ResolveResult<&'o Object<'o>> {
+    fn resolve_reference_names<'o>(&'o self, identifiers: &'o [Identifier<'o>]) -> ResolveResult<(&'o Identifier<'o>, &'o Object<'o>)> {
         let (first, sub_identifiers) = identifiers.split_first()
             .expect("resolve_reference_names: identifiers must not be empty");
 
-        let index = self.identifiers.get(first)
+        let (identifier, index) = self.get_declaration(first.name)
             .ok_or_else(|| ResolveError::ReferenceNotFound { identifier: first.name.to_string() })?;
 
-        let identified = self.objects.get(*index)
+        let identified = self.objects.get(index)
             .expect("Invalid NamedObject::names Index");
 
-        if sub_identifiers.is_empty() {
-            Ok(identified)
+        if let Object::Unset = *identified {
+            Err(ResolveError::ReferenceToUnset { identifier: first.name.to_string() })
 
-        } else {
-            match *identified {
-                Object::Compound(ref compound) => {
-                    compound.overrides.resolve_reference_names(sub_identifiers)
-                },
+        } else if sub_identifiers.is_empty() {
+            Ok((identifier, identified))
 
-                Object::StringLiteral(_) => Err(ResolveError::StringLiteralHasNoProperties),
+        } else {
+            match identified.as_compound() {
+                Some(compound) => compound.overrides.resolve_reference_names(sub_identifiers),
+                None => Err(ResolveError::StringLiteralHasNoProperties),
             }
         }
     }
 }
 
+/// one-based line and column of a byte offset, counting characters
+pub fn line_and_column(source: Source, offset: usize) -> (usize, usize) {
+    let before = &source[.. offset.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (before.matches('\n').count() + 1, before[line_start ..].chars().count() + 1)
+}
+
 /// returns Some(remaining_source) if the next character is the specified symbol
-fn skip_char(source: Source, symbol: char) -> Option<Source> {
+fn skip_char<'s>(source: Source<'s>, symbol: char) -> Option<Source<'s>> {
     expect_char(source, symbol).ok()
 }
 
 /// returns Ok(remaining_source) if the first character is the specified symbol
-fn expect_char(source: Source, symbol: char) -> ParseResult<Source> {
+fn expect_char<'s>(source: Source<'s>, symbol: char) -> ParseResult<'s, Source<'s>> {
     if source.starts_with(symbol) {
         Ok(&source[symbol.len_utf8() .. ])
 
@@ -117,20 +558,20 @@ fn expect_char(source: Source, symbol: char) -> ParseResult<Source> {
     }
 }
 
-fn parse_chars_while<F: Fn(char) -> bool>(source: Source, predicate: F) -> (&str, Source) {
+fn parse_chars_while<'s, F: Fn(char) -> bool>(source: Source<'s>, predicate: F) -> (&'s str, Source<'s>) {
     source.split_at(
         source.char_indices()
             .skip_while(|&(_byte_index, character)| predicate(character))
-            .map(|(byte_index, character)| byte_index)
+            .map(|(byte_index, _character)| byte_index)
             .next().unwrap_or(source.len()) // if the end was reached, split after the last char
     )
 }
 
 /// returns (parsed, remaining), both strings may be empty, discards the delimiter, result strings may start with whitespace
-fn parse_over_delimiter_char(source: Source, delimiter: char) -> ParseResult<(&str, Source)> {
+fn parse_over_delimiter_char<'s>(source: Source<'s>, delimiter: char) -> ParseResult<'s, (&'s str, Source<'s>)> {
     let (parsed, source) = parse_chars_while(source, |character| character != delimiter);
     expect_char(source, delimiter)
-        .map_err(|e| ParseError::UnexpectedEndOfInput { expected: Some(delimiter) })
+        .map_err(|_| ParseError::UnexpectedEndOfInput { expected: Some(delimiter) })
         .map(|source_without_delimiter| (parsed, source_without_delimiter))
 }
 
@@ -138,27 +579,28 @@ fn parse_over_delimiter_char(source: Source, delimiter: char) -> ParseResult<(&s
 
 /// skips whitespace, returns Some(remaining_source) if the first character is the specified symbol
 // TODO perf: on None return, discards trimming, and must be trimmed again..!
-fn skip(source: Source, symbol: char) -> Option<Source> {
-    skip_char(source.trim_left(), symbol)
+fn skip<'s>(source: Source<'s>, symbol: char) -> Option<Source<'s>> {
+    skip_char(source.trim_start(), symbol)
 }
 
 /// skips whitespace, returns Ok(remaining_source) if the first character is the specified symbol
-fn expect(source: Source, expected_symbol: char) -> ParseResult<Source> {
-    expect_char(source.trim_left(), expected_symbol)
+fn expect<'s>(source: Source<'s>, expected_symbol: char) -> ParseResult<'s, Source<'s>> {
+    expect_char(source.trim_start(), expected_symbol)
 }
 
 /// skips white, returns (parsed, remaining), both strings may be empty, discards the delimiter, result strings may start with whitespace
-fn parse_over_delimiter(source: Source, delimiter: char) -> ParseResult<(&str, Source)> {
-    parse_over_delimiter_char(source.trim_left(), delimiter)
+#[allow(dead_code)]
+fn parse_over_delimiter<'s>(source: Source<'s>, delimiter: char) -> ParseResult<'s, (&'s str, Source<'s>)> {
+    parse_over_delimiter_char(source.trim_start(), delimiter)
 }
 
 /// skips leading whitespace, returns (parsed, remaining), both strings may be empty
-fn parse_while<F: Fn(char) -> bool>(source: Source, predicate: F) -> (&str, Source) {
-    parse_chars_while(source.trim_left(), predicate)
+fn parse_while<'s, F: Fn(char) -> bool>(source: Source<'s>, predicate: F) -> (&'s str, Source<'s>) {
+    parse_chars_while(source.trim_start(), predicate)
 }
 
 /// skips leading whitespace, returns Ok(none) if there is no string literal, and an error if there was a string literal detected but it was malformed
-fn parse_string_literal(source: Source) -> ParseResult<(Option<&str>, Source)> {
+fn parse_string_literal<'s>(source: Source<'s>) -> ParseResult<'s, (Option<&'s str>, Source<'s>)> {
     if let Some(source) = skip(source, '\'') {
         parse_over_delimiter_char(source, '\'')
             .map(|(literal, source)| (Some(literal), source))
@@ -169,18 +611,24 @@ fn parse_string_literal(source: Source) -> ParseResult<(Option<&str>, Source)> {
 }
 
 /// skips leading whitespace, may return an empty identifier
-fn parse_identifier(source: Source) -> (Identifier, Source) {
+fn parse_identifier<'s>(source: Source<'s>) -> (Identifier<'s>, Source<'s>) {
     let (name, source) = parse_while(
-        source.trim_left(),
-        |symbol| !symbol.is_whitespace() && !(".:{}").contains(symbol)
+        source.trim_start(),
+        |symbol| !symbol.is_whitespace() && !(".:{}+").contains(symbol)
     );
 
-    (Identifier { name }, source)
+    (Identifier::parsed(name, source), source)
 }
 
 // TODO test these, and test lookup
 /// parse a series of identifiers, separated by dots, e.g. 'label.dimensions.x'
-fn parse_reference(source: Source) -> (Reference, Source) {
+fn parse_reference<'s>(source: Source<'s>) -> (Reference<'s>, Source<'s>) {
+    parse_reference_with(source, parse_identifier)
+}
+
+fn parse_reference_with<'s, F>(source: Source<'s>, parse_identifier: F) -> (Reference<'s>, Source<'s>)
+    where F: Fn(Source<'s>) -> (Identifier<'s>, Source<'s>)
+{
     let mut identifiers = Vec::new();
 
     let (first_identifier, mut source) = parse_identifier(source);
@@ -204,13 +652,12 @@ fn parse_reference(source: Source) -> (Reference, Source) {
 }
 
 /// skips leading whitespace, parses until a '}' is found, throws error on file end without '}'
-fn parse_delimited_named_objects(mut source: Source) -> ParseResult<(NamedObjects, Source)> {
-    let mut names = HashMap::new();
-    let mut objects = Vec::new();
+fn parse_delimited_named_objects<'s>(mut source: Source<'s>) -> ParseResult<'s, (NamedObjects<'s>, Source<'s>)> {
+    let mut objects = NamedObjects::default();
 
     if let Some(mut remaining_source) = skip(source, '{') {
         loop {
-            let remaining_objects = remaining_source.trim_left();
+            let remaining_objects = remaining_source.trim_start();
 
             if remaining_objects.is_empty() { // source is over, without finding delimiter
                 return Err(ParseError::UnexpectedEndOfInput {
@@ -225,11 +672,7 @@ fn parse_delimited_named_objects(mut source: Source) -> ParseResult<(NamedObject
                     break;
 
                 } else { // more overridden properties to parse
-                    let (name, object, new_source) = parse_named_object(remaining_objects)?;
-                    names.insert(name, objects.len());
-                    objects.push(object);
-
-                    remaining_source = new_source;
+                    remaining_source = parse_declaration(remaining_objects, &mut objects)?;
                 }
             }
         }
@@ -237,16 +680,15 @@ fn parse_delimited_named_objects(mut source: Source) -> ParseResult<(NamedObject
         source = remaining_source;
     }
 
-    Ok((NamedObjects { identifiers: names, objects }, source))
+    Ok((objects, source))
 }
 
 /// skips leading whitespace, parses until file end, throws error on unexpected '}'
-fn parse_remaining_named_objects(mut source: Source) -> ParseResult<(NamedObjects, Source)> {
-    let mut names = HashMap::new();
-    let mut objects = Vec::new();
+fn parse_remaining_named_objects<'s>(mut source: Source<'s>) -> ParseResult<'s, (NamedObjects<'s>, Source<'s>)> {
+    let mut objects = NamedObjects::default();
 
     loop {
-        let remaining_objects = source.trim_left();
+        let remaining_objects = source.trim_start();
 
         // no more properties to parse
         if remaining_objects.is_empty() {
@@ -254,56 +696,487 @@ fn parse_remaining_named_objects(mut source: Source) -> ParseResult<(NamedObject
             break;
 
         } else { // text remaining, probably an object
-            let (name, object, new_source) = parse_named_object(remaining_objects)?;
-            names.insert(name, objects.len());
-            objects.push(object);
-            source = new_source;
+            source = parse_declaration(remaining_objects, &mut objects)?;
         }
     }
 
-    Ok((NamedObjects { identifiers: names, objects }, source))
+    Ok((objects, source))
 }
 
 
+/// skips leading whitespace, returns the remaining source if the keyword is next, e.g. `!replace`
+fn skip_keyword<'s>(source: Source<'s>, keyword: &str) -> Option<Source<'s>> {
+    let (name, remaining) = parse_identifier(source);
+    if name.name == keyword { Some(remaining) } else { None }
+}
+
+/// skips leading whitespace, parses optional prototypes and optional overrides
+fn parse_compound<'s>(source: Source<'s>) -> ParseResult<'s, (Compound<'s>, Source<'s>)> {
+    let mut prototypes = Vec::new();
+    let (prototype, mut source) = parse_reference(source);
+
+    if prototype.has_target() {
+        prototypes.push(prototype);
+
+        // further prototypes, e.g. `Clickable + Styled`
+        while let Some(remaining) = skip(source, '+') {
+            let (prototype, remaining) = parse_reference(remaining);
+            if !prototype.has_target() {
+                return Err(expect_identifier(remaining));
+            }
+
+            prototypes.push(prototype);
+            source = remaining;
+        }
+    }
+
+    let (overrides, source) = parse_delimited_named_objects(source)?;
+    Ok((Compound { prototypes, overrides }, source))
+}
+
+/// skips leading whitespace, parses `!append`, `!prepend`, or `!insert_before` and `!insert_after` followed by an index
+fn parse_placement<'s>(source: Source<'s>) -> ParseResult<'s, Option<(Placement<Identifier<'s>>, Source<'s>)>> {
+    let (keyword, remaining) = parse_identifier(source);
+
+    let placement: fn(Identifier<'s>) -> Placement<Identifier<'s>> = match keyword.name {
+        "!append" => return Ok(Some((Placement::Append, remaining))),
+        "!prepend" => return Ok(Some((Placement::Prepend, remaining))),
+        "!insert_before" => Placement::Before,
+        "!insert_after" => Placement::After,
+        _ => return Ok(None),
+    };
+
+    let (anchor, source) = parse_identifier(remaining);
+    if anchor.name.is_empty() {
+        return Err(expect_identifier(source));
+    }
+
+    Ok(Some((placement(anchor), source)))
+}
+
+fn expect_identifier(source: Source) -> ParseError {
+    let source = source.trim_start();
+    if source.is_empty() {
+        ParseError::UnexpectedEndOfInput { expected: None }
+    } else {
+        ParseError::UnexpectedSymbol { expected: None, found: source }
+    }
+}
+
 /// skips leading whitespace, parses either a string literal or a compound overriden object
-fn parse_object(source: Source) -> ParseResult<(Object, Source)> {
-    if let (Some(string_literal), source) = parse_string_literal(source)? {
-        Ok((
-            Object::StringLiteral(string_literal),
-            source
-        ))
+fn parse_object<'s>(source: Source<'s>) -> ParseResult<'s, (Object<'s>, Source<'s>)> {
+    if let (Some(string_literal), remaining) = parse_string_literal(source)? {
+        let literal_start = source.len() - remaining.len() - string_literal.len() - 1;
+
+        match parse_template(&source[literal_start ..], string_literal.len())? {
+            Some(parts) => Ok((Object::Template(parts), remaining)),
+            None => Ok((Object::StringLiteral(string_literal), remaining)),
+        }
+
+    } else if let Some(source) = skip(source, '(') {
+        let (expression, source) = parse_whole_expression(source)?;
+        Ok((Object::Expression(expression), expect(source, ')')?))
+
+    } else if let Some(source) = skip_keyword(source, "!unset") {
+        Ok((Object::Unset, source))
+
+    } else if let Some(source) = skip_keyword(source, "!replace") {
+        let (compound, source) = parse_compound(source)?;
+        Ok((Object::Replacement(compound), source))
+
+    } else if let Some((placement, source)) = parse_placement(source)? {
+        let (compound, source) = parse_compound(source)?;
+        Ok((Object::Extension(placement, compound), source))
 
     } else {
-        let (prototype, source) = parse_reference(source);
-        let (overrides, source) = parse_delimited_named_objects(source)?;
+        let (compound, source) = parse_compound(source)?;
+        Ok((Object::Compound(compound), source))
+    }
+}
+
+
+/// the binary operators of each precedence level, from the loosest to the tightest binding.
+/// longer symbols come first, so that `<=` is not parsed as `<`
+const BINARY_OPERATORS: &[&[(&str, BinaryOperator)]] = &[
+    &[ ("||", BinaryOperator::Or) ],
+    &[ ("&&", BinaryOperator::And) ],
+    &[
+        ("==", BinaryOperator::Equal), ("!=", BinaryOperator::NotEqual),
+        ("<=", BinaryOperator::LessOrEqual), (">=", BinaryOperator::GreaterOrEqual),
+        ("<", BinaryOperator::Less), (">", BinaryOperator::Greater),
+    ],
+    &[ ("~", BinaryOperator::Concatenate) ],
+    &[ ("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract) ],
+    &[ ("*", BinaryOperator::Multiply), ("/", BinaryOperator::Divide), ("%", BinaryOperator::Remainder) ],
+];
+
+const UNARY_OPERATORS: &[(&str, UnaryOperator)] = &[ ("!", UnaryOperator::Not), ("-", UnaryOperator::Negate) ];
+
+/// symbols which end a name inside an expression, in addition to whitespace
+const EXPRESSION_SYMBOLS: &str = ".:{}()+-*/%~=!<>&|?,'$";
+
+/// the expression that starts with `start` and ends where `remaining` begins
+fn expression<'s>(start: Source<'s>, remaining: Source<'s>, operation: Operation<'s>) -> Expression<'s> {
+    Expression { operation, text: &start[.. start.len() - remaining.len()], span: Span { start: start.len(), end: remaining.len() } }
+}
+
+/// skips leading whitespace, returns the operator if the source starts with one of them
+fn parse_operator<'s, T: Copy>(source: Source<'s>, operators: &[(&str, T)]) -> Option<(T, Source<'s>)> {
+    let source = source.trim_start();
+    operators.iter()
+        .find(|&&(symbol, _)| source.starts_with(symbol))
+        .map(|&(symbol, operator)| (operator, &source[symbol.len() ..]))
+}
+
+/// skips leading whitespace, parses a whole expression, including a conditional.
+/// the spans are located in the source, like those of `parse`
+pub fn parse_expression<'s>(source: Source<'s>) -> ParseResult<'s, (Expression<'s>, Source<'s>)> {
+    let (mut expression, remaining) = parse_whole_expression(source)?;
+    locate_expression(&mut expression, source.len());
+    Ok((expression, remaining))
+}
+
+/// skips leading whitespace, parses an expression, including a conditional
+fn parse_whole_expression<'s>(source: Source<'s>) -> ParseResult<'s, (Expression<'s>, Source<'s>)> {
+    let start = source.trim_start();
+    let (condition, source) = parse_binary_expression(start, 0)?;
+
+    let source = match skip(source, '?') {
+        Some(source) => source,
+        None => return Ok((condition, source)),
+    };
+
+    let (then, source) = parse_whole_expression(source)?;
+    let source = expect(source, ':')?;
+    let (otherwise, source) = parse_whole_expression(source)?;
+
+    let operation = Operation::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise));
+    Ok((expression(start, source, operation), source))
+}
+
+/// left associative operators of the precedence level and all tighter levels
+fn parse_binary_expression<'s>(source: Source<'s>, level: usize) -> ParseResult<'s, (Expression<'s>, Source<'s>)> {
+    let operators = match BINARY_OPERATORS.get(level) {
+        Some(operators) => operators,
+        None => return parse_unary_expression(source),
+    };
+
+    let start = source.trim_start();
+    let (mut left, mut source) = parse_binary_expression(start, level + 1)?;
+
+    while let Some((operator, remaining)) = parse_operator(source, operators) {
+        let (right, remaining) = parse_binary_expression(remaining, level + 1)?;
+        left = expression(start, remaining, Operation::Binary(operator, Box::new(left), Box::new(right)));
+        source = remaining;
+    }
+
+    Ok((left, source))
+}
+
+fn parse_unary_expression<'s>(source: Source<'s>) -> ParseResult<'s, (Expression<'s>, Source<'s>)> {
+    let start = source.trim_start();
+
+    match parse_operator(start, UNARY_OPERATORS) {
+        Some((operator, remaining)) => {
+            let (operand, remaining) = parse_unary_expression(remaining)?;
+            Ok((expression(start, remaining, Operation::Unary(operator, Box::new(operand))), remaining))
+        },
 
-        Ok((
-            Object::Compound(Compound {
-                prototype,
-                overrides,
-            }),
-            source
-        ))
+        None => parse_primary_expression(start),
     }
 }
 
+/// literals, numbers, references, function calls, and expressions in parentheses
+fn parse_primary_expression<'s>(source: Source<'s>) -> ParseResult<'s, (Expression<'s>, Source<'s>)> {
+    let start = source.trim_start();
+
+    if let Some(inner) = skip_char(start, '(') {
+        let (inner, remaining) = parse_whole_expression(inner)?;
+        return Ok((inner, expect(remaining, ')')?));
+    }
+
+    if let (Some(literal), remaining) = parse_string_literal(start)? {
+        let operation = match parse_template(&start[1 ..], literal.len())? {
+            Some(parts) => Operation::Template(parts),
+            None => Operation::Literal(literal),
+        };
+
+        return Ok((expression(start, remaining, operation), remaining));
+    }
+
+    if start.starts_with(|symbol: char| symbol.is_ascii_digit()) {
+        let (number, remaining) = parse_chars_while(start, |symbol| symbol.is_ascii_digit() || symbol == '.');
+        return Ok((expression(start, remaining, Operation::Literal(number)), remaining));
+    }
+
+    let parse_identifier = |source| {
+        let (name, source) = parse_while(source, |symbol| !symbol.is_whitespace() && !EXPRESSION_SYMBOLS.contains(symbol));
+        (Identifier::parsed(name, source), source)
+    };
+
+    if let Some(parameter) = skip_char(start, '$') {
+        let (reference, remaining) = parse_reference_with(parameter, parse_identifier);
+        if !reference.has_target() {
+            return Err(expect_identifier(parameter));
+        }
+
+        return Ok((expression(start, remaining, Operation::Parameter(reference)), remaining));
+    }
+
+    let (reference, remaining) = parse_reference_with(start, parse_identifier);
+
+    if !reference.has_target() {
+        return Err(expect_identifier(start));
+    }
+
+    // a single name followed by parentheses calls a builtin function
+    let arguments_source = match skip(remaining, '(') {
+        Some(arguments_source) if reference.identifiers.len() == 1 => arguments_source,
+        _ => return Ok((expression(start, remaining, Operation::Reference(reference)), remaining)),
+    };
+
+    let mut arguments = Vec::new();
+    let mut source = arguments_source;
+
+    if let Some(remaining) = skip(source, ')') {
+        source = remaining;
+
+    } else {
+        loop {
+            let (argument, remaining) = parse_whole_expression(source)?;
+            arguments.push(argument);
+
+            if let Some(remaining) = skip(remaining, ')') {
+                source = remaining;
+                break;
+            }
+
+            source = expect(remaining, ',')?;
+        }
+    }
+
+    let operation = Operation::Call(reference.identifiers[0].name, arguments);
+    Ok((expression(start, source, operation), source))
+}
+
+/// splits a string literal at every `${reference}`, none if it does not contain any.
+/// `source` starts with the literal of the specified length, and continues with the rest of the document
+fn parse_template<'s>(source: Source<'s>, literal_length: usize) -> ParseResult<'s, Option<Vec<TemplatePart<'s>>>> {
+    let literal = &source[.. literal_length];
+    if !literal.contains("${") {
+        return Ok(None);
+    }
+
+    let mut parts = Vec::new();
+    let mut text_start = 0;
+    let mut search_start = 0;
+
+    while let Some(found) = literal[search_start ..].find("${") {
+        let dollar = search_start + found;
+
+        // `\${` is not an interpolation, but the backslash is removed
+        if literal[.. dollar].ends_with('\\') {
+            parts.push(TemplatePart::Text(&literal[text_start .. dollar - 1]));
+            text_start = dollar;
+            search_start = dollar + 2;
+            continue;
+        }
+
+        parts.push(TemplatePart::Text(&literal[text_start .. dollar]));
+
+        let (mut reference, remaining) = parse_reference(&literal[dollar + 2 ..]);
+
+        // the reference is located in the literal, which is followed by the rest of the source
+        for location in reference.identifiers.iter_mut().filter_map(|identifier| identifier.location.as_mut()) {
+            location.start += source.len() - literal_length;
+            location.end += source.len() - literal_length;
+        }
+
+        // errors point into the source, which also contains the closing quote
+        let found = || source[literal_length - remaining.len() ..].trim_start();
+
+        if !reference.has_target() {
+            return Err(ParseError::UnexpectedSymbol { expected: None, found: found() });
+        }
+
+        let remaining = skip(remaining, '}')
+            .ok_or_else(|| ParseError::UnexpectedSymbol { expected: Some('}'), found: found() })?;
+
+        parts.push(TemplatePart::Reference(reference));
+        text_start = literal_length - remaining.len();
+        search_start = text_start;
+    }
+
+    parts.push(TemplatePart::Text(&literal[text_start ..]));
+    parts.retain(|part| *part != TemplatePart::Text(""));
+    Ok(Some(parts))
+}
+
+/// skips leading whitespace, parses a named object or a conditional, and adds it to the objects
+fn parse_declaration<'s>(source: Source<'s>, objects: &mut NamedObjects<'s>) -> ParseResult<'s, Source<'s>> {
+    let (name, remaining) = parse_identifier(source);
+    let is_named = |source: Source| source.trim_start().starts_with([':', '+']);
+
+    // `if condition { overrides }`, unless a property is named `if`
+    if name.name == "if" && !is_named(remaining) {
+        let (condition, remaining) = parse_whole_expression(remaining)?;
+        expect(remaining, '{')?;
+
+        let (overrides, remaining) = parse_delimited_named_objects(remaining)?;
+        objects.conditionals.push(Conditional { condition, overrides });
+        return Ok(remaining);
+    }
+
+    // `name if condition: object`
+    if let Some(remaining) = skip_keyword(remaining, "if") {
+        let (condition, remaining) = parse_whole_expression(remaining)?;
+        let (object, remaining) = parse_named_value(remaining)?;
+
+        let mut overrides = NamedObjects::default();
+        overrides.declare(name, object);
+        objects.conditionals.push(Conditional { condition, overrides });
+        return Ok(remaining);
+    }
+
+    let (name, object, remaining) = parse_named_object(source)?;
+    objects.declare(name, object);
+    Ok(remaining)
+}
 
 /// skips leading whitespace
-fn parse_named_object(source: Source) -> ParseResult<(Identifier, Object, Source)> {
+fn parse_named_object<'s>(source: Source<'s>) -> ParseResult<'s, (Identifier<'s>, Object<'s>, Source<'s>)> {
     let (name, source) = parse_identifier(source);
-    let source = expect(source, ':')?;
-    let (object, source) = parse_object(source)?;
+    let (object, source) = parse_named_value(source)?;
     Ok((name, object, source))
 }
 
+/// the object after a name, starting with `:`. `name += compound` appends to the inherited list
+fn parse_named_value<'s>(source: Source<'s>) -> ParseResult<'s, (Object<'s>, Source<'s>)> {
+    if let Some(source) = skip(source, '+') {
+        let (compound, source) = parse_compound(expect_char(source, '=')?)?;
+        return Ok((Object::Extension(Placement::Append, compound), source));
+    }
+
+    parse_object(expect(source, ':')?)
+}
+
 
 /// parses objects from a string
-pub fn parse(source: Source) -> ParseResult<NamedObjects> {
-    parse_remaining_named_objects(source)
-        .map(|(objects, _rest_src)| objects)
+pub fn parse<'s>(source: Source<'s>) -> ParseResult<'s, NamedObjects<'s>> {
+    let (mut objects, _rest_src) = parse_remaining_named_objects(source)?;
+    locate_named(&mut objects, source.len());
+    Ok(objects)
+}
+
+/// whether `name: ''` declares exactly this name, so that the name can be written into a source.
+/// names starting with `!` are rejected too, as they would read as negations in conditions
+pub fn is_valid_name(name: &str) -> bool {
+    if name.starts_with('!') { return false; }
+
+    let source = format!("{}: ''", name);
+    match parse(&source) {
+        Ok(objects) => objects.objects.len() == 1 && objects.conditionals.is_empty()
+            && objects.identifiers.keys().any(|identifier| identifier.name == name),
+
+        Err(_) => false,
+    }
+}
+
+/// turns the spans of the identifiers and expressions into offsets into the source of the specified length.
+/// while parsing, each parser only sees the remaining source, so spans count the bytes from their start and end to the end of the source
+fn locate_named(objects: &mut NamedObjects, length: usize) {
+    for object in &mut objects.objects {
+        locate_object(object, length);
+    }
+
+    objects.identifiers = objects.identifiers.drain()
+        .map(|(mut identifier, index)| { locate_identifier(&mut identifier, length); (identifier, index) })
+        .collect();
+
+    for (identifier, _) in &mut objects.unnamed {
+        locate_identifier(identifier, length);
+    }
+
+    for conditional in &mut objects.conditionals {
+        locate_expression(&mut conditional.condition, length);
+        locate_named(&mut conditional.overrides, length);
+    }
+}
+
+fn locate_object(object: &mut Object, length: usize) {
+    match *object {
+        Object::StringLiteral(_) | Object::Unset => {},
+        Object::Template(ref mut parts) => locate_template(parts, length),
+        Object::Expression(ref mut expression) => locate_expression(expression, length),
+        Object::Compound(ref mut compound) | Object::Replacement(ref mut compound) => locate_compound(compound, length),
+
+        Object::Extension(ref mut placement, ref mut compound) => {
+            if let Placement::Before(ref mut anchor) | Placement::After(ref mut anchor) = *placement {
+                locate_identifier(anchor, length);
+            }
+
+            locate_compound(compound, length);
+        },
+    }
+}
+
+fn locate_compound(compound: &mut Compound, length: usize) {
+    for prototype in &mut compound.prototypes {
+        locate_reference(prototype, length);
+    }
+
+    locate_named(&mut compound.overrides, length);
+}
+
+fn locate_template(parts: &mut [TemplatePart], length: usize) {
+    for part in parts {
+        if let TemplatePart::Reference(ref mut reference) = *part {
+            locate_reference(reference, length);
+        }
+    }
 }
 
+fn locate_expression(expression: &mut Expression, length: usize) {
+    locate(&mut expression.span, length);
+
+    match expression.operation {
+        Operation::Literal(_) => {},
+        Operation::Template(ref mut parts) => locate_template(parts, length),
+        Operation::Reference(ref mut reference) | Operation::Parameter(ref mut reference) => locate_reference(reference, length),
+        Operation::Unary(_, ref mut operand) => locate_expression(operand, length),
+
+        Operation::Binary(_, ref mut left, ref mut right) => {
+            locate_expression(left, length);
+            locate_expression(right, length);
+        },
+
+        Operation::Conditional(ref mut condition, ref mut then, ref mut otherwise) => {
+            locate_expression(condition, length);
+            locate_expression(then, length);
+            locate_expression(otherwise, length);
+        },
+
+        Operation::Call(_, ref mut arguments) => for argument in arguments {
+            locate_expression(argument, length);
+        },
+    }
+}
+
+fn locate_reference(reference: &mut Reference, length: usize) {
+    for identifier in &mut reference.identifiers {
+        locate_identifier(identifier, length);
+    }
+}
+
+fn locate_identifier(identifier: &mut Identifier, length: usize) {
+    if let Some(ref mut location) = identifier.location {
+        locate(location, length);
+    }
+}
 
+fn locate(span: &mut Span, length: usize) {
+    *span = Span { start: length - span.start, end: length - span.end };
+}
 
 
 #[cfg(test)]
@@ -317,26 +1190,31 @@ mod test_parsing {
         overrides: Vec<(&'s str, Object<'s>)>
     ) -> Object<'s> {
         Object::Compound(Compound {
-            prototype: Reference {
-                identifiers: prototype.iter()
-                    .map(|name| Identifier { name })
-                    .collect()
+            prototypes: if prototype.is_empty() { vec![] } else {
+                vec![ Reference {
+                    identifiers: prototype.iter()
+                        .map(|&name| Identifier::new(name))
+                        .collect()
+                } ]
             },
             overrides: NamedObjects {
                 identifiers: overrides.iter().enumerate()
-                    .map(|(index, &(ref name, _))| {
-                        (Identifier { name }, index)
+                    .map(|(index, (name, _))| {
+                        (Identifier::new(name), index)
                     })
                     .collect(),
 
                 objects: overrides.into_iter()
                     .map(|(_, object)| object)
                     .collect(),
+
+                unnamed: Vec::new(),
+                conditionals: Vec::new(),
             },
         })
     }
 
-    fn compound_with_prototype(prototype: Vec<&str>) -> Object {
+    fn compound_with_prototype<'s>(prototype: Vec<&'s str>) -> Object<'s> {
         compound_with_prototype_and_overrides(prototype, vec![])
     }
 
@@ -363,6 +1241,21 @@ mod test_parsing {
         assert_eq!(skip_char(" \nx ", 'x'), None);
     }
 
+    #[test]
+    fn test_is_valid_name(){
+        assert!(is_valid_name("Comic"));
+        assert!(is_valid_name("html-name"));
+        assert!(is_valid_name("0"));
+
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("!visible"));
+        assert!(!is_valid_name("a.b"));
+        assert!(!is_valid_name("a b"));
+        assert!(!is_valid_name("a: 'x' b"));
+        assert!(!is_valid_name("a+"));
+        assert!(!is_valid_name("{"));
+    }
+
     #[test]
     fn test_expect_symbol(){
         assert_eq!(expect("{}", '{'), Ok("}"));
@@ -413,10 +1306,10 @@ mod test_parsing {
     fn test_parse_while(){
         assert_eq!(parse_while("xy", |c| c != 'y'), ("x", "y"));
         assert_eq!(parse_while("\n xy", |c| c != 'y'), ("x", "y"));
-        assert_eq!(parse_while("xyz", |c| true), ("xyz", ""));
-        assert_eq!(parse_while("xyz", |c| false), ("", "xyz"));
-        assert_eq!(parse_while("", |c| true), ("", ""));
-        assert_eq!(parse_while("", |c| false), ("", ""));
+        assert_eq!(parse_while("xyz", |_| true), ("xyz", ""));
+        assert_eq!(parse_while("xyz", |_| false), ("", "xyz"));
+        assert_eq!(parse_while("", |_| true), ("", ""));
+        assert_eq!(parse_while("", |_| false), ("", ""));
 
         assert_eq!(parse_while("9b", |c| c.is_numeric()), ("9", "b"));
         assert_eq!(parse_while(" 9 b", |c| c.is_numeric()), ("9", " b"));
@@ -436,37 +1329,37 @@ mod test_parsing {
 
     #[test]
     fn test_parse_identifier() {
-        assert_eq!(parse_identifier("x$%&?/(|="), (Identifier { name: "x$%&?/(|=" }, ""));
-        assert_eq!(parse_identifier("xy "), (Identifier { name: "xy" }, " "));
-        assert_eq!(parse_identifier(" xy "), (Identifier { name: "xy" }, " "));
-        assert_eq!(parse_identifier(" xy9 "), (Identifier { name: "xy9" }, " "));
-        assert_eq!(parse_identifier(" 9 "), (Identifier { name: "9" }, " "));
-        assert_eq!(parse_identifier("x§"), (Identifier { name: "x§" }, ""));
+        assert_eq!(parse_identifier("x$%&?/(|="), (Identifier::new("x$%&?/(|="), ""));
+        assert_eq!(parse_identifier("xy "), (Identifier::new("xy"), " "));
+        assert_eq!(parse_identifier(" xy "), (Identifier::new("xy"), " "));
+        assert_eq!(parse_identifier(" xy9 "), (Identifier::new("xy9"), " "));
+        assert_eq!(parse_identifier(" 9 "), (Identifier::new("9"), " "));
+        assert_eq!(parse_identifier("x§"), (Identifier::new("x§"), ""));
     }
 
     #[test]
     fn test_parse_reference() {
-        assert_eq!(parse_reference("x"), (Reference { identifiers: vec![Identifier { name: "x" }]}, ""));
+        assert_eq!(parse_reference("x"), (Reference { identifiers: vec![Identifier::new("x")]}, ""));
         assert_eq!(parse_reference("x.y"), (Reference {
             identifiers: vec![
-                Identifier { name: "x" },
-                Identifier { name: "y" }
+                Identifier::new("x"),
+                Identifier::new("y")
             ] }, "")
         );
 
         assert_eq!(parse_reference("x.y.$"), (Reference {
             identifiers: vec![
-                Identifier { name: "x" },
-                Identifier { name: "y" },
-                Identifier { name: "$" },
+                Identifier::new("x"),
+                Identifier::new("y"),
+                Identifier::new("$"),
             ] }, "")
         );
 
         assert_eq!(parse_reference(" x . y . $ "), (Reference {
             identifiers: vec![
-                Identifier { name: "x" },
-                Identifier { name: "y" },
-                Identifier { name: "$" },
+                Identifier::new("x"),
+                Identifier::new("y"),
+                Identifier::new("$"),
             ] }, " ")
         );
 
@@ -478,12 +1371,99 @@ mod test_parsing {
     fn test_parse_flat_value(){
         assert_eq!(parse_object("'xyz'"), Ok((Object::StringLiteral("xyz"), "")));
         assert_eq!(parse_object(" 'xyz' "), Ok((Object::StringLiteral("xyz"), " ")));
+
+        let reference = |names: Vec<&'static str>| TemplatePart::Reference(Reference {
+            identifiers: names.into_iter().map(Identifier::new).collect()
+        });
+
+        assert_eq!(
+            parse_object("'Hello ${ persons.peter }! \\${x} ${a}' "),
+            Ok((Object::Template(vec![
+                TemplatePart::Text("Hello "), reference(vec!["persons", "peter"]),
+                TemplatePart::Text("! "), TemplatePart::Text("${x} "), reference(vec!["a"]),
+            ]), " "))
+        );
+
+        assert_eq!(parse_object("'${x'"), Err(ParseError::UnexpectedSymbol { expected: Some('}'), found: "'" }));
+
+        let (object, remaining) = parse_object("(a.b * 2 + -len('x') < 3 ? 'yes' : (c ~ 'd')) rest").unwrap();
+        assert_eq!(remaining, " rest");
+
+        let expression = match object { Object::Expression(expression) => expression, _ => panic!("expected an expression") };
+        assert_eq!(expression.text, "a.b * 2 + -len('x') < 3 ? 'yes' : (c ~ 'd')");
+
+        let (condition, then, otherwise) = match expression.operation {
+            Operation::Conditional(condition, then, otherwise) => (condition, then, otherwise),
+            _ => panic!("expected a conditional"),
+        };
+
+        assert_eq!(condition.text, "a.b * 2 + -len('x') < 3");
+        assert_eq!(then.operation, Operation::Literal("yes"));
+        assert_eq!(otherwise.text, "c ~ 'd'");
+
+        match condition.operation {
+            Operation::Binary(BinaryOperator::Less, ref sum, _) => match sum.operation {
+                Operation::Binary(BinaryOperator::Add, ref product, ref call) => {
+                    assert_eq!(product.text, "a.b * 2");
+                    assert_eq!(call.text, "-len('x')");
+                },
+
+                _ => panic!("expected an addition"),
+            },
+
+            _ => panic!("expected a comparison"),
+        }
+
+        assert_eq!(parse_object("(a +)"), Err(ParseError::UnexpectedSymbol { expected: None, found: ")" }));
+        assert_eq!(parse_object("(a b)"), Err(ParseError::UnexpectedSymbol { expected: Some(')'), found: "b)" }));
+        assert_eq!(parse_object("'${}'"), Err(ParseError::UnexpectedSymbol { expected: None, found: "}'" }));
         assert_eq!(parse_object("div"), Ok((compound_with_prototype(vec!["div"]), "")));
         assert_eq!(parse_object(" div!"), Ok((compound_with_prototype(vec!["div!"]), "")));
         assert_eq!(parse_object("div{}"), Ok((compound_with_prototype(vec!["div"]), "")));
         assert_eq!(parse_object(" div { } "), Ok((compound_with_prototype(vec!["div"]), " ")));
         assert_eq!(parse_object(""), Ok((empty_compound(), "")));
 
+        assert_eq!(
+            parse_object(" !replace div { } "),
+            Ok((Object::Replacement(Compound {
+                prototypes: vec![ Reference { identifiers: vec![ Identifier::new("div") ] } ],
+                overrides: NamedObjects { objects: vec![], identifiers: HashMap::new(), unnamed: vec![], conditionals: vec![] },
+            }), " "))
+        );
+
+        assert_eq!(parse_object("!replacement"), Ok((compound_with_prototype(vec!["!replacement"]), "")));
+        assert_eq!(parse_object(" !unset x: "), Ok((Object::Unset, " x: ")));
+
+        let reference = |name| Reference { identifiers: vec![ Identifier::new(name) ] };
+        assert_eq!(
+            parse_object("Clickable+Styled + x.y{}"),
+            Ok((Object::Compound(Compound {
+                prototypes: vec![
+                    reference("Clickable"), reference("Styled"),
+                    Reference { identifiers: vec![ Identifier::new("x"), Identifier::new("y") ] },
+                ],
+                overrides: NamedObjects { objects: vec![], identifiers: HashMap::new(), unnamed: vec![], conditionals: vec![] },
+            }), ""))
+        );
+
+        assert_eq!(
+            parse_object("Clickable + { }"),
+            Err(ParseError::UnexpectedSymbol { expected: None, found: "{ }" })
+        );
+
+        let empty = || NamedObjects { objects: vec![], identifiers: HashMap::new(), unnamed: vec![], conditionals: vec![] };
+        assert_eq!(
+            parse_object(" !insert_after 2 { } "),
+            Ok((Object::Extension(Placement::After(Identifier::new("2")), Compound { prototypes: vec![], overrides: empty() }), " "))
+        );
+
+        assert_eq!(
+            parse_object("!prepend links"),
+            Ok((Object::Extension(Placement::Prepend, Compound { prototypes: vec![ reference("links") ], overrides: empty() }), ""))
+        );
+
+        assert_eq!(parse_object("!insert_before { }"), Err(ParseError::UnexpectedSymbol { expected: None, found: "{ }" }));
+
 
         /* TODO
         assert_eq!(
@@ -499,25 +1479,67 @@ mod test_parsing {
     fn test_parse_flat_named_object(){
         assert_eq!(
             parse_named_object(" text: 'xyz' "),
-            Ok((Identifier { name: "text" }, Object::StringLiteral("xyz"), " "))
+            Ok((Identifier::new("text"), Object::StringLiteral("xyz"), " "))
         );
 
         assert_eq!(
             parse_named_object(" text: div { } "),
             Ok((
-                Identifier { name: "text" },
+                Identifier::new("text"),
                 compound_with_prototype(vec!["div"]),
                 " "
             ))
         );
     }
 
+    #[test]
+    fn test_parse_list(){
+        let (name, object, remaining) = parse_named_object(" nav += { : 'a' 1: 'b' : 'c' } ").unwrap();
+        assert_eq!((name, remaining), (Identifier::new("nav"), " "));
+
+        let entries = match object {
+            Object::Extension(Placement::Append, compound) => compound.overrides,
+            other => panic!("not an extension: {:?}", other),
+        };
+
+        // the explicit name wins over the implicit index
+        let names: Vec<(String, usize)> = entries.in_declaration_order().into_iter()
+            .map(|(name, _, index)| (name.into_owned(), index))
+            .collect();
+
+        assert_eq!(names, vec![ (String::from("0"), 0), (String::from("1"), 1) ]);
+        assert_eq!(entries.duplicate_index().map(|(index, _)| index), Some(1));
+        assert_eq!(entries.get("1"), Some(&Object::StringLiteral("b")));
+        assert_eq!(entries.get("2"), None);
+
+        assert_eq!(parse_named_object("nav + { }"), Err(ParseError::UnexpectedSymbol { expected: Some('='), found: " { }" }));
+    }
+
+    #[test]
+    fn test_parse_conditional(){
+        let parsed = parse("Button: { if: 'x' if $theme == 'dark' { background: 'black' } border if $thin: '1' }").unwrap();
+        let button = match parsed.get("Button") {
+            Some(Object::Compound(compound)) => &compound.overrides,
+            other => panic!("not a compound: {:?}", other),
+        };
+
+        // a property named `if` is still a property
+        assert_eq!(button.get("if"), Some(&Object::StringLiteral("x")));
+        assert_eq!(button.get("background"), None);
+        assert_eq!(button.conditionals.len(), 2);
+
+        assert_eq!(button.conditionals[0].condition.text, "$theme == 'dark'");
+        assert_eq!(button.conditionals[0].overrides.get("background"), Some(&Object::StringLiteral("black")));
+        assert_eq!(button.conditionals[1].condition.operation, Operation::Parameter(Reference { identifiers: vec![ Identifier::new("thin") ] }));
+        assert_eq!(button.conditionals[1].overrides.get("border"), Some(&Object::StringLiteral("1")));
+    }
+
     #[test]
     fn test_parse_nested_object(){
         assert_eq!(
             parse_named_object(" my_div: div { text: 'xy z' } "),
             Ok((
-                Identifier { name: "my_div", },
+                Identifier::new("my_div"),
                 compound_with_prototype_and_overrides(vec!["div"], vec![
                     ("text", Object::StringLiteral("xy z")),
                 ]),
@@ -528,7 +1550,7 @@ mod test_parsing {
         assert_eq!(
             parse_named_object(" my_div: div { text: 'xy z' content: default {} } "),
             Ok((
-                Identifier { name: "my_div" },
+                Identifier::new("my_div"),
                 compound_with_prototype_and_overrides(vec!["div"], vec![
                     ("text", Object::StringLiteral("xy z")),
                     ("content", compound_with_prototype(vec!["default"])),
@@ -542,4 +1564,53 @@ mod test_parsing {
             Err(ParseError::UnexpectedEndOfInput { expected: Some('}') } )
         );
     }
+
+    #[test]
+    fn test_references(){
+        let source = "a: B + C { d: 'x ${e.f}' g: (h ? 1 : len(i)) if $j == k { l: M } }";
+        let parsed = parse(source).unwrap();
+        let references: Vec<String> = parsed.get("a").unwrap().references().iter().map(|reference| reference.to_string()).collect();
+        assert_eq!(references, vec![ "B", "C", "e.f", "h", "i", "k", "M" ]);
+    }
+
+    #[test]
+    fn test_locations(){
+        let source = "a: { b: (1 + c.d) e: !insert_after 0 { : 'x' } }";
+        let parsed = parse(source).unwrap();
+        let reference = Reference { identifiers: vec![ Identifier::new("a") ] };
+        assert_eq!(parsed.resolve_definition(&reference).unwrap().0.location, Some(Span { start: 0, end: 1 }));
+
+        let overrides = &parsed.get("a").unwrap().as_compound().unwrap().overrides;
+        let expression = match *overrides.get("b").unwrap() { Object::Expression(ref expression) => expression, _ => panic!("b is an expression") };
+        assert_eq!(expression.span, Span { start: 9, end: 16 });
+
+        match expression.operation {
+            Operation::Binary(_, _, ref right) => match right.operation {
+                Operation::Reference(ref reference) => assert_eq!((right.span, reference.location()), (Span { start: 13, end: 16 }, Some(right.span))),
+                _ => panic!("the right operand is a reference"),
+            },
+
+            _ => panic!("b is a sum"),
+        }
+
+        match *overrides.get("e").unwrap() {
+            Object::Extension(ref placement, _) => assert_eq!(placement.anchor().unwrap().location, Some(Span { start: 35, end: 36 })),
+            _ => panic!("e is an extension"),
+        }
+
+        let template = parse("a: 'x ${b.c}' d: ('${e}')").unwrap();
+        match *template.get("a").unwrap() {
+            Object::Template(ref parts) => assert_eq!(parts[1], TemplatePart::Reference(Reference { identifiers: vec![ Identifier::new("b"), Identifier::new("c") ] })),
+            _ => panic!("a is a template"),
+        }
+
+        let references = template.references();
+        assert_eq!(references.iter().map(|reference| reference.location()).collect::<Vec<_>>(), vec![ Some(Span { start: 8, end: 11 }), Some(Span { start: 21, end: 22 }) ]);
+
+        let (expression, _) = parse_expression("  1 + b)").unwrap();
+        assert_eq!(expression.span, Span { start: 2, end: 7 });
+
+        // identifiers which were not parsed have no location
+        assert_eq!(Reference { identifiers: vec![ Identifier::new("a") ] }.location(), None);
+    }
 }
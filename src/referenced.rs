@@ -1,19 +1,949 @@
+//! A document where objects refer to their prototypes by id instead of by name.
+//! Unlike flattening, this does not copy any properties,
+//! so single properties can be looked up without resolving the whole document,
+//! and recursive structures can be represented.
+
+use ::std::borrow::Cow;
 use ::std::collections::HashMap;
+use ::std::sync::Arc;
+use ::parse::{ self, NamedObjects, ResolveError, ResolveResult, ParseResult, Source, Span, UnaryOperator, BinaryOperator };
+use ::flat::{ FlatObject, FlatCompound };
+use ::list::Placement;
+
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ObjectId(usize);
 
 pub struct World {
-//    entities: IdMap<Object>,
-//    strings: IdMap<String>,
-//    identifiers: HashMap<Id<String>, Id<Object>>,
+    objects: Vec<Object>,
+    root: ObjectId,
 }
 
-pub struct Object {
-//    overrides: HashMap<Id<String>, Id<Object>>,
-//    prototype: Option<Id<Object>>,
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Object {
+    StringLiteral(String),
+    Compound(Compound),
+    Unset,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Compound {
+    pub prototypes: Vec<Prototype>,
+    pub overrides: HashMap<String, ObjectId>,
+
+    /// declared with `!replace`, so it is never merged with inherited compounds
+    pub replaces: bool,
+
+    /// the value of an expression, so it is not merged with other compounds at all, and also replaces
+    pub computed: bool,
+
+    /// declared with `+=` or similar, so its list entries are added to the inherited list.
+    /// the anchor is the name of the inherited entry, and where that name was written
+    pub extension: Option<Placement<(String, Option<Span>)>>,
+
+    /// overrides which only apply if their condition holds, in declaration order
+    pub conditionals: Vec<Conditional>,
+
+    /// this compound followed by all of its prototypes, see `::linearize`
+    pub linearization: Vec<ObjectId>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Conditional {
+    pub condition: Expression,
+
+    /// a compound without prototypes
+    pub overrides: ObjectId,
 }
 
+/// a condition, where references have been resolved, see `parse::Expression`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Expression {
+    pub operation: Operation,
+
+    /// where the expression was written, to locate errors
+    pub span: Span,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Operation {
+    /// string literals, numbers, and the text of string literals with `${reference}`,
+    /// which are concatenated with their references
+    Literal(String),
+    Reference(Prototype),
+
+    /// `$name`, a property of the compound whose condition is evaluated
+    Parameter(Vec<String>),
+
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+    Call(String, Vec<Expression>),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Prototype {
+    /// the reference as written in the source, e.g. `text.cancel`
+    pub reference: String,
+    pub target: ObjectId,
+}
+
+/// a resolved compound, consisting of several declared compounds,
+/// where earlier layers take precedence over later layers
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Entity {
+    layers: Vec<ObjectId>,
+
+    /// the layers that were reached through a prototype reference,
+    /// which must not be contained in themselves
+    referenced: Vec<ObjectId>,
+
+    /// the entries of an extended list, by their new index, which are looked up here instead of in the layers.
+    /// each entry is the entity it was declared in, and its name there
+    list: Option<Vec<(Entity, String)>>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Value<'w> {
+    StringLiteral(&'w str),
+    Compound(Entity),
+}
+
+
+/// converts the parse result, resolving all references
+struct Builder<'p> {
+    objects: Vec<Object>,
+    ids: HashMap<*const parse::Object<'p>, ObjectId>,
+
+    /// the parsed compounds, whose prototypes are resolved after all objects have an id
+    compounds: Vec<(ObjectId, &'p parse::Compound<'p>)>,
+
+    /// string literals containing `${reference}` and expressions, which are resolved last
+    computed: Vec<(ObjectId, &'p parse::Object<'p>)>,
+
+    /// the compound declaring a conditional, its condition, and its overrides,
+    /// whose references are resolved after all objects have an id
+    conditions: Vec<(ObjectId, &'p parse::Expression<'p>, ObjectId)>,
+}
+
+
 impl World {
-//    pub fn parse(world: &::parse::Object) -> Self {
-        // 1. collect all strings, because identifiers are probably repeated many times
-        // 2. convert string references to id references
-//    }
-}
\ No newline at end of file
+    pub fn parse(source: Source) -> ParseResult<ResolveResult<World>> {
+        ::parse::parse(source).map(|parsed| Self::build(&parsed))
+    }
+
+    pub fn build(parsed: &NamedObjects) -> ResolveResult<World> {
+        let mut builder = Builder {
+            objects: Vec::new(), ids: HashMap::new(),
+            compounds: Vec::new(), computed: Vec::new(), conditions: Vec::new(),
+        };
+
+        let root = builder.placeholder();
+        let overrides = builder.allocate_overrides(root, parsed)?;
+        builder.objects[root.0] = Object::Compound(Compound::plain(overrides));
+
+        builder.resolve_prototypes(parsed)?;
+        builder.resolve_conditions(parsed)?;
+        builder.compute(parsed)?;
+
+        let mut world = World { objects: builder.objects, root };
+        world.linearize_all()?;
+        Ok(world)
+    }
+
+    pub fn object(&self, id: ObjectId) -> &Object {
+        &self.objects[id.0]
+    }
+
+    pub fn root(&self) -> Entity {
+        self.entity(self.root)
+    }
+
+    /// the resolved value of a property, none if it does not exist or has been unset.
+    /// fails if it depends on a list extension or a condition that cannot be resolved
+    pub fn get(&self, entity: &Entity, name: &str) -> ResolveResult<Option<Value<'_>>> {
+        self.property(entity, name, &mut Vec::new(), &mut Vec::new())
+    }
+
+    /// looks up a nested property, e.g. `["text", "cancel"]`, none if a parent is not a compound
+    pub fn get_path<S: AsRef<str>>(&self, entity: &Entity, path: &[S]) -> ResolveResult<Option<Value<'_>>> {
+        let (last, parents) = match path.split_last() {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+
+        let mut entity = entity.clone();
+
+        for name in parents {
+            match self.get(&entity, name.as_ref())? {
+                Some(Value::Compound(child)) => entity = child,
+                Some(Value::StringLiteral(_)) | None => return Ok(None),
+            }
+        }
+
+        self.get(&entity, last.as_ref())
+    }
+
+    /// the names of all properties, including unset ones and the ones whose conditions do not hold, sorted
+    pub fn property_names(&self, entity: &Entity) -> Vec<Cow<'_, str>> {
+        let mut names: Vec<Cow<'_, str>> = entity.layers.iter()
+            .flat_map(|&layer| self.with_conditionals(layer))
+            .flat_map(|layer| self.overrides(layer).keys())
+            .filter(|name| entity.list.is_none() || ::list::index(name).is_none())
+            .map(|name| Cow::Borrowed(name.as_str()))
+            .collect();
+
+        if let Some(ref list) = entity.list {
+            names.extend((0 .. list.len()).map(|index| Cow::Owned(index.to_string())));
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// copies all properties, the same as `FlatObject::build_from_parsed` would for the whole document
+    pub fn flatten(&self, entity: &Entity) -> ResolveResult<FlatObject> {
+        self.flatten_entity(entity, &mut Vec::new()).map(FlatObject::compound)
+    }
+
+
+    fn compound(&self, id: ObjectId) -> Option<&Compound> {
+        match self.objects[id.0] {
+            Object::Compound(ref compound) => Some(compound),
+            _ => None,
+        }
+    }
+
+    fn overrides(&self, layer: ObjectId) -> &HashMap<String, ObjectId> {
+        &self.compound(layer).expect("layers are always compounds").overrides
+    }
+
+    /// the compound and its prototypes
+    fn entity(&self, id: ObjectId) -> Entity {
+        let linearization = &self.compound(id).expect("entities are always compounds").linearization;
+        Entity { layers: linearization.clone(), referenced: linearization[1 ..].to_vec(), list: None }
+    }
+
+    /// the variables which were followed are collected, to detect cycles when flattening.
+    /// `resolving` contains the objects which are being flattened, as conditions may flatten objects
+    fn property(
+        &self, entity: &Entity, name: &str,
+        variables: &mut Vec<ObjectId>, resolving: &mut Vec<ObjectId>
+    ) -> ResolveResult<Option<Value<'_>>> {
+        let layers = self.expand(entity, resolving)?;
+        self.lookup(entity, &layers, name, variables, resolving)
+    }
+
+    /// looks up the property in the specified layers of the entity
+    fn lookup(
+        &self, entity: &Entity, layers: &[ObjectId], name: &str,
+        variables: &mut Vec<ObjectId>, resolving: &mut Vec<ObjectId>
+    ) -> ResolveResult<Option<Value<'_>>> {
+        // the entries of an extended list have been renumbered
+        if let (Some(list), Some(index)) = (entity.list.as_ref(), ::list::index(name)) {
+            return match list.get(index) {
+                Some((declared_in, declared_name)) => self.property(declared_in, declared_name, variables, resolving),
+                None => Ok(None),
+            };
+        }
+
+        let index = match layers.iter().position(|&layer| self.overrides(layer).contains_key(name)) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let id = self.overrides(layers[index])[name];
+
+        Ok(match self.objects[id.0] {
+            Object::StringLiteral(ref literal) => Some(Value::StringLiteral(literal)),
+            Object::Unset => None,
+
+            Object::Compound(ref compound) if compound.extension.is_some() => {
+                let later = Entity { layers: layers[index + 1 ..].to_vec(), referenced: entity.referenced.clone(), list: None };
+                Some(Value::Compound(self.extend(&later, name, id, variables, resolving)?))
+            },
+
+            Object::Compound(ref compound) if compound.is_variable() => Some(self.inline(id, variables)),
+
+            // a compound without a prototype is merged with the compounds of the same name in the later layers
+            Object::Compound(ref compound) if compound.prototypes.is_empty() && !compound.replaces => {
+                let mut merged = self.entity(id);
+
+                for &layer in &layers[index + 1 ..] {
+                    let inherited_id = match self.overrides(layer).get(name) {
+                        None => continue,
+                        Some(&inherited_id) => inherited_id,
+                    };
+
+                    match self.objects[inherited_id.0] {
+                        Object::Compound(ref inherited) if inherited.extension.is_none() && !inherited.computed => {
+                            let inherited_entity = self.entity(inherited_id);
+                            merged.layers.extend(inherited_entity.layers);
+                            merged.referenced.extend(inherited_entity.referenced);
+
+                            // a compound with a prototype, or a replacement, replaces everything it inherits
+                            if !inherited.prototypes.is_empty() || inherited.replaces {
+                                break;
+                            }
+                        },
+
+                        _ => break, // literals, unset properties, extensions and computed compounds are not merged
+                    }
+                }
+
+                Some(Value::Compound(merged))
+            },
+
+            Object::Compound(_) => Some(Value::Compound(self.entity(id))),
+        })
+    }
+
+    /// the list of the property in the `later` layers, with the entries of the extension added, see `::list`
+    fn extend(
+        &self, later: &Entity, name: &str, id: ObjectId,
+        variables: &mut Vec<ObjectId>, resolving: &mut Vec<ObjectId>
+    ) -> ResolveResult<Entity> {
+        let placement = self.compound(id).and_then(|compound| compound.extension.as_ref())
+            .expect("only extensions are extended");
+
+        // the layers have already been expanded
+        let inherited = match self.lookup(later, &later.layers, name, variables, resolving)? {
+            Some(Value::Compound(inherited)) => inherited,
+
+            // string literals are replaced, like by any other compound
+            _ => Entity { layers: Vec::new(), referenced: Vec::new(), list: None },
+        };
+
+        let added = self.entity(id);
+        let inherited_entries = self.list_entries(&inherited, resolving)?;
+        let added_entries = self.list_entries(&added, resolving)?;
+
+        let order = ::list::extend(
+            &inherited_entries.iter().map(String::as_str).collect::<Vec<_>>(),
+            &added_entries.iter().map(String::as_str).collect::<Vec<_>>(),
+            &placement.map(|(anchor, _)| anchor.as_str())
+        );
+
+        let order = order.ok_or_else(|| {
+            let (identifier, span) = placement.anchor().cloned().expect("only anchors can be missing");
+            ResolveError::ListEntryNotFound { identifier, span }
+        })?;
+
+        let list = order.into_iter()
+            .map(|(name, is_added)| (if is_added { added.clone() } else { inherited.clone() }, name.to_string()))
+            .collect();
+
+        let mut referenced = added.referenced.clone();
+        referenced.extend(inherited.referenced.iter().filter(|id| !added.referenced.contains(id)));
+
+        Ok(Entity {
+            layers: added.layers.iter().chain(&inherited.layers).cloned().collect(),
+            referenced, list: Some(list),
+        })
+    }
+
+    /// the names of the list entries which have not been unset, sorted by their index
+    fn list_entries(&self, entity: &Entity, resolving: &mut Vec<ObjectId>) -> ResolveResult<Vec<String>> {
+        let names = self.property_names(entity);
+        let mut entries = Vec::new();
+
+        for name in ::list::entries(names.iter().map(|name| name.as_ref())) {
+            if self.property(entity, name, &mut Vec::new(), resolving)?.is_some() {
+                entries.push(name.to_string());
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// the value of a variable is a copy of its prototype
+    fn inline(&self, mut id: ObjectId, variables: &mut Vec<ObjectId>) -> Value<'_> {
+        loop {
+            match self.objects[id.0] {
+                Object::StringLiteral(ref literal) => return Value::StringLiteral(literal),
+                Object::Compound(ref compound) if compound.is_variable() => {
+                    variables.push(id);
+                    id = compound.prototypes[0].target;
+                },
+
+                Object::Compound(_) => return Value::Compound(self.entity(id)),
+                Object::Unset => unreachable!("references to unset objects are rejected when building"),
+            }
+        }
+    }
+
+    fn flatten_entity(&self, entity: &Entity, resolving: &mut Vec<ObjectId>) -> ResolveResult<FlatCompound> {
+        let outer_length = resolving.len();
+
+        for &referenced in &entity.referenced {
+            self.enter(referenced, &entity.layers, resolving)?;
+        }
+
+        let mut properties = FlatCompound::new();
+
+        for name in self.property_names(entity) {
+            let mut variables = Vec::new();
+            let value = match self.property(entity, &name, &mut variables, resolving)? {
+                Some(value) => value,
+                None => continue,
+            };
+
+            properties.insert(name.to_string(), self.flatten_value(value, variables, resolving)?);
+        }
+
+        resolving.truncate(outer_length);
+        Ok(properties)
+    }
+
+    /// `variables` are the variables that were followed to get the value
+    fn flatten_value(&self, value: Value, variables: Vec<ObjectId>, resolving: &mut Vec<ObjectId>) -> ResolveResult<FlatObject> {
+        let outer_length = resolving.len();
+
+        for variable in variables {
+            let target = self.compound(variable).expect("variables are compounds").prototypes[0].target;
+            self.enter(target, &[ variable ], resolving)?;
+        }
+
+        let flattened = match value {
+            Value::StringLiteral(literal) => FlatObject::StringLiteral(literal.to_string()),
+            Value::Compound(child) => FlatObject::compound(self.flatten_entity(&child, resolving)?),
+        };
+
+        resolving.truncate(outer_length);
+        Ok(flattened)
+    }
+
+    /// the layers of the entity, where the overrides of the conditionals whose conditions hold
+    /// are inserted before the layer that declares them, and later conditionals take precedence
+    fn expand(&self, entity: &Entity, resolving: &mut Vec<ObjectId>) -> ResolveResult<Vec<ObjectId>> {
+        let mut layers = Vec::with_capacity(entity.layers.len());
+
+        for &layer in &entity.layers {
+            self.expand_layer(layer, entity, &mut layers, resolving)?;
+        }
+
+        Ok(layers)
+    }
+
+    fn expand_layer(&self, layer: ObjectId, parameters: &Entity, layers: &mut Vec<ObjectId>, resolving: &mut Vec<ObjectId>) -> ResolveResult<()> {
+        let conditionals = &self.compound(layer).expect("layers are always compounds").conditionals;
+
+        for conditional in conditionals.iter().rev() {
+            let condition = &conditional.condition;
+            let value = self.evaluate(condition, parameters, resolving)?;
+            let holds = ::evaluate::truth(&value)
+                .map_err(|message| ResolveError::TypeError { message, span: Some(condition.span) })?;
+
+            if holds {
+                self.expand_layer(conditional.overrides, parameters, layers, resolving)?;
+            }
+        }
+
+        layers.push(layer);
+        Ok(())
+    }
+
+    /// the layer and the overrides of all of its conditionals, whether they hold or not
+    fn with_conditionals(&self, layer: ObjectId) -> Vec<ObjectId> {
+        let mut layers = vec![ layer ];
+
+        for conditional in &self.compound(layer).expect("layers are always compounds").conditionals {
+            layers.extend(self.with_conditionals(conditional.overrides));
+        }
+
+        layers
+    }
+
+    /// evaluates a condition, where `$name` refers to the properties of the entity, ignoring its conditionals
+    fn evaluate(&self, expression: &Expression, parameters: &Entity, resolving: &mut Vec<ObjectId>) -> ResolveResult<FlatObject> {
+        let located = |message| ResolveError::TypeError { message, span: Some(expression.span) };
+
+        match expression.operation {
+            Operation::Literal(ref literal) => Ok(FlatObject::StringLiteral(literal.clone())),
+
+            Operation::Reference(ref reference) => {
+                if resolving.contains(&reference.target) {
+                    return Err(ResolveError::CyclicReference { identifier: reference.reference.clone() });
+                }
+
+                let mut variables = Vec::new();
+                let value = self.inline(reference.target, &mut variables);
+
+                resolving.push(reference.target);
+                let flattened = self.flatten_value(value, variables, resolving);
+                resolving.pop();
+                flattened
+            },
+
+            Operation::Parameter(ref path) => {
+                let (first, rest) = path.split_first().expect("parameters are never empty");
+                let mut variables = Vec::new();
+
+                let value = match self.lookup(parameters, &parameters.layers, first, &mut variables, resolving)? {
+                    Some(value) => Some(self.flatten_value(value, variables, resolving)?),
+                    None => None,
+                };
+
+                value.and_then(|value| value.get_path(rest).cloned())
+                    .ok_or_else(|| ResolveError::ReferenceNotFound { identifier: format!("${}", path.join(".")) })
+            },
+
+            Operation::Unary(operator, ref operand) => {
+                let operand = self.evaluate(operand, parameters, resolving)?;
+                ::evaluate::unary(operator, &operand).map_err(located)
+            },
+
+            Operation::Binary(operator @ BinaryOperator::And, ref left, ref right) |
+            Operation::Binary(operator @ BinaryOperator::Or, ref left, ref right) => {
+                let left_value = self.evaluate_truth(left, parameters, resolving)?;
+
+                // the right operand decides, unless the left one already did
+                if left_value == (operator == BinaryOperator::Or) {
+                    Ok(::evaluate::boolean(left_value))
+                } else {
+                    Ok(::evaluate::boolean(self.evaluate_truth(right, parameters, resolving)?))
+                }
+            },
+
+            Operation::Binary(operator, ref left, ref right) => {
+                let left = self.evaluate(left, parameters, resolving)?;
+                let right = self.evaluate(right, parameters, resolving)?;
+                ::evaluate::binary(operator, &left, &right).map_err(located)
+            },
+
+            Operation::Conditional(ref condition, ref then, ref otherwise) => {
+                if self.evaluate_truth(condition, parameters, resolving)? {
+                    self.evaluate(then, parameters, resolving)
+                } else {
+                    self.evaluate(otherwise, parameters, resolving)
+                }
+            },
+
+            Operation::Call(ref function, ref arguments) => {
+                let arguments = arguments.iter()
+                    .map(|argument| self.evaluate(argument, parameters, resolving))
+                    .collect::<ResolveResult<Vec<FlatObject>>>()?;
+
+                ::evaluate::call(function, &arguments).map_err(located)
+            },
+        }
+    }
+
+    fn evaluate_truth(&self, expression: &Expression, parameters: &Entity, resolving: &mut Vec<ObjectId>) -> ResolveResult<bool> {
+        let value = self.evaluate(expression, parameters, resolving)?;
+        ::evaluate::truth(&value).map_err(|message| ResolveError::TypeError { message, span: Some(expression.span) })
+    }
+
+    /// fails if the object is already being resolved, which would recurse infinitely.
+    /// `referencing` contains the compound whose prototype refers to the object
+    fn enter(&self, id: ObjectId, referencing: &[ObjectId], resolving: &mut Vec<ObjectId>) -> ResolveResult<()> {
+        if resolving.contains(&id) {
+            let reference = referencing.iter()
+                .filter_map(|&layer| self.compound(layer))
+                .flat_map(|compound| &compound.prototypes)
+                .find(|prototype| prototype.target == id)
+                .map_or_else(String::new, |prototype| prototype.reference.clone());
+
+            return Err(ResolveError::CyclicReference { identifier: reference });
+        }
+
+        resolving.push(id);
+        Ok(())
+    }
+
+    fn linearize_all(&mut self) -> ResolveResult<()> {
+        let mut linearizations = vec![ None; self.objects.len() ];
+
+        for index in 0 .. self.objects.len() {
+            if self.compound(ObjectId(index)).is_some() {
+                self.linearize(ObjectId(index), &mut linearizations, &mut Vec::new())?;
+            }
+        }
+
+        for (object, linearization) in self.objects.iter_mut().zip(linearizations) {
+            if let (Object::Compound(compound), Some(linearization)) = (object, linearization) {
+                compound.linearization = linearization;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// memoized, `visiting` contains the compounds whose linearization is being computed
+    fn linearize(
+        &self, id: ObjectId,
+        linearizations: &mut Vec<Option<Vec<ObjectId>>>,
+        visiting: &mut Vec<ObjectId>
+    ) -> ResolveResult<Vec<ObjectId>> {
+        if let Some(ref linearization) = linearizations[id.0] {
+            return Ok(linearization.clone());
+        }
+
+        let compound = self.compound(id).expect("only compounds are linearized");
+        visiting.push(id);
+
+        let mut sequences = Vec::with_capacity(compound.prototypes.len() + 1);
+        for prototype in &compound.prototypes {
+            if visiting.contains(&prototype.target) {
+                return Err(ResolveError::CyclicReference { identifier: prototype.reference.clone() });
+            }
+
+            // string literals have no properties to inherit
+            if self.compound(prototype.target).is_some() {
+                sequences.push(self.linearize(prototype.target, linearizations, visiting)?);
+            }
+        }
+
+        visiting.pop();
+
+        let direct_prototypes = sequences.iter().map(|sequence| sequence[0]).collect();
+        sequences.push(direct_prototypes);
+
+        let merged = ::linearize::c3_merge(sequences).ok_or_else(|| ResolveError::InconsistentPrototypeOrder {
+            prototypes: compound.prototypes.iter()
+                .map(|prototype| prototype.reference.as_str())
+                .collect::<Vec<_>>().join(" + ")
+        })?;
+
+        let linearization: Vec<ObjectId> = ::std::iter::once(id).chain(merged).collect();
+        linearizations[id.0] = Some(linearization.clone());
+        Ok(linearization)
+    }
+}
+
+impl Compound {
+    /// a compound without overrides is a copy of its prototype
+    pub fn is_variable(&self) -> bool {
+        self.overrides.is_empty() && self.conditionals.is_empty() && self.prototypes.len() == 1
+    }
+
+    /// without prototypes, conditionals, or a special way of overriding
+    fn plain(overrides: HashMap<String, ObjectId>) -> Compound {
+        Compound {
+            prototypes: Vec::new(), overrides,
+            replaces: false, computed: false, extension: None, conditionals: Vec::new(),
+            linearization: Vec::new(), // computed later
+        }
+    }
+}
+
+
+impl<'p> Builder<'p> {
+    fn placeholder(&mut self) -> ObjectId {
+        self.objects.push(Object::Unset);
+        ObjectId(self.objects.len() - 1)
+    }
+
+    /// allocates the named objects, and the overrides of the conditionals of the compound.
+    /// fails if an unnamed entry would be ignored, because its index is also declared by name
+    fn allocate_overrides(&mut self, compound: ObjectId, objects: &'p NamedObjects<'p>) -> ResolveResult<HashMap<String, ObjectId>> {
+        if let Some((index, identifier)) = objects.duplicate_index() {
+            return Err(ResolveError::DuplicateIndex { index, span: identifier.location });
+        }
+
+        for conditional in &objects.conditionals {
+            let block = self.placeholder();
+            let overrides = self.allocate_overrides(block, &conditional.overrides)?;
+            self.objects[block.0] = Object::Compound(Compound::plain(overrides));
+            self.conditions.push((compound, &conditional.condition, block));
+        }
+
+        objects.in_declaration_order().into_iter()
+            .map(|(name, _, index)| Ok((name.into_owned(), self.allocate(&objects.objects[index])?)))
+            .collect()
+    }
+
+    fn allocate(&mut self, parsed: &'p parse::Object<'p>) -> ResolveResult<ObjectId> {
+        let id = self.placeholder();
+        self.ids.insert(parsed as *const _, id);
+
+        self.objects[id.0] = match *parsed {
+            parse::Object::StringLiteral(literal) => Object::StringLiteral(literal.to_string()),
+
+            parse::Object::Template(_) | parse::Object::Expression(_) => {
+                self.computed.push((id, parsed));
+                Object::StringLiteral(String::new()) // computed later
+            },
+            parse::Object::Unset => Object::Unset,
+
+            parse::Object::Compound(ref compound) | parse::Object::Replacement(ref compound) | parse::Object::Extension(_, ref compound) => {
+                self.compounds.push((id, compound));
+
+                let extension = match *parsed {
+                    parse::Object::Extension(ref placement, _) => Some(placement.map(|anchor| (anchor.name.to_string(), anchor.location))),
+                    _ => None,
+                };
+
+                Object::Compound(Compound {
+                    prototypes: Vec::new(), // resolved later
+                    overrides: self.allocate_overrides(id, &compound.overrides)?,
+                    replaces: matches!(*parsed, parse::Object::Replacement(_)),
+                    computed: false,
+                    extension,
+                    conditionals: Vec::new(), // resolved later
+                    linearization: Vec::new(), // computed later
+                })
+            },
+        };
+
+        Ok(id)
+    }
+
+    fn resolve_prototypes(&mut self, parsed: &'p NamedObjects<'p>) -> ResolveResult<()> {
+        for &(id, compound) in &self.compounds {
+            let mut prototypes = Vec::with_capacity(compound.prototypes.len());
+
+            for reference in &compound.prototypes {
+                prototypes.push(self.prototype(parsed, reference)?);
+            }
+
+            if let Object::Compound(ref mut allocated) = self.objects[id.0] {
+                allocated.prototypes = prototypes;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn prototype(&self, parsed: &'p NamedObjects<'p>, reference: &'p parse::Reference<'p>) -> ResolveResult<Prototype> {
+        let target = parsed.resolve_reference(reference)?;
+        Ok(Prototype { reference: reference.to_string(), target: self.ids[&(target as *const _)] })
+    }
+
+    fn resolve_conditions(&mut self, parsed: &'p NamedObjects<'p>) -> ResolveResult<()> {
+        for &(id, condition, overrides) in &self.conditions {
+            let condition = self.expression(parsed, condition)?;
+
+            if let Object::Compound(ref mut compound) = self.objects[id.0] {
+                compound.conditionals.push(Conditional { condition, overrides });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn expression(&self, parsed: &'p NamedObjects<'p>, expression: &'p parse::Expression<'p>) -> ResolveResult<Expression> {
+        let span = expression.span;
+        let boxed = |expression| self.expression(parsed, expression).map(Box::new);
+
+        let operation = match expression.operation {
+            parse::Operation::Literal(literal) => Operation::Literal(literal.to_string()),
+            parse::Operation::Reference(ref reference) => Operation::Reference(self.prototype(parsed, reference)?),
+
+            parse::Operation::Parameter(ref reference) => Operation::Parameter(
+                reference.identifiers.iter().map(|identifier| identifier.name.to_string()).collect()
+            ),
+
+            // `'a${b}'` is the same as `'a' ~ b`
+            parse::Operation::Template(ref parts) => {
+                let mut parts = parts.iter().map(|part| Ok(Expression { span, operation: match *part {
+                    parse::TemplatePart::Text(text) => Operation::Literal(text.to_string()),
+                    parse::TemplatePart::Reference(ref reference) => Operation::Reference(self.prototype(parsed, reference)?),
+                } }));
+
+                let first = parts.next().expect("templates are never empty")?;
+                return parts.try_fold(first, |left, right| Ok(Expression {
+                    span, operation: Operation::Binary(BinaryOperator::Concatenate, Box::new(left), Box::new(right?)),
+                }));
+            },
+
+            parse::Operation::Unary(operator, ref operand) => Operation::Unary(operator, boxed(operand)?),
+            parse::Operation::Binary(operator, ref left, ref right) => Operation::Binary(operator, boxed(left)?, boxed(right)?),
+
+            parse::Operation::Conditional(ref condition, ref then, ref otherwise) => {
+                Operation::Conditional(boxed(condition)?, boxed(then)?, boxed(otherwise)?)
+            },
+
+            parse::Operation::Call(function, ref arguments) => Operation::Call(
+                function.to_string(),
+                arguments.iter().map(|argument| self.expression(parsed, argument)).collect::<ResolveResult<_>>()?
+            ),
+        };
+
+        Ok(Expression { operation, span })
+    }
+
+    /// string literals with interpolations and expressions are resolved like flattening would,
+    /// because they only depend on the declared objects and not on inheritance
+    fn compute(&mut self, parsed: &'p NamedObjects<'p>) -> ResolveResult<()> {
+        for &(id, object) in &self.computed {
+            let value = FlatObject::resolve_object(parsed, object)?;
+            self.objects[id.0] = Builder::allocate_flat(&mut self.objects, value);
+        }
+
+        Ok(())
+    }
+
+    /// computed compounds have no prototypes and are never merged, like their expressions
+    fn allocate_flat(objects: &mut Vec<Object>, value: FlatObject) -> Object {
+        match value {
+            FlatObject::StringLiteral(text) => Object::StringLiteral(text),
+            FlatObject::Compound(properties) => {
+                let overrides = Arc::unwrap_or_clone(properties).into_iter().map(|(name, value)| {
+                    let object = Builder::allocate_flat(objects, value);
+                    objects.push(object);
+                    (name, ObjectId(objects.len() - 1))
+                }).collect();
+
+                Object::Compound(Compound { replaces: true, computed: true, .. Compound::plain(overrides) })
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_flattens_like_flat_object(source: &str) {
+        let world = World::parse(source).expect("Parsing Error").expect("Resolve Error");
+        assert_eq!(
+            world.flatten(&world.root()),
+            FlatObject::parse(source).expect("Parsing Error")
+        );
+    }
+
+    #[test]
+    fn test_flatten(){
+        assert_flattens_like_flat_object(r#"
+            ok_text: 'Ok'
+            Button: { visible: 'true' text: 'Click Here' size: { w: '1' h: '2' } }
+            ok: Button { text: ok_text size: { w: '3' } }
+            plain: Button { size: !replace { } visible: !unset }
+            copy: ok
+        "#);
+
+        assert_flattens_like_flat_object(r#"
+            Comic: { author: { name: 'unknown' age: 'unknown' } }
+            Special: Comic { author: !replace { name: 'Stan' } }
+            s: Special { author: { age: '2' } }
+        "#);
+
+        assert_flattens_like_flat_object("ok: 'fine' Comic: { x: { a: 'b' } } c: Comic { x: !replace ok }");
+
+        assert_flattens_like_flat_object(r#"
+            Element: { id: 'element' tag: 'div' style: { color: 'black' } }
+            Clickable: Element { on_click: 'click' tag: 'button' }
+            Styled: Element { style: { font: 'serif' } class: 'styled' tag: 'span' }
+            Button: Clickable + Styled { id: 'button' }
+        "#);
+    }
+
+    #[test]
+    fn test_interpolation(){
+        assert_flattens_like_flat_object(r#"
+            persons: { peter: { real_name: 'Peter Parker' } }
+            name: persons.peter.real_name
+            greeting: 'Hello ${name}!'
+            nested: { text: '${greeting} (${ persons.peter.real_name }) costs \${price}' }
+        "#);
+
+        let source = "persons: { peter: { } }\ngreeting: 'Hello ${persons.peter}!'";
+        let error = World::parse(source).unwrap().err().unwrap();
+        let reference_start = source.find("persons.peter}").unwrap();
+        assert_eq!(error.span(), Some(::parse::Span { start: reference_start, end: reference_start + 13 }));
+        assert!(World::parse("a: '${b}' b: '${a}'").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_expressions(){
+        assert_flattens_like_flat_object(r#"
+            size: { width: '40' height: '30' }
+            area: (size.width * size.height)
+            name: 'box'
+            Box: { dimensions: (size.width > '10' ? size : name) }
+            box: Box { dimensions: { depth: '1' } }
+        "#);
+    }
+
+    #[test]
+    fn test_list_extension(){
+        assert_flattens_like_flat_object(r#"
+            Page: { nav: { : 'home' : 'blog' title: 'Menu' } }
+            About: Page { nav += { : 'about' } }
+            Contact: About { nav += { : 'contact' title: 'Contact' } }
+            Landing: Page { nav: !prepend { : 'welcome' } }
+            Archive: Page { nav: !insert_after 0 { : 'archive' : 'tags' } }
+            Sparse: { nav: { 3: 'c' 10: 'd' 4: !unset } }
+            Inserted: Sparse { nav: !insert_before 10 { : 'x' } }
+        "#);
+
+        let world = World::parse("Page: { nav: { : 'home' } } Landing: Page { nav: !prepend { : 'welcome' } }").unwrap().unwrap();
+        let root = world.root();
+        assert_eq!(world.get_path(&root, &["Landing", "nav", "1"]), Ok(Some(Value::StringLiteral("home"))));
+        assert_eq!(world.get_path(&root, &["Landing", "nav", "2"]), Ok(None));
+
+        let missing = World::parse("Page: { nav: { } } About: Page { nav: !insert_after 4 { } }").unwrap().unwrap();
+        assert!(missing.flatten(&missing.root()).is_err());
+
+        let duplicate = World::parse("Page: { nav: { 1: 'b' : 'a' : 'c' } }").unwrap();
+        assert_eq!(duplicate.err(), Some(ResolveError::DuplicateIndex { index: 1, span: Some(Span { start: 28, end: 28 }) }));
+    }
+
+    #[test]
+    fn test_conditional(){
+        assert_flattens_like_flat_object(r#"
+            dark_theme: 'dark'
+            Button: {
+                theme: 'light'
+                background: 'white'
+                if $theme == dark_theme { background: 'black' if $size.large { border: 'none' } }
+                if $theme == 'dark' && $size.large { background: 'gray' }
+                size: { large: 'false' }
+                label if $size.large: 'Big ${dark_theme}'
+            }
+
+            dark: Button { theme: 'dark' }
+            large: dark { size: { large: 'true' } }
+            fixed: large { background: 'red' theme: ('d' ~ 'ark') }
+        "#);
+
+        let world = World::parse("Button: { theme: 'light' if $theme == 'dark' { background: 'black' } } dark: Button { theme: 'dark' }").unwrap().unwrap();
+        let root = world.root();
+        assert_eq!(world.get_path(&root, &["dark", "background"]), Ok(Some(Value::StringLiteral("black"))));
+        assert_eq!(world.get_path(&root, &["Button", "background"]), Ok(None));
+
+        // references point at the declared objects, so conditions cannot observe their own result
+        let source = "Button: { if Button.x == 'a' { x: 'b' } x: 'a' }";
+        let world = World::parse(source).unwrap().unwrap();
+        assert_eq!(world.get_path(&world.root(), &["Button", "x"]), Ok(Some(Value::StringLiteral("b"))));
+        assert_eq!(world.flatten(&world.root()), FlatObject::parse(source).unwrap());
+    }
+
+    #[test]
+    fn test_lookup(){
+        let world = World::parse(r#"
+            Element: { id: 'element' tag: 'div' }
+            Clickable: Element { tag: 'button' }
+            Styled: Element { tag: 'span' class: 'styled' }
+            Button: Clickable + Styled { }
+            tree: { child: tree }
+        "#).unwrap().unwrap();
+
+        let root = world.root();
+        assert_eq!(world.get_path(&root, &["Button", "tag"]), Ok(Some(Value::StringLiteral("button"))));
+        assert_eq!(world.get_path(&root, &["Button", "class"]), Ok(Some(Value::StringLiteral("styled"))));
+        assert_eq!(world.get_path(&root, &["Button", "missing"]), Ok(None));
+
+        // recursive structures can be looked up, but not flattened
+        assert!(world.get_path(&root, &["tree", "child", "child", "child"]).unwrap().is_some());
+        assert_eq!(world.flatten(&root), Err(ResolveError::CyclicReference { identifier: String::from("tree") }));
+    }
+
+    #[test]
+    fn test_build_errors(){
+        assert!(World::parse("a: b {} b: a {}").unwrap().is_err());
+
+        assert_eq!(
+            World::parse("X: {} Y: {} A: X + Y {} B: Y + X {} C: A + B {}").unwrap().err(),
+            Some(ResolveError::InconsistentPrototypeOrder { prototypes: String::from("A + B") })
+        );
+
+        assert_eq!(
+            World::parse("a: missing").unwrap().err(),
+            Some(ResolveError::ReferenceNotFound { identifier: String::from("missing") })
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! Where references are looked up. Besides the objects of the document,
+//! the system processing the document can supply objects while resolving, see `Host`.
+//!
+//! External variables are string literals reachable in the reserved namespace `env`, like `env.DEPLOY_TARGET`.
+//! Prelude documents, and then native objects, are consulted after the document,
+//! so a document can use and shadow default objects like `div`. References inside a prelude
+//! only see that prelude and the native objects, so a document cannot change what a prelude object means.
+//!
+//! Native prototypes and functions are implemented in Rust. A compound that inherits from a native prototype,
+//! like `Rgb { r: '255' g: '0' b: '0' }`, is resolved as usual, and then replaced by what the prototype returns
+//! for the resolved properties. Native functions can be called in expressions, like `(uuid())`,
+//! unless a builtin function has the same name.
+
+use ::std::collections::{ HashMap, HashSet };
+use ::parse::{ NamedObjects, Object, Compound, Identifier, Reference, Expression, Operation, Span, ResolveError, ResolveResult };
+use ::list::Placement;
+use ::flat::{ FlatObject, FlatCompound };
+
+
+/// the name of the namespace that contains the external variables
+pub const VARIABLES: &str = "env";
+
+/// called with the evaluated arguments. errors are messages, which are located at the call.
+/// natives can be called from several threads at once, see `FlatObject::build_parallel_in`
+pub type NativeFunction<'h> = Box<dyn Fn(&[FlatObject]) -> Result<FlatObject, String> + Send + Sync + 'h>;
+
+/// called with the resolved properties of a compound that inherits from the prototype,
+/// returns the value of the compound. errors are messages
+pub type NativePrototype<'h> = Box<dyn Fn(&FlatCompound) -> Result<FlatObject, String> + Send + Sync + 'h>;
+
+/// the objects that references can refer to
+#[derive(Clone, Copy)]
+pub struct Scope<'o> {
+    pub document: &'o NamedObjects<'o>,
+
+    /// none if only the document is available
+    host: Option<&'o Host<'o>>,
+}
+
+/// everything the system processing a document supplies, see `FlatObject::build_in`
+#[derive(Default)]
+pub struct Host<'h> {
+    /// a document that only declares `env`, none if the namespace is not reserved
+    variables: Option<NamedObjects<'h>>,
+
+    /// consulted in order, if the document does not declare a name
+    prelude: Vec<&'h NamedObjects<'h>>,
+
+    /// the index of the prelude that contains a reference, by the address of the reference
+    prelude_references: HashMap<usize, usize>,
+
+    /// the addresses of the declared identifiers, expressions, and list anchors of the preludes, which are located in other sources
+    prelude_parts: HashSet<usize>,
+
+    /// native objects, and an empty compound for each native prototype, consulted after the prelude
+    natives: NamedObjects<'h>,
+
+    prototypes: Vec<(&'h str, NativePrototype<'h>)>,
+    functions: HashMap<String, NativeFunction<'h>>,
+}
+
+
+impl<'o> Scope<'o> {
+    /// only the objects of the document, where `env` is not reserved
+    pub fn document(document: &'o NamedObjects<'o>) -> Self {
+        Scope { document, host: None }
+    }
+
+    pub fn resolve_reference(&self, reference: &'o Reference<'o>) -> ResolveResult<&'o Object<'o>> {
+        self.resolve_definition(reference).map(|(_, object)| object)
+    }
+
+    /// also returns the identifier of the declaration, which only points into the source for objects of the document
+    pub fn resolve_definition(&self, reference: &'o Reference<'o>) -> ResolveResult<(&'o Identifier<'o>, &'o Object<'o>)> {
+        let definition = self.root(reference).resolve_definition(reference);
+
+        match self.variables() {
+            Some(_) if reference.identifiers[0].name == VARIABLES => definition.map_err(|error| match error {
+                ResolveError::ReferenceNotFound { identifier } => ResolveError::MissingVariable { name: identifier },
+                error => error,
+            }),
+
+            _ => definition,
+        }
+    }
+
+    /// the objects the first name of the reference is looked up in
+    pub fn root(&self, reference: &Reference) -> &'o NamedObjects<'o> {
+        match self.variables() {
+            Some(variables) if reference.identifiers[0].name == VARIABLES => variables,
+
+            _ => match (self.prelude_containing(reference), self.prelude_declaring(reference)) {
+                (Some(prelude), _) if prelude.get(reference.identifiers[0].name).is_some() => prelude,
+                (Some(_), _) => &self.host.expect("preludes are part of the host").natives,
+                (None, Some(prelude)) => prelude,
+                (None, None) => self.document,
+            },
+        }
+    }
+
+    /// whether the reference refers to an object of the prelude, instead of the document or a variable
+    pub fn is_prelude(&self, reference: &Reference) -> bool {
+        (self.prelude_containing(reference).is_some() || self.prelude_declaring(reference).is_some())
+            && !(self.variables().is_some() && reference.identifiers[0].name == VARIABLES)
+    }
+
+    /// the native prototype whose compound has these overrides, see `Host::prototype`
+    pub fn native_prototype(&self, overrides: &NamedObjects) -> Option<(&'o str, &'o NativePrototype<'o>)> {
+        let host = self.host?;
+
+        host.prototypes.iter()
+            .find(|&&(name, _)| match host.natives.get(name) {
+                Some(Object::Compound(compound)) => ::std::ptr::eq(&compound.overrides, overrides),
+                _ => false,
+            })
+            .map(|&(name, ref prototype)| (name, prototype))
+    }
+
+    pub fn native_function(&self, name: &str) -> Option<&'o NativeFunction<'o>> {
+        self.host?.functions.get(name)
+    }
+
+    fn variables(&self) -> Option<&'o NamedObjects<'o>> {
+        self.host?.variables.as_ref()
+    }
+
+    /// the span of a part of the parse result, none if it is written in a prelude,
+    /// because the span would refer to the source of the prelude, instead of the document
+    pub fn locate<T>(&self, part: &T, span: Option<Span>) -> Option<Span> {
+        if self.is_prelude_part(part) { None } else { span }
+    }
+
+    /// whether a part of the parse result, like the identifier of a declaration, is written in a prelude
+    pub fn is_prelude_part<T>(&self, part: &T) -> bool {
+        let address = part as *const T as usize;
+        self.host.is_some_and(|host| host.prelude_parts.contains(&address) || host.prelude_references.contains_key(&address))
+    }
+
+    /// the prelude which the reference is written in, none if it is part of the document
+    fn prelude_containing(&self, reference: &Reference) -> Option<&'o NamedObjects<'o>> {
+        let host = self.host?;
+        let index = host.prelude_references.get(&(reference as *const Reference as usize))?;
+        Some(host.prelude[*index])
+    }
+
+    /// the first prelude that declares the first name of the reference, unless the document declares it
+    fn prelude_declaring(&self, reference: &Reference) -> Option<&'o NamedObjects<'o>> {
+        let host = self.host?;
+        let name = reference.identifiers[0].name;
+        if self.document.get(name).is_some() { return None; }
+
+        host.prelude.iter().cloned().chain(::std::iter::once(&host.natives))
+            .find(|prelude| prelude.get(name).is_some())
+    }
+}
+
+impl<'h> Host<'h> {
+    /// makes the variables available as `env.name`, and reserves `env`
+    pub fn variables(&mut self, variables: &'h HashMap<String, String>) -> &mut Self {
+        let mut namespace = NamedObjects::default();
+
+        for (name, value) in variables {
+            namespace.declare(Identifier::new(name), Object::StringLiteral(value));
+        }
+
+        let mut root = NamedObjects::default();
+        root.declare(Identifier::new(VARIABLES), Object::Compound(Compound { prototypes: Vec::new(), overrides: namespace }));
+        self.variables = Some(root);
+        self
+    }
+
+    /// the objects of the document are available to all documents resolved with this host,
+    /// unless they declare the same name. earlier preludes take precedence over later ones
+    pub fn prelude(&mut self, document: &'h NamedObjects<'h>) -> &mut Self {
+        for reference in document.references() {
+            self.prelude_references.insert(reference as *const Reference as usize, self.prelude.len());
+        }
+
+        collect_parts(document, &mut self.prelude_parts);
+
+        self.prelude.push(document);
+        self
+    }
+
+    /// like a prelude object, but supplied by the host directly. preludes take precedence over these
+    pub fn object(&mut self, name: &'h str, object: &'h FlatObject) -> &mut Self {
+        self.natives.declare(Identifier::new(name), object.to_object());
+        self
+    }
+
+    /// a prototype which is looked up like a native object
+    pub fn prototype<P>(&mut self, name: &'h str, prototype: P) -> &mut Self
+        where P: Fn(&FlatCompound) -> Result<FlatObject, String> + Send + Sync + 'h
+    {
+        self.natives.declare(Identifier::new(name), Object::Compound(Compound { prototypes: Vec::new(), overrides: NamedObjects::default() }));
+        self.prototypes.push((name, Box::new(prototype)));
+        self
+    }
+
+    /// a function that expressions can call, unless a builtin function has the same name
+    pub fn function<F>(&mut self, name: &str, function: F) -> &mut Self
+        where F: Fn(&[FlatObject]) -> Result<FlatObject, String> + Send + Sync + 'h
+    {
+        self.functions.insert(name.to_string(), Box::new(function));
+        self
+    }
+
+    /// fails if the document declares the reserved namespace `env` while variables are supplied
+    pub fn scope<'o>(&'o self, document: &'o NamedObjects<'o>) -> ResolveResult<Scope<'o>> {
+        let reserved = self.variables.as_ref().and_then(|_| {
+            document.identifiers.keys().find(|identifier| identifier.name == VARIABLES)
+        });
+
+        if let Some(identifier) = reserved {
+            return Err(ResolveError::ReservedName {
+                identifier: identifier.name.to_string(), span: identifier.location,
+            });
+        }
+
+        Ok(Scope { document, host: Some(self) })
+    }
+}
+
+
+/// the addresses of the identifiers of declarations, including unnamed entries,
+/// of the expressions, including their operands, and of the anchors of list extensions
+fn collect_parts(objects: &NamedObjects, parts: &mut HashSet<usize>) {
+    let declared = objects.identifiers.keys().chain(objects.unnamed.iter().map(|(identifier, _)| identifier));
+
+    for identifier in declared {
+        parts.insert(identifier as *const Identifier as usize);
+    }
+
+    for object in &objects.objects {
+        match *object {
+            Object::Expression(ref expression) => collect_expression_parts(expression, parts),
+            Object::Compound(ref compound) | Object::Replacement(ref compound) => collect_parts(&compound.overrides, parts),
+
+            Object::Extension(ref placement, ref compound) => {
+                if let Placement::Before(ref anchor) | Placement::After(ref anchor) = *placement {
+                    parts.insert(anchor as *const Identifier as usize);
+                }
+
+                collect_parts(&compound.overrides, parts);
+            },
+
+            Object::StringLiteral(_) | Object::Template(_) | Object::Unset => {},
+        }
+    }
+
+    for conditional in &objects.conditionals {
+        collect_expression_parts(&conditional.condition, parts);
+        collect_parts(&conditional.overrides, parts);
+    }
+}
+
+fn collect_expression_parts(expression: &Expression, parts: &mut HashSet<usize>) {
+    parts.insert(expression as *const Expression as usize);
+
+    match expression.operation {
+        Operation::Literal(_) | Operation::Template(_) | Operation::Reference(_) | Operation::Parameter(_) => {},
+        Operation::Unary(_, ref operand) => collect_expression_parts(operand, parts),
+
+        Operation::Binary(_, ref left, ref right) => {
+            collect_expression_parts(left, parts);
+            collect_expression_parts(right, parts);
+        },
+
+        Operation::Conditional(ref condition, ref then, ref otherwise) => {
+            collect_expression_parts(condition, parts);
+            collect_expression_parts(then, parts);
+            collect_expression_parts(otherwise, parts);
+        },
+
+        Operation::Call(_, ref arguments) => for argument in arguments {
+            collect_expression_parts(argument, parts);
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reference(names: Vec<&'static str>) -> Reference<'static> {
+        Reference { identifiers: names.into_iter().map(Identifier::new).collect() }
+    }
+
+    #[test]
+    fn test_variables(){
+        let supplied: HashMap<String, String> = vec![ (String::from("TARGET"), String::from("prod")) ].into_iter().collect();
+        let document = ::parse::parse("env_name: 'x'").unwrap();
+        let (target, missing) = (reference(vec![ "env", "TARGET" ]), reference(vec![ "env", "USER" ]));
+
+        let mut host = Host::default();
+        host.variables(&supplied);
+
+        let scope = host.scope(&document).unwrap();
+        assert_eq!(scope.resolve_reference(&target), Ok(&Object::StringLiteral("prod")));
+        assert_eq!(scope.resolve_reference(&missing), Err(ResolveError::MissingVariable { name: String::from("USER") }));
+
+        // without variables, `env` is an ordinary name
+        assert_eq!(Scope::document(&document).resolve_reference(&target), Err(ResolveError::ReferenceNotFound { identifier: String::from("env") }));
+
+        let reserved = ::parse::parse("env: { }").unwrap();
+        assert_eq!(host.scope(&reserved).err().map(|error| error.to_string()).as_deref(), Some("`env` is reserved for external variables"));
+        assert!(Host::default().scope(&reserved).is_ok());
+    }
+
+    #[test]
+    fn test_prelude(){
+        let (first, second) = (::parse::parse("div: 'first' p: 'first'").unwrap(), ::parse::parse("div: 'second' br: 'second'").unwrap());
+        let native = FlatObject::StringLiteral(String::from("native"));
+        let document = ::parse::parse("p: 'document'").unwrap();
+
+        let mut host = Host::default();
+        host.object("br", &native).object("hr", &native).prelude(&first).prelude(&second);
+
+        let scope = host.scope(&document).unwrap();
+        let resolve = |name: &'static str| match scope.resolve_reference(&reference(vec![ name ])) {
+            Ok(&Object::StringLiteral(literal)) => Some(literal.to_string()),
+            _ => None,
+        };
+
+        assert_eq!(resolve("p"), Some(String::from("document")));
+        assert_eq!(resolve("div"), Some(String::from("first")));
+        assert_eq!(resolve("br"), Some(String::from("second")));
+        assert_eq!(resolve("hr"), Some(String::from("native")));
+        assert_eq!(resolve("h1"), None);
+
+        assert!(scope.is_prelude(&reference(vec![ "div" ])));
+        assert!(!scope.is_prelude(&reference(vec![ "p" ])));
+    }
+}
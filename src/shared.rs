@@ -0,0 +1,108 @@
+//! Shares resolved compounds which are equal, so that the instances of a prototype
+//! do not each own a copy of the compounds they inherit, like `author` in every `Comic`.
+//!
+//! Compounds are built from the innermost ones outwards, so the properties of a new compound are already shared.
+//! Two compounds are then equal if they have the same names in the same order, equal string literals,
+//! and the same shared compounds, which only compares the direct properties, and not the whole tree.
+
+use ::std::collections::HashSet;
+use ::std::hash::{ Hash, Hasher };
+use ::std::sync::Arc;
+use ::flat::{ FlatObject, FlatCompound };
+
+
+/// remembers every compound it returned
+#[derive(Default)]
+pub struct Interner {
+    compounds: HashSet<Shallow>,
+}
+
+/// compares properties by address
+struct Shallow(Arc<FlatCompound>);
+
+
+impl Interner {
+    /// an earlier compound if it is equal, or else the new one
+    pub fn compound(&mut self, properties: FlatCompound) -> FlatObject {
+        let compound = Shallow(Arc::new(properties));
+
+        if let Some(shared) = self.compounds.get(&compound) {
+            return FlatObject::Compound(shared.0.clone());
+        }
+
+        let properties = compound.0.clone();
+        self.compounds.insert(compound);
+        FlatObject::Compound(properties)
+    }
+
+    /// how many different compounds were returned
+    pub fn len(&self) -> usize {
+        self.compounds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.compounds.is_empty()
+    }
+}
+
+impl PartialEq for Shallow {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len() && self.0.iter().zip(other.0.iter()).all(|((name, value), (other_name, other_value))| {
+            name == other_name && match (value, other_value) {
+                (FlatObject::StringLiteral(text), FlatObject::StringLiteral(other)) => text == other,
+                (FlatObject::Compound(properties), FlatObject::Compound(other)) => Arc::ptr_eq(properties, other),
+                _ => false,
+            }
+        })
+    }
+}
+
+impl Eq for Shallow {}
+
+impl Hash for Shallow {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        for (name, value) in self.0.iter() {
+            name.hash(hasher);
+
+            match *value {
+                FlatObject::StringLiteral(ref text) => text.hash(hasher),
+                FlatObject::Compound(ref properties) => Arc::as_ptr(properties).hash(hasher),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shared(){
+        let source = "
+            Comic: { title: 'Untitled' author: { name: 'unknown' } }
+            comics: { spiderman: Comic { title: 'Spiderman' } hulk: Comic { title: 'Hulk' } copy: Comic { title: 'Hulk' } }
+        ";
+
+        let resolved = FlatObject::parse(source).unwrap().unwrap();
+        let compound = |path: &[&str]| match resolved.get_path(path) {
+            Some(FlatObject::Compound(properties)) => properties.clone(),
+            _ => panic!("not a compound"),
+        };
+
+        assert!(Arc::ptr_eq(&compound(&["comics", "spiderman", "author"]), &compound(&["comics", "hulk", "author"])));
+        assert!(Arc::ptr_eq(&compound(&["comics", "hulk"]), &compound(&["comics", "copy"])));
+        assert!(!Arc::ptr_eq(&compound(&["comics", "hulk"]), &compound(&["comics", "spiderman"])));
+
+        let mut interner = Interner::default();
+        let properties = vec![ (String::from("b"), FlatObject::StringLiteral(String::from("x"))), (String::from("a"), FlatObject::StringLiteral(String::from("y"))) ];
+        let reordered: FlatCompound = properties.iter().cloned().collect();
+        let ordered: FlatCompound = properties.into_iter().rev().collect();
+
+        // the order of the properties is kept, even if the compounds are equal
+        assert_eq!(interner.compound(reordered.clone()), interner.compound(ordered));
+        assert_eq!(interner.len(), 2);
+        interner.compound(reordered);
+        assert_eq!(interner.len(), 2);
+    }
+}
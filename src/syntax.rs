@@ -0,0 +1,618 @@
+//! A lossless syntax tree, where every byte of the source belongs to exactly one token,
+//! including the whitespace between them, so that writing all tokens reproduces the source.
+//! Tools that rewrite the source, like formatters and refactorings, need it, because `parse::Object` only keeps
+//! the names and literals. The language has no comments, so whitespace is the only trivia.
+//!
+//! The tree has its own parser, which follows the grammar of `parse`, but also builds a tree for broken sources:
+//! text that cannot be parsed becomes an error token, and missing symbols become empty error tokens.
+//! String literals, templates, and expressions are single tokens.
+
+use ::std::fmt;
+use ::parse::{ Source, Span, ParseError };
+
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Kind {
+    /// the whole source
+    Document,
+
+    /// `name: object`, `name += compound`, or `name if condition: object`
+    Declaration,
+
+    /// `if condition { declarations }`
+    Conditional,
+
+    /// a compound with optional prototypes and braces, including `!replace` and list extensions
+    Compound,
+
+    /// a prototype, like `Comic` or `persons.peter`
+    Reference,
+
+    // the objects and the conditions, which are tokens
+    StringLiteral,
+    Expression,
+    Unset,
+
+    // the other tokens
+    Name,
+    Keyword,
+    Colon,
+    Dot,
+    Plus,
+    Append,
+    OpenBrace,
+    CloseBrace,
+    Whitespace,
+
+    /// text that cannot be parsed, or an empty token where a symbol is missing
+    Error,
+}
+
+/// a token if it has no children
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Node {
+    pub kind: Kind,
+    pub span: Span,
+    pub children: Vec<Node>,
+}
+
+/// borrows the source it was parsed from
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct SyntaxTree<'s> {
+    source: Source<'s>,
+    root: Node,
+}
+
+
+impl<'s> SyntaxTree<'s> {
+    /// also succeeds for broken sources, whose problems are error tokens
+    pub fn parse(source: Source<'s>) -> Self {
+        let mut parser = Parser { source, offset: 0 };
+        let mut members = Vec::new();
+        parser.members(&mut members, false);
+
+        let root = Node { kind: Kind::Document, span: Span { start: 0, end: source.len() }, children: members };
+        SyntaxTree { source, root }
+    }
+
+    pub fn source(&self) -> Source<'s> {
+        self.source
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    /// the tree without the source, whose spans still point into it
+    pub fn into_root(self) -> Node {
+        self.root
+    }
+
+    pub fn text(&self, node: &Node) -> &'s str {
+        &self.source[node.span.start .. node.span.end]
+    }
+
+    /// all tokens in source order, which together are the whole source
+    pub fn tokens(&self) -> Vec<&Node> {
+        let mut tokens = Vec::new();
+        self.root.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    /// the error tokens in source order, none if `parse::parse` accepts the source
+    pub fn errors(&self) -> Vec<&Node> {
+        self.tokens().into_iter().filter(|token| token.kind == Kind::Error).collect()
+    }
+
+    /// the nodes that contain the offset, from the document to the innermost node
+    pub fn nodes_at(&self, offset: usize) -> Vec<&Node> {
+        let mut nodes = vec![ &self.root ];
+
+        while let Some(child) = nodes[nodes.len() - 1].children.iter()
+            .find(|child| child.span.start <= offset && offset < child.span.end)
+        {
+            nodes.push(child);
+        }
+
+        nodes
+    }
+}
+
+impl Node {
+    pub fn is_token(&self) -> bool {
+        self.children.is_empty() && self.kind != Kind::Document
+    }
+
+    fn collect_tokens<'n>(&'n self, tokens: &mut Vec<&'n Node>) {
+        if self.is_token() { tokens.push(self); }
+        for child in &self.children { child.collect_tokens(tokens); }
+    }
+}
+
+impl<'s> fmt::Display for SyntaxTree<'s> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        for token in self.tokens() {
+            formatter.write_str(self.text(token))?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// consumes the source from left to right, every method adds the tokens and nodes it parsed to the children
+struct Parser<'s> {
+    source: Source<'s>,
+    offset: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn rest(&self) -> Source<'s> {
+        &self.source[self.offset ..]
+    }
+
+    /// the next symbol after whitespace
+    fn peek(&self) -> Option<char> {
+        self.rest().trim_start().chars().next()
+    }
+
+    /// the next name after whitespace, which may be empty
+    fn peek_name(&self) -> &'s str {
+        let rest = self.rest().trim_start();
+        &rest[.. name_length(rest)]
+    }
+
+    fn push(&mut self, kind: Kind, length: usize, children: &mut Vec<Node>) {
+        children.push(token(kind, Span { start: self.offset, end: self.offset + length }));
+        self.offset += length;
+    }
+
+    fn whitespace(&mut self, children: &mut Vec<Node>) {
+        let length = self.rest().len() - self.rest().trim_start().len();
+        if length != 0 { self.push(Kind::Whitespace, length, children); }
+    }
+
+    /// the empty error token where a symbol is missing
+    fn missing(&mut self, children: &mut Vec<Node>) {
+        self.push(Kind::Error, 0, children);
+    }
+
+    /// the declarations of the document, or those between braces, which stop before the closing brace
+    fn members(&mut self, children: &mut Vec<Node>, delimited: bool) {
+        loop {
+            self.whitespace(children);
+
+            let symbol = match self.rest().chars().next() {
+                Some('}') if delimited => return,
+                Some(symbol) => symbol,
+                None => {
+                    if delimited { self.missing(children); }
+                    return;
+                },
+            };
+
+            let declaration = self.declaration();
+
+            // a symbol like `}` or `{` that cannot start a declaration
+            if declaration.span.start == declaration.span.end {
+                self.push(Kind::Error, symbol.len_utf8(), children);
+            } else {
+                children.push(declaration);
+            }
+        }
+    }
+
+    /// a declaration or a conditional, starting at a symbol that is not whitespace
+    fn declaration(&mut self) -> Node {
+        let start = self.offset;
+        let mut children = Vec::new();
+
+        let name = self.peek_name();
+        let is_named = self.rest()[name.len() ..].trim_start().starts_with([':', '+']);
+
+        // `if condition { overrides }`, unless a property is named `if`
+        if name == "if" && !is_named {
+            self.push(Kind::Keyword, name.len(), &mut children);
+            self.condition(&mut children);
+            self.braces(&mut children);
+            return node(Kind::Conditional, children, start);
+        }
+
+        if !name.is_empty() {
+            self.push(Kind::Name, name.len(), &mut children);
+        }
+
+        // `name if condition: object`
+        if self.peek_name() == "if" {
+            self.whitespace(&mut children);
+            self.push(Kind::Keyword, "if".len(), &mut children);
+            self.condition(&mut children);
+        }
+
+        match self.peek() {
+            Some('+') => {
+                self.whitespace(&mut children);
+
+                if self.rest().starts_with("+=") {
+                    let mut compound = Vec::new();
+                    self.push(Kind::Append, "+=".len(), &mut compound);
+                    children.push(self.compound(compound));
+
+                } else {
+                    self.push(Kind::Plus, 1, &mut children);
+                    self.missing(&mut children);
+                }
+            },
+
+            Some(':') => {
+                self.whitespace(&mut children);
+                self.push(Kind::Colon, 1, &mut children);
+                self.object(&mut children);
+            },
+
+            _ => self.missing(&mut children),
+        }
+
+        node(Kind::Declaration, children, start)
+    }
+
+    /// the expression after `if`, or an error token up to where it cannot be parsed
+    fn condition(&mut self, children: &mut Vec<Node>) {
+        self.whitespace(children);
+
+        match ::parse::parse_expression(self.rest()) {
+            Ok((_, remaining)) => {
+                let length = self.rest()[.. self.rest().len() - remaining.len()].trim_end().len();
+                self.push(Kind::Expression, length, children);
+            },
+
+            Err(error) => {
+                let length = self.error_offset(&error) - self.offset;
+                self.push(Kind::Error, length, children);
+            },
+        }
+    }
+
+    /// the object after a colon
+    fn object(&mut self, children: &mut Vec<Node>) {
+        let rest = self.rest().trim_start();
+        let keyword = &rest[.. name_length(rest)];
+        let mut compound = Vec::new();
+
+        if rest.starts_with('\'') {
+            self.whitespace(children);
+            return self.string_literal(children);
+        }
+
+        if rest.starts_with('(') {
+            self.whitespace(children);
+            return self.expression(children);
+        }
+
+        match keyword {
+            "!unset" => {
+                self.whitespace(children);
+                return self.push(Kind::Unset, keyword.len(), children);
+            },
+
+            "!replace" | "!append" | "!prepend" => {
+                self.whitespace(children);
+                self.push(Kind::Keyword, keyword.len(), &mut compound);
+            },
+
+            "!insert_before" | "!insert_after" => {
+                self.whitespace(children);
+                self.push(Kind::Keyword, keyword.len(), &mut compound);
+
+                let anchor = self.peek_name();
+                if anchor.is_empty() {
+                    self.missing(&mut compound);
+                } else {
+                    self.whitespace(&mut compound);
+                    self.push(Kind::Name, anchor.len(), &mut compound);
+                }
+            },
+
+            // an empty compound, like the one of `name:` at the end of the source
+            _ if keyword.is_empty() && !rest.starts_with('{') => {
+                return self.push(Kind::Compound, 0, children);
+            },
+
+            _ => self.whitespace(children),
+        }
+
+        children.push(self.compound(compound));
+    }
+
+    /// a literal without escapes, which is an error token if it is not closed or its template is broken
+    fn string_literal(&mut self, children: &mut Vec<Node>) {
+        let literal = &self.rest()[1 ..];
+
+        match literal.find('\'') {
+            Some(length) => {
+                let kind = if is_valid_template(&literal[.. length]) { Kind::StringLiteral } else { Kind::Error };
+                self.push(kind, length + 2, children);
+            },
+
+            None => {
+                let length = self.rest().len();
+                self.push(Kind::Error, length, children);
+            },
+        }
+    }
+
+    /// an expression in parentheses, which is an error token up to where it cannot be parsed
+    fn expression(&mut self, children: &mut Vec<Node>) {
+        let end = match ::parse::parse_expression(&self.rest()[1 ..]) {
+            Ok((_, remaining)) => {
+                if remaining.trim_start().starts_with(')') {
+                    let length = self.rest().len() - remaining.trim_start().len() + 1;
+                    return self.push(Kind::Expression, length, children);
+                }
+
+                self.source.len() - remaining.len()
+            },
+
+            Err(error) => self.error_offset(&error),
+        };
+
+        let length = end - self.offset;
+        self.push(Kind::Error, length, children);
+    }
+
+    /// optional prototypes and optional braces, after the keywords that are already in the children
+    fn compound(&mut self, mut children: Vec<Node>) -> Node {
+        let start = self.offset;
+
+        if self.reference(&mut children) {
+            // further prototypes, e.g. `Clickable + Styled`
+            while self.peek() == Some('+') {
+                self.whitespace(&mut children);
+                self.push(Kind::Plus, 1, &mut children);
+
+                if !self.reference(&mut children) {
+                    self.missing(&mut children);
+                    break;
+                }
+            }
+        }
+
+        if self.peek() == Some('{') {
+            self.braces(&mut children);
+        }
+
+        node(Kind::Compound, children, start)
+    }
+
+    /// false if there is no reference after whitespace
+    fn reference(&mut self, children: &mut Vec<Node>) -> bool {
+        let rest = self.rest().trim_start();
+
+        match reference_length(rest) {
+            Some(length) => {
+                self.whitespace(children);
+
+                let span = Span { start: self.offset, end: self.offset + length };
+                let mut tokens = Vec::new();
+                tokenize(self.source, span, &mut tokens);
+
+                children.push(Node { kind: Kind::Reference, span, children: tokens });
+                self.offset += length;
+                true
+            },
+
+            None => false,
+        }
+    }
+
+    /// `{ declarations }`, or an empty error token where the opening brace is missing
+    fn braces(&mut self, children: &mut Vec<Node>) {
+        if self.peek() != Some('{') {
+            return self.missing(children);
+        }
+
+        self.whitespace(children);
+        self.push(Kind::OpenBrace, 1, children);
+        self.members(children, true);
+
+        if self.rest().starts_with('}') {
+            self.push(Kind::CloseBrace, 1, children);
+        }
+    }
+
+    fn error_offset(&self, error: &ParseError) -> usize {
+        match *error {
+            ParseError::UnexpectedSymbol { found, .. } => self.source.len() - found.len(),
+            ParseError::UnexpectedEndOfInput { .. } => self.source.len(),
+        }
+    }
+}
+
+
+/// the length of the name at the start of the text, like `parse::parse_identifier`
+fn name_length(text: &str) -> usize {
+    text.find(|symbol: char| symbol.is_whitespace() || ".:{}+".contains(symbol)).unwrap_or(text.len())
+}
+
+/// the length of the reference at the start of the text, like `persons . peter`, none if it has no name
+fn reference_length(text: &str) -> Option<usize> {
+    let mut end = name_length(text);
+    if end == 0 { return None; }
+
+    loop {
+        let rest = text[end ..].trim_start();
+        if !rest.starts_with('.') { return Some(end); }
+
+        let dot_end = text.len() - rest.len() + 1;
+        let name = text[dot_end ..].trim_start();
+        let name_length = name_length(name);
+
+        end = if name_length == 0 { dot_end } else { text.len() - name.len() + name_length };
+    }
+}
+
+/// whether every `${` of the literal, except the escaped `\${`, contains a reference and is closed
+fn is_valid_template(literal: &str) -> bool {
+    let mut search_start = 0;
+
+    while let Some(found) = literal[search_start ..].find("${") {
+        let dollar = search_start + found;
+        search_start = dollar + 2;
+
+        if literal[.. dollar].ends_with('\\') {
+            continue;
+        }
+
+        let reference = literal[search_start ..].trim_start();
+        let rest = match reference_length(reference) {
+            Some(length) => reference[length ..].trim_start(),
+            None => return false,
+        };
+
+        if !rest.starts_with('}') { return false; }
+        search_start = literal.len() - rest.len() + 1;
+    }
+
+    true
+}
+
+fn token(kind: Kind, span: Span) -> Node {
+    Node { kind, span, children: Vec::new() }
+}
+
+/// spans from the first to the last child, or is empty at the offset
+fn node(kind: Kind, children: Vec<Node>, offset: usize) -> Node {
+    let span = match (children.first(), children.last()) {
+        (Some(first), Some(last)) => Span { start: first.span.start, end: last.span.end },
+        _ => Span { start: offset, end: offset },
+    };
+
+    Node { kind, span, children }
+}
+
+/// splits a reference into names, dots, and whitespace
+fn tokenize(source: Source, span: Span, tokens: &mut Vec<Node>) {
+    let mut offset = span.start;
+
+    while offset < span.end {
+        let rest = &source[offset .. span.end];
+
+        let (kind, length) = match rest.chars().next().expect("the span is not empty") {
+            symbol if symbol.is_whitespace() => (Kind::Whitespace, rest.len() - rest.trim_start().len()),
+            '.' => (Kind::Dot, 1),
+            _ => (Kind::Name, name_length(rest)),
+        };
+
+        tokens.push(token(kind, Span { start: offset, end: offset + length }));
+        offset += length;
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lossless(){
+        let sources = [
+            include_str!("../assets/example.ptl"), include_str!("../assets/html.ptl"),
+            "", "  \n", "a:'x'", "list += { : 'a' }\n", "b: !replace A + B.c {d:!unset}  e: !insert_after 0 { : 'x' }",
+            "if $x == 'y' { a if (1 < 2) : ('z') if 'q' { } }\ng: 'in ${a} \\${}'\t\r\n",
+        ];
+
+        for source in &sources {
+            let tree = SyntaxTree::parse(source);
+            assert_eq!(tree.to_string(), *source);
+            assert!(tree.errors().is_empty());
+
+            let tokens = tree.tokens();
+            assert!(tokens.windows(2).all(|pair| pair[0].span.end == pair[1].span.start));
+            assert_eq!(tokens.first().map_or(0, |token| token.span.start), 0);
+        }
+    }
+
+    #[test]
+    fn test_structure(){
+        let source = "Special: Comic + Print { edition: 'x' } if $y { z: (1) }";
+        let tree = SyntaxTree::parse(source);
+
+        let kinds = |node: &Node| node.children.iter().map(|child| child.kind).collect::<Vec<Kind>>();
+        let root = tree.root();
+
+        assert_eq!(kinds(root), vec![ Kind::Declaration, Kind::Whitespace, Kind::Conditional ]);
+        assert_eq!(kinds(&root.children[0]), vec![ Kind::Name, Kind::Colon, Kind::Whitespace, Kind::Compound ]);
+
+        let compound = &root.children[0].children[3];
+        assert_eq!(kinds(compound), vec![
+            Kind::Reference, Kind::Whitespace, Kind::Plus, Kind::Whitespace, Kind::Reference, Kind::Whitespace,
+            Kind::OpenBrace, Kind::Whitespace, Kind::Declaration, Kind::Whitespace, Kind::CloseBrace,
+        ]);
+
+        assert_eq!(tree.text(&compound.children[4]), "Print");
+        assert_eq!(kinds(&root.children[2]), vec![
+            Kind::Keyword, Kind::Whitespace, Kind::Expression, Kind::Whitespace, Kind::OpenBrace,
+            Kind::Whitespace, Kind::Declaration, Kind::Whitespace, Kind::CloseBrace,
+        ]);
+
+        let conditional = SyntaxTree::parse("a if (1 < 2) : ('z')");
+        let declaration = &conditional.root().children[0];
+        assert_eq!(kinds(declaration), vec![
+            Kind::Name, Kind::Whitespace, Kind::Keyword, Kind::Whitespace, Kind::Expression,
+            Kind::Whitespace, Kind::Colon, Kind::Whitespace, Kind::Expression,
+        ]);
+
+        assert_eq!(conditional.text(&declaration.children[4]), "(1 < 2)");
+
+        let at_edition: Vec<Kind> = tree.nodes_at(source.find("'x'").unwrap()).iter().map(|node| node.kind).collect();
+        assert_eq!(at_edition, vec![ Kind::Document, Kind::Declaration, Kind::Compound, Kind::Declaration, Kind::StringLiteral ]);
+    }
+
+    #[test]
+    fn test_implicit_entries(){
+        // the last entry collides with the explicit index, but the tree still contains every declaration as written
+        let tree = SyntaxTree::parse("list: { : 'a' 1: 'b' : 'c' }");
+        let compound = &tree.root().children[0].children[3];
+
+        let declarations: Vec<&str> = compound.children.iter()
+            .filter(|child| child.kind == Kind::Declaration).map(|child| tree.text(child)).collect();
+
+        let names: Vec<&str> = tree.tokens().into_iter()
+            .filter(|token| token.kind == Kind::Name).map(|token| tree.text(token)).collect();
+
+        assert_eq!(declarations, vec![ ": 'a'", "1: 'b'", ": 'c'" ]);
+        assert_eq!(names, vec![ "list", "1" ]);
+    }
+
+    #[test]
+    fn test_errors(){
+        let sources = [
+            "}", "a", "a b: 'x'", "a: 'x", "a: (1 +", "a: (1) b: (2", "a: 'in ${}'", "a: { b: 'x'", "a: A + { }",
+            "if { a: 'x' }", "if $x a: 'y'", "a if : 'x'", "a: !insert_after { }", "a +", "a: { } } b: 'y'", "{",
+            "a:", "a: }", "a += B", "a: 'in \\${}' b: A.c + D. { }", "if: 'x' a if $y: ('z')",
+        ];
+
+        for source in &sources {
+            let tree = SyntaxTree::parse(source);
+            assert_eq!(tree.to_string(), *source);
+            assert_eq!(tree.errors().is_empty(), ::parse::parse(source).is_ok(), "{}", source);
+
+            let tokens = tree.tokens();
+            assert!(tokens.windows(2).all(|pair| pair[0].span.end == pair[1].span.start));
+        }
+
+        // the declarations after a broken one are still parsed
+        let tree = SyntaxTree::parse("a: (1 + 2\nb: 'y'\nc: { d }\ne: E");
+        let kinds = |node: &Node| node.children.iter().map(|child| child.kind).collect::<Vec<Kind>>();
+        let errors: Vec<&str> = tree.errors().into_iter().map(|error| tree.text(error)).collect();
+        assert_eq!(errors, vec![ "(1 + 2", "" ]);
+
+        let last = &tree.root().children[tree.root().children.len() - 1];
+        assert_eq!(kinds(last), vec![ Kind::Name, Kind::Colon, Kind::Whitespace, Kind::Compound ]);
+        assert_eq!(tree.text(last), "e: E");
+
+        let missing = SyntaxTree::parse("a: { b: 'x'");
+        let error = missing.errors()[0];
+        assert_eq!((error.span.start, error.span.end), (11, 11));
+    }
+}
@@ -0,0 +1,289 @@
+//! Records where the properties of a resolved document came from,
+//! which is otherwise lost when flattening the prototype chain.
+
+use ::std::collections::HashMap;
+use ::std::sync::Arc;
+use ::parse::{ Source, Span, line_and_column };
+
+
+/// where a declaration is written
+#[derive(Eq, PartialEq, Debug, Hash, Clone, Copy)]
+pub enum Location {
+    /// the identifier of the declaration in the source of the document
+    Document(Span),
+
+    /// an object of a prelude, whose source is not part of the trace
+    Prelude,
+}
+
+/// an object that took part in resolving a property, e.g. a prototype or a variable
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Origin {
+    /// the reference as written in the source, e.g. `text.cancel`
+    pub name: String,
+
+    /// the referenced declaration
+    pub location: Location,
+}
+
+/// origins, starting with the first one. chains that end with the same origins share them,
+/// so the properties of long prototype chains do not each copy the whole chain
+#[derive(Default, Clone)]
+pub struct Chain {
+    first: Option<Arc<Link>>,
+}
+
+struct Link {
+    origin: Origin,
+    rest: Chain,
+    len: usize,
+}
+
+/// the origins of a chain, see `Chain::iter`
+pub struct Origins<'c> {
+    next: Option<&'c Link>,
+}
+
+/// how a resolved property got its value
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Provenance {
+    /// the property declaration which was used
+    pub defined_at: Location,
+
+    /// the prototypes the property was inherited through, starting with the direct prototype.
+    /// empty if the property is declared in the object itself
+    pub inherited_from: Chain,
+
+    /// the variables the value was copied from, in the order they were followed
+    pub inlined_from: Vec<Origin>,
+}
+
+/// the provenance of every property of a resolved document, by path
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Trace {
+    pub provenances: HashMap<Vec<String>, Provenance>,
+
+    /// properties that were removed on purpose, using `!unset`.
+    /// the provenance describes where `!unset` was written
+    pub unset: HashMap<Vec<String>, Provenance>,
+
+    /// properties which several prototypes declare independently of each other
+    pub conflicts: Vec<Conflict>,
+}
+
+/// a property that is inherited from two prototypes, where neither inherits from the other.
+/// the linearization decides which one is used, but the document should probably be explicit
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Conflict {
+    pub path: Vec<String>,
+
+    /// the prototype whose property is used
+    pub chosen: Origin,
+
+    /// the prototype whose property is shadowed
+    pub ignored: Origin,
+}
+
+
+impl Location {
+    /// none if the declaration is written in a prelude
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            Location::Document(span) => Some(span),
+            Location::Prelude => None,
+        }
+    }
+}
+
+impl Chain {
+    pub fn len(&self) -> usize {
+        self.first.as_ref().map_or(0, |link| link.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.first.is_none()
+    }
+
+    pub fn first(&self) -> Option<&Origin> {
+        self.first.as_ref().map(|link| &link.origin)
+    }
+
+    pub fn last(&self) -> Option<&Origin> {
+        self.iter().last()
+    }
+
+    pub fn iter(&self) -> Origins<'_> {
+        Origins { next: self.first.as_deref() }
+    }
+
+    /// the origins of both chains, sharing this one
+    pub fn after(&self, prefix: &Chain) -> Chain {
+        if self.is_empty() {
+            return prefix.clone();
+        }
+
+        let prefix: Vec<&Origin> = prefix.iter().collect();
+        prefix.into_iter().rev().cloned().fold(self.clone(), Chain::prepend)
+    }
+
+    fn prepend(self, origin: Origin) -> Chain {
+        let len = self.len() + 1;
+        Chain { first: Some(Arc::new(Link { origin, rest: self, len })) }
+    }
+
+    /// the chain without its first origins, sharing them
+    pub fn skip(&self, count: usize) -> Chain {
+        let mut rest = self;
+        for _ in 0 .. count {
+            match rest.first {
+                Some(ref link) => rest = &link.rest,
+                None => break,
+            }
+        }
+
+        rest.clone()
+    }
+}
+
+impl ::std::iter::FromIterator<Origin> for Chain {
+    fn from_iter<I: IntoIterator<Item = Origin>>(origins: I) -> Self {
+        let origins: Vec<Origin> = origins.into_iter().collect();
+        origins.into_iter().rev().fold(Chain::default(), Chain::prepend)
+    }
+}
+
+impl<'c> Iterator for Origins<'c> {
+    type Item = &'c Origin;
+
+    fn next(&mut self) -> Option<&'c Origin> {
+        let link = self.next?;
+        self.next = link.rest.first.as_deref();
+        Some(&link.origin)
+    }
+}
+
+impl<'c> IntoIterator for &'c Chain {
+    type Item = &'c Origin;
+    type IntoIter = Origins<'c>;
+
+    fn into_iter(self) -> Origins<'c> {
+        self.iter()
+    }
+}
+
+impl PartialEq for Chain {
+    fn eq(&self, other: &Self) -> bool {
+        let shared = match (self.first.as_ref(), other.first.as_ref()) {
+            (Some(link), Some(other)) => Arc::ptr_eq(link, other),
+            (None, None) => true,
+            _ => false,
+        };
+
+        shared || (self.len() == other.len() && self.iter().eq(other.iter()))
+    }
+}
+
+impl Eq for Chain {}
+
+impl ::std::fmt::Debug for Chain {
+    fn fmt(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        formatter.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Provenance {
+    pub fn is_local(&self) -> bool {
+        self.inherited_from.is_empty()
+    }
+}
+
+impl Trace {
+    pub fn get<S: AsRef<str>>(&self, path: &[S]) -> Option<&Provenance> {
+        self.provenances.get(&owned_path(path))
+    }
+
+    /// true if the property does not exist because it was removed with `!unset`,
+    /// as opposed to never having been declared
+    pub fn is_unset<S: AsRef<str>>(&self, path: &[S]) -> bool {
+        self.unset.contains_key(&owned_path(path))
+    }
+
+    /// describes every step of the path, one line each, none if the path does not exist
+    pub fn explain<S: AsRef<str>>(&self, source: Source, path: &[S]) -> Option<String> {
+        let location = |location: Location| match location {
+            Location::Document(span) => {
+                let (line, column) = line_and_column(source, span.start);
+                format!("{}:{}", line, column)
+            },
+
+            Location::Prelude => String::from("prelude"),
+        };
+
+        // `at 4:5`, or `in a prelude`
+        let position = |defined_at: Location| match defined_at {
+            Location::Document(_) => format!("at {}", location(defined_at)),
+            Location::Prelude => String::from("in a prelude"),
+        };
+
+        let mut explanation = String::new();
+
+        for end in 1 ..= path.len() {
+            let name = path[end - 1].as_ref();
+
+            // an unset property has no children, so the explanation ends here
+            if let Some(unset) = self.unset.get(&owned_path(&path[.. end])) {
+                explanation += &format!("`{}` is unset {}", name, position(unset.defined_at));
+                if !unset.is_local() {
+                    explanation += &format!(", inherited from {}", describe_chain(&unset.inherited_from, &location));
+                }
+
+                explanation.push('\n');
+                return Some(explanation);
+            }
+
+            let provenance = self.get(&path[.. end])?;
+
+            if provenance.is_local() {
+                explanation += &format!("`{}` is defined {}", name, position(provenance.defined_at));
+
+            } else {
+                explanation += &format!(
+                    "`{}` is inherited from {}, defined {}",
+                    name, describe_chain(&provenance.inherited_from, &location),
+                    position(provenance.defined_at)
+                );
+            }
+
+            let path_so_far = owned_path(&path[.. end]);
+            for conflict in self.conflicts.iter().filter(|conflict| conflict.path == path_so_far) {
+                explanation += &format!(
+                    ", shadowing `{}` ({})",
+                    conflict.ignored.name, location(conflict.ignored.location)
+                );
+            }
+
+            for variable in &provenance.inlined_from {
+                explanation += &format!(
+                    ", copied from variable `{}` ({})",
+                    variable.name, location(variable.location)
+                );
+            }
+
+            explanation.push('\n');
+        }
+
+        Some(explanation)
+    }
+}
+
+fn owned_path<S: AsRef<str>>(path: &[S]) -> Vec<String> {
+    path.iter().map(|name| name.as_ref().to_string()).collect()
+}
+
+fn describe_chain<F: Fn(Location) -> String>(chain: &Chain, location: &F) -> String {
+    let origins: Vec<String> = chain.iter()
+        .map(|origin| format!("`{}` ({})", origin.name, location(origin.location)))
+        .collect();
+
+    origins.join(" through ")
+}
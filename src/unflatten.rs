@@ -0,0 +1,531 @@
+//! Infers prototypes from a resolved document, to remove repetition from legacy data.
+//!
+//! Compounds which share properties with the same values are rewritten as `Prototype { overrides }`,
+//! where the prototype contains the shared properties. Prototypes are chosen greedily,
+//! always taking the one that saves the most source text, until no prototype makes the source shorter.
+//!
+//! The prototypes are written into their own document, which must be used as a prelude, see `::scope::Host`,
+//! because prototypes declared in the document itself would be part of the resolved document.
+//! Resolving the rewritten document with that prelude gives back the original object.
+
+use ::std::fmt;
+use ::std::collections::{ HashMap, HashSet };
+use ::std::collections::hash_map::DefaultHasher;
+use ::std::hash::{ Hash, Hasher };
+use ::flat::{ FlatObject, FlatCompound };
+use ::json::Json;
+
+
+pub type UnflattenResult<T> = ::std::result::Result<T, UnflattenError>;
+
+/// the paths point to the property that cannot be written, like `comics.0.title`
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum UnflattenError {
+    /// documents are compounds
+    ExpectedCompound,
+
+    /// a name that the parser would read differently, like `a.b` or an empty name
+    InvalidName { path: String, name: String },
+
+    /// string literals cannot contain `'`, and there is no way to write `\${`
+    InvalidLiteral { path: String },
+
+    /// json null has no equivalent
+    Null { path: String },
+}
+
+/// two documents which resolve to the original object, when `prototypes` is used as a prelude
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Unflattened {
+    pub prototypes: String,
+    pub document: String,
+}
+
+/// a compound of the document, which may inherit from one of the inferred prototypes
+struct Instance<'f> {
+    path: Vec<&'f str>,
+    properties: &'f FlatCompound,
+
+    /// the name of the property that contains the compound, or the list that contains it
+    hint: &'f str,
+}
+
+struct Prototype<'f> {
+    name: String,
+    properties: Vec<(&'f str, &'f FlatObject)>,
+}
+
+/// which instances inherit from which prototype, by path
+struct Inferred<'f> {
+    prototypes: Vec<Prototype<'f>>,
+    inheriting: HashMap<Vec<&'f str>, usize>,
+}
+
+
+pub fn unflatten(object: &FlatObject) -> UnflattenResult<Unflattened> {
+    let properties = match *object {
+        FlatObject::Compound(ref properties) => properties,
+        FlatObject::StringLiteral(_) => return Err(UnflattenError::ExpectedCompound),
+    };
+
+    let inferred = infer(properties);
+    let mut prototypes = String::new();
+
+    for prototype in &inferred.prototypes {
+        prototypes.push_str(&prototype.name);
+        prototypes.push_str(": ");
+
+        let body: FlatCompound = prototype.properties.iter().map(|&(name, value)| (name.to_string(), value.clone())).collect();
+        write_compound(&body, None, &mut vec![ prototype.name.as_str() ], 0, &mut prototypes)?;
+        prototypes.push('\n');
+    }
+
+    let mut document = String::new();
+    write_properties(properties, &[], Some(&inferred), &mut Vec::new(), 0, &mut document)?;
+
+    Ok(Unflattened { prototypes, document })
+}
+
+/// strings, numbers, and booleans become string literals, and arrays become lists
+pub fn from_json(json: &Json) -> UnflattenResult<FlatObject> {
+    from_json_at(json, &mut Vec::new())
+}
+
+fn from_json_at(json: &Json, path: &mut Vec<String>) -> UnflattenResult<FlatObject> {
+    let mut convert_member = |name: String, value: &Json| {
+        path.push(name.clone());
+        let converted = from_json_at(value, path).map(|value| (name, value));
+        path.pop();
+        converted
+    };
+
+    Ok(match *json {
+        Json::Null => return Err(UnflattenError::Null { path: path.join(".") }),
+        Json::Bool(boolean) => FlatObject::StringLiteral(boolean.to_string()),
+        Json::Number(number) => FlatObject::StringLiteral(::evaluate::format_number(number)),
+        Json::String(ref text) => FlatObject::StringLiteral(text.clone()),
+
+        Json::Array(ref elements) => FlatObject::compound(elements.iter().enumerate()
+            .map(|(index, element)| convert_member(index.to_string(), element))
+            .collect::<UnflattenResult<FlatCompound>>()?),
+
+        Json::Object(ref members) => FlatObject::compound(members.iter()
+            .map(|(name, value)| convert_member(name.clone(), value))
+            .collect::<UnflattenResult<FlatCompound>>()?),
+    })
+}
+
+fn infer(root: &FlatCompound) -> Inferred<'_> {
+    let mut instances = Vec::new();
+    collect_instances(root, &mut Vec::new(), "", &mut instances);
+
+    // equal properties of different instances have the same index
+    let mut indices: HashMap<(&str, u64), usize> = HashMap::new();
+    let mut properties: Vec<(&str, &FlatObject)> = Vec::new();
+
+    let keys: Vec<Vec<usize>> = instances.iter()
+        .map(|instance| {
+            let mut keys: Vec<usize> = instance.properties.iter()
+                .map(|(name, value)| *indices.entry((name.as_str(), hash_object(value))).or_insert_with(|| {
+                    properties.push((name.as_str(), value));
+                    properties.len() - 1
+                }))
+                .collect();
+
+            keys.sort_unstable();
+            keys
+        })
+        .collect();
+
+    // every set of properties that two compounds share could become a prototype
+    let mut candidates: Vec<Vec<(&str, &FlatObject)>> = Vec::new();
+    let mut known: HashSet<Vec<usize>> = HashSet::new();
+    let mut shared = Vec::new();
+
+    for (index, own) in keys.iter().enumerate() {
+        for other in &keys[index + 1 ..] {
+            shared.clear();
+            intersect(own, other, &mut shared);
+
+            if shared.is_empty() || known.contains(&shared) { continue; }
+            known.insert(shared.clone());
+
+            let mut candidate: Vec<(&str, &FlatObject)> = shared.iter().map(|&key| properties[key]).collect();
+            candidate.sort_by_key(|&(name, _)| name);
+            candidates.push(candidate);
+        }
+    }
+
+    let reserved: HashSet<&str> = root.keys().map(String::as_str).collect();
+    let mut inferred = Inferred { prototypes: Vec::new(), inheriting: HashMap::new() };
+
+    // the properties of instances which inherit them are not written, and neither are the compounds inside them
+    let mut removed: Vec<Vec<&str>> = Vec::new();
+
+    loop {
+        let available: Vec<&Instance> = instances.iter()
+            .filter(|instance| !inferred.inheriting.contains_key(&instance.path))
+            .filter(|instance| !removed.iter().any(|prefix| instance.path.starts_with(prefix)))
+            .collect();
+
+        let best = candidates.iter()
+            .map(|candidate| {
+                let users: Vec<&Instance> = available.iter().cloned()
+                    .filter(|instance| candidate.iter().all(|&(name, value)| instance.properties.get(name) == Some(value)))
+                    .collect();
+
+                let name = prototype_name(&users, &reserved, &inferred.prototypes);
+                let size = candidate.iter().map(|&(name, value)| property_size(name, value)).sum::<usize>() as i64;
+                let users_count = users.len() as i64;
+                let saved = users_count * size - (name.len() as i64 + 4 + size) - users_count * (name.len() as i64 + 1);
+
+                (saved, candidate, users, name)
+            })
+            .max_by_key(|&(saved, _, _, _)| saved);
+
+        let (candidate, users, name) = match best {
+            Some((saved, candidate, users, name)) if saved > 0 => (candidate, users, name),
+            _ => break,
+        };
+
+        for user in &users {
+            inferred.inheriting.insert(user.path.clone(), inferred.prototypes.len());
+            remove_inherited(user, candidate, &mut removed);
+        }
+
+        // in the order the first instance declares them
+        let mut properties = candidate.clone();
+        properties.sort_by_key(|&(name, _)| users[0].properties.keys().position(|declared| declared == name));
+        inferred.prototypes.push(Prototype { name, properties });
+    }
+
+    // other compounds can still inherit from a prototype, if they override the string literals that differ
+    for instance in &instances {
+        let is_available = !inferred.inheriting.contains_key(&instance.path)
+            && !removed.iter().any(|prefix| instance.path.starts_with(prefix));
+
+        if !is_available { continue; }
+
+        let best = inferred.prototypes.iter().enumerate()
+            .filter(|(_, prototype)| prototype.properties.iter().all(|&(name, value)| match (instance.properties.get(name), value) {
+                (Some(own), value) if own == value => true,
+                (Some(FlatObject::StringLiteral(_)), FlatObject::StringLiteral(_)) => true,
+                _ => false,
+            }))
+            .map(|(index, prototype)| {
+                let inherited = prototype.properties.iter()
+                    .filter(|&&(name, value)| instance.properties.get(name) == Some(value))
+                    .map(|&(name, value)| property_size(name, value))
+                    .sum::<usize>();
+
+                (inherited as i64 - prototype.name.len() as i64 - 1, index)
+            })
+            .max();
+
+        if let Some((saved, index)) = best {
+            if saved > 0 {
+                inferred.inheriting.insert(instance.path.clone(), index);
+                remove_inherited(instance, &inferred.prototypes[index].properties, &mut removed);
+            }
+        }
+    }
+
+    inferred
+}
+
+/// the compounds inside the inherited properties are not written
+fn remove_inherited<'f>(instance: &Instance<'f>, properties: &[(&'f str, &'f FlatObject)], removed: &mut Vec<Vec<&'f str>>) {
+    for &(name, value) in properties {
+        if instance.properties.get(name) == Some(value) {
+            let mut prefix = instance.path.clone();
+            prefix.push(name);
+            removed.push(prefix);
+        }
+    }
+}
+
+fn collect_instances<'f>(properties: &'f FlatCompound, path: &mut Vec<&'f str>, hint: &'f str, instances: &mut Vec<Instance<'f>>) {
+    for (name, value) in properties.iter() {
+        if let FlatObject::Compound(ref nested) = *value {
+            let nested_hint = if ::list::index(name).is_some() && !hint.is_empty() { hint } else { name.as_str() };
+
+            path.push(name);
+            instances.push(Instance { path: path.clone(), properties: nested, hint: nested_hint });
+            collect_instances(nested, path, nested_hint, instances);
+            path.pop();
+        }
+    }
+}
+
+/// the capitalized name of the property that contains most of the instances, like `Server` for `servers`,
+/// which neither the document nor another prototype declares
+fn prototype_name(instances: &[&Instance], reserved: &HashSet<&str>, prototypes: &[Prototype]) -> String {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for instance in instances { *counts.entry(instance.hint).or_insert(0) += 1; }
+
+    let hint = counts.into_iter().max_by_key(|&(hint, count)| (count, ::std::cmp::Reverse(hint)))
+        .map_or("", |(hint, _)| hint);
+
+    let mut base: String = hint.chars().filter(|character| character.is_alphanumeric() || *character == '_').collect();
+    if !base.starts_with(char::is_alphabetic) { base.insert_str(0, "Prototype"); }
+
+    let first = base.chars().next().expect("the name is not empty");
+    base = first.to_uppercase().chain(base[first.len_utf8() ..].chars()).collect();
+
+    let is_free = |name: &str| !reserved.contains(name) && !prototypes.iter().any(|prototype| prototype.name == name);
+    if is_free(&base) { return base; }
+
+    (2 ..).map(|number| format!("{}{}", base, number)).find(|name| is_free(name)).expect("names are unlimited")
+}
+
+/// equal objects have equal hashes, regardless of the order of their properties.
+/// properties whose hashes collide are still compared when choosing the instances of a prototype
+fn hash_object(object: &FlatObject) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    match *object {
+        FlatObject::StringLiteral(ref text) => text.hash(&mut hasher),
+        FlatObject::Compound(ref properties) => {
+            let mut hashes: Vec<(&str, u64)> = properties.iter().map(|(name, value)| (name.as_str(), hash_object(value))).collect();
+            hashes.sort_unstable();
+            hashes.hash(&mut hasher);
+        },
+    }
+
+    hasher.finish()
+}
+
+/// the elements that both sorted slices contain
+fn intersect(own: &[usize], other: &[usize], shared: &mut Vec<usize>) {
+    let (mut own, mut other) = (own.iter().peekable(), other.iter().peekable());
+
+    while let (Some(&&left), Some(&&right)) = (own.peek(), other.peek()) {
+        if left <= right { own.next(); }
+        if right <= left { other.next(); }
+        if left == right { shared.push(left); }
+    }
+}
+
+/// the approximate length of `name: value` in the source
+fn property_size(name: &str, value: &FlatObject) -> usize {
+    name.len() + 2 + match *value {
+        FlatObject::StringLiteral(ref text) => text.len() + 2,
+        FlatObject::Compound(ref properties) => 2 + properties.iter().map(|(name, value)| property_size(name, value) + 1).sum::<usize>(),
+    }
+}
+
+/// writes the object after its name. prototypes are used for the instances of the document,
+/// and not inside prototypes, where `inferred` is none
+fn write_object<'f>(object: &'f FlatObject, inferred: Option<&Inferred<'f>>, path: &mut Vec<&'f str>, indentation: usize, source: &mut String) -> UnflattenResult<()> {
+    match *object {
+        FlatObject::StringLiteral(ref text) => {
+            if text.contains('\'') || text.contains("\\${") {
+                return Err(UnflattenError::InvalidLiteral { path: path.join(".") });
+            }
+
+            source.push('\'');
+            source.push_str(&text.replace("${", "\\${"));
+            source.push('\'');
+            Ok(())
+        },
+
+        FlatObject::Compound(ref properties) => {
+            let prototype = inferred.and_then(|inferred| inferred.inheriting.get(path.as_slice()).map(|&index| &inferred.prototypes[index]));
+
+            match prototype {
+                None => write_compound(properties, inferred, path, indentation, source),
+                Some(prototype) => {
+                    source.push_str(&prototype.name);
+
+                    let inherited = &prototype.properties;
+                    if properties.iter().all(|(name, value)| inherited.contains(&(name.as_str(), value))) { return Ok(()); }
+
+                    source.push_str(" {\n");
+                    write_properties(properties, inherited, inferred, path, indentation + 1, source)?;
+                    source.push_str(&"    ".repeat(indentation));
+                    source.push('}');
+                    Ok(())
+                },
+            }
+        },
+    }
+}
+
+fn write_compound<'f>(properties: &'f FlatCompound, inferred: Option<&Inferred<'f>>, path: &mut Vec<&'f str>, indentation: usize, source: &mut String) -> UnflattenResult<()> {
+    if properties.is_empty() {
+        source.push_str("{}");
+        return Ok(());
+    }
+
+    source.push_str("{\n");
+    write_properties(properties, &[], inferred, path, indentation + 1, source)?;
+    source.push_str(&"    ".repeat(indentation));
+    source.push('}');
+    Ok(())
+}
+
+/// one property per line, except the inherited ones. lists are written without indices, ordered by their index
+fn write_properties<'f>(properties: &'f FlatCompound, inherited: &[(&str, &FlatObject)], inferred: Option<&Inferred<'f>>, path: &mut Vec<&'f str>, indentation: usize, source: &mut String) -> UnflattenResult<()> {
+    let is_list = inherited.is_empty() && ::export::list(properties).is_some();
+
+    // implicit indices are numbered in the order the entries are written
+    let mut entries: Vec<(&'f String, &'f FlatObject)> = properties.iter().collect();
+    if is_list { entries.sort_by_key(|&(name, _)| ::list::index(name)); }
+
+    for (name, value) in entries {
+        if inherited.contains(&(name.as_str(), value)) { continue; }
+
+        if !::parse::is_valid_name(name) {
+            let mut name_path = path.clone();
+            name_path.push(name);
+            return Err(UnflattenError::InvalidName { path: name_path.join("."), name: name.clone() });
+        }
+
+        source.push_str(&"    ".repeat(indentation));
+        if !is_list { source.push_str(name); }
+        source.push_str(": ");
+
+        path.push(name);
+        write_object(value, inferred, path, indentation, source)?;
+        path.pop();
+
+        source.push('\n');
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for UnflattenError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnflattenError::ExpectedCompound => formatter.write_str("only compounds can be unflattened"),
+
+            UnflattenError::InvalidName { ref path, ref name } => write!(
+                formatter, "`{}` cannot be written as a name, at `{}`", name, path
+            ),
+
+            UnflattenError::InvalidLiteral { ref path } => write!(
+                formatter, "`{}` contains `'` or `\\${{`, which cannot be written in a string literal", path
+            ),
+
+            UnflattenError::Null { ref path } => write!(
+                formatter, "`{}` is null, which has no equivalent", path
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::scope::Host;
+
+    /// also checks that the unflattened documents resolve to the original object
+    fn unflatten_resolved(original: &FlatObject) -> Unflattened {
+        let unflattened = unflatten(original).expect("Unflatten Error");
+
+        {
+            let prelude = ::parse::parse(&unflattened.prototypes).expect("Parsing Error");
+            let document = ::parse::parse(&unflattened.document).expect("Parsing Error");
+
+            let mut host = Host::default();
+            host.prelude(&prelude);
+            assert_eq!(FlatObject::build_in(&document, &host).as_ref(), Ok(original));
+        }
+
+        unflattened
+    }
+
+    #[test]
+    fn test_unflatten(){
+        let source = "
+            servers: {
+                : { image: 'nginx' replicas: '3' region: 'eu' name: 'web' }
+                : { image: 'nginx' replicas: '3' region: 'eu' name: 'api' }
+                : { image: 'nginx' replicas: '3' region: 'us' name: 'admin' }
+            }
+            backup: { image: 'nginx' replicas: '3' region: 'eu' name: 'backup' ports: { : '80' } }
+            motd: 'welcome to \\${name}'
+        ";
+
+        let original = FlatObject::parse(source).unwrap().unwrap();
+        assert_eq!(unflatten_resolved(&original), Unflattened {
+            prototypes: String::from("\
+Servers: {
+    image: 'nginx'
+    replicas: '3'
+    region: 'eu'
+}
+"),
+            document: String::from("\
+servers: {
+    : Servers {
+        name: 'web'
+    }
+    : Servers {
+        name: 'api'
+    }
+    : Servers {
+        region: 'us'
+        name: 'admin'
+    }
+}
+backup: Servers {
+    name: 'backup'
+    ports: {
+        : '80'
+    }
+}
+motd: 'welcome to \\${name}'
+"),
+        });
+
+        for source in &[ include_str!("../assets/example.ptl"), include_str!("../assets/html.ptl"), "a: { } b: { }" ] {
+            unflatten_resolved(&FlatObject::parse(source).unwrap().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_unordered_lists(){
+        let entry = |index: usize| (index.to_string(), FlatObject::StringLiteral(format!("entry {}", index)));
+        let nested = |index: usize| (index.to_string(), FlatObject::compound(vec![ entry(1), entry(0) ].into_iter().collect()));
+
+        // every order in which four entries can be declared
+        for permutation in 0 .. 24 {
+            let mut remaining: Vec<usize> = (0 .. 4).collect();
+            let mut order = Vec::new();
+            let mut choice = permutation;
+
+            for length in (1 ..= 4).rev() {
+                order.push(remaining.remove(choice % length));
+                choice /= length;
+            }
+
+            let list: FlatCompound = order.iter().map(|&index| if index % 2 == 0 { entry(index) } else { nested(index) }).collect();
+            let original = FlatObject::compound(vec![ (String::from("l"), FlatObject::compound(list)) ].into_iter().collect());
+
+            unflatten_resolved(&original);
+        }
+    }
+
+    #[test]
+    fn test_json(){
+        let json = Json::parse(r#"{ "users": [ { "role": "admin", "active": true, "name": "a" }, { "role": "admin", "active": true, "name": "b" } ] }"#).unwrap();
+        let unflattened = unflatten_resolved(&from_json(&json).unwrap());
+
+        assert_eq!(unflattened.prototypes, "Users: {\n    role: 'admin'\n    active: 'true'\n}\n");
+        assert_eq!(from_json(&Json::parse(r#"{ "a": [ 1, null ] }"#).unwrap()), Err(UnflattenError::Null { path: String::from("a.1") }));
+
+        let json = Json::parse(r#"{ "été": [ { "region": "europe", "image": "nginx", "c": "1" }, { "region": "europe", "image": "nginx", "c": "2" } ] }"#).unwrap();
+        assert!(unflatten_resolved(&from_json(&json).unwrap()).prototypes.starts_with("Été: {"));
+    }
+
+    #[test]
+    fn test_unrepresentable(){
+        let unflatten_json = |json: &str| unflatten(&from_json(&Json::parse(json).unwrap()).unwrap()).map_err(|error| error.to_string());
+        assert_eq!(unflatten_json(r#"{ "a": { "b.c": "x" } }"#), Err(String::from("`b.c` cannot be written as a name, at `a.b.c`")));
+        assert_eq!(unflatten_json(r#"{ "a": "it's" }"#), Err(String::from("`a` contains `'` or `\\${`, which cannot be written in a string literal")));
+        assert_eq!(unflatten(&FlatObject::StringLiteral(String::new())), Err(UnflattenError::ExpectedCompound));
+    }
+}